@@ -0,0 +1,84 @@
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::Path;
+
+// Subcommands an alias may not shadow
+const BUILTIN_COMMANDS: &[&str] = &["run", "sessions", "index-deps", "check-ignore", "merge", "lint-context", "schema", "self-update", "help"];
+
+// An `[alias]` entry: a shell-style string or a list of arguments
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Alias {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl Alias {
+    fn args(&self, name: &str) -> Result<Vec<String>> {
+        match self {
+            Alias::Line(line) => shlex::split(line).with_context(|| format!("Alias '{}' has unbalanced quotes", name)),
+            Alias::Args(args) => Ok(args.clone()),
+        }
+    }
+}
+
+// Replace a leading alias (`contree test ...`) with the arguments it stands for, followed by
+// the arguments given after it. Like cargo aliases, built-in subcommands always win and an
+// alias can't expand to another alias.
+pub fn expand(argv: Vec<OsString>, dir: &Path) -> Result<Vec<OsString>> {
+    let Some(name) = argv.get(1).and_then(|arg| arg.to_str()) else {
+        return Ok(argv);
+    };
+    if name.starts_with('-') || BUILTIN_COMMANDS.contains(&name) {
+        return Ok(argv);
+    }
+    let config = Config::load(dir)?;
+    let Some(alias) = config.alias.get(name) else {
+        return Ok(argv);
+    };
+    let args = alias.args(name)?;
+    if let Some(first) = args.first().filter(|first| config.alias.contains_key(*first)) {
+        bail!("Alias '{}' expands to another alias '{}', which is not supported", name, first);
+    }
+
+    let mut expanded = vec![argv[0].clone()];
+    expanded.extend(args.into_iter().map(OsString::from));
+    expanded.extend(argv.into_iter().skip(2));
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn expand_in(config: &str, argv: &[&str]) -> Result<Vec<String>> {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(crate::config::CONFIG_FILE), config).unwrap();
+        let argv = argv.iter().map(OsString::from).collect();
+        Ok(expand(argv, dir.path())?
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn expands_leading_alias_and_keeps_trailing_args() {
+        let config = "[alias]\nerrs = \"--capture-filter errors -g 'a b'\"\ndeps = [\"-D\"]\n";
+        assert_eq!(
+            expand_in(config, &["contree", "errs", "-o", "out.md"]).unwrap(),
+            ["contree", "--capture-filter", "errors", "-g", "a b", "-o", "out.md"]
+        );
+        assert_eq!(expand_in(config, &["contree", "deps"]).unwrap(), ["contree", "-D"]);
+        assert_eq!(expand_in(config, &["contree", "src"]).unwrap(), ["contree", "src"]);
+    }
+
+    #[test]
+    fn builtins_win_and_aliases_do_not_chain() {
+        let config = "[alias]\nsessions = \"-D\"\na = \"b\"\nb = \"-D\"\n";
+        assert_eq!(expand_in(config, &["contree", "sessions", "list"]).unwrap(), ["contree", "sessions", "list"]);
+        assert!(expand_in(config, &["contree", "a"]).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use clap::ValueEnum;
+
+// How the model is asked to structure its answer, for `--answer-format`. Each shape is one a
+// tool can apply without a person in between.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnswerFormat {
+    /// A unified diff that `git apply` accepts
+    Diff,
+    /// Whole changed files, each under a `File: <path>` header as in contree's text output
+    Files,
+    /// One JSON object listing the changed files and their new contents
+    Json,
+}
+
+impl AnswerFormat {
+    // The instruction block appended after the context. `root` is the scanned directory when
+    // paths are shown in full, since a diff's paths must be relative to it.
+    pub fn instructions(self, root: Option<&str>) -> String {
+        match self {
+            AnswerFormat::Diff => format!(
+                "Answer with your changes as one unified diff that `git apply` accepts, in a single ```diff fenced block.\n\
+                 Give each changed file a `--- a/<path>` and `+++ b/<path>` header pair, with the path relative to the project root{}, \
+                 and hunks with at least 3 lines of unchanged context that match the files above exactly.\n\
+                 Use `--- /dev/null` for a new file and `+++ /dev/null` for a deleted one.\n\
+                 Explain the change in a few sentences before the block, and write nothing after it.",
+                root.map(|root| format!(" `{}`", root)).unwrap_or_default()
+            ),
+            AnswerFormat::Files => {
+                "Answer with the complete new contents of every file you change.\n\
+                 Start each file with a line `File: <path>`, with the path as shown in this context, followed by its contents in a fenced code block.\n\
+                 Give whole files, never excerpts or placeholders such as `...`, and leave out files you don't change.\n\
+                 To delete a file, write its `File: <path>` line followed by a line `(deleted)` instead of a code block."
+                    .to_string()
+            }
+            AnswerFormat::Json => {
+                "Answer with a single JSON object and nothing else, not even a code fence:\n\
+                 {\"summary\": string, \"files\": [{\"path\": string, \"action\": \"create\" | \"replace\" | \"delete\", \"contents\": string}]}\n\
+                 `path` is as shown in this context, and `contents` is the complete new file (left out for \"delete\").\n\
+                 List only files you change."
+                    .to_string()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_paths_are_relative_to_the_shown_root() {
+        let diff = AnswerFormat::Diff.instructions(Some("/work/app"));
+        assert!(diff.contains("relative to the project root `/work/app`,"), "{}", diff);
+        assert!(AnswerFormat::Diff.instructions(None).contains("relative to the project root,"));
+        assert!(AnswerFormat::Files.instructions(Some("/work/app")).starts_with("Answer with the complete new contents"));
+    }
+}
@@ -0,0 +1,173 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// The attributes GitHub's linguist uses to keep files out of language statistics and diffs
+const GENERATED: &str = "linguist-generated";
+const VENDORED: &str = "linguist-vendored";
+
+// One `.gitattributes` line that sets or unsets the linguist attributes
+struct Rule {
+    matcher: Gitignore,
+    source: PathBuf,
+    // 1-based
+    line: usize,
+    pattern: String,
+    generated: Option<bool>,
+    vendored: Option<bool>,
+}
+
+// The line that marks a file generated or vendored
+#[derive(Debug, PartialEq, Eq)]
+pub struct Marking {
+    // `linguist-generated` or `linguist-vendored`
+    pub attribute: &'static str,
+    pub source: PathBuf,
+    pub line: usize,
+    pub pattern: String,
+}
+
+// The linguist attributes of the repository's files, read like git reads them: the
+// `.gitattributes` of each directory from the repository root down, deeper ones overriding,
+// then `.git/info/attributes`. Within a file the last matching line wins. Each directory's
+// file is read once, when the first path below it is looked up.
+pub struct Attributes {
+    top: PathBuf,
+    info: Vec<Rule>,
+    directories: Mutex<HashMap<PathBuf, Arc<Vec<Rule>>>>,
+}
+
+impl Attributes {
+    // For the repository holding `dir`, or `dir` alone outside one. Paths are compared
+    // canonicalized, so a relative or symlinked `dir` still finds the repository's files.
+    pub fn load(dir: &Path) -> Attributes {
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let top = dir.ancestors().find(|ancestor| ancestor.join(".git").exists()).unwrap_or(&dir).to_path_buf();
+        let info = read_rules(&top.join(".git/info/attributes"), &top);
+        Attributes { top, info, directories: Mutex::new(HashMap::new()) }
+    }
+
+    // The line marking a file `linguist-generated` (or else `linguist-vendored`), if one does
+    pub fn linguist(&self, path: &Path) -> Option<Marking> {
+        // The directory rather than the file, so a symlink is judged by its own name; paths that
+        // don't exist (yet) are taken as they are
+        let parent = path.parent()?;
+        let path = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf()).join(path.file_name()?);
+        let relative = path.strip_prefix(&self.top).ok()?;
+        let mut dir = self.top.clone();
+        let mut layers = vec![(dir.clone(), self.directory_rules(&dir))];
+        for component in relative.parent().into_iter().flat_map(Path::components) {
+            dir.push(component);
+            layers.push((dir.clone(), self.directory_rules(&dir)));
+        }
+
+        let mut generated: Option<&Rule> = None;
+        let mut vendored: Option<&Rule> = None;
+        let layers = layers.iter().map(|(dir, rules)| (dir.as_path(), rules.as_slice()));
+        for (dir, rules) in layers.chain([(self.top.as_path(), self.info.as_slice())]) {
+            let relative = path.strip_prefix(dir).unwrap_or(&path);
+            for rule in rules.iter().filter(|rule| rule.matcher.matched(relative, false).is_ignore()) {
+                if let Some(set) = rule.generated {
+                    generated = set.then_some(rule);
+                }
+                if let Some(set) = rule.vendored {
+                    vendored = set.then_some(rule);
+                }
+            }
+        }
+        let (attribute, rule) = generated.map(|rule| (GENERATED, rule)).or(vendored.map(|rule| (VENDORED, rule)))?;
+        Some(Marking { attribute, source: rule.source.clone(), line: rule.line, pattern: rule.pattern.clone() })
+    }
+
+    fn directory_rules(&self, dir: &Path) -> Arc<Vec<Rule>> {
+        let mut directories = self.directories.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        directories.entry(dir.to_path_buf()).or_insert_with(|| Arc::new(read_rules(&dir.join(".gitattributes"), dir))).clone()
+    }
+}
+
+// The lines of an attributes file that mention the linguist attributes; a missing or unreadable
+// file has none
+fn read_rules(path: &Path, base: &Path) -> Vec<Rule> {
+    fs::read_to_string(path).map(|text| parse_rules(&text, path, base)).unwrap_or_default()
+}
+
+// `pattern attr...` lines, where `attr` sets, `-attr` and `attr=false` unset, and `!attr` returns
+// to unspecified (which for these attributes is the same as unset). Macro definitions and
+// negative patterns, which git rejects, are skipped.
+fn parse_rules(text: &str, source: &Path, base: &Path) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let Some(pattern) = fields.next().filter(|pattern| !pattern.starts_with(['#', '!']) && !pattern.starts_with("[attr]")) else {
+            continue;
+        };
+        let mut rule = Rule {
+            matcher: Gitignore::empty(),
+            source: source.to_path_buf(),
+            line: number + 1,
+            pattern: pattern.to_string(),
+            generated: None,
+            vendored: None,
+        };
+        for attribute in fields {
+            let (name, value) = match attribute.split_once('=') {
+                Some((name, value)) => (name, !matches!(value, "false" | "0")),
+                None => match attribute.strip_prefix(['-', '!']) {
+                    Some(name) => (name, false),
+                    None => (attribute, true),
+                },
+            };
+            match name {
+                GENERATED => rule.generated = Some(value),
+                VENDORED => rule.vendored = Some(value),
+                _ => {}
+            }
+        }
+        if rule.generated.is_none() && rule.vendored.is_none() {
+            continue;
+        }
+        let mut builder = GitignoreBuilder::new(base);
+        let Some(matcher) = builder.add_line(None, pattern).ok().and_then(|builder| builder.build().ok()) else {
+            continue;
+        };
+        rules.push(Rule { matcher, ..rule });
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_linguist_attributes_like_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = &fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("web/vendor")).unwrap();
+        fs::write(
+            root.join(".gitattributes"),
+            "# generated clients\n*.pb.go linguist-generated=true\napi/** linguist-generated\napi/keep.go -linguist-generated\n\
+             vendor linguist-vendored\n*.rs text eol=lf\n",
+        )
+        .unwrap();
+        fs::write(root.join("web/.gitattributes"), "vendor/** linguist-vendored\nvendor/ours.js linguist-vendored=false\n").unwrap();
+        fs::write(root.join(".git/info/attributes"), "api/local.go !linguist-generated\n").unwrap();
+
+        let attributes = Attributes::load(root);
+        let linguist = |path: &str| attributes.linguist(&root.join(path)).map(|marking| (marking.attribute, marking.line));
+        assert_eq!(linguist("proto/user.pb.go"), Some(("linguist-generated", 2)));
+        assert_eq!(linguist("api/client/mod.go"), Some(("linguist-generated", 3)));
+        assert_eq!(linguist("api/keep.go"), None);
+        assert_eq!(linguist("api/local.go"), None);
+        let marking = attributes.linguist(&root.join("web/vendor/jquery.js")).unwrap();
+        assert_eq!((marking.source, marking.line, marking.pattern.as_str()), (root.join("web/.gitattributes"), 1, "vendor/**"));
+        assert_eq!(linguist("web/vendor/ours.js"), None);
+        // Patterns naming a directory don't reach the files inside it
+        assert_eq!(linguist("vendor/lib.js"), None);
+        assert_eq!(linguist("src/main.rs"), None);
+        assert_eq!(attributes.linguist(Path::new("/elsewhere/a.pb.go")), None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+// How binary files are previewed in place of the bare `[binary file]` placeholder
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryPreview {
+    /// Hex dump of the leading bytes, with an ASCII column
+    Hex,
+    /// Printable strings (4+ characters) found in the leading bytes
+    Strings,
+}
+
+pub const DEFAULT_PREVIEW_BYTES: usize = 512;
+
+// Shortest run of printable characters reported by the strings preview (as with `strings`)
+const MIN_STRING_LEN: usize = 4;
+
+// Bytes read to infer a binary file's type and image dimensions
+const SNIFF_BYTES: u64 = 64 * 1024;
+
+// Describe a binary file for its placeholder, e.g. "image/png, 2.4 KiB, 640x480"
+pub fn describe(path: &Path) -> io::Result<String> {
+    let file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let mut head = Vec::new();
+    file.take(SNIFF_BYTES).read_to_end(&mut head)?;
+
+    let mut parts = vec![infer::get(&head)
+        .map(|kind| kind.mime_type())
+        .unwrap_or("application/octet-stream")
+        .to_string()];
+    parts.push(format_size(total));
+    if let Ok(size) = imagesize::blob_size(&head) {
+        parts.push(format!("{}x{}", size.width, size.height));
+    }
+    Ok(parts.join(", "))
+}
+
+// Human-readable byte size using binary units
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+// Render a preview of the first `limit` bytes of a binary file
+pub fn preview(path: &Path, mode: BinaryPreview, limit: usize) -> io::Result<String> {
+    let file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let mut bytes = Vec::with_capacity(limit);
+    file.take(limit as u64).read_to_end(&mut bytes)?;
+
+    let mut preview = match mode {
+        BinaryPreview::Hex => hex_dump(&bytes),
+        BinaryPreview::Strings => printable_strings(&bytes),
+    };
+    if total > bytes.len() as u64 {
+        preview.push_str(&format!("[... first {} of {} bytes shown ...]\n", bytes.len(), total));
+    }
+    Ok(preview)
+}
+
+// `xxd`-style dump: offset, sixteen hex bytes, and their printable ASCII
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        dump.push_str(&format!("{:08x}: {:<47}  {}\n", i * 16, hex.join(" "), ascii));
+    }
+    dump
+}
+
+fn printable_strings(bytes: &[u8]) -> String {
+    let mut strings = String::new();
+    for run in bytes.split(|&b| !(b.is_ascii_graphic() || b == b' ' || b == b'\t')) {
+        if run.len() >= MIN_STRING_LEN {
+            strings.push_str(&String::from_utf8_lossy(run));
+            strings.push('\n');
+        }
+    }
+    if strings.is_empty() {
+        strings.push_str("[no printable strings]\n");
+    }
+    strings
+}
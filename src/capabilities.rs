@@ -0,0 +1,51 @@
+use crate::{answer, binary, capture, clipboard, compress, content, project, render, schema, tokens};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+// Names of a value enum's variants as accepted on the command line
+fn variants<T: ValueEnum>() -> Vec<String> {
+    T::value_variants()
+        .iter()
+        .filter_map(|variant| variant.to_possible_value())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+// What this build of contree supports, for wrapper tools that feature-detect across versions
+// and build variants. Keys are only ever added; a missing key means an older contree.
+pub fn describe() -> Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "formats": variants::<render::Format>(),
+        "tokenizers": [tokens::TOKENIZER],
+        "parsers": {
+            "public_api": "syn",
+            "extract_paths": ["json", "ndjson"],
+            "stack_traces": ["miri", "asan", "tsan"],
+        },
+        "tree_sitter_grammars": grammars(),
+        "binary_previews": variants::<binary::BinaryPreview>(),
+        "capture_filters": variants::<capture::CaptureFilter>(),
+        "project_types": variants::<project::Ecosystem>(),
+        "schemas": variants::<schema::SchemaKind>(),
+        "answer_formats": variants::<answer::AnswerFormat>(),
+        "grep_targets": variants::<content::GrepTarget>(),
+        "grep_modes": variants::<content::GrepMode>(),
+        "upload_targets": ["gist", "http"],
+        "object_stores": ["s3", "gs"],
+        "encryption": ["age"],
+        "signing": ["minisign", "ssh"],
+        "clipboard": clipboard::TOOLS,
+        "compression": variants::<compress::Compression>(),
+    })
+}
+
+#[cfg(feature = "syntax")]
+fn grammars() -> Vec<&'static str> {
+    crate::syntax::GRAMMARS.to_vec()
+}
+
+#[cfg(not(feature = "syntax"))]
+fn grammars() -> Vec<&'static str> {
+    Vec::new()
+}
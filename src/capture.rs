@@ -0,0 +1,508 @@
+use anyhow::{Context, Result};
+use regex::{Regex, RegexSet};
+use crate::content::{self, FileContent};
+use crate::project::Ecosystem;
+use clap::ValueEnum;
+use crate::spill::{MemoryBudget, SpillBuffer};
+use crate::warnings;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+// Where passed-through output is echoed: stdout, unless the context itself goes there in a
+// format that can't have other text mixed in
+fn echo_stream(to_stderr: bool) -> Box<dyn Write> {
+    if to_stderr {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+// Read piped stdin line by line, passing it through to the console while capturing it
+pub fn read_stdin_passthrough(budget: &MemoryBudget, echo_to_stderr: bool) -> Result<FileContent> {
+    let mut full_output = SpillBuffer::new(budget);
+    let mut line_buffer = String::new();
+    let mut stdin_reader = BufReader::new(io::stdin());
+    let mut echo = echo_stream(echo_to_stderr);
+
+    loop {
+        line_buffer.clear();
+        match stdin_reader.read_line(&mut line_buffer) {
+            Ok(0) => break, // EOF
+            Ok(_) => {
+                echo.write_all(line_buffer.as_bytes())?;
+                echo.flush()?;
+                full_output.push_str(&line_buffer)?;
+            }
+            Err(e) => eprintln!("Error reading stdin: {}", e),
+        }
+    }
+
+    Ok(full_output.into_text()?)
+}
+
+// How the captured command ended. Piped input only reveals when the pipe closed, so the
+// duration is measured from contree's start and the exit status is unknown.
+#[derive(Debug, Clone)]
+pub struct CommandRun {
+    // The command line, when contree ran it with --run
+    pub command: Option<String>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    // Left out of --reproducible output
+    pub duration: Option<Duration>,
+}
+
+impl CommandRun {
+    pub fn piped(duration: Duration) -> Self {
+        CommandRun {
+            command: None,
+            exit_code: None,
+            signal: None,
+            duration: Some(duration),
+        }
+    }
+
+    fn finished(command: &str, status: ExitStatus, duration: Duration) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+        CommandRun {
+            command: Some(command.to_string()),
+            exit_code: status.code(),
+            signal,
+            duration: Some(duration),
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.exit_code.is_some_and(|code| code != 0) || self.signal.is_some()
+    }
+
+    // Header lines describing the run, e.g. `Exit status: 101` and `Duration: 14m 2s`
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(command) = &self.command {
+            lines.push(format!("Command: {}", command));
+        }
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => lines.push(format!("Exit status: {}", code)),
+            (None, Some(signal)) => lines.push(format!("Exit status: killed by signal {}", signal)),
+            (None, None) => {}
+        }
+        match (self.duration.map(format_duration), &self.command) {
+            (Some(duration), Some(_)) => lines.push(format!("Duration: {}", duration)),
+            (Some(duration), None) => lines.push(format!("Duration: {} (until the input closed)", duration)),
+            (None, _) => {}
+        }
+        lines
+    }
+}
+
+// `850ms`, `3.2s`, `14m 2s`, `1h 3m 20s`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {}m {}s", secs / 3600, secs % 3600 / 60, secs % 60)
+    }
+}
+
+// Split a `--run` command line like a shell would (without running one)
+pub fn split_command_line(command_line: &str) -> Result<Vec<String>> {
+    shlex::split(command_line)
+        .filter(|argv| !argv.is_empty())
+        .with_context(|| format!("Invalid --run command (empty or unbalanced quotes): {}", command_line))
+}
+
+// Run a command, passing its stdout and stderr through to the console as
+// `COMMAND 2>&1 | contree` would while capturing both
+pub fn run_command(argv: &[String], budget: &MemoryBudget, echo_to_stderr: bool) -> Result<(FileContent, CommandRun)> {
+    let command_line = shlex::try_join(argv.iter().map(String::as_str)).unwrap_or_else(|_| argv.join(" "));
+    let started = Instant::now();
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", argv[0]))?;
+
+    // Lines from both streams, in the order they arrive
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    let readers: Vec<Box<dyn io::Read + Send>> = vec![
+        Box::new(child.stdout.take().expect("piped stdout")),
+        Box::new(child.stderr.take().expect("piped stderr")),
+    ];
+    let threads: Vec<_> = readers
+        .into_iter()
+        .map(|stream| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    let mut line = Vec::new();
+                    match reader.read_until(b'\n', &mut line) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {
+                            if sender.send(line).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut full_output = SpillBuffer::new(budget);
+    let mut echo = echo_stream(echo_to_stderr);
+    for line in receiver {
+        echo.write_all(&line)?;
+        echo.flush()?;
+        full_output.push_str(&String::from_utf8_lossy(&line))?;
+    }
+    for thread in threads {
+        let _ = thread.join();
+    }
+    let status = child.wait()?;
+    let run = CommandRun::finished(&command_line, status, started.elapsed());
+    Ok((full_output.into_text()?, run))
+}
+
+// Append a previously captured log file to the capture buffer.
+// Logs often contain stray non-UTF-8 bytes (progress bars, colour codes), so decode lossily.
+fn read_input_file(path: &Path, buffer: &mut SpillBuffer) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to read input log: {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .with_context(|| format!("Failed to read input log: {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        buffer.push_str(&String::from_utf8_lossy(&line))?;
+    }
+    if !line.is_empty() && !line.ends_with(b"\n") {
+        buffer.push_str("\n")?;
+    }
+    Ok(())
+}
+
+// Read and merge every given log, expanding directories to the files they contain (sorted
+// for stable output). Error references are extracted from the merged text, so a failure
+// spread over build, test, and deploy logs is resolved as one.
+pub fn read_input_logs(paths: &[PathBuf], budget: &MemoryBudget) -> Result<FileContent> {
+    let mut merged = SpillBuffer::new(budget);
+    for path in paths {
+        let files: Vec<PathBuf> = if path.is_dir() {
+            WalkDir::new(path)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| match content::special_kind(e.file_type()) {
+                    Some(kind) => {
+                        warnings::warn(warnings::Kind::Skipped, format!("Skipping {} {} in input logs", kind, e.path().display()));
+                        false
+                    }
+                    None => e.file_type().is_file(),
+                })
+                .map(|e| e.into_path())
+                .collect()
+        } else {
+            vec![path.clone()]
+        };
+
+        for file in files {
+            read_input_file(&file, &mut merged)?;
+        }
+    }
+    Ok(merged.into_text()?)
+}
+
+// The outputs of several commands run in order, one after another, each ending in a newline
+pub fn join_outputs(outputs: &[FileContent], budget: &MemoryBudget) -> Result<FileContent> {
+    let mut joined = SpillBuffer::new(budget);
+    for output in outputs {
+        joined.push_str(output)?;
+        if !output.is_empty() && !output.ends_with('\n') {
+            joined.push_str("\n")?;
+        }
+    }
+    Ok(joined.into_text()?)
+}
+
+// How the captured command output is trimmed before it is recorded in the context
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureFilter {
+    /// Keep only error, warning, and panic lines
+    Errors,
+}
+
+// Diagnostic lines worth keeping whatever the toolchain
+const GENERIC_ERROR_PATTERNS: &[&str] = &[r"(?i)\b(fatal|error|failed|failure)\b"];
+
+// Compile the filter's heuristics (those of the project's ecosystems, or of every ecosystem
+// when none was detected) and extra patterns into one set, so each line is scanned once
+fn error_line_set(filter: CaptureFilter, ecosystems: &[Ecosystem], extra_patterns: &[String]) -> Result<RegexSet> {
+    for pattern in extra_patterns {
+        Regex::new(pattern).with_context(|| format!("Invalid capture filter pattern: {}", pattern))?;
+    }
+    let ecosystems = if ecosystems.is_empty() { Ecosystem::value_variants() } else { ecosystems };
+    let patterns = match filter {
+        CaptureFilter::Errors => ecosystems
+            .iter()
+            .flat_map(|ecosystem| ecosystem.error_patterns())
+            .chain(GENERIC_ERROR_PATTERNS)
+            .copied()
+            .chain(extra_patterns.iter().map(String::as_str)),
+    };
+    RegexSet::new(patterns).context("Invalid capture filter pattern")
+}
+
+// Runs shorter than this are left alone: the marker would not save anything
+const MIN_COLLAPSED_RUN: usize = 3;
+
+// Tracks a run of identical or near-identical lines (differing only in numbers, such as
+// counters, addresses, or timestamps). Identical runs become the line and a
+// `<repeated N times>` marker; near-identical runs keep their first and last lines around a
+// `<N similar lines>` marker, since the numbers may matter (e.g. which test cases failed).
+struct RepeatCollapser {
+    digits: Regex,
+    // The run's leading lines, as many as a run too short to collapse can hold
+    head: Vec<String>,
+    last: String,
+    key: String,
+    identical: bool,
+    run: usize,
+}
+
+impl RepeatCollapser {
+    fn new() -> Self {
+        RepeatCollapser {
+            digits: Regex::new(r"\d+").expect("valid regex"),
+            head: Vec::new(),
+            last: String::new(),
+            key: String::new(),
+            identical: true,
+            run: 0,
+        }
+    }
+
+    fn push(&mut self, line: &str, out: &mut SpillBuffer) -> io::Result<()> {
+        let key = self.digits.replace_all(line.trim_end(), "#");
+        if self.run == 0 || key != self.key {
+            self.flush(out)?;
+            self.key = key.into_owned();
+            self.identical = true;
+        } else {
+            self.identical &= line.trim_end() == self.head[0].trim_end();
+        }
+        if self.head.len() < MIN_COLLAPSED_RUN {
+            self.head.push(line.to_string());
+        }
+        self.last = line.to_string();
+        self.run += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self, out: &mut SpillBuffer) -> io::Result<()> {
+        let run = std::mem::take(&mut self.run);
+        let head = std::mem::take(&mut self.head);
+        let lines = if self.identical && run >= MIN_COLLAPSED_RUN {
+            vec![head[0].clone(), format!("<repeated {} times>", run)]
+        } else if run > MIN_COLLAPSED_RUN {
+            vec![head[0].clone(), format!("<{} similar lines>", run - 2), self.last.clone()]
+        } else {
+            head
+        };
+        for line in lines {
+            out.push_str(&line)?;
+            out.push_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+// Cargo status lines that carry no diagnostic value once a build has failed. Each matches
+// cargo's exact shape (a right-aligned status verb, then a package and version or a fixed
+// phrase), so similar-looking lines from other tools are kept.
+const CARGO_NOISE_PATTERNS: &[&str] = &[
+    r"^ {0,12}(Compiling|Checking|Downloaded|Adding|Removing|Fresh|Documenting|Packaging|Verifying|Archiving|Unpacking|Installing) [\w-]+ v\d+\.\d+\.\d+\S*( \(.*\))?$",
+    r"^ {0,12}Finished `[^`]+` profile \[.*\] target\(s\) in ",
+    r"^ {0,12}Downloading( \d+ crates?( \(.*\))?| crates \.\.\.)$",
+    r"^ {0,12}Updating (crates\.io index|`.*` index|git repository `.*`)$",
+    r"^ {0,12}Locking \d+ packages? to latest",
+    r"^ {0,12}Blocking waiting for file lock on ",
+    r"^ {0,12}Building \[[ =>]*\] \d+/\d+",
+];
+
+// How the captured output is cleaned up before it is recorded
+pub struct CaptureOptions<'a> {
+    pub strip_cargo_noise: bool,
+    pub filter: Option<CaptureFilter>,
+    // Whose error heuristics the filter uses
+    pub ecosystems: &'a [Ecosystem],
+    pub filter_patterns: &'a [String],
+    pub collapse_repeats: bool,
+}
+
+// Produce the recorded command output in one streaming pass: strip cargo's progress and
+// status lines (progress bars redraw with carriage returns, so only the final state of each
+// line is kept), apply the error filter, then collapse repeated runs
+pub fn process_output(output: &str, options: &CaptureOptions, budget: &MemoryBudget) -> Result<FileContent> {
+    let noise = RegexSet::new(CARGO_NOISE_PATTERNS).expect("valid regex");
+    let keep = match options.filter {
+        Some(filter) => Some(error_line_set(filter, options.ecosystems, options.filter_patterns)?),
+        None => None,
+    };
+    let mut collapser = RepeatCollapser::new();
+    let mut recorded = SpillBuffer::new(budget);
+
+    for line in output.lines() {
+        let line = if options.strip_cargo_noise {
+            let line = line.rsplit('\r').find(|part| !part.is_empty()).unwrap_or("");
+            if noise.is_match(line) {
+                continue;
+            }
+            line
+        } else {
+            line
+        };
+        if let Some(keep) = &keep {
+            if !keep.is_match(line) {
+                continue;
+            }
+        }
+        if options.collapse_repeats {
+            collapser.push(line, &mut recorded)?;
+        } else {
+            recorded.push_str(line)?;
+            recorded.push_str("\n")?;
+        }
+    }
+    collapser.flush(&mut recorded)?;
+
+    Ok(recorded.into_text()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collapse(input: &str) -> String {
+        let options = CaptureOptions {
+            strip_cargo_noise: false,
+            filter: None,
+            ecosystems: &[],
+            filter_patterns: &[],
+            collapse_repeats: true,
+        };
+        process_output(input, &options, &MemoryBudget::new(usize::MAX)).unwrap().to_string()
+    }
+
+    #[test]
+    fn formats_durations_for_humans() {
+        assert_eq!(format_duration(Duration::from_millis(850)), "850ms");
+        assert_eq!(format_duration(Duration::from_millis(3240)), "3.2s");
+        assert_eq!(format_duration(Duration::from_secs(14 * 60 + 2)), "14m 2s");
+        assert_eq!(format_duration(Duration::from_secs(3600 + 3 * 60 + 20)), "1h 3m 20s");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_captures_both_streams_and_exit_status() {
+        let argv = split_command_line("sh -c 'echo out; echo err >&2; exit 101'").unwrap();
+        let (output, run) = run_command(&argv, &MemoryBudget::new(usize::MAX), true).unwrap();
+        assert!(output.contains("out\n") && output.contains("err\n"));
+        assert_eq!(run.exit_code, Some(101));
+        assert!(run.failed());
+        let summary = run.summary();
+        assert_eq!(summary[..2], ["Command: sh -c 'echo out; echo err >&2; exit 101'", "Exit status: 101"]);
+        assert!(summary[2].starts_with("Duration: "));
+    }
+
+    #[test]
+    fn piped_runs_only_report_duration() {
+        let run = CommandRun::piped(Duration::from_secs(61));
+        assert!(!run.failed());
+        assert_eq!(run.summary(), ["Duration: 1m 1s (until the input closed)"]);
+    }
+
+    #[test]
+    fn strips_only_cargo_shaped_status_lines() {
+        let options = CaptureOptions {
+            strip_cargo_noise: true,
+            filter: None,
+            ecosystems: &[],
+            filter_patterns: &[],
+            collapse_repeats: false,
+        };
+        let input = "   Compiling serde v1.0.219\n\
+                     \x20  Compiling app v0.1.0 (/src/app)\n\
+                     \x20 Downloaded ureq v2.12.1\n\
+                     \x20   Updating crates.io index\n\
+                     \x20   Finished `dev` profile [unoptimized + debuginfo] target(s) in 1.20s\n\
+                     \x20   Building [=====>   ] 12/40: syn\r    Building [=======> ] 30/40: serde\n\
+                     \x20 Adding user to group\n\
+                     Finished processing 3 records\n\
+                     error[E0308]: mismatched types\n";
+        let output = process_output(input, &options, &MemoryBudget::new(usize::MAX)).unwrap().to_string();
+        assert_eq!(output, "  Adding user to group\nFinished processing 3 records\nerror[E0308]: mismatched types\n");
+    }
+
+    #[test]
+    fn error_filter_uses_the_project_ecosystems() {
+        let filtered = |ecosystems: &[Ecosystem]| {
+            let options = CaptureOptions {
+                strip_cargo_noise: false,
+                filter: Some(CaptureFilter::Errors),
+                ecosystems,
+                filter_patterns: &[],
+                collapse_repeats: false,
+            };
+            let input = "Traceback (most recent call last)\n--- FAIL: TestX\nok\nbuild failed\n";
+            process_output(input, &options, &MemoryBudget::new(usize::MAX)).unwrap().to_string()
+        };
+        assert_eq!(filtered(&[Ecosystem::Go]), "--- FAIL: TestX\nbuild failed\n");
+        assert_eq!(filtered(&[]), "Traceback (most recent call last)\n--- FAIL: TestX\nbuild failed\n");
+    }
+
+    #[test]
+    fn identical_runs_become_a_repeat_marker() {
+        assert_eq!(collapse("a\nwait\nwait\nwait\nb\n"), "a\nwait\n<repeated 3 times>\nb\n");
+    }
+
+    #[test]
+    fn short_runs_are_kept() {
+        assert_eq!(collapse("wait\nwait\n"), "wait\nwait\n");
+        let failures = "test case_1 ... FAILED\ntest case_2 ... FAILED\ntest case_3 ... FAILED\n";
+        assert_eq!(collapse(failures), failures);
+    }
+
+    #[test]
+    fn similar_runs_keep_first_and_last_lines() {
+        let input = "step 1\nstep 2\nstep 3\nstep 4\nstep 5\ndone\n";
+        assert_eq!(collapse(input), "step 1\n<3 similar lines>\nstep 5\ndone\n");
+    }
+
+    #[test]
+    fn identical_prefix_of_similar_run_is_not_called_repeated() {
+        assert_eq!(collapse("n 1\nn 1\nn 1\nn 2\n"), "n 1\n<2 similar lines>\nn 2\n");
+    }
+}
@@ -0,0 +1,255 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Decides whether a dependency source file can be compiled with the crate's resolved
+// features. A file is gated off when its own `#![cfg(...)]` or the `#[cfg(...)]` on the
+// `mod` declaration of it (or of any ancestor module) is false for those features.
+// Predicates other than `feature = "..."` are unknown and never exclude a file.
+pub struct FeatureFilter<'a> {
+    crate_dir: &'a Path,
+    features: &'a [String],
+    known: HashMap<PathBuf, bool>,
+    inner_cfg: Regex,
+    mod_decl: Regex,
+}
+
+impl<'a> FeatureFilter<'a> {
+    pub fn new(crate_dir: &'a Path, features: &'a [String]) -> Self {
+        FeatureFilter {
+            crate_dir,
+            features,
+            known: HashMap::new(),
+            inner_cfg: Regex::new(r"#!\[\s*cfg\s*\(([^\]]*)\)\s*\]").unwrap(),
+            mod_decl: Regex::new(r"((?:#\[[^\]]*\]\s*)*)(?:pub(?:\([^)]*\))?\s+)?mod\s+(\w+)\s*;").unwrap(),
+        }
+    }
+
+    // `relative` is the file's path within the crate directory
+    pub fn is_enabled(&mut self, relative: &Path) -> bool {
+        if let Some(&enabled) = self.known.get(relative) {
+            return enabled;
+        }
+        let enabled = self.check(relative);
+        self.known.insert(relative.to_path_buf(), enabled);
+        enabled
+    }
+
+    fn check(&mut self, relative: &Path) -> bool {
+        let Ok(content) = fs::read_to_string(self.crate_dir.join(relative)) else {
+            return true;
+        };
+        // Inner attributes only count in the file header, before the first item
+        let header = content
+            .lines()
+            .map(str::trim)
+            .take_while(|line| line.is_empty() || line.starts_with("#!") || line.starts_with("//"));
+        for line in header {
+            if let Some(cap) = self.inner_cfg.captures(line) {
+                if evaluate(&cap[1], self.features) == Some(false) {
+                    return false;
+                }
+            }
+        }
+
+        let Some((name, parents)) = module_parents(relative) else {
+            return true; // Crate roots, build scripts, tests, and examples
+        };
+        for parent in parents {
+            let Ok(parent_content) = fs::read_to_string(self.crate_dir.join(&parent)) else {
+                continue;
+            };
+            let declaration = self
+                .mod_decl
+                .captures_iter(&parent_content)
+                .find(|cap| cap[2] == *name)
+                .map(|cap| cap[1].to_string());
+            if let Some(attributes) = declaration {
+                let gated_off = attribute_cfgs(&attributes)
+                    .iter()
+                    .any(|cfg| evaluate(cfg, self.features) == Some(false));
+                return !gated_off && self.is_enabled(&parent);
+            }
+        }
+        true
+    }
+}
+
+// The module name a file defines and the files that could declare it:
+// `src/a/b.rs` and `src/a/b/mod.rs` are declared as `mod b;` in `src/a.rs` or `src/a/mod.rs`
+fn module_parents(relative: &Path) -> Option<(String, Vec<PathBuf>)> {
+    let file_name = relative.file_name()?.to_str()?;
+    let dir = relative.parent()?;
+    let (name, module_dir) = if file_name == "mod.rs" {
+        (dir.file_name()?.to_str()?.to_string(), dir.parent()?)
+    } else {
+        (relative.file_stem()?.to_str()?.to_string(), dir)
+    };
+    if module_dir == Path::new("src") {
+        if dir == Path::new("src") && matches!(file_name, "lib.rs" | "main.rs") {
+            return None;
+        }
+        return Some((name, vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/main.rs")]));
+    }
+    if !module_dir.starts_with("src") {
+        return None;
+    }
+    Some((
+        name,
+        vec![module_dir.with_extension("rs"), module_dir.join("mod.rs")],
+    ))
+}
+
+// The predicates of every `#[cfg(...)]` in a run of outer attributes
+fn attribute_cfgs(attributes: &str) -> Vec<String> {
+    let re = Regex::new(r"#\[\s*cfg\s*\(([^\]]*)\)\s*\]").unwrap();
+    re.captures_iter(attributes).map(|cap| cap[1].to_string()).collect()
+}
+
+// Three-valued evaluation of a cfg predicate: Some(false) only when it is certainly false
+fn evaluate(predicate: &str, features: &[String]) -> Option<bool> {
+    let tokens = tokenize(predicate);
+    let mut position = 0;
+    let result = parse_predicate(&tokens, &mut position, features);
+    if position == tokens.len() {
+        result
+    } else {
+        None // Trailing input we don't understand
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Open,
+    Close,
+    Comma,
+    Equals,
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '=' => tokens.push(Token::Equals),
+            '"' => {
+                let mut value = String::new();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    value.push(c);
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+fn parse_predicate(tokens: &[Token], position: &mut usize, features: &[String]) -> Option<bool> {
+    let Some(Token::Ident(name)) = tokens.get(*position) else {
+        *position = tokens.len();
+        return None;
+    };
+    *position += 1;
+    match tokens.get(*position) {
+        Some(Token::Equals) => {
+            *position += 1;
+            let Some(Token::Str(value)) = tokens.get(*position) else {
+                return None;
+            };
+            *position += 1;
+            (name == "feature").then(|| features.iter().any(|f| f == value))
+        }
+        Some(Token::Open) => {
+            *position += 1;
+            let mut values = Vec::new();
+            while *position < tokens.len() && tokens[*position] != Token::Close {
+                values.push(parse_predicate(tokens, position, features));
+                if tokens.get(*position) == Some(&Token::Comma) {
+                    *position += 1;
+                }
+            }
+            *position += 1;
+            match name.as_str() {
+                "not" if values.len() == 1 => values[0].map(|v| !v),
+                "all" if values.contains(&Some(false)) => Some(false),
+                "all" if values.iter().all(|v| *v == Some(true)) => Some(true),
+                "any" if values.contains(&Some(true)) => Some(true),
+                "any" if values.iter().all(|v| *v == Some(false)) => Some(false),
+                _ => None,
+            }
+        }
+        _ => None, // Bare predicates such as `unix` or `test`
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(predicate: &str) -> Option<bool> {
+        evaluate(predicate, &["std".to_string(), "serde".to_string()])
+    }
+
+    #[test]
+    fn features() {
+        assert_eq!(eval(r#"feature = "std""#), Some(true));
+        assert_eq!(eval(r#"feature = "alloc""#), Some(false));
+    }
+
+    #[test]
+    fn combinators() {
+        assert_eq!(eval(r#"not(feature = "alloc")"#), Some(true));
+        assert_eq!(eval(r#"all(feature = "std", feature = "serde")"#), Some(true));
+        assert_eq!(eval(r#"all(feature = "std", feature = "alloc")"#), Some(false));
+        assert_eq!(eval(r#"any(feature = "alloc", feature = "serde")"#), Some(true));
+        assert_eq!(eval(r#"any(feature = "alloc", feature = "rayon")"#), Some(false));
+        // Like rustc: an empty any() is false and an empty all() is true
+        assert_eq!(eval("any()"), Some(false));
+        assert_eq!(eval("all()"), Some(true));
+    }
+
+    #[test]
+    fn unknown_predicates_are_never_false() {
+        assert_eq!(eval("unix"), None);
+        assert_eq!(eval(r#"target_os = "linux""#), None);
+        assert_eq!(eval(r#"not(unix)"#), None);
+        // An unknown operand only decides the result when the known ones don't
+        assert_eq!(eval(r#"all(unix, feature = "alloc")"#), Some(false));
+        assert_eq!(eval(r#"all(unix, feature = "std")"#), None);
+        assert_eq!(eval(r#"any(unix, feature = "std")"#), Some(true));
+        assert_eq!(eval(r#"any(unix, feature = "alloc")"#), None);
+        assert_eq!(eval(r#"feature = "std" extra"#), None);
+    }
+
+    #[test]
+    fn module_parents_of_nested_files() {
+        assert_eq!(module_parents(Path::new("src/lib.rs")), None);
+        assert_eq!(
+            module_parents(Path::new("src/net.rs")),
+            Some(("net".to_string(), vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/main.rs")]))
+        );
+        assert_eq!(
+            module_parents(Path::new("src/net/tcp/mod.rs")),
+            Some(("tcp".to_string(), vec![PathBuf::from("src/net.rs"), PathBuf::from("src/net/mod.rs")]))
+        );
+        assert_eq!(module_parents(Path::new("tests/it.rs")), None);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::vcs;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// How a file differs from the `--annotate-changes` base
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Modified,
+    Unchanged,
+}
+
+// A file's change, with its diffstat when `--diffstat` asked for one (and the file is text)
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileChange {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<u64>,
+}
+
+impl FileChange {
+    // "modified (+12 -3)", as shown in file headers
+    pub fn summary(&self) -> String {
+        let status = match self.status {
+            Status::Added => "added",
+            Status::Modified => "modified",
+            Status::Unchanged => "unchanged",
+        };
+        match (self.added, self.removed) {
+            (Some(added), Some(removed)) if self.status != Status::Unchanged => format!("{} (+{} -{})", status, added, removed),
+            _ => status.to_string(),
+        }
+    }
+}
+
+// The working copy's changes since a revision: committed and uncommitted edits, plus untracked
+// files (which count as added), from whichever of git, Jujutsu, or Mercurial manages the tree.
+// Renames show as the new path added.
+pub struct Changes {
+    base: String,
+    // Repository root, canonicalized like the looked-up paths
+    top: PathBuf,
+    files: HashMap<PathBuf, FileChange>,
+}
+
+impl Changes {
+    pub fn load(cwd: &Path, base: &str, diffstat: bool) -> Result<Changes> {
+        let vcs = vcs::detect(cwd)?;
+        let files = vcs.changes(base, diffstat)?;
+        Ok(Changes { base: base.to_string(), top: vcs.root().to_path_buf(), files })
+    }
+
+    // Changes since the repository's usual review base, as picked by `Vcs::default_base`
+    pub fn load_from_default_base(cwd: &Path, diffstat: bool) -> Result<Changes> {
+        let base = vcs::detect(cwd)?.default_base()?;
+        Changes::load(cwd, &base, diffstat)
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    // The change of a file; files outside the repository have none
+    pub fn get(&self, path: &Path) -> Option<FileChange> {
+        let path = fs::canonicalize(path).ok()?;
+        if !path.starts_with(&self.top) {
+            return None;
+        }
+        Some(self.files.get(&path).cloned().unwrap_or(FileChange { status: Status::Unchanged, added: None, removed: None }))
+    }
+
+    // Whether a file was added or modified
+    pub fn is_changed(&self, path: &Path) -> bool {
+        self.get(path).is_some_and(|change| change.status != Status::Unchanged)
+    }
+}
+
+// The kinds of uncommitted files `--staged`, `--unstaged`, and `--untracked` ask for
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StatusFilter {
+    pub staged: bool,
+    pub unstaged: bool,
+    pub untracked: bool,
+}
+
+impl StatusFilter {
+    pub fn is_set(&self) -> bool {
+        self.staged || self.unstaged || self.untracked
+    }
+
+    // "staged or untracked", for the note about what was included
+    pub fn describe(&self) -> String {
+        let kinds: Vec<&str> = [(self.staged, "staged"), (self.unstaged, "unstaged"), (self.untracked, "untracked")]
+            .into_iter()
+            .filter_map(|(set, kind)| set.then_some(kind))
+            .collect();
+        kinds.join(" or ")
+    }
+}
+
+// The files `git status` lists as one of the kinds a StatusFilter asks for
+pub struct StatusSelection {
+    // Repository root, canonicalized like the looked-up paths
+    top: PathBuf,
+    files: HashSet<PathBuf>,
+}
+
+impl StatusSelection {
+    pub fn load(cwd: &Path, filter: StatusFilter) -> Result<StatusSelection> {
+        let vcs = vcs::detect(cwd)?;
+        let files = vcs
+            .status()?
+            .into_iter()
+            .filter(|(_, status)| {
+                (filter.staged && status.staged) || (filter.unstaged && status.unstaged) || (filter.untracked && status.untracked)
+            })
+            .map(|(path, _)| path)
+            .collect();
+        Ok(StatusSelection { top: vcs.root().to_path_buf(), files })
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        fs::canonicalize(path).is_ok_and(|path| path.starts_with(&self.top) && self.files.contains(&path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn run(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@t", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    #[test]
+    fn classifies_files_against_a_ref() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        run(root, &["init", "-q"]);
+        fs::write(root.join("kept.rs"), "fn a() {}\n").unwrap();
+        fs::write(root.join("edited.rs"), "fn b() {}\nfn c() {}\n").unwrap();
+        run(root, &["add", "."]);
+        run(root, &["commit", "-qm", "base"]);
+        fs::write(root.join("edited.rs"), "fn b() {}\nfn d() {}\nfn e() {}\n").unwrap();
+        fs::write(root.join("new.rs"), "fn f() {}\n").unwrap();
+
+        let changes = Changes::load(root, "HEAD", true).unwrap();
+        let summary = |name: &str| changes.get(&root.join(name)).map(|change| change.summary());
+        assert_eq!(summary("kept.rs").as_deref(), Some("unchanged"));
+        assert_eq!(summary("edited.rs").as_deref(), Some("modified (+2 -1)"));
+        assert_eq!(summary("new.rs").as_deref(), Some("added (+1 -0)"));
+        assert_eq!(changes.get(Path::new("/")), None);
+
+        let without_stats = Changes::load(root, "HEAD", false).unwrap();
+        assert_eq!(without_stats.get(&root.join("edited.rs")).unwrap().summary(), "modified");
+        assert!(Changes::load(root, "no-such-ref", false).is_err());
+        assert!(changes.is_changed(&root.join("new.rs")) && !changes.is_changed(&root.join("kept.rs")));
+    }
+
+    #[test]
+    fn default_base_is_where_the_branch_forked() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        run(root, &["init", "-q", "-b", "main"]);
+        fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        run(root, &["add", "."]);
+        run(root, &["commit", "-qm", "base"]);
+        assert_eq!(Changes::load_from_default_base(root, false).unwrap().base(), "HEAD");
+
+        run(root, &["checkout", "-qb", "feature"]);
+        fs::write(root.join("b.rs"), "fn b() {}\n").unwrap();
+        run(root, &["add", "."]);
+        run(root, &["commit", "-qm", "feature"]);
+        let changes = Changes::load_from_default_base(root, false).unwrap();
+        assert_eq!(changes.base().len(), 40, "{}", changes.base());
+        assert!(changes.is_changed(&root.join("b.rs")) && !changes.is_changed(&root.join("a.rs")));
+    }
+
+    #[test]
+    fn selects_files_by_git_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        run(root, &["init", "-q"]);
+        for name in ["kept.rs", "staged.rs", "edited.rs"] {
+            fs::write(root.join(name), "fn a() {}\n").unwrap();
+        }
+        run(root, &["add", "."]);
+        run(root, &["commit", "-qm", "base"]);
+        fs::write(root.join("staged.rs"), "fn b() {}\n").unwrap();
+        run(root, &["add", "staged.rs"]);
+        fs::write(root.join("edited.rs"), "fn c() {}\n").unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/new.rs"), "fn d() {}\n").unwrap();
+
+        let selected = |filter: StatusFilter| {
+            let selection = StatusSelection::load(root, filter).unwrap();
+            let mut names: Vec<&str> =
+                ["kept.rs", "staged.rs", "edited.rs", "src/new.rs"].into_iter().filter(|name| selection.contains(&root.join(name))).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(selected(StatusFilter { staged: true, ..StatusFilter::default() }), ["staged.rs"]);
+        assert_eq!(selected(StatusFilter { unstaged: true, untracked: true, ..StatusFilter::default() }), ["edited.rs", "src/new.rs"]);
+        assert_eq!(StatusFilter { staged: true, untracked: true, ..StatusFilter::default() }.describe(), "staged or untracked");
+    }
+}
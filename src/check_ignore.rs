@@ -0,0 +1,318 @@
+use crate::attributes::Attributes;
+use crate::config::{self, Config};
+use crate::hgignore::HgIgnore;
+use crate::language::LanguageMap;
+use crate::pathspec::{self, Pathspec};
+use crate::project::Project;
+use crate::vcs;
+use crate::warnings;
+use anyhow::Result;
+use ignore::gitignore::{Gitignore, Glob};
+use ignore::Match;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Ignore files the walk reads in every directory, highest precedence first
+const IGNORE_FILES: &[&str] = &[".contreeignore", ".ignore", ".gitignore"];
+
+// Directories the walk always skips
+const ALWAYS_SKIPPED: &[&str] = &[".git", ".jj", ".hg", ".contree"];
+
+// The rule that decides whether a path is walked, as `git check-ignore -v` reports it.
+// `ignored` is false for a `!pattern` that re-includes the path.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub source: String,
+    pub line: Option<usize>,
+    pub pattern: String,
+    pub ignored: bool,
+}
+
+impl Rule {
+    fn from_glob(glob: &Glob, ignored: bool) -> Self {
+        let source = glob.from().map(|path| path.display().to_string()).unwrap_or_default();
+        Rule {
+            line: glob.from().and_then(|path| find_line(path, |line| line.trim_end() == glob.original())),
+            source,
+            pattern: glob.original().to_string(),
+            ignored,
+        }
+    }
+}
+
+// contree's layered walk rules for one scanned directory: the built-in skips, ignore files in
+// every directory from the path up to the filesystem root (.gitignore only within the git
+// repository, plus the global gitignore), a Mercurial repository's .hgignore, exclude pathspecs, the project's default excludes,
+// files .gitattributes marks generated or vendored, and `skip` entries in [languages]
+pub struct Rules {
+    root: PathBuf,
+    pathspecs: Vec<Pathspec>,
+    project: Project,
+    // Whether [project] replaces the ecosystems' default excludes
+    configured_excludes: bool,
+    languages: LanguageMap,
+    attributes: Attributes,
+    hgignore: Option<HgIgnore>,
+    // The .contree.toml holding [languages] and [project], for reporting its lines
+    config_path: PathBuf,
+    global: Gitignore,
+    // Directory -> its ignore files, in IGNORE_FILES order
+    matchers: HashMap<PathBuf, Vec<Gitignore>>,
+}
+
+impl Rules {
+    pub fn new(root: &Path, pathspecs: &[Pathspec]) -> Result<Self> {
+        let config = Config::load(root)?;
+        Ok(Rules {
+            root: absolute(root)?,
+            pathspecs: pathspecs.to_vec(),
+            project: Project::detect(root, &config.project)?,
+            configured_excludes: config.project.exclude.is_some(),
+            languages: LanguageMap::new(&config.languages)?,
+            attributes: Attributes::load(root),
+            hgignore: HgIgnore::load(root),
+            config_path: config.path.clone().unwrap_or_else(|| root.join(config::CONFIG_FILE)),
+            global: Gitignore::global().0,
+            matchers: HashMap::new(),
+        })
+    }
+
+    // The rule that keeps `path` out of the walk, or the `!pattern` that lets it in. A path is
+    // skipped if any directory above it is, so directories are checked from the top down,
+    // just as the walk reaches them.
+    pub fn check(&mut self, path: &Path) -> Result<Option<Rule>> {
+        let path = absolute(path)?;
+        let Ok(relative) = path.strip_prefix(&self.root).map(Path::to_path_buf) else {
+            anyhow::bail!("{} is outside {}", path.display(), self.root.display());
+        };
+        let mut candidate = self.root.clone();
+        let mut whitelisted = None;
+        let components: Vec<_> = relative.components().collect();
+        for (i, component) in components.iter().enumerate() {
+            candidate.push(component);
+            let is_dir = i + 1 < components.len() || candidate.is_dir();
+            match self.check_entry(&candidate, is_dir) {
+                Some(rule) if rule.ignored => return Ok(Some(rule)),
+                Some(rule) => whitelisted = Some(rule),
+                None => {}
+            }
+        }
+        if let Some(marking) = self.attributes.linguist(&path).filter(|_| !path.is_dir()) {
+            return Ok(Some(Rule {
+                source: marking.source.display().to_string(),
+                line: Some(marking.line),
+                pattern: format!("{} {}", marking.pattern, marking.attribute),
+                ignored: true,
+            }));
+        }
+        if let Some(pattern) = self.languages.skip_pattern(&path, &self.root) {
+            return Ok(Some(Rule {
+                source: self.config_path.display().to_string(),
+                line: find_line(&self.config_path, |line| {
+                    line.split('=').next().unwrap_or_default().trim().trim_matches(['"', '\'']) == pattern
+                }),
+                pattern: pattern.to_string(),
+                ignored: true,
+            }));
+        }
+        Ok(whitelisted)
+    }
+
+    // The rule matching one entry of the walk, in the walk's order of precedence
+    fn check_entry(&mut self, path: &Path, is_dir: bool) -> Option<Rule> {
+        let name = path.file_name().unwrap_or_default();
+        if let Some(skipped) = ALWAYS_SKIPPED.iter().find(|skipped| name == **skipped) {
+            return Some(Rule {
+                source: "<built-in>".to_string(),
+                line: None,
+                pattern: skipped.to_string(),
+                ignored: true,
+            });
+        }
+        if let Some(rule) = self.ignore_file_rule(path, is_dir) {
+            return Some(rule);
+        }
+        if let Some(marking) = self.hgignore.as_ref().and_then(|hgignore| hgignore.matched(path, is_dir)) {
+            return Some(Rule {
+                source: marking.source.display().to_string(),
+                line: Some(marking.line),
+                pattern: marking.pattern,
+                ignored: true,
+            });
+        }
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if let Some(pathspec) = pathspec::excluded_by(&self.pathspecs, relative) {
+            return Some(Rule {
+                source: "pathspec".to_string(),
+                line: None,
+                pattern: pathspec.spec().to_string(),
+                ignored: true,
+            });
+        }
+        let pattern = self.project.excluded_by(relative, is_dir)?;
+        let (source, line) = if self.configured_excludes {
+            let line = find_line(&self.config_path, |line| line.contains(&format!("\"{}\"", pattern)));
+            (self.config_path.display().to_string(), line)
+        } else {
+            ("<project>".to_string(), None)
+        };
+        Some(Rule {
+            source,
+            line,
+            pattern: pattern.to_string(),
+            ignored: true,
+        })
+    }
+
+    // Like the `ignore` crate: for each kind of ignore file the closest one with a matching
+    // pattern decides, and the kinds are consulted in IGNORE_FILES order, then the global
+    // gitignore. .gitignore files only count inside a git repository or Jujutsu workspace, up to its root.
+    fn ignore_file_rule(&mut self, path: &Path, is_dir: bool) -> Option<Rule> {
+        let dirs: Vec<PathBuf> = path.ancestors().skip(1).map(Path::to_path_buf).collect();
+        let in_git = dirs.iter().any(|dir| vcs::is_gitignore_root(dir));
+        let mut found: Vec<Option<Rule>> = vec![None, None, None];
+        let mut saw_git = false;
+        for dir in &dirs {
+            let matchers = self.matchers.entry(dir.clone()).or_insert_with(|| load_ignore_files(dir));
+            for (kind, matcher) in matchers.iter().enumerate() {
+                let is_gitignore = IGNORE_FILES[kind] == ".gitignore";
+                if found[kind].is_some() || (is_gitignore && (!in_git || saw_git)) {
+                    continue;
+                }
+                found[kind] = rule_for(matcher.matched(path, is_dir));
+            }
+            saw_git = saw_git || vcs::is_gitignore_root(dir);
+        }
+        found
+            .into_iter()
+            .flatten()
+            .next()
+            .or_else(|| in_git.then(|| rule_for(self.global.matched(path, is_dir))).flatten())
+    }
+}
+
+fn load_ignore_files(dir: &Path) -> Vec<Gitignore> {
+    IGNORE_FILES
+        .iter()
+        .map(|name| {
+            let path = dir.join(name);
+            if path.is_file() {
+                Gitignore::new(&path).0
+            } else {
+                Gitignore::empty()
+            }
+        })
+        .collect()
+}
+
+fn rule_for(matched: Match<&Glob>) -> Option<Rule> {
+    match matched {
+        Match::None => None,
+        Match::Ignore(glob) => Some(Rule::from_glob(glob, true)),
+        Match::Whitelist(glob) => Some(Rule::from_glob(glob, false)),
+    }
+}
+
+// 1-based number of the last line of `path` accepted by `is_match`; later patterns override earlier ones
+fn find_line(path: &Path, is_match: impl Fn(&str) -> bool) -> Option<usize> {
+    let text = fs::read_to_string(path).ok()?;
+    text.lines().enumerate().filter(|(_, line)| is_match(line)).map(|(i, _)| i + 1).last()
+}
+
+// Absolute path with `.` components removed, without requiring the path to exist
+fn absolute(path: &Path) -> Result<PathBuf> {
+    Ok(env::current_dir()?.join(path).components().collect())
+}
+
+// `contree check-ignore`: print each ignored path (with the deciding `source:line:pattern` when
+// verbose, and unmatched paths too with `non_matching`). Returns whether any path was ignored.
+pub fn check_ignore(
+    root: &Path,
+    paths: &[PathBuf],
+    pathspecs: &[Pathspec],
+    verbose: bool,
+    non_matching: bool,
+    writer: &mut dyn Write,
+) -> Result<bool> {
+    let mut rules = Rules::new(root, pathspecs)?;
+    let current_dir = env::current_dir()?;
+    let mut any_ignored = false;
+    for path in paths {
+        let rule = match rules.check(path) {
+            Ok(rule) => rule,
+            Err(e) => {
+                warnings::warn(warnings::Kind::Skipped, format!("Not checking {}: {}", path.display(), e));
+                None
+            }
+        };
+        any_ignored |= rule.as_ref().is_some_and(|rule| rule.ignored);
+        match rule {
+            Some(rule) if verbose => {
+                // Sources below the current directory are shown relative to it, as git does
+                let source = Path::new(&rule.source).strip_prefix(&current_dir).map(|source| source.display().to_string());
+                let line = rule.line.map(|line| line.to_string()).unwrap_or_default();
+                writeln!(writer, "{}:{}:{}\t{}", source.as_deref().unwrap_or(&rule.source), line, rule.pattern, path.display())?;
+            }
+            Some(rule) if rule.ignored => writeln!(writer, "{}", path.display())?,
+            None if non_matching => writeln!(writer, "::\t{}", path.display())?,
+            _ => {}
+        }
+    }
+    Ok(any_ignored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(root: &Path, path: &str, pathspecs: &[&str]) -> Option<(String, Option<usize>, String, bool)> {
+        let pathspecs: Vec<_> = pathspecs.iter().map(|spec| Pathspec::parse(spec).unwrap()).collect();
+        let rule = Rules::new(root, &pathspecs).unwrap().check(&root.join(path)).unwrap()?;
+        let source = Path::new(&rule.source).strip_prefix(root).map(|p| p.display().to_string()).unwrap_or(rule.source);
+        Some((source, rule.line, rule.pattern, rule.ignored))
+    }
+
+    #[test]
+    fn reports_the_deciding_file_and_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::write(root.join(".gitignore"), "# build output\ntarget/\n*.log\n!keep.log\n").unwrap();
+        fs::write(root.join("src/.contreeignore"), "gen\n").unwrap();
+        fs::write(root.join(config::CONFIG_FILE), "[languages]\n\"*.snap\" = \"skip\"\n").unwrap();
+        fs::write(root.join(".gitattributes"), "*.lock -diff\n*.pb.go linguist-generated\n").unwrap();
+
+        let some = |source: &str, line, pattern: &str, ignored| Some((source.to_string(), line, pattern.to_string(), ignored));
+        assert_eq!(check(root, "target/debug/app", &[]), some(".gitignore", Some(2), "target/", true));
+        assert_eq!(check(root, "run.log", &[]), some(".gitignore", Some(3), "*.log", true));
+        assert_eq!(check(root, "keep.log", &[]), some(".gitignore", Some(4), "!keep.log", false));
+        assert_eq!(check(root, "src/gen/out.rs", &[]), some("src/.contreeignore", Some(1), "gen", true));
+        assert_eq!(check(root, "tests/a.snap", &[]), some(".contree.toml", Some(2), "*.snap", true));
+        assert_eq!(check(root, ".git/config", &[]), some("<built-in>", None, ".git", true));
+        assert_eq!(check(root, "api/user.pb.go", &[]), some(".gitattributes", Some(2), "*.pb.go linguist-generated", true));
+        assert_eq!(check(root, "vendor/x.rs", &[":!vendor"]), some("pathspec", None, ":!vendor", true));
+        assert_eq!(check(root, "src/main.rs", &[]), None);
+    }
+
+    #[test]
+    fn reports_project_default_excludes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("package.json"), "{}").unwrap();
+        fs::create_dir_all(dir.path().join("node_modules/left-pad")).unwrap();
+        let rule = check(dir.path(), "node_modules/left-pad/index.js", &[]);
+        assert_eq!(rule, Some(("<project>".to_string(), None, "node_modules/".to_string(), true)));
+    }
+
+    #[test]
+    fn gitignore_needs_a_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+        fs::write(dir.path().join(".ignore"), "*.tmp\n").unwrap();
+        assert_eq!(check(dir.path(), "run.log", &[]), None);
+        assert!(check(dir.path(), "a.tmp", &[]).is_some());
+    }
+}
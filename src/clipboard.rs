@@ -0,0 +1,117 @@
+use crate::binary::format_size;
+use crate::truncate;
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Larger contexts are copied in parts of this size, since chat boxes and some clipboard
+// managers give up on pastes much bigger than this
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+// A command that copies its stdin to the clipboard
+struct Tool {
+    program: &'static str,
+    args: &'static [&'static str],
+}
+
+impl Tool {
+    // clip.exe reads the console code page unless the text is UTF-16 with a byte order mark
+    fn wants_utf16(&self) -> bool {
+        self.program.starts_with("clip")
+    }
+}
+
+// The commands `tool` chooses from, as listed by --capabilities
+pub const TOOLS: &[&str] = &["pbcopy", "clip", "wl-copy", "xclip", "xsel", "clip.exe"];
+
+// pbcopy on macOS, clip on Windows, wl-copy on Wayland, xclip or xsel on X11, and clip.exe from
+// WSL, where the Windows clipboard is the one that gets pasted into a browser
+fn tool() -> Result<Tool> {
+    if cfg!(target_os = "macos") {
+        return Ok(Tool { program: "pbcopy", args: &[] });
+    }
+    if cfg!(windows) {
+        return Ok(Tool { program: "clip", args: &[] });
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() && on_path("wl-copy") {
+        return Ok(Tool { program: "wl-copy", args: &[] });
+    }
+    if env::var_os("DISPLAY").is_some() {
+        if on_path("xclip") {
+            return Ok(Tool { program: "xclip", args: &["-selection", "clipboard"] });
+        }
+        if on_path("xsel") {
+            return Ok(Tool { program: "xsel", args: &["--clipboard", "--input"] });
+        }
+    }
+    if on_path("clip.exe") {
+        return Ok(Tool { program: "clip.exe", args: &[] });
+    }
+    bail!("--clipboard found no clipboard command; install wl-clipboard (Wayland), xclip, or xsel (X11)")
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+// Copy the context to the clipboard. One bigger than `chunk_size` is copied in parts, cut on
+// line boundaries; after each part but the last, Enter on the terminal copies the next, so
+// there is time to paste it first.
+pub fn copy(content: &[u8], chunk_size: usize) -> Result<()> {
+    let tool = tool()?;
+    let parts = truncate::chunk_ranges(content, chunk_size);
+    if parts.len() <= 1 {
+        copy_with(&tool, content)?;
+        eprintln!("Copied the context ({}) to the clipboard", format_size(content.len() as u64));
+        return Ok(());
+    }
+    let mut terminal = terminal().with_context(|| {
+        format!("The context is {} parts, and copying them one at a time needs a terminal to wait on", parts.len())
+    })?;
+    for (i, range) in parts.iter().enumerate() {
+        copy_with(&tool, &content[range.clone()])?;
+        let copied = format!("Copied part {}/{} ({}) to the clipboard", i + 1, parts.len(), format_size(range.len() as u64));
+        if i + 1 == parts.len() {
+            eprintln!("{}", copied);
+        } else {
+            eprint!("{}; paste it, then press Enter for the next part ", copied);
+            terminal.read_line(&mut String::new()).context("Failed to read from the terminal")?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_with(tool: &Tool, text: &[u8]) -> Result<()> {
+    let input = if tool.wants_utf16() {
+        let text = String::from_utf8_lossy(text);
+        [0xFF, 0xFE].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()
+    } else {
+        text.to_vec()
+    };
+    let mut child = Command::new(tool.program)
+        .args(tool.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {} to copy to the clipboard", tool.program))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(&input)
+        .with_context(|| format!("Failed to write to {}", tool.program))?;
+    if !child.wait()?.success() {
+        bail!("{} failed to copy to the clipboard", tool.program);
+    }
+    Ok(())
+}
+
+// The terminal, which stdin may not be while a command's output is piped in
+fn terminal() -> Result<BufReader<File>> {
+    let path = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    let file = File::open(Path::new(path)).with_context(|| format!("Failed to open {}", path))?;
+    Ok(BufReader::new(file))
+}
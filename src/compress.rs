@@ -0,0 +1,111 @@
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+// How the context is compressed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, built in
+    Gzip,
+    /// Zstandard, through the zstd command
+    Zstd,
+}
+
+impl Compression {
+    // The compression an output name asks for with its extension, `.gz` or `.zst`
+    pub fn from_extension(path: &Path) -> Option<Compression> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+// A stream compressing into `W`; `finish` ends it and returns `W`
+pub enum CompressingWriter<W: Write> {
+    Gzip(GzEncoder<W>),
+    // zstd compresses into a temporary file, copied into `W` once it exits
+    Zstd {
+        child: Child,
+        stdin: ChildStdin,
+        compressed: File,
+        output: W,
+    },
+}
+
+pub fn wrap<W: Write>(compression: Compression, output: W) -> Result<CompressingWriter<W>> {
+    match compression {
+        Compression::Gzip => Ok(CompressingWriter::Gzip(GzEncoder::new(output, flate2::Compression::default()))),
+        Compression::Zstd => {
+            let compressed = tempfile::tempfile().context("Failed to create a temporary file for zstd")?;
+            let mut child = Command::new("zstd")
+                .args(["-q", "-c"])
+                .stdin(Stdio::piped())
+                .stdout(compressed.try_clone()?)
+                .spawn()
+                .context("Failed to run zstd (is it installed?)")?;
+            let stdin = child.stdin.take().expect("stdin is piped");
+            Ok(CompressingWriter::Zstd { child, stdin, compressed, output })
+        }
+    }
+}
+
+impl<W: Write> CompressingWriter<W> {
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CompressingWriter::Gzip(encoder) => encoder.finish(),
+            CompressingWriter::Zstd { mut child, stdin, mut compressed, mut output } => {
+                // Closing its input lets zstd finish the frame and exit
+                drop(stdin);
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!("zstd failed ({})", status)));
+                }
+                compressed.seek(SeekFrom::Start(0))?;
+                io::copy(&mut compressed, &mut output)?;
+                Ok(output)
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for CompressingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressingWriter::Gzip(encoder) => encoder.write(buf),
+            CompressingWriter::Zstd { stdin, .. } => stdin.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressingWriter::Gzip(encoder) => encoder.flush(),
+            CompressingWriter::Zstd { stdin, .. } => stdin.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn gzips_the_stream() {
+        assert_eq!(Compression::from_extension(Path::new("ctx.md.gz")), Some(Compression::Gzip));
+        assert_eq!(Compression::from_extension(Path::new("ctx.zst")), Some(Compression::Zstd));
+        assert_eq!(Compression::from_extension(Path::new("ctx.md")), None);
+
+        let mut writer = wrap(Compression::Gzip, Vec::new()).unwrap();
+        writer.write_all(b"File: src/main.rs\n").unwrap();
+        let compressed = writer.finish().unwrap();
+        let mut text = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut text).unwrap();
+        assert_eq!(text, "File: src/main.rs\n");
+    }
+}
@@ -0,0 +1,91 @@
+use crate::alias::Alias;
+use crate::project::ProjectConfig;
+use crate::sets::SetConfig;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = ".contree.toml";
+
+// Options as in `[defaults]`: long option name -> value
+pub type Options = BTreeMap<String, toml::Value>;
+
+// Settings shared by every project, from $XDG_CONFIG_HOME/contree/config.toml (falling back to
+// ~/.config/contree/config.toml)
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalConfig {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Options>,
+}
+
+impl GlobalConfig {
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("contree").join("config.toml"))
+    }
+
+    pub fn load() -> Result<GlobalConfig> {
+        let Some(path) = GlobalConfig::path().filter(|path| path.is_file()) else {
+            return Ok(GlobalConfig::default());
+        };
+        let text = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid {}", path.display()))
+    }
+}
+
+// Settings read from the nearest `.contree.toml`, in the scanned directory or a parent
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // File name or glob -> language name, or `skip` to exclude matching files
+    #[serde(default)]
+    pub languages: BTreeMap<String, String>,
+    // Name -> arguments substituted for `contree <name>`
+    #[serde(default)]
+    pub alias: BTreeMap<String, Alias>,
+    // Virtual directory name -> real directory it shows
+    #[serde(default)]
+    pub layout: BTreeMap<String, String>,
+    // Overrides for the detected ecosystems and their default excludes
+    #[serde(default)]
+    pub project: ProjectConfig,
+    // Long option name -> value used when the command line doesn't give one
+    #[serde(default)]
+    pub defaults: Options,
+    // Name -> saved selection picked with `--set <name>`
+    #[serde(default)]
+    pub sets: BTreeMap<String, SetConfig>,
+    // Name -> options applied with `--profile <name>`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Options>,
+    // The file the settings came from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+impl Config {
+    // The closest file wins, so a project's settings apply in its subdirectories. No file is an
+    // empty config; a malformed one is an error rather than silently ignored.
+    pub fn load(root: &Path) -> Result<Config> {
+        let root = std::path::absolute(root)?;
+        let Some(path) = root.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file()) else {
+            return Ok(Config::default());
+        };
+        let text = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut config: Config = toml::from_str(&text).with_context(|| format!("Invalid {}", path.display()))?;
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    // The directory holding the file, which relative `[layout]` directories start from
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.as_deref().and_then(Path::parent)
+    }
+}
@@ -0,0 +1,281 @@
+use crate::truncate;
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use regex::Regex;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::Path;
+
+// Files at least this large are memory-mapped instead of copied onto the heap
+const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+// A file's text, either read into memory or mapped directly from disk
+pub enum FileContent {
+    Heap(String),
+    Mapped(Mmap),
+}
+
+impl Deref for FileContent {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            FileContent::Heap(contents) => contents,
+            // SAFETY: the mapping was validated as UTF-8 in `read_text`
+            FileContent::Mapped(map) => unsafe { std::str::from_utf8_unchecked(map) },
+        }
+    }
+}
+
+// Name of a special file type that must never be opened for reading: a named pipe blocks until
+// a writer appears and a device can produce endless data
+pub fn special_kind(file_type: fs::FileType) -> Option<&'static str> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Some("named pipe");
+        }
+        if file_type.is_socket() {
+            return Some("socket");
+        }
+        if file_type.is_block_device() || file_type.is_char_device() {
+            return Some("device file");
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = file_type;
+    None
+}
+
+// Read a file as UTF-8 text, memory-mapping it when it is large.
+// Fails with `InvalidData` (like `fs::read_to_string`) when the file is not valid UTF-8.
+pub fn read_text(path: &Path) -> io::Result<FileContent> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len < MMAP_THRESHOLD {
+        return fs::read_to_string(path).map(FileContent::Heap);
+    }
+
+    // SAFETY: contree only reads the mapping; a file truncated by another process while
+    // mapped is the same hazard every mmap-based tool (ripgrep included) accepts
+    let map = unsafe { Mmap::map(&file)? };
+    if std::str::from_utf8(&map).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        ));
+    }
+    Ok(FileContent::Mapped(map))
+}
+
+// What happens to files over `--max-file-size`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Oversized {
+    /// Leave the file out, with a note where it would have been
+    Skip,
+    /// Keep the file's first SIZE bytes, cut at a line boundary
+    Truncate,
+}
+
+// Read at most `limit` bytes of a file as UTF-8 text, cut as `truncate::cut_point` does: back to
+// the last full line, or full character when the first line is longer. Fails with
+// `InvalidData` if the prefix isn't text.
+pub fn read_text_prefix(path: &Path, limit: usize) -> io::Result<String> {
+    // One byte more tells whether the limit cut anything
+    let mut bytes = Vec::with_capacity(limit + 1);
+    File::open(path)?.take(limit as u64 + 1).read_to_end(&mut bytes)?;
+    bytes.truncate(truncate::cut_point(&bytes, limit));
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))
+}
+
+// What `--grep` is matched against
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrepTarget {
+    /// File contents
+    Content,
+    /// Paths relative to the scanned directory
+    Name,
+    /// Either the path or the contents
+    Both,
+}
+
+// How several `--grep` patterns combine
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrepMode {
+    /// Keep files matching any of the patterns
+    Any,
+    /// Keep files matching every pattern
+    All,
+}
+
+// One regex matching wherever any of the patterns does. Each keeps its own flags, since inline
+// flags only reach the end of the group they're in.
+pub fn union_regex(patterns: &[Regex]) -> Result<Regex> {
+    if let [only] = patterns {
+        return Ok(only.clone());
+    }
+    let alternatives: Vec<String> = patterns.iter().map(|regex| format!("(?:{})", regex.as_str())).collect();
+    Regex::new(&alternatives.join("|")).context("Invalid grep pattern")
+}
+
+// Whether grep patterns ignore case
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrepCase {
+    // Literal patterns ignore case, `/regex/` patterns don't
+    Default,
+    // `--case-sensitive`: neither form ignores case
+    Sensitive,
+    // `--smart-case`: either form ignores case unless it has an uppercase letter
+    Smart,
+}
+
+// Whether a pattern has an uppercase letter of its own; in a regex, the letter after a
+// backslash is part of an escape like `\W` or `\S` and doesn't count
+fn has_uppercase(pattern: &str, regex: bool) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if regex && c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+// `/regex/` is a regular expression; anything else matches literally. Ignoring case is spelled
+// as an inline `(?i)` rather than a builder option, so it survives in `Regex::as_str` and
+// `union_regex` keeps it.
+pub fn grep_regex(pattern: &str, case: GrepCase) -> Result<Regex> {
+    let trimmed = pattern.trim();
+    let regex = trimmed.starts_with('/') && trimmed.ends_with('/') && trimmed.len() > 1;
+    let body = if regex { &trimmed[1..trimmed.len() - 1] } else { trimmed };
+    let ignore_case = match case {
+        GrepCase::Default => !regex,
+        GrepCase::Sensitive => false,
+        GrepCase::Smart => !has_uppercase(body, regex),
+    };
+    let flags = if ignore_case { "(?i)" } else { "" };
+    if regex {
+        Regex::new(&format!("{}{}", flags, body)).context("Invalid regex pattern")
+    } else {
+        Regex::new(&format!("{}{}", flags, regex::escape(body))).context("Invalid grep pattern")
+    }
+}
+
+// Size of the read buffer used when streaming a file through a grep pattern
+const GREP_CHUNK_SIZE: usize = 64 * 1024;
+
+// Bytes of the previous chunk kept in front of the next one, so matches up to this long that
+// straddle a chunk boundary are still found
+const GREP_OVERLAP: usize = 4 * 1024;
+
+// Test whether a file matches `regex`, stopping at the first match. Patterns that only match
+// within a line are run over fixed-size chunks (with an overlap window), so huge and single-line
+// files are never loaded whole; patterns that can span lines or anchor on the start and end of
+// the file are matched against the whole file. Fails with `InvalidData` on non-UTF-8 content,
+// so binary files are skipped as before.
+pub fn grep_file(path: &Path, regex: &Regex) -> io::Result<bool> {
+    if spans_lines(regex.as_str()) {
+        return read_text(path).map(|contents| regex.is_match(&contents));
+    }
+
+    let mut file = File::open(path)?;
+    let mut buffer: Vec<u8> = Vec::with_capacity(GREP_OVERLAP + GREP_CHUNK_SIZE);
+    let mut chunk = vec![0; GREP_CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut chunk)?;
+        buffer.extend_from_slice(&chunk[..read]);
+        // A chunk can end mid-character; the partial character waits for the next read
+        let valid = match std::str::from_utf8(&buffer) {
+            Ok(text) => text,
+            Err(e) if e.error_len().is_none() && read > 0 => {
+                // SAFETY: `valid_up_to` is the length of the longest valid UTF-8 prefix
+                unsafe { std::str::from_utf8_unchecked(&buffer[..e.valid_up_to()]) }
+            }
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")),
+        };
+        if regex.is_match(valid) {
+            return Ok(true);
+        }
+        if read == 0 {
+            return Ok(false);
+        }
+
+        // Keep the tail (from a character boundary) as the overlap for the next chunk
+        let mut keep_from = valid.len().saturating_sub(GREP_OVERLAP);
+        while !valid.is_char_boundary(keep_from) {
+            keep_from += 1;
+        }
+        buffer.drain(..keep_from);
+    }
+}
+
+// Whether a pattern could match a newline or depends on file-level anchors, judged from its
+// source: unescaped `^`/`$`, `\n`, whitespace and negated classes, and the `s`/`m`/`x` flags
+fn spans_lines(pattern: &str) -> bool {
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next().is_some_and(|escaped| "nsWDApPxz".contains(escaped)) => return true,
+            '^' | '$' | '\n' => return true,
+            '[' if chars.peek() == Some(&':') => return true,
+            '(' if chars.peek() == Some(&'?') => {
+                chars.next();
+                let flags: String = chars.clone().take_while(|c| c.is_ascii_alphabetic() || *c == '-').collect();
+                if flags.contains(['s', 'm', 'x']) {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_end_at_a_line_or_character() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        assert_eq!(read_text_prefix(&path, 10).unwrap(), "one\ntwo\n");
+        fs::write(&path, "añb").unwrap();
+        assert_eq!(read_text_prefix(&path, 2).unwrap(), "a");
+        fs::write(&path, [b'a', 0xff, b'b']).unwrap();
+        assert!(crate::fileio::is_not_text(&read_text_prefix(&path, 3).unwrap_err()));
+    }
+
+    #[test]
+    fn union_keeps_each_patterns_flags() {
+        let patterns = [grep_regex("Retry", GrepCase::Default).unwrap(), grep_regex("/^Fn/", GrepCase::Default).unwrap()];
+        let union = union_regex(&patterns).unwrap();
+        assert!(union.is_match("retry later"));
+        assert!(union.is_match("Fn once"));
+        assert!(!union.is_match("fn once"));
+    }
+
+    #[test]
+    fn case_flags_apply_to_literals_and_regexes() {
+        let matches = |pattern: &str, case: GrepCase, text: &str| grep_regex(pattern, case).unwrap().is_match(text);
+        assert!(matches("box", GrepCase::Default, "Box::new"));
+        assert!(!matches("/box/", GrepCase::Default, "Box::new"));
+        assert!(!matches("box", GrepCase::Sensitive, "Box::new"));
+        assert!(matches("box", GrepCase::Smart, "Box::new"));
+        assert!(matches("/box/", GrepCase::Smart, "Box::new"));
+        assert!(!matches("Box", GrepCase::Smart, "boxed"));
+        // Escapes like `\W` aren't uppercase letters of the pattern
+        assert!(matches("/box\\W/", GrepCase::Smart, "Box::new"));
+    }
+
+    #[test]
+    fn special_files_are_named() {
+        assert_eq!(special_kind(fs::metadata("/dev/null").unwrap().file_type()), Some("device file"));
+        assert_eq!(special_kind(fs::metadata("/").unwrap().file_type()), None);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::{Config, GlobalConfig, Options};
+use anyhow::{bail, Result};
+use clap::Command;
+use std::ffi::OsString;
+
+// Options a `[defaults]` or profile entry can't set: --dir decides which .contree.toml is read,
+// and profiles don't nest
+const UNSUPPORTED: &[&str] = &["dir", "profile", "help", "version"];
+
+// Repeatable options that the command line replaces rather than adds to: more patterns only
+// widen `--grep-mode any`, so a configured pattern could otherwise never be narrowed or dropped
+const REPLACED: &[&str] = &["grep"];
+
+// `[defaults]` or a profile (named `table` in errors) as arguments: `grep = "retry"` is
+// `--grep retry`, `true` is a bare flag (and `false` leaves it out), a list repeats the option,
+// and `paths` adds pathspecs. Keys are long option names, with `_` or `-`.
+fn option_args(options: &Options, table: &str, command: &Command) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        if key == "paths" {
+            for value in values {
+                let toml::Value::String(spec) = value else {
+                    bail!("{} {} takes strings, not {}", table, key, value);
+                };
+                args.push(spec.clone());
+            }
+            continue;
+        }
+        let long = key.replace('_', "-");
+        let Some(arg) = command.get_arguments().find(|arg| arg.get_long() == Some(long.as_str())) else {
+            bail!("{} has '{}', which is not a contree option", table, key);
+        };
+        if UNSUPPORTED.contains(&long.as_str()) {
+            bail!("{} can't set --{}", table, long);
+        }
+        let takes_values = arg.get_action().takes_values();
+        for value in values {
+            match value {
+                toml::Value::Boolean(_) if takes_values => bail!("{} {} needs a value, not {}", table, key, value),
+                toml::Value::Boolean(true) => args.push(format!("--{}", long)),
+                toml::Value::Boolean(false) => {}
+                _ if !takes_values => bail!("{} {} is a flag; use `{} = true`", table, key, key),
+                toml::Value::String(text) => args.extend([format!("--{}", long), text.clone()]),
+                toml::Value::Array(_) | toml::Value::Table(_) => bail!("{} {} can't be nested", table, key),
+                other => args.extend([format!("--{}", long), other.to_string()]),
+            }
+        }
+    }
+    Ok(args)
+}
+
+// Look up `--profile <name>`: the project's `.contree.toml` first, then the global config
+fn profile_args(name: &str, config: &Config, command: &Command) -> Result<Vec<String>> {
+    let table = format!("[profiles.{}]", name);
+    if let Some(options) = config.profiles.get(name) {
+        return option_args(options, &table, command);
+    }
+    let global = GlobalConfig::load()?;
+    if let Some(options) = global.profiles.get(name) {
+        return option_args(options, &table, command);
+    }
+    let mut known: Vec<&str> = config.profiles.keys().chain(global.profiles.keys()).map(String::as_str).collect();
+    known.sort_unstable();
+    known.dedup();
+    if known.is_empty() {
+        bail!("No profile '{}': define {} in .contree.toml or {}", name, table, global_config_name());
+    }
+    bail!("No profile '{}'; available: {}", name, known.join(", "))
+}
+
+fn global_config_name() -> String {
+    GlobalConfig::path().map_or_else(|| "the global config".to_string(), |path| path.display().to_string())
+}
+
+// Put the defaults, then the profile, ahead of the given arguments so the command line
+// overrides both: a repeated option replaces the earlier value, list options (`--include`,
+// pathspecs) add to them, and a `--grep` on the command line drops the configured ones
+pub fn apply(argv: Vec<OsString>, config: &Config, profile: Option<&str>, command: &Command) -> Result<Vec<OsString>> {
+    let mut inserted = option_args(&config.defaults, "[defaults]", command)?;
+    if let Some(name) = profile {
+        inserted.extend(profile_args(name, config, command)?);
+    }
+    for long in REPLACED.iter().filter(|long| given(&argv, long, command)) {
+        inserted = drop_option(inserted, long);
+    }
+    if inserted.is_empty() {
+        return Ok(argv);
+    }
+    let mut argv = argv.into_iter();
+    let mut applied: Vec<OsString> = argv.next().into_iter().collect();
+    applied.extend(inserted.into_iter().map(OsString::from));
+    applied.extend(argv);
+    Ok(applied)
+}
+
+// Whether the command line (before any `--`) sets `--long`, by its long or short name
+fn given(argv: &[OsString], long: &str, command: &Command) -> bool {
+    let short = command.get_arguments().find(|arg| arg.get_long() == Some(long)).and_then(|arg| arg.get_short());
+    argv.iter()
+        .skip(1)
+        .map_while(|arg| arg.to_str().filter(|arg| *arg != "--"))
+        .any(|arg| match arg.strip_prefix("--") {
+            Some(name) => name.split('=').next() == Some(long),
+            None => short.is_some_and(|short| arg.strip_prefix('-').is_some_and(|rest| rest.starts_with(short))),
+        })
+}
+
+// `args` without `--long value` pairs, as `option_args` writes them
+fn drop_option(args: Vec<String>, long: &str) -> Vec<String> {
+    let flag = format!("--{}", long);
+    let mut kept = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            args.next();
+        } else {
+            kept.push(arg);
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    fn command() -> Command {
+        Command::new("contree")
+            .arg(Arg::new("dir").long("dir"))
+            .arg(Arg::new("grep").long("grep").short('g').action(ArgAction::Append))
+            .arg(Arg::new("max_depth").long("max-depth"))
+            .arg(Arg::new("include").long("include").action(ArgAction::Append))
+            .arg(Arg::new("include_deps").long("include-deps").action(ArgAction::SetTrue))
+            .arg(Arg::new("explain_errors").long("explain-errors").action(ArgAction::SetTrue))
+            .arg(Arg::new("exclude").long("exclude").action(ArgAction::Append))
+    }
+
+    fn applied_with(config: &str, profile: Option<&str>, argv: &[&str]) -> Result<Vec<String>> {
+        let config: Config = toml::from_str(config).unwrap();
+        let argv = argv.iter().map(OsString::from).collect();
+        Ok(apply(argv, &config, profile, &command())?.into_iter().map(|arg| arg.into_string().unwrap()).collect())
+    }
+
+    fn applied(defaults: &str, argv: &[&str]) -> Result<Vec<String>> {
+        applied_with(&format!("[defaults]\n{}", defaults), None, argv)
+    }
+
+    #[test]
+    fn defaults_go_before_the_command_line() {
+        let defaults = "grep = \"retry\"\nmax_depth = 3\ninclude = [\"a.rs\", \"b.rs\"]\ninclude-deps = true\nexplain_errors = false\nexclude = [\"vendor\"]";
+        assert_eq!(
+            applied(defaults, &["contree", "--grep", "fail"]).unwrap(),
+            [
+                "contree", "--exclude", "vendor", "--include", "a.rs", "--include", "b.rs", "--include-deps",
+                "--max-depth", "3", "--grep", "fail",
+            ]
+        );
+        assert_eq!(applied(defaults, &["contree", "-gfail"]).unwrap().iter().filter(|arg| *arg == "retry").count(), 0);
+        assert_eq!(applied("grep = \"retry\"", &["contree", "src"]).unwrap(), ["contree", "--grep", "retry", "src"]);
+        // Past `--`, `-g` is a path
+        assert_eq!(applied("grep = \"retry\"", &["contree", "--", "-g"]).unwrap(), ["contree", "--grep", "retry", "--", "-g"]);
+        assert_eq!(applied("", &["contree", "src"]).unwrap(), ["contree", "src"]);
+    }
+
+    #[test]
+    fn rejects_unknown_options_and_mismatched_values() {
+        assert!(applied("colour = true", &["contree"]).is_err());
+        assert!(applied("dir = \"..\"", &["contree"]).is_err());
+        assert!(applied("grep = true", &["contree"]).is_err());
+        assert!(applied("include_deps = \"yes\"", &["contree"]).is_err());
+        assert!(applied("paths = [1]", &["contree"]).is_err());
+    }
+
+    #[test]
+    fn profiles_follow_defaults() {
+        let config = "[defaults]\ngrep = \"retry\"\n\n[profiles.review]\ngrep = \"todo\"\ninclude_deps = true\n";
+        assert_eq!(
+            applied_with(config, Some("review"), &["contree", "src"]).unwrap(),
+            ["contree", "--grep", "retry", "--grep", "todo", "--include-deps", "src"]
+        );
+        let unknown = applied_with(config, Some("docs"), &["contree"]).unwrap_err().to_string();
+        assert!(unknown.starts_with("No profile 'docs'"), "{}", unknown);
+        assert!(applied_with("[profiles.nested]\nprofile = \"review\"\n", Some("nested"), &["contree"]).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result};
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// Bumped whenever the on-disk index layout changes; older files are rebuilt
+const INDEX_VERSION: u32 = 2;
+
+// Per crate-version index of a registry source directory. Published crate sources never
+// change, so an index stays valid forever once written. Symbol hits are recorded lazily:
+// each lowercase pattern is searched for once, then answered from the cache on later runs.
+#[derive(Serialize, Deserialize, Default)]
+pub struct CrateIndex {
+    version: u32,
+    // `.rs` files relative to the crate directory
+    pub files: Vec<String>,
+    // Lowercase pattern -> indexes into `files` whose lowercased content contains it
+    hits: BTreeMap<String, BTreeSet<usize>>,
+    // Full symbol index built by `contree index-deps`, kept in its own file next to this one
+    // and only read when a pattern isn't already answered by `hits`
+    #[serde(skip)]
+    symbols: Option<SymbolIndex>,
+    #[serde(skip)]
+    location: Option<PathBuf>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+// Every lowercase identifier, and every `macro_rules! name` definition, mapped to the files
+// containing it (indexes into `CrateIndex::files`)
+#[derive(Serialize, Deserialize)]
+struct SymbolIndex {
+    version: u32,
+    symbols: BTreeMap<String, BTreeSet<usize>>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+// contree's cache directory: $XDG_CACHE_HOME/contree, falling back to ~/.cache/contree
+pub fn cache_root() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("contree"))
+}
+
+// Where indexes live: deps/ in the cache directory
+pub fn cache_dir() -> Option<PathBuf> {
+    cache_root().map(|dir| dir.join("deps"))
+}
+
+impl CrateIndex {
+    // Load the cached index for a crate directory, or list its files to start a new one.
+    // Only registry sources are cached (see `cache_location`); others are indexed afresh.
+    pub fn load(crate_dir: &Path) -> CrateIndex {
+        let location = cache_dir().and_then(|dir| cache_location(&dir, crate_dir));
+
+        if let Some(index) = location
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice::<CrateIndex>(&bytes).ok())
+            .filter(|index| index.version == INDEX_VERSION)
+        {
+            return CrateIndex { location, ..index };
+        }
+        // A symbol index is only meaningful alongside the file list it was built from
+        if let Some(symbols_location) = location.as_deref().map(symbols_location) {
+            let _ = fs::remove_file(symbols_location);
+        }
+
+        let mut files: Vec<String> = WalkDir::new(crate_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.path().extension().and_then(|s| s.to_str()) == Some("rs"))
+            .filter_map(|e| Some(e.path().strip_prefix(crate_dir).ok()?.to_str()?.to_string()))
+            .collect();
+        files.sort();
+        CrateIndex {
+            version: INDEX_VERSION,
+            files,
+            hits: BTreeMap::new(),
+            symbols: None,
+            location,
+            dirty: true,
+        }
+    }
+
+    // For each lowercase pattern, the files whose lowercased content contains it.
+    // Only patterns not seen before cost a pass over the crate's files.
+    pub fn hits(&mut self, crate_dir: &Path, patterns: &[String]) -> Result<Vec<BTreeSet<usize>>> {
+        let missing: Vec<&String> = patterns
+            .iter()
+            .filter(|pattern| !self.hits.contains_key(*pattern))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        // With a full symbol index, identifier patterns are answered without reading files
+        if !missing.is_empty() {
+            self.load_symbols();
+        }
+        let (indexed, missing): (Vec<&String>, Vec<&String>) = match &self.symbols {
+            Some(_) => missing.into_iter().partition(|pattern| symbol_query(pattern).is_some()),
+            None => (Vec::new(), missing),
+        };
+        for pattern in indexed {
+            let files = self.lookup_symbol(pattern);
+            self.hits.insert(pattern.clone(), files);
+            self.dirty = true;
+        }
+
+        if !missing.is_empty() {
+            let set = RegexSet::new(missing.iter().map(|pattern| regex::escape(pattern)))?;
+            let mut found = vec![BTreeSet::new(); missing.len()];
+            for (file_index, file) in self.files.iter().enumerate() {
+                let content = fs::read_to_string(crate_dir.join(file)).unwrap_or_default().to_lowercase();
+                for pattern_index in set.matches(&content).iter() {
+                    found[pattern_index].insert(file_index);
+                }
+            }
+            for (pattern, files) in missing.into_iter().zip(found) {
+                self.hits.insert(pattern.clone(), files);
+            }
+            self.dirty = true;
+        }
+        Ok(patterns.iter().map(|pattern| self.hits[pattern].clone()).collect())
+    }
+
+    // Whether the index is kept between runs
+    pub fn is_cached(&self) -> bool {
+        self.location.is_some()
+    }
+
+    pub fn has_symbols(&self) -> bool {
+        self.symbols.is_some() || self.location.as_deref().is_some_and(|path| symbols_location(path).is_file())
+    }
+
+    fn load_symbols(&mut self) {
+        if self.symbols.is_some() {
+            return;
+        }
+        self.symbols = self
+            .location
+            .as_deref()
+            .and_then(|path| fs::read(symbols_location(path)).ok())
+            .and_then(|bytes| serde_json::from_slice::<SymbolIndex>(&bytes).ok())
+            .filter(|index| index.version == INDEX_VERSION);
+    }
+
+    // Build the full symbol index so later lookups never need to scan this crate
+    pub fn build_symbols(&mut self, crate_dir: &Path) {
+        let mut symbols: BTreeMap<String, BTreeSet<usize>> = BTreeMap::new();
+        for (file_index, file) in self.files.iter().enumerate() {
+            let content = fs::read_to_string(crate_dir.join(file)).unwrap_or_default().to_lowercase();
+            for token in content.split(|c: char| !is_identifier_char(c)).filter(|t| !t.is_empty()) {
+                symbols.entry(token.to_string()).or_default().insert(file_index);
+            }
+            for (offset, _) in content.match_indices("macro_rules! ") {
+                let rest = &content[offset + "macro_rules! ".len()..];
+                let end = rest.find(|c: char| !is_identifier_char(c)).unwrap_or(rest.len());
+                if end > 0 {
+                    symbols.entry(format!("macro_rules! {}", &rest[..end])).or_default().insert(file_index);
+                }
+            }
+        }
+        self.symbols = Some(SymbolIndex {
+            version: INDEX_VERSION,
+            symbols,
+            dirty: true,
+        });
+    }
+
+    // Identifiers are looked up exactly, so `Error` finds files using `Error` rather than every
+    // file with an `...Error...` identifier; only when no token matches exactly does the lookup
+    // fall back to substring matching, like the full-text scan. Macros match definitions whose
+    // name starts with the queried name, found with a range over the sorted keys.
+    fn lookup_symbol(&self, pattern: &str) -> BTreeSet<usize> {
+        let (Some(index), Some(query)) = (&self.symbols, symbol_query(pattern)) else {
+            return BTreeSet::new();
+        };
+        let symbols = &index.symbols;
+        match query {
+            SymbolQuery::Macro => symbols
+                .range::<str, _>((Bound::Included(pattern), Bound::Unbounded))
+                .take_while(|(symbol, _)| symbol.starts_with(pattern))
+                .flat_map(|(_, files)| files.iter().copied())
+                .collect(),
+            SymbolQuery::Identifier => match symbols.get(pattern) {
+                Some(files) => files.clone(),
+                None => symbols
+                    .iter()
+                    .filter(|(symbol, _)| !symbol.starts_with("macro_rules! ") && symbol.contains(pattern))
+                    .flat_map(|(_, files)| files.iter().copied())
+                    .collect(),
+            },
+        }
+    }
+
+    // Persist new entries; written via temp files so concurrent runs never see a partial index
+    pub fn save(&mut self) -> Result<()> {
+        let Some(location) = self.location.clone() else {
+            return Ok(());
+        };
+        if let Some(symbols) = self.symbols.as_mut().filter(|symbols| symbols.dirty) {
+            write_atomically(&symbols_location(&location), &serde_json::to_vec(symbols)?)?;
+            symbols.dirty = false;
+        }
+        if self.dirty {
+            write_atomically(&location, &serde_json::to_vec(self)?)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+// Where a crate's index is cached, keyed by registry source and crate-version so identically
+// named crates from different registries don't collide. Only `registry/src/<source>/<name>-<version>`
+// directories qualify, since only their path fixes their contents: vendored directories and git
+// checkouts change in place, and their names (`vendor/serde`, `.../crates/foo`) repeat across
+// projects and revisions.
+fn cache_location(cache_dir: &Path, crate_dir: &Path) -> Option<PathBuf> {
+    let source = crate_dir.parent()?;
+    if !source.parent()?.ends_with("registry/src") {
+        return None;
+    }
+    let name = crate_dir.file_name()?.to_str()?;
+    Some(cache_dir.join(source.file_name()?).join(format!("{}.json", name)))
+}
+
+// The symbol index for the crate whose hit index is at `location`
+fn symbols_location(location: &Path) -> PathBuf {
+    location.with_extension("symbols.json")
+}
+
+// Replace a cache file through a temporary file, so concurrent runs never see a partial one
+pub fn write_atomically(location: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = location.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(bytes)?;
+    file.persist(location)
+        .with_context(|| format!("Failed to write {}", location.display()))?;
+    Ok(())
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Clone, Copy)]
+enum SymbolQuery {
+    Identifier,
+    // `macro_rules! name` matches definitions whose name starts with `name`
+    Macro,
+}
+
+// Patterns the symbol index can answer: plain identifiers and `macro_rules! name`
+fn symbol_query(pattern: &str) -> Option<SymbolQuery> {
+    let is_identifier = |s: &str| !s.is_empty() && s.chars().all(is_identifier_char);
+    match pattern.strip_prefix("macro_rules! ") {
+        Some(name) if is_identifier(name) => Some(SymbolQuery::Macro),
+        None if is_identifier(pattern) => Some(SymbolQuery::Identifier),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caches_registry_sources_only() {
+        let cache = Path::new("/cache/deps");
+        assert_eq!(
+            cache_location(cache, Path::new("/home/u/.cargo/registry/src/index.crates.io-1949cf8c6b5b557f/serde-1.0.210")),
+            Some(PathBuf::from("/cache/deps/index.crates.io-1949cf8c6b5b557f/serde-1.0.210.json"))
+        );
+        assert_eq!(cache_location(cache, Path::new("/work/a/vendor/serde")), None);
+        assert_eq!(cache_location(cache, Path::new("/home/u/.cargo/git/checkouts/foo-1a2b/3c4d5e6/crates/foo")), None);
+    }
+}
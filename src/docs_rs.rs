@@ -0,0 +1,72 @@
+use regex::Regex;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// docs.rs URL of the module a crates.io dependency file defines, e.g.
+// `.../index.crates.io-xxxx/regex-1.11.1/src/regex/string.rs` ->
+// `https://docs.rs/regex/1.11.1/regex/regex/string/index.html`.
+// Returns None for files outside a crates.io registry checkout or outside the library's modules.
+// Reads the module declarations along the way, since private modules have no page.
+pub fn module_url(path: &Path) -> Option<String> {
+    let crate_version = Regex::new(r"^(.+?)-(\d+\.\d+\.\d+.*)$").unwrap();
+    let components: Vec<&str> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect();
+
+    // Only crates.io sources are documented on docs.rs
+    let registry = components
+        .iter()
+        .position(|part| part.starts_with("index.crates.io-") || part.starts_with("github.com-"))?;
+    if registry < 2 || components[registry - 2..registry] != ["registry", "src"] {
+        return None;
+    }
+    let cap = crate_version.captures(components.get(registry + 1)?)?;
+    let (name, version) = (&cap[1], &cap[2]);
+
+    let module = &components[registry + 2..];
+    let (file, dirs) = module.split_last()?;
+    if dirs.first() != Some(&"src") || dirs.get(1) == Some(&"bin") {
+        return None;
+    }
+    let mut segments: Vec<&str> = dirs[1..].to_vec();
+    match *file {
+        "lib.rs" | "main.rs" if segments.is_empty() => {}
+        "mod.rs" => {}
+        other => segments.push(other.strip_suffix(".rs")?),
+    }
+
+    // Private modules have no page; link the deepest public ancestor instead, which is
+    // where the file's public items are re-exported in practice
+    let crate_dir: PathBuf = path.components().take(path.components().count() - module.len()).collect();
+    let public_depth = (0..segments.len())
+        .find(|&depth| !declares_public_module(&crate_dir, &segments[..depth], segments[depth]))
+        .unwrap_or(segments.len());
+    segments.truncate(public_depth);
+
+    let mut url = format!("https://docs.rs/{}/{}/{}/", name, version, name.replace('-', "_"));
+    for segment in segments {
+        url.push_str(segment);
+        url.push('/');
+    }
+    url.push_str("index.html");
+    Some(url)
+}
+
+// Whether the module at `parent` (a path of module names under `src`) declares `pub mod name`
+fn declares_public_module(crate_dir: &Path, parent: &[&str], name: &str) -> bool {
+    let declaration = Regex::new(&format!(r"\bpub\s+mod\s+{}\b", regex::escape(name))).unwrap();
+    let candidates = if parent.is_empty() {
+        vec![crate_dir.join("src/lib.rs")]
+    } else {
+        let dir = crate_dir.join("src").join(parent.join("/"));
+        vec![dir.with_extension("rs"), dir.join("mod.rs")]
+    };
+    candidates
+        .iter()
+        .filter_map(|candidate| fs::read_to_string(candidate).ok())
+        .any(|content| declaration.is_match(&content))
+}
@@ -0,0 +1,78 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Open buffers reported by an editor plugin, e.g.
+// `{"buffers": [{"path": "src/main.rs", "cursor": {"line": 12, "column": 4}, "active": true}]}`
+#[derive(Deserialize, Debug, Clone)]
+pub struct EditorState {
+    #[serde(default)]
+    pub buffers: Vec<Buffer>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Buffer {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub cursor: Option<Cursor>,
+    #[serde(default)]
+    pub active: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Cursor {
+    pub line: u64,
+    #[serde(default)]
+    pub column: Option<u64>,
+}
+
+impl EditorState {
+    // Accept the payload inline (starting with `{`) or as a path to a JSON file.
+    // Used as a clap value parser so a bad payload fails before any output is written.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let json = if value.trim_start().starts_with('{') {
+            value.to_string()
+        } else {
+            fs::read_to_string(value).map_err(|e| format!("Failed to read {}: {}", value, e))?
+        };
+        let mut state: EditorState =
+            serde_json::from_str(&json).map_err(|e| format!("Invalid editor state: {}", e))?;
+        // The active buffer leads; the rest keep the editor's order
+        state.buffers.sort_by_key(|buffer| !buffer.active);
+        Ok(state)
+    }
+
+    // Buffer paths resolved against the scanned directory, in priority order
+    pub fn paths(&self, root: &Path) -> Vec<PathBuf> {
+        self.buffers
+            .iter()
+            .map(|buffer| {
+                if buffer.path.is_relative() {
+                    root.join(&buffer.path)
+                } else {
+                    buffer.path.clone()
+                }
+            })
+            .collect()
+    }
+
+    // One line per open buffer with its cursor position, listed ahead of the project files;
+    // each path is shown as `display` renders it (so reproducible output stays machine-independent)
+    pub fn summary(&self, display: &dyn Fn(&Path) -> String) -> Vec<String> {
+        self.buffers
+            .iter()
+            .map(|buffer| {
+                let mut line = display(&buffer.path);
+                match &buffer.cursor {
+                    Some(Cursor { line: row, column: Some(column) }) => line.push_str(&format!(" (cursor {}:{})", row, column)),
+                    Some(Cursor { line: row, column: None }) => line.push_str(&format!(" (cursor line {})", row)),
+                    None => {}
+                }
+                if buffer.active {
+                    line.push_str(" [active]");
+                }
+                line
+            })
+            .collect()
+    }
+}
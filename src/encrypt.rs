@@ -0,0 +1,82 @@
+use age::armor::{ArmoredWriter, Format};
+use age::stream::StreamWriter;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+// Parse a single age recipient: a native `age1...` key or an SSH public key
+fn parse_recipient(recipient: &str) -> Result<Box<dyn age::Recipient>> {
+    if recipient.starts_with("age1") {
+        let parsed: age::x25519::Recipient = recipient
+            .parse()
+            .map_err(|e| anyhow!("Invalid age recipient '{}': {}", recipient, e))?;
+        return Ok(Box::new(parsed));
+    }
+    if recipient.starts_with("ssh-") {
+        let parsed: age::ssh::Recipient = recipient
+            .parse()
+            .map_err(|e| anyhow!("Invalid SSH recipient '{}': {:?}", recipient, e))?;
+        return Ok(Box::new(parsed));
+    }
+    bail!("Unrecognised recipient '{}' (expected an age1... or ssh- public key)", recipient)
+}
+
+// Resolve each --encrypt-to value into recipients. A value naming a file is read as a
+// recipients file (one key per line, `#` comments), so a team can share one list.
+fn parse_recipients(values: &[String]) -> Result<Vec<Box<dyn age::Recipient>>> {
+    let mut recipients = Vec::new();
+    for value in values {
+        let value = value.trim();
+        if !value.starts_with("age1") && !value.starts_with("ssh-") && Path::new(value).is_file() {
+            let list = fs::read_to_string(value)
+                .with_context(|| format!("Failed to read recipients file: {}", value))?;
+            for line in list.lines().map(str::trim) {
+                if !line.is_empty() && !line.starts_with('#') {
+                    recipients.push(parse_recipient(line)?);
+                }
+            }
+        } else {
+            recipients.push(parse_recipient(value)?);
+        }
+    }
+    if recipients.is_empty() {
+        bail!("No recipients to encrypt to");
+    }
+    Ok(recipients)
+}
+
+// Writes age-encrypted data to the inner writer; `finish` must be called to complete the file
+pub struct EncryptingWriter<W: Write> {
+    stream: StreamWriter<ArmoredWriter<W>>,
+}
+
+// Start an encrypted stream to the given recipients. ASCII armor is used for text
+// destinations such as the terminal or paste services.
+pub fn wrap<W: Write>(recipients: &[String], armor: bool, output: W) -> Result<EncryptingWriter<W>> {
+    let recipients = parse_recipients(recipients)?;
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref()))
+        .map_err(|e| anyhow!("Failed to set up encryption: {}", e))?;
+    let format = if armor { Format::AsciiArmor } else { Format::Binary };
+    let armored = ArmoredWriter::wrap_output(output, format)?;
+    let stream = encryptor
+        .wrap_output(armored)
+        .context("Failed to start encrypted output")?;
+    Ok(EncryptingWriter { stream })
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn finish(self) -> io::Result<W> {
+        self.stream.finish()?.finish()
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,40 @@
+use crate::warnings;
+use regex::Regex;
+use std::io;
+use std::process::Command;
+
+// Rust error codes in the order they first appear, e.g. `error[E0308]` -> `E0308`
+pub fn error_codes(output: &str) -> Vec<String> {
+    let re = Regex::new(r"\berror\[(E\d{4})\]").unwrap();
+    let mut codes: Vec<String> = Vec::new();
+    for cap in re.captures_iter(output) {
+        if !codes.iter().any(|code| code == &cap[1]) {
+            codes.push(cap[1].to_string());
+        }
+    }
+    codes
+}
+
+// The `rustc --explain` text for every error code in the output.
+// Codes rustc doesn't know (or a missing rustc) are skipped with a warning.
+pub fn explanations(output: &str) -> Vec<(String, String)> {
+    error_codes(output)
+        .into_iter()
+        .filter_map(|code| match explain(&code) {
+            Ok(text) => Some((code, text)),
+            Err(e) => {
+                warnings::warn(warnings::Kind::Dependencies, format!("Failed to run rustc --explain {}: {}", code, e));
+                None
+            }
+        })
+        .collect()
+}
+
+fn explain(code: &str) -> io::Result<String> {
+    let output = Command::new("rustc").args(["--explain", code]).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,207 @@
+use regex::Regex;
+use serde_json::Value;
+
+// A file location pulled out of command output, with what it was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: String,
+    pub line: Option<u64>,
+    pub origin: &'static str,
+}
+
+// A jq-like path expression such as `.diagnostics[].location | .file`.
+// Supports `.key`, `."quoted key"`, `.[]`, `.[N]`, `.["key"]`, and `|` between them;
+// pipes only chain path steps, so `.a | .b` is the same as `.a.b`.
+#[derive(Debug, Clone)]
+pub struct PathExpression {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Key(String),
+    Index(i64),
+    Iterate,
+}
+
+// Keys checked, in order, when an expression yields an object rather than a string
+const PATH_KEYS: &[&str] = &["path", "file", "filename", "file_path", "filePath", "uri"];
+const LINE_KEYS: &[&str] = &["line", "line_number", "lineno", "start_line", "startLine"];
+
+impl PathExpression {
+    // Parse an expression; used as a clap value parser so typos fail before any work is done
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for term in expression.split('|').map(str::trim) {
+            if !term.starts_with('.') {
+                return Err(format!("'{}' must start with '.' (e.g. '.file' or '.items[].path')", term));
+            }
+            let mut rest = term;
+            while !rest.is_empty() {
+                rest = parse_step(rest, &mut steps)
+                    .ok_or_else(|| format!("Invalid path expression near '{}'", rest))?;
+            }
+        }
+        Ok(PathExpression { steps })
+    }
+
+    // Evaluate against every record: a single JSON document, or one JSON value per line (NDJSON).
+    // Lines that aren't JSON are skipped so mixed tool output still works.
+    pub fn extract(&self, input: &str) -> Vec<Location> {
+        let mut locations = Vec::new();
+        let records: Vec<Value> = match serde_json::from_str::<Value>(input) {
+            Ok(document) => vec![document],
+            Err(_) => input
+                .lines()
+                .filter_map(|line| serde_json::from_str(line.trim()).ok())
+                .collect(),
+        };
+        for record in &records {
+            for value in evaluate(record, &self.steps) {
+                if let Some(location) = to_location(value) {
+                    if !locations.contains(&location) {
+                        locations.push(location);
+                    }
+                }
+            }
+        }
+        locations
+    }
+}
+
+// Consume one step from the front of `rest` and return what remains
+fn parse_step<'a>(rest: &'a str, steps: &mut Vec<Step>) -> Option<&'a str> {
+    let rest = rest.trim_start();
+    if let Some(after) = rest.strip_prefix('[') {
+        let (inner, after) = after.split_once(']')?;
+        let inner = inner.trim();
+        steps.push(if inner.is_empty() {
+            Step::Iterate
+        } else if let Some(key) = inner.strip_prefix('"').and_then(|k| k.strip_suffix('"')) {
+            Step::Key(key.to_string())
+        } else {
+            Step::Index(inner.parse().ok()?)
+        });
+        return Some(after.strip_prefix('?').unwrap_or(after));
+    }
+
+    let after = rest.strip_prefix('.')?;
+    if after.is_empty() || after.starts_with('[') {
+        return Some(after); // Identity, or `.[]` handled on the next call
+    }
+    if let Some(quoted) = after.strip_prefix('"') {
+        let (key, after) = quoted.split_once('"')?;
+        steps.push(Step::Key(key.to_string()));
+        return Some(after.strip_prefix('?').unwrap_or(after));
+    }
+    let end = after
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+        .unwrap_or(after.len());
+    if end == 0 {
+        return None;
+    }
+    steps.push(Step::Key(after[..end].to_string()));
+    let after = &after[end..];
+    Some(after.strip_prefix('?').unwrap_or(after))
+}
+
+// Missing keys and type mismatches yield nothing rather than errors, like jq's `?`
+fn evaluate<'a>(value: &'a Value, steps: &[Step]) -> Vec<&'a Value> {
+    let Some((step, rest)) = steps.split_first() else {
+        return vec![value];
+    };
+    let next: Vec<&Value> = match (step, value) {
+        (Step::Key(key), Value::Object(map)) => map.get(key).into_iter().collect(),
+        (Step::Index(index), Value::Array(items)) => {
+            let index = if *index < 0 { items.len() as i64 + index } else { *index };
+            usize::try_from(index).ok().and_then(|i| items.get(i)).into_iter().collect()
+        }
+        (Step::Iterate, Value::Array(items)) => items.iter().collect(),
+        (Step::Iterate, Value::Object(map)) => map.values().collect(),
+        _ => Vec::new(),
+    };
+    next.into_iter().flat_map(|v| evaluate(v, rest)).collect()
+}
+
+// Strings may carry `path:line[:column]`; objects are searched for common path/line keys
+fn to_location(value: &Value) -> Option<Location> {
+    match value {
+        Value::String(text) => {
+            let text = text.strip_prefix("file://").unwrap_or(text);
+            let re = Regex::new(r"^(.+?):(\d+)(?::\d+)?$").unwrap();
+            Some(match re.captures(text) {
+                Some(cap) => Location {
+                    path: cap[1].to_string(),
+                    line: cap[2].parse().ok(),
+                    origin: "structured output",
+                },
+                None => Location {
+                    path: text.to_string(),
+                    line: None,
+                    origin: "structured output",
+                },
+            })
+            .filter(|location| !location.path.is_empty())
+        }
+        Value::Object(map) => {
+            let mut location = PATH_KEYS
+                .iter()
+                .find_map(|key| map.get(*key))
+                .and_then(to_location)?;
+            if let Some(line) = LINE_KEYS.iter().find_map(|key| map.get(*key)) {
+                location.line = line.as_u64().or_else(|| line.as_str().and_then(|s| s.parse().ok()));
+            }
+            Some(location)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(expression: &str, input: &str) -> Vec<(String, Option<u64>)> {
+        PathExpression::parse(expression)
+            .unwrap()
+            .extract(input)
+            .into_iter()
+            .map(|location| (location.path, location.line))
+            .collect()
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(PathExpression::parse("file").is_err());
+        assert!(PathExpression::parse(".a[").is_err());
+        assert!(PathExpression::parse(".a[x]").is_err());
+        assert!(PathExpression::parse(".a | b").is_err());
+        assert!(PathExpression::parse(".").is_ok());
+    }
+
+    #[test]
+    fn keys_indexes_and_iteration() {
+        let input = r#"{"items": [{"path": "a.rs"}, {"path": "b.rs"}, {"path": "c.rs"}]}"#;
+        assert_eq!(paths(".items[].path", input).len(), 3);
+        assert_eq!(paths(".items[1].path", input), vec![("b.rs".to_string(), None)]);
+        assert_eq!(paths(".items[-1].path", input), vec![("c.rs".to_string(), None)]);
+        assert_eq!(paths(r#".["items"][0] | ."path""#, input), vec![("a.rs".to_string(), None)]);
+        assert!(paths(".missing[].path", input).is_empty());
+    }
+
+    #[test]
+    fn objects_and_strings_carry_lines() {
+        let input = r#"{"loc": {"file": "src/x.rs", "line": 12}, "s": "file://src/y.rs:3:7"}"#;
+        assert_eq!(paths(".loc", input), vec![("src/x.rs".to_string(), Some(12))]);
+        assert_eq!(paths(".s", input), vec![("src/y.rs".to_string(), Some(3))]);
+    }
+
+    #[test]
+    fn ndjson_records_skip_non_json_lines_and_dedupe() {
+        let input = "{\"file\": \"a.rs\"}\nwarning: not json\n{\"file\": \"a.rs\"}\n{\"file\": \"b.rs\"}\n";
+        assert_eq!(
+            paths(".file", input),
+            vec![("a.rs".to_string(), None), ("b.rs".to_string(), None)]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use crate::content;
+use crate::hgignore::HgIgnore;
+use crate::vcs;
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Failed reads are told apart by `io::ErrorKind`, never by message, which changes across
+// platforms and locales
+
+// Whether a read failed because the file isn't UTF-8 text (`read_text` and `grep_file` report
+// it as `InvalidData`, like `fs::read_to_string`), so it is treated as binary
+pub fn is_not_text(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::InvalidData
+}
+
+// What a directory entry is, as far as the context is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    // A named pipe, socket, or device, which must never be opened for reading
+    Special(&'static str),
+    // A symlink the walk doesn't follow
+    Other,
+}
+
+impl EntryKind {
+    fn of(file_type: fs::FileType) -> Self {
+        match content::special_kind(file_type) {
+            Some(kind) => EntryKind::Special(kind),
+            None if file_type.is_file() => EntryKind::File,
+            None if file_type.is_dir() => EntryKind::Dir,
+            None => EntryKind::Other,
+        }
+    }
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+// Decides whether the walk keeps an entry (and, for directories, descends into it), given its
+// path and whether it is a directory
+pub type EntryFilter = Arc<dyn Fn(&Path, bool) -> bool + Send + Sync>;
+
+pub struct WalkOptions {
+    pub roots: Vec<PathBuf>,
+    pub max_depth: Option<usize>,
+    // Visit entries in file name order rather than the filesystem's
+    pub sorted: bool,
+    pub filter: EntryFilter,
+}
+
+// The filesystem operations file selection needs, so the selection pipeline can run against
+// an in-memory tree in tests
+pub trait FileSystem {
+    fn kind(&self, path: &Path) -> io::Result<EntryKind>;
+    // Entries below the roots, in walk order
+    fn walk(&self, options: WalkOptions) -> Box<dyn Iterator<Item = io::Result<Entry>>>;
+    // Whether the file matches, failing with `InvalidData` if it isn't text
+    fn grep(&self, path: &Path, regex: &Regex) -> io::Result<bool>;
+}
+
+// Why a path given on the command line can't be included, for warnings
+pub fn not_a_file_reason(fs: &dyn FileSystem, path: &Path) -> &'static str {
+    match fs.kind(path) {
+        Ok(EntryKind::Special(kind)) => kind,
+        Ok(_) => "is not a file",
+        Err(e) if e.kind() == io::ErrorKind::NotFound => "does not exist",
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => "permission denied",
+        Err(_) => "can't be read",
+    }
+}
+
+// The real filesystem. Walks respect .gitignore (inside git repositories and Jujutsu workspaces), the root
+// .hgignore of a Mercurial repository, .ignore, and .contreeignore files, and never enter .git directories.
+pub struct Disk;
+
+impl FileSystem for Disk {
+    fn kind(&self, path: &Path) -> io::Result<EntryKind> {
+        fs::metadata(path).map(|metadata| EntryKind::of(metadata.file_type()))
+    }
+
+    fn walk(&self, options: WalkOptions) -> Box<dyn Iterator<Item = io::Result<Entry>>> {
+        let Some(first) = options.roots.first() else {
+            return Box::new(std::iter::empty());
+        };
+        let mut builder = WalkBuilder::new(first);
+        for root in options.roots.iter().skip(1) {
+            builder.add(root);
+        }
+        builder.standard_filters(true); // Respect .gitignore, etc.
+        builder.hidden(false); // Skip hidden files/directories like .git by default
+        builder.git_ignore(true); // Respect .gitignore
+        builder.require_git(!vcs::in_plain_jujutsu(first)); // Jujutsu reads .gitignore without a .git
+        builder.git_exclude(false);
+        builder.add_custom_ignore_filename(".contreeignore");
+        builder.add_ignore(".git"); // Explicitly ignore .git directories
+        builder.max_depth(options.max_depth);
+        if options.sorted {
+            builder.sort_by_file_name(|a, b| a.cmp(b)); // Stable order regardless of filesystem
+        }
+        let filter = options.filter;
+        let hgignore = HgIgnore::load(first);
+        builder.filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+            let kept = hgignore.as_ref().is_none_or(|hgignore| hgignore.matched(entry.path(), is_dir).is_none());
+            kept && filter(entry.path(), is_dir)
+        });
+        Box::new(builder.build().map(|entry| {
+            let entry = entry.map_err(|e| e.into_io_error().unwrap_or_else(|| io::Error::other("Failed to read directory entry")))?;
+            Ok(Entry {
+                kind: entry.file_type().map_or(EntryKind::Other, EntryKind::of),
+                path: entry.into_path(),
+            })
+        }))
+    }
+
+    fn grep(&self, path: &Path, regex: &Regex) -> io::Result<bool> {
+        content::grep_file(path, regex)
+    }
+}
+
+// A tree of files held in memory: path -> contents. Directories exist implicitly above their
+// files, walks visit them in path order, and ignore files have no effect.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryFs {
+    files: std::collections::BTreeMap<PathBuf, Vec<u8>>,
+}
+
+#[cfg(test)]
+impl MemoryFs {
+    pub fn with(mut self, path: &str, contents: impl Into<Vec<u8>>) -> Self {
+        self.files.insert(PathBuf::from(path), contents.into());
+        self
+    }
+
+    fn entries(&self) -> std::collections::BTreeMap<PathBuf, EntryKind> {
+        let mut entries = std::collections::BTreeMap::new();
+        for path in self.files.keys() {
+            for dir in path.ancestors().skip(1).filter(|dir| dir.parent().is_some()) {
+                entries.insert(dir.to_path_buf(), EntryKind::Dir);
+            }
+            entries.insert(path.clone(), EntryKind::File);
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+impl FileSystem for MemoryFs {
+    fn kind(&self, path: &Path) -> io::Result<EntryKind> {
+        self.entries()
+            .get(path)
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not in the tree", path.display())))
+    }
+
+    fn walk(&self, options: WalkOptions) -> Box<dyn Iterator<Item = io::Result<Entry>>> {
+        let entries = self.entries();
+        let mut walked = Vec::new();
+        for root in &options.roots {
+            let mut pruned: Vec<PathBuf> = Vec::new();
+            for (path, kind) in entries.range(root.clone()..) {
+                let Ok(relative) = path.strip_prefix(root) else {
+                    break;
+                };
+                let depth = relative.components().count();
+                if pruned.iter().any(|dir| path.starts_with(dir)) || options.max_depth.is_some_and(|max| depth > max) {
+                    continue;
+                }
+                // Like the real walk, the roots themselves are never filtered out
+                if depth > 0 && !(options.filter)(path, *kind == EntryKind::Dir) {
+                    pruned.push(path.clone());
+                    continue;
+                }
+                walked.push(Ok(Entry { path: path.clone(), kind: *kind }));
+            }
+        }
+        Box::new(walked.into_iter())
+    }
+
+    fn grep(&self, path: &Path, regex: &Regex) -> io::Result<bool> {
+        let contents = self.files.get(path).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let text = std::str::from_utf8(contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(regex.is_match(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_error_kind() {
+        let fs = MemoryFs::default().with("/p/src/main.rs", "fn main() {}").with("/p/logo.png", [0x89, 0xff]);
+        assert_eq!(not_a_file_reason(&fs, Path::new("/p/src")), "is not a file");
+        assert_eq!(not_a_file_reason(&fs, Path::new("/p/missing.rs")), "does not exist");
+        let regex = Regex::new("main").unwrap();
+        assert!(fs.grep(Path::new("/p/src/main.rs"), &regex).unwrap());
+        assert!(is_not_text(&fs.grep(Path::new("/p/logo.png"), &regex).unwrap_err()));
+        // Whatever the message says, only the kind counts
+        assert!(!is_not_text(&io::Error::new(io::ErrorKind::NotFound, "stream did not contain valid UTF-8")));
+    }
+
+    #[test]
+    fn memory_walk_prunes_filtered_directories() {
+        let fs = MemoryFs::default().with("/p/a.rs", "").with("/p/target/debug/x", "").with("/p/src/deep/b.rs", "");
+        let walk = |max_depth| {
+            let filter: EntryFilter = Arc::new(|path: &Path, is_dir| !(is_dir && path.ends_with("target")));
+            let options = WalkOptions { roots: vec![PathBuf::from("/p")], max_depth, sorted: true, filter };
+            fs.walk(options).map(|entry| entry.unwrap().path.display().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(walk(None), ["/p", "/p/a.rs", "/p/src", "/p/src/deep", "/p/src/deep/b.rs"]);
+        assert_eq!(walk(Some(1)), ["/p", "/p/a.rs", "/p/src"]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::binary::format_size;
+use crate::session;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path};
+
+const FINGERPRINT_FILE: &str = "fingerprint.json";
+
+// Version of the saved layout; a fingerprint from another version is treated as missing
+const FINGERPRINT_VERSION: u32 = 1;
+
+// The shape of the scanned files, cheap to compare between runs: how many there are, their
+// total size, how they spread over the top-level entries, and their languages
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    version: u32,
+    files: usize,
+    bytes: u64,
+    // File count under each top-level entry; directories end with `/`
+    top_level: BTreeMap<String, usize>,
+    languages: BTreeMap<String, usize>,
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Fingerprint { version: FINGERPRINT_VERSION, ..Fingerprint::default() }
+    }
+
+    // Count a file, by its path relative to the scanned directory
+    pub fn add(&mut self, relative: &Path, bytes: u64, language: Option<&str>) {
+        self.files += 1;
+        self.bytes += bytes;
+        let mut components = relative.components().filter(|component| matches!(component, Component::Normal(_)));
+        if let Some(first) = components.next() {
+            let name = first.as_os_str().to_string_lossy();
+            let entry = if components.next().is_some() { format!("{}/", name) } else { name.into_owned() };
+            *self.top_level.entry(entry).or_default() += 1;
+        }
+        if let Some(language) = language {
+            *self.languages.entry(language.to_string()).or_default() += 1;
+        }
+    }
+
+    // The fingerprint saved by the last run, if it can be read
+    pub fn load(root: &Path) -> Option<Fingerprint> {
+        let bytes = fs::read(root.join(session::CONTREE_DIR).join(FINGERPRINT_FILE)).ok()?;
+        serde_json::from_slice::<Fingerprint>(&bytes).ok().filter(|fingerprint| fingerprint.version == FINGERPRINT_VERSION)
+    }
+
+    pub fn save(&self, root: &Path) -> Result<()> {
+        let path = session::contree_dir(root)?.join(FINGERPRINT_FILE);
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    // A one-line remark for the context: what changed since `previous`, or this fingerprint's
+    // totals when there is nothing to compare with or nothing changed
+    pub fn summary(&self, previous: Option<&Fingerprint>) -> String {
+        let totals = format!("{} files, {}", self.files, format_size(self.bytes));
+        let Some(previous) = previous else {
+            return format!("Fingerprint recorded: {}; later runs with --fingerprint report what changed", totals);
+        };
+        let changes = self.changes_since(previous);
+        if changes.is_empty() {
+            format!("Same structure as the last run: {}", totals)
+        } else {
+            format!("Since the last run: {} (now {})", changes.join(", "), totals)
+        }
+    }
+
+    // "+212 files", "+1.2 MiB", "new directory migrations/", "src/ -3 files", "new language go", ...
+    fn changes_since(&self, previous: &Fingerprint) -> Vec<String> {
+        let mut changes = Vec::new();
+        if let Some(delta) = signed(self.files as i64 - previous.files as i64) {
+            changes.push(format!("{} files", delta));
+        }
+        if self.bytes != previous.bytes {
+            let sign = if self.bytes > previous.bytes { '+' } else { '-' };
+            changes.push(format!("{}{}", sign, format_size(self.bytes.abs_diff(previous.bytes))));
+        }
+        let kind = |entry: &str| if entry.ends_with('/') { "directory" } else { "file" };
+        for (entry, count) in &self.top_level {
+            match previous.top_level.get(entry) {
+                None => changes.push(format!("new {} {}", kind(entry), entry)),
+                Some(before) if entry.ends_with('/') => {
+                    if let Some(delta) = signed(*count as i64 - *before as i64) {
+                        changes.push(format!("{} {} files", entry, delta));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for entry in previous.top_level.keys().filter(|entry| !self.top_level.contains_key(*entry)) {
+            changes.push(format!("{} {} gone", kind(entry), entry));
+        }
+        for language in self.languages.keys().filter(|language| !previous.languages.contains_key(*language)) {
+            changes.push(format!("new language {}", language));
+        }
+        for language in previous.languages.keys().filter(|language| !self.languages.contains_key(*language)) {
+            changes.push(format!("no more {} files", language));
+        }
+        changes
+    }
+}
+
+// `+3` or `-3`, and None for no change
+fn signed(delta: i64) -> Option<String> {
+    (delta != 0).then(|| format!("{:+}", delta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(files: &[(&str, u64, Option<&str>)]) -> Fingerprint {
+        let mut fingerprint = Fingerprint::new();
+        for (path, bytes, language) in files {
+            fingerprint.add(Path::new(path), *bytes, *language);
+        }
+        fingerprint
+    }
+
+    #[test]
+    fn reports_structural_changes() {
+        let before = fingerprint(&[
+            ("src/main.rs", 1000, Some("rust")),
+            ("old/tool.rb", 100, Some("ruby")),
+            ("README.md", 24, Some("markdown")),
+        ]);
+        let after = fingerprint(&[
+            ("src/main.rs", 1000, Some("rust")),
+            ("src/lib.rs", 3000, Some("rust")),
+            ("migrations/001.sql", 48, Some("sql")),
+            ("README.md", 24, Some("markdown")),
+        ]);
+        assert_eq!(
+            after.summary(Some(&before)),
+            "Since the last run: +1 files, +2.9 KiB, new directory migrations/, src/ +1 files, directory old/ gone, \
+             new language sql, no more ruby files (now 4 files, 4.0 KiB)"
+        );
+        assert_eq!(after.summary(Some(&after)), "Same structure as the last run: 4 files, 4.0 KiB");
+        assert!(after.summary(None).starts_with("Fingerprint recorded: 4 files, 4.0 KiB;"));
+    }
+
+    #[test]
+    fn saves_under_the_contree_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Fingerprint::load(dir.path()), None);
+        let saved = fingerprint(&[("a.rs", 1, Some("rust"))]);
+        saved.save(dir.path()).unwrap();
+        assert!(dir.path().join(".contree/fingerprint.json").is_file());
+        assert_eq!(Fingerprint::load(dir.path()), Some(saved));
+    }
+}
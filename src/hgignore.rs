@@ -0,0 +1,225 @@
+use crate::vcs;
+use crate::warnings;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// How a line of `.hgignore` is matched: `syntax: glob` and `glob:` patterns at any depth,
+// `rootglob:` and `path:` from the repository root, and regular expressions (the default)
+// searched for anywhere in the repository-relative path
+#[derive(Clone, Copy)]
+enum Syntax {
+    Glob,
+    RootGlob,
+    Path,
+    Regexp,
+}
+
+enum Matcher {
+    Glob(Gitignore),
+    Regex(Regex),
+}
+
+struct Rule {
+    // 1-based
+    line: usize,
+    pattern: String,
+    matcher: Matcher,
+}
+
+// The line of `.hgignore` that matches a path
+#[derive(Debug, PartialEq, Eq)]
+pub struct Marking {
+    pub source: PathBuf,
+    pub line: usize,
+    pub pattern: String,
+}
+
+// The root `.hgignore` of the Mercurial repository holding a directory. Mercurial ignores a
+// path if any line matches it or a directory above it; there are no negations.
+pub struct HgIgnore {
+    root: PathBuf,
+    source: PathBuf,
+    // The directory as given, and where it is below the root, so walked paths needn't be
+    // canonicalized one by one
+    base: PathBuf,
+    base_relative: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl HgIgnore {
+    // For `dir` when Mercurial manages it (rather than git or Jujutsu) and its root has a
+    // `.hgignore`
+    pub fn load(dir: &Path) -> Option<HgIgnore> {
+        let vcs = vcs::detect(dir).ok().filter(|vcs| vcs.name() == "hg")?;
+        let root = vcs.root().to_path_buf();
+        let source = root.join(".hgignore");
+        let text = fs::read_to_string(&source).ok()?;
+        let base_relative = fs::canonicalize(dir).ok()?.strip_prefix(&root).ok()?.to_path_buf();
+        let rules = parse_rules(&text, &source, &root);
+        Some(HgIgnore { root, source, base: dir.to_path_buf(), base_relative, rules })
+    }
+
+    // The line ignoring `path`, if one does; directories above it are the caller's to check,
+    // as the walk prunes them before reaching their files
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<Marking> {
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(below) => self.base_relative.join(below),
+            Err(_) => {
+                let parent = fs::canonicalize(path.parent()?).ok()?;
+                parent.join(path.file_name()?).strip_prefix(&self.root).ok()?.to_path_buf()
+            }
+        };
+        let text = relative.to_str()?.replace('\\', "/");
+        if text.is_empty() {
+            return None;
+        }
+        let rule = self.rules.iter().find(|rule| match &rule.matcher {
+            Matcher::Glob(glob) => glob.matched(&relative, is_dir).is_ignore(),
+            // A directory is also tried with a trailing slash, so `^build/` prunes `build`
+            Matcher::Regex(regex) => regex.is_match(&text) || (is_dir && regex.is_match(&format!("{}/", text))),
+        })?;
+        Some(Marking { source: self.source.clone(), line: rule.line, pattern: rule.pattern.clone() })
+    }
+}
+
+// `.hgignore` lines, switching syntax at `syntax:` lines and per line with a `glob:`-style
+// prefix. `include:` and `subinclude:` lines aren't followed, and invalid regular expressions
+// are skipped with a warning, as Mercurial would refuse them.
+fn parse_rules(text: &str, source: &Path, root: &Path) -> Vec<Rule> {
+    let mut syntax = Syntax::Regexp;
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("syntax:") {
+            match name.trim() {
+                "glob" | "relglob" => syntax = Syntax::Glob,
+                "rootglob" => syntax = Syntax::RootGlob,
+                "regexp" | "re" | "relre" => syntax = Syntax::Regexp,
+                _ => {}
+            }
+            continue;
+        }
+        let (line_syntax, pattern) = match line.split_once(':') {
+            Some(("glob" | "relglob", pattern)) => (Some(Syntax::Glob), pattern),
+            Some(("rootglob", pattern)) => (Some(Syntax::RootGlob), pattern),
+            Some(("path", pattern)) => (Some(Syntax::Path), pattern),
+            Some(("re" | "regexp" | "relre", pattern)) => (Some(Syntax::Regexp), pattern),
+            Some(("include" | "subinclude" | "relpath" | "rootfilesin", _)) => continue,
+            _ => (None, line),
+        };
+        let matcher = match line_syntax.unwrap_or(syntax) {
+            Syntax::Regexp => match Regex::new(pattern) {
+                Ok(regex) => Matcher::Regex(regex),
+                Err(e) => {
+                    let message = format!("Ignoring {} line {}: {}", source.display(), number + 1, e);
+                    warnings::warn(warnings::Kind::Ignored, message);
+                    continue;
+                }
+            },
+            syntax => {
+                let glob = match syntax {
+                    Syntax::Glob => format!("**/{}", escape_gitignore(pattern)),
+                    Syntax::Path => format!("/{}", escape_glob(pattern)),
+                    _ => format!("/{}", escape_gitignore(pattern)),
+                };
+                let mut builder = GitignoreBuilder::new(root);
+                let Some(glob) = builder.add_line(None, &glob).ok().and_then(|builder| builder.build().ok()) else {
+                    continue;
+                };
+                Matcher::Glob(glob)
+            }
+        };
+        rules.push(Rule { line: number + 1, pattern: line.to_string(), matcher });
+    }
+    rules
+}
+
+// `#` starts a comment unless escaped as `\#`
+fn strip_comment(line: &str) -> String {
+    let mut kept = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'#') => kept.push(chars.next().unwrap_or('#')),
+            '#' => break,
+            c => kept.push(c),
+        }
+    }
+    kept
+}
+
+// A Mercurial glob as a gitignore pattern body: a leading `!` is literal in Mercurial
+fn escape_gitignore(glob: &str) -> String {
+    match glob.strip_prefix('!') {
+        Some(rest) => format!("\\!{}", rest),
+        None => glob.trim_start_matches('/').to_string(),
+    }
+}
+
+// A literal path as a glob
+fn escape_glob(path: &str) -> String {
+    let mut escaped = String::new();
+    for c in path.trim_start_matches('/').chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\' | '!') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileio::{Disk, FileSystem, WalkOptions};
+    use std::sync::Arc;
+
+    #[test]
+    fn reads_hgignore_like_mercurial() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = &fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join(".hg")).unwrap();
+        for path in ["src/main.rs", "src/main.rs.orig", "target/debug/app", "web/dist/app.js", "dist/keep.js", "notes#1.txt", "logs/a.log"] {
+            fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+            fs::write(root.join(path), "").unwrap();
+        }
+        fs::write(
+            root.join(".hgignore"),
+            "# build output\n^target/\n\\.orig$\nsyntax: glob\nweb/dist\nnotes\\#1.txt\nrootglob:logs/*.log\nre:[unclosed\n",
+        )
+        .unwrap();
+
+        let hgignore = HgIgnore::load(&root.join("src")).unwrap();
+        let line = |path: &str, is_dir| hgignore.matched(&root.join(path), is_dir).map(|marking| marking.line);
+        assert_eq!(line("target", true), Some(2));
+        assert_eq!(line("src/main.rs.orig", false), Some(3));
+        assert_eq!(line("web/dist", true), Some(5));
+        assert_eq!(line("notes#1.txt", false), Some(6));
+        assert_eq!(line("logs/a.log", false), Some(7));
+        assert_eq!(line("dist", true), None);
+        assert_eq!(line("src/main.rs", false), None);
+
+        let walk = Disk.walk(WalkOptions {
+            roots: vec![root.clone()],
+            max_depth: None,
+            sorted: true,
+            filter: Arc::new(|_, _| true),
+        });
+        let files: Vec<String> = walk
+            .map(Result::unwrap)
+            .filter(|entry| entry.kind == crate::fileio::EntryKind::File && !entry.path.starts_with(root.join(".hg")))
+            .map(|entry| entry.path.strip_prefix(root).unwrap().display().to_string())
+            .collect();
+        assert_eq!(files, [".hgignore", "dist/keep.js", "src/main.rs"]);
+
+        // Outside Mercurial, .hgignore is just a file
+        fs::remove_dir(root.join(".hg")).unwrap();
+        assert!(HgIgnore::load(root).is_none());
+    }
+}
@@ -0,0 +1,129 @@
+use regex::Regex;
+use std::ops::Range;
+
+// Byte ranges of the grep pattern's matches, for highlighting why a file was selected.
+// Empty matches (e.g. `/^/`) have nothing to show and are dropped.
+pub fn find_matches(regex: &Regex, text: &str) -> Vec<Range<usize>> {
+    regex
+        .find_iter(text)
+        .map(|m| m.range())
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+// Append a ` <comment> MATCH` marker to each line where a match starts, shifting the match
+// ranges to the new text
+pub fn add_markers(text: &str, matches: &[Range<usize>], comment: &str) -> (String, Vec<Range<usize>>) {
+    let marker = format!(" {} MATCH", comment);
+    let mut marked = String::with_capacity(text.len() + matches.len() * marker.len());
+    // (offset in `text`, bytes inserted before it) at each marker
+    let mut shifts = Vec::new();
+    let mut remaining = matches.iter().map(|range| range.start).peekable();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let end = offset + content.len();
+        let mut has_match = false;
+        while remaining.next_if(|&start| start <= end).is_some() {
+            has_match = true;
+        }
+        marked.push_str(content);
+        if has_match {
+            marked.push_str(&marker);
+            shifts.push((end, marker.len()));
+        }
+        marked.push_str(&line[content.len()..]);
+        offset += line.len();
+    }
+
+    let shift = |position: usize| -> usize {
+        position + shifts.iter().filter(|(at, _)| *at < position).map(|(_, len)| len).sum::<usize>()
+    };
+    let shifted = matches.iter().map(|range| shift(range.start)..shift(range.end)).collect();
+    (marked, shifted)
+}
+
+// Cut a text down to the lines with matches plus `context` lines on either side, for
+// `--only-matches`. Each line is prefixed with its number, followed by `:` on matching lines and
+// `-` on context lines as in grep, and `--` separates runs of lines that aren't adjacent. The
+// match ranges are shifted to the excerpt.
+pub fn excerpt(text: &str, matches: &[Range<usize>], context: usize) -> (String, Vec<Range<usize>>) {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some(start)
+        })
+        .collect();
+    let line_of = |position: usize| starts.partition_point(|&start| start <= position).saturating_sub(1);
+
+    let mut kept = vec![false; lines.len()];
+    let mut matching = vec![false; lines.len()];
+    for range in matches {
+        let (first, last) = (line_of(range.start), line_of(range.end - 1));
+        matching[first..=last].iter_mut().for_each(|line| *line = true);
+        let end = (last + context).min(lines.len() - 1);
+        kept[first.saturating_sub(context)..=end].iter_mut().for_each(|line| *line = true);
+    }
+
+    let width = lines.len().to_string().len();
+    let mut excerpt = String::new();
+    // Where each kept line's text starts in the excerpt, after its number
+    let mut moved = vec![0; lines.len()];
+    let mut previous = None;
+    for (index, line) in lines.iter().enumerate().filter(|(index, _)| kept[*index]) {
+        if previous.is_some_and(|previous| previous + 1 != index) {
+            excerpt.push_str("--\n");
+        }
+        let separator = if matching[index] { ':' } else { '-' };
+        excerpt.push_str(&format!("{:>width$}{} ", index + 1, separator, width = width));
+        moved[index] = excerpt.len();
+        excerpt.push_str(line);
+        previous = Some(index);
+    }
+    if !excerpt.ends_with('\n') {
+        excerpt.push('\n');
+    }
+
+    let shift = |position: usize| {
+        let line = line_of(position);
+        moved[line] + position - starts[line]
+    };
+    let shifted = matches.iter().map(|range| shift(range.start)..shift(range.end - 1) + 1).collect();
+    (excerpt, shifted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_non_empty_matches() {
+        let regex = Regex::new("(?i)retry").unwrap();
+        assert_eq!(find_matches(&regex, "Retry once\nno\nretry"), [0..5, 14..19]);
+        assert!(find_matches(&Regex::new("^").unwrap(), "a").is_empty());
+    }
+
+    #[test]
+    fn markers_follow_matching_lines_and_ranges_shift() {
+        let text = "let a = retry();\nlet b = 1;\nretry(); retry();\n";
+        let regex = Regex::new("retry").unwrap();
+        let (marked, ranges) = add_markers(text, &find_matches(&regex, text), "//");
+        assert_eq!(marked, "let a = retry(); // MATCH\nlet b = 1;\nretry(); retry(); // MATCH\n");
+        assert_eq!(ranges.len(), 3);
+        assert!(ranges.iter().all(|range| &marked[range.clone()] == "retry"));
+    }
+
+    #[test]
+    fn excerpts_keep_numbered_matches_and_context() {
+        let text = (1..=12).map(|n| if n == 3 || n == 10 { format!("retry {}\n", n) } else { format!("line {}\n", n) }).collect::<String>();
+        let regex = Regex::new("retry").unwrap();
+        let (excerpt, ranges) = excerpt(&text, &find_matches(&regex, &text), 1);
+        assert_eq!(excerpt, " 2- line 2\n 3: retry 3\n 4- line 4\n--\n 9- line 9\n10: retry 10\n11- line 11\n");
+        assert!(ranges.iter().all(|range| &excerpt[range.clone()] == "retry"));
+        let (whole, _) = super::excerpt("a\nretry", &find_matches(&regex, "a\nretry"), 5);
+        assert_eq!(whole, "1- a\n2: retry\n");
+    }
+}
@@ -0,0 +1,68 @@
+use crate::manifest::Manifest;
+use anyhow::{bail, Context, Result};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Run the `--on-exceed` command after a guard was tripped, with the manifest path appended to
+// its arguments and the reasons in CONTREE_EXCEEDED (one per line). Without --manifest, the
+// manifest goes to a temporary file that lives until the command exits. A failing command fails
+// the run, so CI can turn a truncated context into an error.
+pub fn on_exceed(command_line: &str, manifest: &Manifest, manifest_path: Option<&Path>) -> Result<()> {
+    let reasons = manifest.exceeded();
+    if reasons.is_empty() {
+        return Ok(());
+    }
+    let argv = shlex::split(command_line)
+        .filter(|argv| !argv.is_empty())
+        .with_context(|| format!("Invalid --on-exceed command (empty or unbalanced quotes): {}", command_line))?;
+    let temporary = match manifest_path {
+        Some(_) => None,
+        None => {
+            let file = tempfile::Builder::new().prefix("contree-manifest-").suffix(".json").tempfile()?;
+            manifest.write(file.path())?;
+            Some(file)
+        }
+    };
+    let manifest_path = manifest_path.or(temporary.as_ref().map(|file| file.path())).expect("manifest path");
+
+    // The context may be on stdout, so the command's stdout is passed on to stderr instead
+    let output = Command::new(&argv[0])
+        .args(&argv[1..])
+        .arg(manifest_path)
+        .env("CONTREE_EXCEEDED", reasons.join("\n"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run --on-exceed command: {}", argv[0]))?;
+    io::stderr().write_all(&output.stdout)?;
+    if !output.status.success() {
+        bail!("--on-exceed command failed ({}): {}", output.status, command_line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_only_when_a_guard_was_tripped() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let command = format!("sh -c 'printf \"%s|%s\" \"$CONTREE_EXCEEDED\" \"$1\" > {}' hook", marker.display());
+        let mut manifest = Manifest::new();
+        on_exceed(&command, &manifest, None).unwrap();
+        assert!(!marker.exists());
+
+        manifest.walk_stopped = Some("--max-files 3 was reached".to_string());
+        let manifest_path = dir.path().join("manifest.json");
+        on_exceed(&command, &manifest, Some(&manifest_path)).unwrap();
+        let seen = std::fs::read_to_string(&marker).unwrap();
+        assert_eq!(seen, format!("--max-files 3 was reached|{}", manifest_path.display()));
+
+        assert!(on_exceed("sh -c 'exit 2'", &manifest, None).is_err());
+    }
+}
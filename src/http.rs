@@ -0,0 +1,14 @@
+use anyhow::anyhow;
+
+pub const USER_AGENT: &str = concat!("contree/", env!("CARGO_PKG_VERSION"));
+
+// Include the response body in HTTP status errors, since services put the useful detail there
+pub fn describe_error(err: ureq::Error) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            anyhow!("HTTP {}: {}", code, body.trim())
+        }
+        other => anyhow::Error::from(other),
+    }
+}
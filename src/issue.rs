@@ -0,0 +1,190 @@
+use crate::http;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+// Comments shown after the issue body, the latest ones
+const RECENT_COMMENTS: usize = 5;
+
+// The largest page the API serves
+const PAGE_SIZE: u64 = 100;
+
+// A GitHub issue or pull request
+#[derive(Debug, PartialEq, Eq)]
+pub struct IssueRef {
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+// `https://github.com/o/r/issues/12` (or `/pull/12`), `o/r#12`, or `#12` and `12`, which are in
+// the repository of the `origin` remote
+pub fn parse_reference(reference: &str, cwd: &Path) -> Result<IssueRef> {
+    let reference = reference.trim();
+    let invalid = || anyhow!("--issue takes a GitHub issue URL, owner/repo#number, or a number: {}", reference);
+    if let Some(path) = reference.strip_prefix("https://github.com/").or_else(|| reference.strip_prefix("http://github.com/")) {
+        let path = path.split(['#', '?']).next().unwrap_or_default();
+        let parts: Vec<&str> = path.split('/').collect();
+        return match parts[..] {
+            [owner, repo, "issues" | "pull", number, ..] => Ok(IssueRef {
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                number: number.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        };
+    }
+    let (repository, number) = reference.rsplit_once('#').unwrap_or(("", reference));
+    let number = number.parse().map_err(|_| invalid())?;
+    let (owner, repo) = match repository.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() => (owner.to_string(), repo.to_string()),
+        _ if repository.is_empty() => origin_repository(cwd)?,
+        _ => return Err(invalid()),
+    };
+    Ok(IssueRef { owner, repo, number })
+}
+
+// The GitHub repository `origin` points at
+fn origin_repository(cwd: &Path) -> Result<(String, String)> {
+    let output = Command::new("git")
+        .args(["remote", "get-url", "origin"])
+        .current_dir(cwd)
+        .output()
+        .context("Failed to run git to find the repository of --issue")?;
+    let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || url.is_empty() {
+        bail!("--issue with only a number needs a git remote named origin; give owner/repo#number instead");
+    }
+    parse_remote(&url).ok_or_else(|| anyhow!("The origin remote {} is not on GitHub; give --issue a full issue URL", url))
+}
+
+// `git@github.com:o/r.git`, `https://github.com/o/r`, `ssh://git@github.com/o/r.git`
+fn parse_remote(url: &str) -> Option<(String, String)> {
+    let (_, path) = url.split_once("github.com")?;
+    let path = path.trim_start_matches([':', '/']).trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, repo) = path.split_once('/')?;
+    (!owner.is_empty() && !repo.is_empty() && !repo.contains('/')).then(|| (owner.to_string(), repo.to_string()))
+}
+
+// Fetch the issue and its latest comments, as the text of a "Problem Statement" section.
+// GITHUB_API_URL points at GitHub Enterprise; a token from GITHUB_TOKEN or GH_TOKEN reaches
+// private repositories.
+pub fn problem_statement(issue: &IssueRef) -> Result<String> {
+    let api = env::var("GITHUB_API_URL").unwrap_or_else(|_| "https://api.github.com".to_string());
+    let url = format!("{}/repos/{}/{}/issues/{}", api.trim_end_matches('/'), issue.owner, issue.repo, issue.number);
+    let body = get(&url)?;
+    let count = body["comments"].as_u64().unwrap_or(0);
+    let comments = if count == 0 {
+        Vec::new()
+    } else {
+        // Comments come oldest first, so the latest are on the last page
+        let page = (count - 1) / PAGE_SIZE + 1;
+        let comments = get(&format!("{}/comments?per_page={}&page={}", url, PAGE_SIZE, page))?;
+        comments.as_array().cloned().unwrap_or_default()
+    };
+    Ok(format_issue(issue, &body, &comments, count as usize))
+}
+
+fn get(url: &str) -> Result<Value> {
+    let mut request = ureq::get(url).set("User-Agent", http::USER_AGENT).set("Accept", "application/vnd.github+json");
+    if let Ok(token) = env::var("GITHUB_TOKEN").or_else(|_| env::var("GH_TOKEN")) {
+        request = request.set("Authorization", &format!("Bearer {}", token));
+    }
+    let response = request
+        .call()
+        .map_err(http::describe_error)
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    serde_json::from_reader(response.into_reader()).with_context(|| format!("GitHub returned an unexpected response for {}", url))
+}
+
+// The title and metadata, the body, then the latest comments. `count` is how many comments the
+// issue has in all.
+fn format_issue(issue: &IssueRef, body: &Value, comments: &[Value], count: usize) -> String {
+    // Bodies written on the web have Windows line endings
+    let text = |value: &Value| value.as_str().unwrap_or_default().trim().replace("\r\n", "\n");
+    let date = |value: &Value| value.as_str().and_then(|date| date.get(..10)).unwrap_or_default().to_string();
+    let kind = if body["pull_request"].is_object() { "Pull request" } else { "Issue" };
+    let mut lines = vec![format!("{} {}/{}#{}: {}", kind, issue.owner, issue.repo, issue.number, text(&body["title"]))];
+    let mut meta = vec![
+        text(&body["state"]),
+        format!("opened by {} on {}", text(&body["user"]["login"]), date(&body["created_at"])),
+    ];
+    let labels: Vec<String> = body["labels"].as_array().into_iter().flatten().map(|label| text(&label["name"])).collect();
+    if !labels.is_empty() {
+        meta.push(format!("labels: {}", labels.join(", ")));
+    }
+    lines.push(format!("State: {}", meta.join("; ")));
+    lines.push(format!("URL: {}", text(&body["html_url"])));
+    lines.push(String::new());
+    let description = text(&body["body"]);
+    lines.push(if description.is_empty() { "(no description)".to_string() } else { description });
+
+    let shown = &comments[comments.len().saturating_sub(RECENT_COMMENTS)..];
+    if count > shown.len() {
+        lines.push(String::new());
+        lines.push(format!("({} earlier comments not shown)", count - shown.len()));
+    }
+    for comment in shown {
+        lines.push(String::new());
+        lines.push(format!("Comment by {} on {}:", text(&comment["user"]["login"]), date(&comment["created_at"])));
+        lines.push(text(&comment["body"]));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn issue(owner: &str, repo: &str, number: u64) -> IssueRef {
+        IssueRef { owner: owner.to_string(), repo: repo.to_string(), number }
+    }
+
+    #[test]
+    fn parses_issue_references() {
+        let cwd = Path::new("/");
+        assert_eq!(parse_reference("https://github.com/o/r/issues/12#issuecomment-1", cwd).unwrap(), issue("o", "r", 12));
+        assert_eq!(parse_reference("https://github.com/o/r/pull/7/files", cwd).unwrap(), issue("o", "r", 7));
+        assert_eq!(parse_reference("o/r#3", cwd).unwrap(), issue("o", "r", 3));
+        assert!(parse_reference("https://github.com/o/r", cwd).is_err());
+        assert!(parse_reference("o/r#x", cwd).is_err());
+
+        assert_eq!(parse_remote("git@github.com:o/r.git"), Some(("o".to_string(), "r".to_string())));
+        assert_eq!(parse_remote("https://github.com/o/r/"), Some(("o".to_string(), "r".to_string())));
+        assert_eq!(parse_remote("https://gitlab.com/o/r.git"), None);
+    }
+
+    #[test]
+    fn formats_the_issue_and_its_latest_comments() {
+        let body = json!({
+            "title": "Retries never stop",
+            "state": "open",
+            "user": { "login": "ana" },
+            "created_at": "2026-01-02T10:00:00Z",
+            "labels": [{ "name": "bug" }],
+            "html_url": "https://github.com/o/r/issues/12",
+            "body": "Steps:\r\n1. run it\n",
+        });
+        let comments: Vec<Value> = (1..=6)
+            .map(|n| json!({ "user": { "login": "bo" }, "created_at": "2026-01-03T00:00:00Z", "body": format!("note {}", n) }))
+            .collect();
+        let text = format_issue(&issue("o", "r", 12), &body, &comments, 8);
+        assert!(
+            text.starts_with(
+                "Issue o/r#12: Retries never stop\n\
+                 State: open; opened by ana on 2026-01-02; labels: bug\n\
+                 URL: https://github.com/o/r/issues/12\n\n\
+                 Steps:\n1. run it\n\n\
+                 (3 earlier comments not shown)\n\n\
+                 Comment by bo on 2026-01-03:\nnote 2\n"
+            ),
+            "{}",
+            text
+        );
+        assert!(text.ends_with("note 6"));
+    }
+}
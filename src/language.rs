@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobMatcher};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+// The language name that excludes matching files instead of naming a language
+const SKIP: &str = "skip";
+
+// How a file is treated: as a language (fence tag, grammar, comment syntax) or skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Handler {
+    Language(String),
+    Skip,
+}
+
+// Maps files to languages: `[languages]` overrides from the config first, then built-in
+// detection by file name and extension, then the `#!` line of files without an extension.
+// Every module asks this one place, so fence tags, syntax-aware truncation, comment markers,
+// `--lang`, and exclusions always agree.
+#[derive(Debug, Default)]
+pub struct LanguageMap {
+    overrides: Vec<(String, GlobMatcher, Handler)>,
+}
+
+impl LanguageMap {
+    // Patterns without `/` match the file name; others match the path relative to the root.
+    // Exact names beat globs, and longer globs beat shorter ones, so `*.gen.rs` wins over `*.rs`.
+    pub fn new(overrides: &BTreeMap<String, String>) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (pattern, language) in overrides {
+            let matcher = Glob::new(pattern)
+                .with_context(|| format!("Invalid [languages] pattern '{}'", pattern))?
+                .compile_matcher();
+            let handler = match language.trim() {
+                SKIP => Handler::Skip,
+                other => Handler::Language(other.to_lowercase()),
+            };
+            rules.push((pattern.clone(), matcher, handler));
+        }
+        let is_glob = |pattern: &str| pattern.contains(['*', '?', '[', '{']);
+        rules.sort_by_key(|(pattern, _, _)| (is_glob(pattern), std::cmp::Reverse(pattern.len())));
+        Ok(LanguageMap { overrides: rules })
+    }
+
+    pub fn handler(&self, path: &Path, root: &Path) -> Option<Handler> {
+        if let Some((_, handler)) = self.matching_override(path, root) {
+            return Some(handler.clone());
+        }
+        default_language(path).map(|language| Handler::Language(language.to_string()))
+    }
+
+    fn matching_override(&self, path: &Path, root: &Path) -> Option<(&str, &Handler)> {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let file_name = Path::new(path.file_name().unwrap_or_default());
+        self.overrides.iter().find_map(|(pattern, matcher, handler)| {
+            let candidate = if pattern.contains('/') { relative } else { file_name };
+            matcher.is_match(candidate).then_some((pattern.as_str(), handler))
+        })
+    }
+
+    // `file` is where a `#!` line is read from, since `path` can be a [layout] virtual path
+    pub fn language(&self, path: &Path, root: &Path, file: &Path) -> Option<String> {
+        match self.handler(path, root) {
+            Some(Handler::Language(language)) => Some(language),
+            Some(Handler::Skip) => None,
+            None if file.extension().is_none() => shebang_language(file).map(str::to_string),
+            None => None,
+        }
+    }
+
+    // Whether `--lang` can name this language: a built-in one, or one from `[languages]`
+    pub fn knows(&self, language: &str) -> bool {
+        LANGUAGES.contains(&language)
+            || self.overrides.iter().any(|(_, _, handler)| *handler == Handler::Language(language.to_string()))
+    }
+
+    pub fn is_skipped(&self, path: &Path, root: &Path) -> bool {
+        self.handler(path, root) == Some(Handler::Skip)
+    }
+
+    // The `[languages]` pattern that skips a file, if any
+    pub fn skip_pattern(&self, path: &Path, root: &Path) -> Option<&str> {
+        match self.matching_override(path, root)? {
+            (pattern, Handler::Skip) => Some(pattern),
+            _ => None,
+        }
+    }
+}
+
+// Every name built-in detection gives
+pub const LANGUAGES: &[&str] = &[
+    "bash", "c", "cmake", "cpp", "csharp", "css", "dockerfile", "go", "html", "java", "javascript", "json", "kotlin",
+    "lua", "make", "markdown", "perl", "php", "protobuf", "python", "ruby", "rust", "scala", "scss", "sql", "swift",
+    "toml", "tsx", "typescript", "xml", "yaml",
+];
+
+// Built-in detection; names double as Markdown fence tags
+pub fn default_language(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+    let by_name = match file_name {
+        "Makefile" | "GNUmakefile" | "makefile" => Some("make"),
+        "Dockerfile" | "Containerfile" => Some("dockerfile"),
+        "CMakeLists.txt" => Some("cmake"),
+        "Cargo.lock" => Some("toml"),
+        _ => None,
+    };
+    if by_name.is_some() {
+        return by_name;
+    }
+
+    let language = match path.extension()?.to_str()? {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "jsx" | "mjs" | "cjs" => "javascript",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "cpp",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "rb" => "ruby",
+        "pl" | "pm" => "perl",
+        "php" => "php",
+        "cs" => "csharp",
+        "scala" => "scala",
+        "lua" => "lua",
+        "sql" => "sql",
+        "sh" | "bash" | "zsh" => "bash",
+        "toml" => "toml",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "md" | "markdown" => "markdown",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "xml" => "xml",
+        "proto" => "protobuf",
+        "mk" => "make",
+        _ => return None,
+    };
+    Some(language)
+}
+
+// The language of a script from its `#!` line: `#!/bin/sh`, `#!/usr/bin/env -S python3 -u`
+fn shebang_language(file: &Path) -> Option<&'static str> {
+    let mut line = String::new();
+    BufReader::new(File::open(file).ok()?.take(256)).read_line(&mut line).ok()?;
+    interpreter_language(&line)
+}
+
+fn interpreter_language(line: &str) -> Option<&'static str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    // `python3.12` -> `python`
+    let language = match program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "sh" | "bash" | "zsh" | "dash" | "ksh" => "bash",
+        "python" | "pypy" => "python",
+        "node" | "nodejs" | "bun" => "javascript",
+        "deno" | "ts-node" | "tsx" => "typescript",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        "php" => "php",
+        "lua" | "luajit" => "lua",
+        "make" => "make",
+        _ => return None,
+    };
+    Some(language)
+}
+
+// Line-comment syntax used for omission markers in truncated files
+pub fn comment_prefix(language: &str) -> Option<&'static str> {
+    let prefix = match language {
+        "rust" | "javascript" | "typescript" | "tsx" | "go" | "c" | "cpp" | "java" | "kotlin"
+        | "swift" | "php" | "csharp" | "scala" | "scss" | "protobuf" => "//",
+        "python" | "ruby" | "perl" | "bash" | "toml" | "yaml" | "make" | "dockerfile" | "cmake" => "#",
+        "lua" | "sql" => "--",
+        _ => return None,
+    };
+    Some(prefix)
+}
+
+// Block-comment delimiters, for telling comment lines from code in line statistics
+pub fn block_comment(language: &str) -> Option<(&'static str, &'static str)> {
+    let delimiters = match language {
+        "rust" | "javascript" | "typescript" | "tsx" | "go" | "c" | "cpp" | "java" | "kotlin"
+        | "swift" | "php" | "csharp" | "scala" | "css" | "scss" | "protobuf" | "sql" => ("/*", "*/"),
+        "html" | "xml" | "markdown" => ("<!--", "-->"),
+        "lua" => ("--[[", "]]"),
+        _ => return None,
+    };
+    Some(delimiters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_interpreter_from_shebangs() {
+        assert_eq!(interpreter_language("#!/bin/sh\n"), Some("bash"));
+        assert_eq!(interpreter_language("#!/usr/bin/env -S python3.12 -u\n"), Some("python"));
+        assert_eq!(interpreter_language("#! /usr/local/bin/node"), Some("javascript"));
+        assert_eq!(interpreter_language("#!/usr/bin/env awk -f"), None);
+        assert_eq!(interpreter_language("echo hi"), None);
+        for name in ["bash", "python", "javascript", "typescript", "ruby", "perl", "php", "lua", "make"] {
+            assert!(LANGUAGES.contains(&name), "{}", name);
+        }
+    }
+}
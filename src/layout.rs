@@ -0,0 +1,154 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// One entry of `[layout]`: a real directory shown under a name in the virtual tree
+#[derive(Debug, Clone)]
+struct Mount {
+    name: PathBuf,
+    dir: PathBuf,
+}
+
+// A virtual tree assembled from several real directories, from `[layout]` in .contree.toml,
+// e.g. `backend = "~/code/api"`. With a layout, the walk covers the mapped directories instead
+// of the scanned one, and paths are shown (and pathspecs and `[languages]` patterns matched)
+// as `backend/src/main.rs` rather than as absolute paths into unrelated checkouts.
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    mounts: Vec<Mount>,
+}
+
+impl Layout {
+    // Directories may start with `~/` or be relative to `base`, the directory of .contree.toml;
+    // they are canonicalized so paths in command output line up with the walked ones
+    pub fn new(entries: &BTreeMap<String, String>, base: &Path) -> Result<Self> {
+        let mut mounts: Vec<Mount> = Vec::new();
+        for (name, dir) in entries {
+            let virtual_name = Path::new(name.trim_end_matches('/'));
+            let is_plain = virtual_name.components().count() > 0
+                && virtual_name.components().all(|component| matches!(component, Component::Normal(_)));
+            if !is_plain {
+                bail!("Invalid [layout] name '{}': use a relative path such as 'backend'", name);
+            }
+            if mounts.iter().any(|mount| mount.name == virtual_name) {
+                bail!("[layout] maps '{}' twice", virtual_name.display());
+            }
+            let real = fs::canonicalize(base.join(expand_home(dir)))
+                .with_context(|| format!("[layout] '{}' maps to {}, which can't be read", name, dir))?;
+            if !real.is_dir() {
+                bail!("[layout] '{}' maps to {}, which is not a directory", name, dir);
+            }
+            mounts.push(Mount { name: virtual_name.to_path_buf(), dir: real });
+        }
+        Ok(Layout { mounts })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mounts.is_empty()
+    }
+
+    // Where the walk starts for a pathspec's leading directory (`base`, relative to the
+    // virtual tree): the directory it names, or every mount below it
+    pub fn walk_roots(&self, root: &Path, base: &Path) -> Vec<PathBuf> {
+        if self.is_empty() {
+            return vec![root.join(base)];
+        }
+        if let Some(real) = self.real_path(base) {
+            return vec![real];
+        }
+        self.mounts
+            .iter()
+            .filter(|mount| mount.name.starts_with(base))
+            .map(|mount| mount.dir.clone())
+            .collect()
+    }
+
+    // A path in the virtual tree as the real path it maps to; the deepest mount wins
+    fn real_path(&self, relative: &Path) -> Option<PathBuf> {
+        self.mounts
+            .iter()
+            .filter_map(|mount| relative.strip_prefix(&mount.name).ok().map(|rest| (mount, rest)))
+            .max_by_key(|(mount, _)| mount.name.components().count())
+            .map(|(mount, rest)| mount.dir.join(rest))
+    }
+
+    // A real path as its path in the virtual tree, if it lies in a mapped directory
+    pub fn virtual_path(&self, path: &Path) -> Option<PathBuf> {
+        self.mounts
+            .iter()
+            .filter_map(|mount| path.strip_prefix(&mount.dir).ok().map(|rest| (mount, rest)))
+            .max_by_key(|(mount, _)| mount.dir.components().count())
+            .map(|(mount, rest)| mount.name.join(rest))
+    }
+
+    // A walked path relative to the tree pathspecs and `[languages]` patterns are matched against
+    pub fn relative(&self, path: &Path, root: &Path) -> PathBuf {
+        self.virtual_path(path)
+            .unwrap_or_else(|| path.strip_prefix(root).unwrap_or(path).to_path_buf())
+    }
+
+    // Replace the mapped directories in free text (command output, reasons) with their names
+    pub fn rewrite_text(&self, text: &str) -> Option<String> {
+        let mut mounts: Vec<&Mount> = self.mounts.iter().collect();
+        // Deeper directories first, so a nested mount isn't rewritten by its parent's prefix
+        mounts.sort_by_key(|mount| std::cmp::Reverse(mount.dir.components().count()));
+        let mut rewritten: Option<String> = None;
+        for mount in mounts {
+            let prefix = format!("{}/", mount.dir.display());
+            let current = rewritten.as_deref().unwrap_or(text);
+            if current.contains(&prefix) {
+                rewritten = Some(current.replace(&prefix, &format!("{}/", mount.name.display())));
+            }
+        }
+        rewritten
+    }
+}
+
+fn expand_home(dir: &str) -> PathBuf {
+    match (dir.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(dir),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(root: &Path, entries: &[(&str, &str)]) -> Result<Layout> {
+        let entries = entries.iter().map(|(name, dir)| (name.to_string(), dir.to_string())).collect();
+        Layout::new(&entries, root)
+    }
+
+    #[test]
+    fn maps_between_real_and_virtual_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("api/src")).unwrap();
+        fs::create_dir_all(root.join("web")).unwrap();
+        let layout = layout(&root, &[("backend/", "api"), ("apps/frontend", "web")]).unwrap();
+
+        let api = root.join("api");
+        assert_eq!(layout.virtual_path(&api.join("src/main.rs")), Some(PathBuf::from("backend/src/main.rs")));
+        assert_eq!(layout.virtual_path(&root.join("elsewhere.rs")), None);
+        assert_eq!(layout.walk_roots(&root, Path::new("backend/src")), [api.join("src")]);
+        assert_eq!(layout.walk_roots(&root, Path::new("apps")), [root.join("web")]);
+        assert_eq!(layout.walk_roots(&root, Path::new("")).len(), 2);
+        assert!(layout.walk_roots(&root, Path::new("docs")).is_empty());
+
+        let output = format!("error at {}/src/main.rs:3", api.display());
+        assert_eq!(layout.rewrite_text(&output).as_deref(), Some("error at backend/src/main.rs:3"));
+        assert_eq!(layout.rewrite_text("nothing to see"), None);
+    }
+
+    #[test]
+    fn rejects_bad_names_and_missing_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(layout(dir.path(), &[("../up", ".")]).is_err());
+        assert!(layout(dir.path(), &[("/abs", ".")]).is_err());
+        assert!(layout(dir.path(), &[("backend", "missing")]).is_err());
+        assert!(layout(dir.path(), &[("backend", ".")]).is_ok());
+    }
+}
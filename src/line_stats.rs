@@ -0,0 +1,81 @@
+use crate::language;
+use serde::Serialize;
+
+// Lines of a file split the way tokei counts them: blank lines, lines holding only comments,
+// and lines with any code (including code followed by a comment)
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct LineCounts {
+    pub code: usize,
+    pub comment: usize,
+    pub blank: usize,
+}
+
+// Classify each line using the language's comment syntax; without a known syntax every
+// non-blank line is code. Comment markers inside string literals aren't recognized.
+pub fn count(language: Option<&str>, text: &str) -> LineCounts {
+    let line_comment = language.and_then(language::comment_prefix);
+    let block_comment = language.and_then(language::block_comment);
+    let mut counts = LineCounts::default();
+    let mut in_block = false;
+    for line in text.lines() {
+        let mut rest = line.trim();
+        if rest.is_empty() && !in_block {
+            counts.blank += 1;
+            continue;
+        }
+        let mut has_code = false;
+        loop {
+            if in_block {
+                let Some((_, end)) = block_comment else { break };
+                match rest.find(end) {
+                    Some(i) => {
+                        rest = rest[i + end.len()..].trim_start();
+                        in_block = false;
+                    }
+                    None => break,
+                }
+            }
+            // Block comments first: Lua's `--[[` starts with its line comment `--`
+            if let Some((start, _)) = block_comment.filter(|(start, _)| rest.starts_with(start)) {
+                rest = &rest[start.len()..];
+                in_block = true;
+                continue;
+            }
+            if rest.is_empty() || line_comment.is_some_and(|prefix| rest.starts_with(prefix)) {
+                break;
+            }
+            has_code = true;
+            // Code up to a block comment that opens later on the line
+            match block_comment.and_then(|(start, _)| rest.find(start).map(|i| (i, start))) {
+                Some((i, start)) => {
+                    rest = &rest[i + start.len()..];
+                    in_block = true;
+                }
+                None => break,
+            }
+        }
+        if has_code {
+            counts.code += 1;
+        } else if line.trim().is_empty() {
+            counts.blank += 1;
+        } else {
+            counts.comment += 1;
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_code_comments_and_blanks() {
+        let rust = "// header\nfn main() {\n\n    /* block\n       still comment */\n    let a = 1; // trailing\n    /* x */ let b = 2;\n}\n";
+        assert_eq!(count(Some("rust"), rust), LineCounts { code: 4, comment: 3, blank: 1 });
+        assert_eq!(count(Some("lua"), "--[[ a\nb ]]\nx = 1\n"), LineCounts { code: 1, comment: 2, blank: 0 });
+        let python = "# comment\nimport os\n\n";
+        assert_eq!(count(Some("python"), python), LineCounts { code: 1, comment: 1, blank: 1 });
+        assert_eq!(count(None, "a\n# b\n"), LineCounts { code: 2, comment: 0, blank: 0 });
+    }
+}
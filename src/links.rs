@@ -0,0 +1,70 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Files already written to the context. Symlinks are caught by their canonical path; hard
+// links (pnpm stores, nix-style layouts) share no path, so files with more than one link are
+// also tracked by device and inode.
+#[derive(Default)]
+pub struct EmittedFiles {
+    paths: HashSet<PathBuf>,
+    inodes: HashMap<(u64, u64), PathBuf>,
+}
+
+pub enum Seen {
+    New,
+    // Same path as an emitted file, possibly through a symlink
+    Duplicate,
+    // Another name for the content of this emitted file
+    HardLink(PathBuf),
+}
+
+impl EmittedFiles {
+    pub fn check(&mut self, path: &Path) -> io::Result<Seen> {
+        if !self.paths.insert(fs::canonicalize(path)?) {
+            return Ok(Seen::Duplicate);
+        }
+        if let Some(inode) = shared_inode(path)? {
+            if let Some(first) = self.inodes.get(&inode) {
+                return Ok(Seen::HardLink(first.clone()));
+            }
+            self.inodes.insert(inode, path.to_path_buf());
+        }
+        Ok(Seen::New)
+    }
+}
+
+// Device and inode of a file with several hard links
+#[cfg(unix)]
+fn shared_inode(path: &Path) -> io::Result<Option<(u64, u64)>> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino())))
+}
+
+#[cfg(not(unix))]
+fn shared_inode(_path: &Path) -> io::Result<Option<(u64, u64)>> {
+    Ok(None)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_links_and_symlinks_are_recognized() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first");
+        fs::write(&first, "content").unwrap();
+        fs::hard_link(&first, dir.path().join("second")).unwrap();
+        std::os::unix::fs::symlink(&first, dir.path().join("alias")).unwrap();
+        fs::write(dir.path().join("other"), "content").unwrap();
+
+        let mut emitted = EmittedFiles::default();
+        assert!(matches!(emitted.check(&first).unwrap(), Seen::New));
+        assert!(matches!(emitted.check(&dir.path().join("alias")).unwrap(), Seen::Duplicate));
+        assert!(matches!(emitted.check(&dir.path().join("second")).unwrap(), Seen::HardLink(p) if p == first));
+        assert!(matches!(emitted.check(&dir.path().join("other")).unwrap(), Seen::New));
+    }
+}
@@ -1,217 +1,2092 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use ignore::WalkBuilder;
+use clap::{CommandFactory, Parser, Subcommand};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::process::Command;
+use std::time::{Duration, Instant};
+use manifest::{Manifest, ManifestDependency, ManifestFile, ManifestTokens};
+use timings::Phase;
 use walkdir::WalkDir;
 use atty::Stream;
 
+mod alias;
+mod answer;
+mod attributes;
+mod binary;
+mod capabilities;
+mod capture;
+mod cfg;
+mod changes;
+mod check_ignore;
+mod clipboard;
+mod clock;
+mod compress;
+mod config;
+mod content;
+mod defaults;
+mod depcache;
+mod digest;
+mod docs_rs;
+mod editor;
+mod explain;
+mod encrypt;
+mod extract;
+mod fileio;
+mod fingerprint;
+mod hgignore;
+mod highlight;
+mod hook;
+mod http;
+mod issue;
+mod language;
+mod layout;
+mod line_stats;
+mod links;
+mod lint;
+mod manifest;
+mod merge;
+mod metadata;
+mod minify;
+mod pathspec;
+mod project;
+mod public_api;
+mod rank;
+mod remote;
+mod render;
+mod session;
+mod sets;
+mod sanitizer;
+mod schema;
+mod self_update;
+mod sign;
+mod spill;
+mod split;
+mod store;
+#[cfg(feature = "syntax")]
+mod syntax;
+mod test_summary;
+mod timings;
+mod tokens;
+mod tree;
+mod truncate;
+mod upload;
+mod vcs;
+mod warnings;
+
 /// A utility to provide context for projects after running commands
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+// A repeated option replaces the earlier value, so the command line overrides `[defaults]`
+#[command(author, version, about, long_about = None, args_override_self = true)]
 struct Args {
     /// Directory to scan (defaults to current working directory)
     #[arg(short, long)]
     dir: Option<PathBuf>,
 
+    /// Apply a named preset of options from [profiles.<NAME>] in .contree.toml or the global config
+    /// ($XDG_CONFIG_HOME/contree/config.toml); options on the command line override it
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Limit the scan to these files or directories, relative to the scanned directory (ignore rules still apply).
+    /// Supports git pathspec magic: ':(exclude)vendor' or ':!vendor', ':(glob)src/**/*.rs', ':(icase)readme*'.
+    /// Put paths named like a subcommand after `--` (e.g. `contree -g retry -- sessions`)
+    #[arg(value_name = "PATHSPEC", value_parser = pathspec::Pathspec::parse)]
+    paths: Vec<pathspec::Pathspec>,
+
+    /// Limit the scan to paths matching this glob, relative to the scanned directory (e.g. 'src/**/*.rs');
+    /// `*` stops at `/` and `**` spans directories. Repeatable; a file matching any glob or path is kept
+    #[arg(long, value_name = "GLOB", value_parser = pathspec::Pathspec::parse_glob)]
+    include_glob: Vec<pathspec::Pathspec>,
+
+    /// Include the project's manifests (Cargo.toml, package.json, tsconfig.json, pyproject.toml, go.mod, ...) even when --grep or pathspecs leave them out
+    #[arg(long)]
+    include_manifests: bool,
+
     /// Include dependency files referenced in errors (Rust projects only)
     #[arg(short = 'D', long)]
     include_deps: bool,
 
-    /// Output file for project and dependency files
+    /// Reduce dependency files to their public API: private items dropped, function bodies elided
+    #[arg(long)]
+    deps_public_only: bool,
+
+    /// Annotate each dependency file with the docs.rs URL of the module it defines
+    #[arg(long)]
+    docs_links: bool,
+
+    /// Add an "Error Code Reference" section with `rustc --explain` text for error codes in the output
+    #[arg(long)]
+    explain_errors: bool,
+
+    /// Cargo home holding the registry used by --include-deps (defaults to $CARGO_HOME, then ~/.cargo)
+    #[arg(long, value_name = "DIR", global = true)]
+    cargo_home: Option<PathBuf>,
+
+    /// Output format: plain text sections, a self-contained HTML report, JSON, Markdown, XML, or a tar archive of the files
+    #[arg(long, value_enum, default_value = "text")]
+    format: render::Format,
+
+    /// End the context with instructions on how to structure the answer: a unified diff, whole files, or JSON
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    answer_format: Option<answer::AnswerFormat>,
+
+    /// Output file for project and dependency files (or s3://bucket/key, gs://bucket/key)
     #[arg(short = 'o', long)]
     output: Option<PathBuf>,
 
-    /// Grep pattern to filter files (e.g., 'transaction' or '/regex/')
+    /// Grep pattern to filter files (e.g., 'transaction' or '/regex/'); repeat for several, combined by --grep-mode
     #[arg(short = 'g', long)]
-    grep: Option<String>,
+    grep: Vec<String>,
+
+    /// With several --grep patterns, keep files matching any of them or all of them
+    #[arg(long, value_enum, value_name = "MODE", default_value = "any", requires = "grep")]
+    grep_mode: content::GrepMode,
+
+    /// Match grep patterns case-sensitively, both literal and '/regex/' ones (by default only regexes are)
+    #[arg(long, conflicts_with = "smart_case")]
+    case_sensitive: bool,
+
+    /// Ignore case in grep patterns, literal or '/regex/', unless the pattern has an uppercase letter
+    #[arg(long)]
+    smart_case: bool,
+
+    /// Leave out files whose contents match this pattern (e.g., 'generated by protoc' or '/regex/'), alone or with --grep
+    #[arg(long, value_name = "PATTERN")]
+    grep_not: Option<String>,
+
+    /// What --grep matches: file contents, paths relative to the scanned directory, or either
+    #[arg(long, value_enum, value_name = "TARGET", default_value = "content", requires = "grep")]
+    grep_target: content::GrepTarget,
+
+    /// With --grep, append a `// MATCH` comment (in the file's comment syntax) to each matching line of text, Markdown, or XML output
+    #[arg(long, requires = "grep")]
+    mark_matches: bool,
+
+    /// When to highlight --grep matches with ANSI colors in text output: on a terminal (auto), always, or never
+    #[arg(long, value_enum, value_name = "WHEN", default_value = "auto")]
+    color: render::ColorMode,
+
+    /// With --grep, emit only each file's matching lines, numbered, with --context lines around them
+    #[arg(long, requires = "grep")]
+    only_matches: bool,
+
+    /// Lines of context around each match with --only-matches
+    #[arg(long, value_name = "N", default_value_t = 2, requires = "only_matches")]
+    context: usize,
+
+    /// Mark each project file's header as added, modified, or unchanged since this revision of the git, jj, or hg repository (e.g. 'main', 'HEAD~3', or '@-')
+    #[arg(long, value_name = "REF")]
+    annotate_changes: Option<String>,
+
+    /// Only include files added or modified since this revision, uncommitted edits included; without
+    /// a revision, since the current branch forked from main (or HEAD on main itself)
+    #[arg(long, value_name = "REF", num_args = 0..=1, default_missing_value = "")]
+    changed_since: Option<String>,
+
+    /// Only include files with changes staged in git's index; combine with --unstaged and
+    /// --untracked to include any of them
+    #[arg(long)]
+    staged: bool,
+
+    /// Only include files with changes in the git working tree that aren't staged
+    #[arg(long)]
+    unstaged: bool,
+
+    /// Only include files git doesn't track yet (ignored files stay out)
+    #[arg(long)]
+    untracked: bool,
+
+    /// With --annotate-changes, add each changed file's lines added and removed (`modified (+12 -3)`)
+    #[arg(long, requires = "annotate_changes")]
+    diffstat: bool,
+
+    /// Only include files with these extensions (comma-separated, e.g. 'rs,toml,md')
+    #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+    ext: Vec<String>,
+
+    /// Leave out files with these extensions (comma-separated, e.g. 'png,lock')
+    #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+    no_ext: Vec<String>,
+
+    /// Leave out tests: test directories (tests/, __tests__/, ...) and files like *_test.rs, *.spec.ts, test_*.py
+    #[arg(long, conflicts_with = "tests_only")]
+    no_tests: bool,
 
-    /// List of files to include (comma-separated), even if they don't match grep or are outside the directory
+    /// Only include tests, as recognized by --no-tests
+    #[arg(long)]
+    tests_only: bool,
+
+    /// Keep files .gitattributes marks linguist-generated or linguist-vendored, which are left out by default
+    #[arg(long)]
+    include_generated: bool,
+
+    /// What to do with git submodules: scan them like the rest of the project, skip them, or note each with its pinned commit
+    #[arg(long, value_enum, value_name = "MODE", default_value = "include")]
+    submodules: vcs::SubmoduleMode,
+
+    /// Only include files in these languages (comma-separated, e.g. 'rust,typescript')
+    #[arg(long, value_name = "LANGS", value_delimiter = ',')]
+    lang: Vec<String>,
+
+    /// Leave out paths matching this glob, in .gitignore syntax (e.g. 'target/**' or '*.snap'); repeatable
+    #[arg(short = 'e', long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Keep only files in this named set from [sets] in .contree.toml (repeat to combine sets)
+    #[arg(long, value_name = "NAME")]
+    set: Vec<String>,
+
+    /// List of files to include (comma-separated), even if they don't match grep or are outside the directory;
+    /// http(s) URLs are fetched and included as documents
     #[arg(short = 'i', long, value_delimiter = ',', value_parser = parse_pathbuf)]
     include: Option<Vec<PathBuf>>,
+
+    /// Read at most this much (e.g. '200kb') of each --include URL, cutting the rest
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "1MiB")]
+    max_url_size: usize,
+
+    /// Convert HTML pages fetched for --include URLs to plain text
+    #[arg(long)]
+    html_to_text: bool,
+
+    /// Upload the generated context and print its URL ('gist' or a paste endpoint URL)
+    #[arg(long, value_name = "TARGET")]
+    upload: Option<String>,
+
+    /// With --upload to a paste endpoint, send the context in resumable chunks of at most this size (e.g. 1M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "upload")]
+    upload_chunk_size: Option<usize>,
+
+    /// With --upload, send at most this many requests per minute
+    #[arg(long, value_name = "REQUESTS", requires = "upload")]
+    upload_rate_limit: Option<u32>,
+
+    /// Copy the context to the clipboard instead of printing it (and as well as writing --output)
+    #[arg(long)]
+    clipboard: bool,
+
+    /// Compress the context; implied by an --output ending in .gz or .zst
+    #[arg(long, value_enum, value_name = "FORMAT", conflicts_with_all = ["upload", "clipboard", "split_tokens", "split_output"])]
+    compress: Option<compress::Compression>,
+
+    /// With --clipboard, copy a larger context in parts of at most this size, pressing Enter between them
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value_t = clipboard::DEFAULT_CHUNK_SIZE, requires = "clipboard")]
+    clipboard_chunk_size: usize,
+
+    /// Run this command line and capture its stdout and stderr, recording its exit status and duration;
+    /// repeat to run several in order, each captured in a section of its own
+    #[arg(long, value_name = "COMMAND")]
+    run: Vec<String>,
+
+    /// Generate no context when the captured command succeeded, only passing its output through;
+    /// for piped input and logs, failed tests or compiler errors in the output count as failing
+    #[arg(long)]
+    only_on_failure: bool,
+
+    /// Read previously captured log files (or directories of logs) as if they had been piped in; repeatable
+    #[arg(long = "input", value_name = "PATH")]
+    inputs: Vec<PathBuf>,
+
+    /// Trim the recorded command output (e.g. 'errors' keeps only error/warning/panic lines)
+    #[arg(long, value_enum, value_name = "FILTER")]
+    capture_filter: Option<capture::CaptureFilter>,
+
+    /// Additional regex for lines to keep with --capture-filter; repeatable
+    #[arg(long, value_name = "REGEX", requires = "capture_filter")]
+    capture_filter_regex: Vec<String>,
+
+    /// Keep runs of repeated lines in the recorded command output instead of collapsing them
+    #[arg(long)]
+    keep_repeated_lines: bool,
+
+    /// Start the context with the git remote, branch, and HEAD commit it was taken at, and whether
+    /// the working tree had uncommitted changes
+    #[arg(long)]
+    git_info: bool,
+
+    /// Include a GitHub issue's title, description, and latest comments as the problem statement:
+    /// an issue URL, OWNER/REPO#NUMBER, or a number in the repository of the origin remote
+    #[arg(long, value_name = "ISSUE")]
+    issue: Option<String>,
+
+    /// Leave out the "Test Failures" summary of failed tests found in the command output
+    #[arg(long)]
+    no_test_summary: bool,
+
+    /// Keep cargo's Compiling/Downloading/progress lines in the recorded command output
+    #[arg(long)]
+    keep_cargo_noise: bool,
+
+    /// Leave out files larger than this size (e.g. '200kb'), checked before reading them; see --oversized
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<usize>,
+
+    /// What to do with files over --max-file-size: skip them, or keep their first SIZE bytes
+    #[arg(long, value_enum, value_name = "ACTION", default_value = "skip", requires = "max_file_size")]
+    oversized: content::Oversized,
+
+    /// Truncate files longer than this many lines, cutting at function/item boundaries where possible
+    #[arg(long, value_name = "LINES")]
+    max_file_lines: Option<usize>,
+
+    /// Stop scanning after this many seconds, keeping the files collected so far
+    #[arg(long, value_name = "SECONDS")]
+    max_walk_seconds: Option<u64>,
+
+    /// Only scan this many levels deep below each scanned directory (1 is its own files), like `find -maxdepth`
+    #[arg(long, value_name = "DEPTH")]
+    max_depth: Option<usize>,
+
+    /// Stop scanning once this many project files have been included
+    #[arg(long, value_name = "COUNT")]
+    max_files: Option<usize>,
+
+    /// Order the scanned files by this instead of walk order, e.g. hottest first with `churn`
+    #[arg(long, value_enum, value_name = "ORDER")]
+    rank: Option<rank::Rank>,
+
+    /// Leave out files once the context would exceed about this many tokens (estimated for cl100k, GPT-4's tokenizer)
+    #[arg(long, value_name = "COUNT")]
+    max_tokens: Option<usize>,
+
+    /// Write the context as parts of about this many tokens each (context.part1.md, ...) next to --output,
+    /// with an index (context.index.json) of which files live in which part
+    #[arg(long, value_name = "COUNT", requires = "output", conflicts_with_all = ["upload", "clipboard", "encrypt_to", "sign", "hash"])]
+    split_tokens: Option<usize>,
+
+    /// Like --split-tokens, with parts of about this size (e.g. '100k') instead
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "output", conflicts_with_all = ["split_tokens", "upload", "clipboard", "encrypt_to", "sign", "hash"])]
+    split_output: Option<usize>,
+
+    /// Start with a tree of the project files that made it past the filters, like `tree`
+    #[arg(long)]
+    tree: bool,
+
+    /// Memory cap for buffered content (e.g. 512M); beyond it, buffers spill to temp files
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memory_cap: Option<usize>,
+
+    /// Report wall time spent in each phase (walking, filtering, reading, dependencies, writing) on stderr
+    #[arg(long)]
+    timings: bool,
+
+    /// Preview binary files as a hex dump or their printable strings instead of a placeholder
+    #[arg(long, value_enum, value_name = "MODE")]
+    binary_preview: Option<binary::BinaryPreview>,
+
+    /// Number of leading bytes examined by --binary-preview
+    #[arg(long, value_name = "BYTES", default_value_t = binary::DEFAULT_PREVIEW_BYTES, requires = "binary_preview")]
+    binary_preview_bytes: usize,
+
+    /// Include each file's SHA-256 in its header and a digest of the whole output in a footer
+    #[arg(long)]
+    hash: bool,
+
+    /// Produce byte-identical output for the same tree: sorted walk, relative and environment-independent paths
+    #[arg(long)]
+    reproducible: bool,
+
+    /// Encrypt the output to an age recipient (age1... or ssh- public key, or a recipients file); repeatable
+    #[arg(long, value_name = "RECIPIENT")]
+    encrypt_to: Vec<String>,
+
+    /// Sign the output with a minisign secret key or SSH private key, writing a detached signature next to it
+    #[arg(long, value_name = "KEY")]
+    sign: Option<PathBuf>,
+
+    /// Write a JSON manifest of the included files (and signer, when signing) to this path
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Run this command (with the manifest path appended) when --max-files, --max-walk-seconds, or --max-tokens leaves the context incomplete; if it fails, so does contree
+    #[arg(long, value_name = "COMMAND")]
+    on_exceed: Option<String>,
+
+    /// Collapse blank-line runs and strip trailing whitespace in emitted content
+    #[arg(long)]
+    minify_whitespace: bool,
+
+    /// With --minify-whitespace, also reduce each indentation level to a single space
+    #[arg(long, requires = "minify_whitespace")]
+    minify_indent: bool,
+
+    /// For JSON/NDJSON input, a jq-like path expression (e.g. '.diagnostics[].file') selecting file paths to include
+    #[arg(long, value_name = "EXPR", value_parser = extract::PathExpression::parse)]
+    extract_paths: Option<extract::PathExpression>,
+
+    /// Editor buffer list as inline JSON or a JSON file path; open files are listed and emitted first
+    #[arg(long, value_name = "JSON|PATH", value_parser = editor::EditorState::parse)]
+    editor_state: Option<editor::EditorState>,
+
+    /// Print a JSON description of this build's formats, parsers, grammars, and integrations, then exit
+    #[arg(long)]
+    capabilities: bool,
+
+    /// Note how the scanned files changed (count, size, top-level entries, languages) since the last
+    /// run with this flag, and save their fingerprint in .contree/fingerprint.json
+    #[arg(long)]
+    fingerprint: bool,
+
+    /// Record this run (arguments, resolved options, captured output, manifest) under .contree/sessions
+    #[arg(long)]
+    record: bool,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+impl Args {
+    // The positional pathspecs and `--include-glob` patterns, which select files together
+    fn pathspecs(&self) -> Vec<pathspec::Pathspec> {
+        self.paths.iter().chain(&self.include_glob).cloned().collect()
+    }
+
+    // The `--compress` format, or the one the `--output` extension asks for
+    fn compression(&self) -> Option<compress::Compression> {
+        self.compress.or_else(|| self.output.as_deref().and_then(compress::Compression::from_extension))
+    }
+
+    // The part size of `--split-tokens` or `--split-output`, if the context is split
+    fn split_limit(&self) -> Option<split::Limit> {
+        self.split_tokens.map(split::Limit::Tokens).or(self.split_output.map(split::Limit::Bytes))
+    }
+
+    // Which uncommitted files `--staged`, `--unstaged`, and `--untracked` select
+    fn status_filter(&self) -> changes::StatusFilter {
+        changes::StatusFilter { staged: self.staged, unstaged: self.unstaged, untracked: self.untracked }
+    }
+
+    fn grep_case(&self) -> content::GrepCase {
+        if self.case_sensitive {
+            content::GrepCase::Sensitive
+        } else if self.smart_case {
+            content::GrepCase::Smart
+        } else {
+            content::GrepCase::Default
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run a command, capturing its stdout and stderr, then append the project context (`contree -D run cargo test`)
+    Run {
+        /// The command and its arguments; everything after `run` belongs to it
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Inspect or regenerate runs recorded with --record
+    Sessions {
+        #[command(subcommand)]
+        action: SessionsAction,
+    },
+    /// Pre-build the dependency symbol index for the crates the project uses, so --include-deps lookups skip scanning
+    IndexDeps {
+        /// Build the index in a detached background process and return immediately
+        #[arg(long)]
+        background: bool,
+    },
+    /// Show which ignore file and pattern (or contree rule) keeps each path out of the walk, like `git check-ignore`
+    CheckIgnore {
+        /// Paths to check
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Print the deciding `source:line:pattern` for each path, including `!pattern` re-includes
+        #[arg(short, long)]
+        verbose: bool,
+        /// With --verbose, also list paths no rule matches
+        #[arg(short, long, requires = "verbose")]
+        non_matching: bool,
+        /// Exclude pathspec as passed to a normal run (e.g. ':!vendor'); repeatable
+        #[arg(long, value_name = "PATHSPEC", value_parser = pathspec::Pathspec::parse_exclude)]
+        exclude: Vec<pathspec::Pathspec>,
+    },
+    /// Merge previously generated contexts into one bundle, keeping one copy of each file (the most recently generated)
+    Merge {
+        /// Context files written by contree
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Write the merged context to a file instead of stdout
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Check a text-format context, or what this selection would generate, for secrets, truncated files, binary placeholders, missing manifests, duplicates, and budget overruns
+    LintContext {
+        /// A context written with --format text; without one, the files the other options select are checked
+        context: Option<PathBuf>,
+    },
+    /// Replace this executable with the latest release from GitHub, verified against its published SHA-256
+    SelfUpdate {
+        /// Only report whether a newer release is available
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print the JSON Schema of a machine-readable output
+    Schema {
+        #[arg(value_enum, default_value = "manifest")]
+        kind: schema::SchemaKind,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionsAction {
+    /// List recorded sessions, oldest first
+    List,
+    /// Show a session's arguments, resolved options, included files, and captured output
+    Show {
+        /// Session id from `sessions list`, or `latest`
+        id: String,
+    },
+    /// Regenerate a session's context from its recorded output; arguments after `--` are appended
+    Replay {
+        /// Session id from `sessions list`, or `latest`
+        id: String,
+        #[arg(last = true)]
+        extra: Vec<String>,
+    },
+}
+
+// Options controlling how each file's contents are emitted
+struct OutputOptions {
+    root: PathBuf,
+    cargo_home: Option<PathBuf>,
+    reproducible: bool,
+    // Writing a tar archive, whose members need relative paths
+    archive: bool,
+    hash: bool,
+    max_file_lines: Option<usize>,
+    max_file_size: Option<usize>,
+    oversized: content::Oversized,
+    binary_preview: Option<binary::BinaryPreview>,
+    binary_preview_bytes: usize,
+    minify_whitespace: bool,
+    minify_indent: bool,
+    deps_public_only: bool,
+    docs_links: bool,
+    max_walk: Option<Duration>,
+    max_depth: Option<usize>,
+    max_files: Option<usize>,
+    // The --grep patterns as one, whose matches are highlighted in emitted files
+    grep: Option<Regex>,
+    // What a walked file must match, each of them: the union of the --grep patterns, or every
+    // pattern on its own with `--grep-mode all`
+    grep_filters: Vec<Regex>,
+    grep_target: content::GrepTarget,
+    // The compiled --grep-not pattern
+    grep_not: Option<Regex>,
+    // Each file's change since the `--annotate-changes` ref
+    changes: Option<changes::Changes>,
+    // The files `--changed-since` keeps
+    changed_since: Option<changes::Changes>,
+    // The files `--staged`, `--unstaged`, and `--untracked` keep
+    git_status: Option<changes::StatusSelection>,
+    // Change frequency for `--rank churn`
+    churn: Option<rank::Churn>,
+    // The `--exclude` globs, matched relative to the scanned directory
+    excludes: Gitignore,
+    // The `--ext` and `--no-ext` file name endings, lowercased with a leading dot
+    extensions: Vec<String>,
+    excluded_extensions: Vec<String>,
+    no_tests: bool,
+    tests_only: bool,
+    // The repository's .gitattributes, whose generated and vendored files are left out, unless
+    // --include-generated keeps them
+    attributes: Option<Arc<attributes::Attributes>>,
+    submodule_mode: vcs::SubmoduleMode,
+    // The submodules the walk leaves out: all of them unless `--submodules include`
+    submodules: Vec<vcs::Submodule>,
+    // The `--lang` languages, lowercased
+    only_languages: Vec<String>,
+    // The `--set` selections, of which a walked file must belong to one
+    sets: Vec<sets::FileSet>,
+    mark_matches: bool,
+    // Lines of context kept around matches with `--only-matches`
+    only_matches: Option<usize>,
+    languages: language::LanguageMap,
+    layout: layout::Layout,
+    project: project::Project,
+}
+
+impl OutputOptions {
+    fn from_args(args: &Args, root: &Path) -> Result<Self> {
+        let config = config::Config::load(root)?;
+        let case = args.grep_case();
+        let patterns = args.grep.iter().map(|pattern| content::grep_regex(pattern, case)).collect::<Result<Vec<_>>>()?;
+        let grep = (!patterns.is_empty()).then(|| content::union_regex(&patterns)).transpose()?;
+        let grep_filters = match args.grep_mode {
+            content::GrepMode::Any => grep.iter().cloned().collect(),
+            content::GrepMode::All => patterns,
+        };
+        let languages = language::LanguageMap::new(&config.languages)?;
+        let only_languages: Vec<String> = args.lang.iter().map(|name| name.trim().to_lowercase()).filter(|name| !name.is_empty()).collect();
+        if let Some(unknown) = only_languages.iter().find(|name| !languages.knows(name)) {
+            anyhow::bail!("Unknown --lang '{}'; known languages are {}", unknown, language::LANGUAGES.join(", "));
+        }
+        Ok(OutputOptions {
+            root: root.to_path_buf(),
+            cargo_home: metadata::cargo_home(args.cargo_home.as_deref()),
+            reproducible: args.reproducible,
+            archive: args.format == render::Format::Tar,
+            hash: args.hash,
+            max_file_lines: args.max_file_lines,
+            max_file_size: args.max_file_size,
+            oversized: args.oversized,
+            binary_preview: args.binary_preview,
+            binary_preview_bytes: args.binary_preview_bytes,
+            minify_whitespace: args.minify_whitespace,
+            minify_indent: args.minify_indent,
+            deps_public_only: args.deps_public_only,
+            docs_links: args.docs_links,
+            max_walk: args.max_walk_seconds.map(Duration::from_secs),
+            max_depth: args.max_depth,
+            max_files: args.max_files,
+            grep,
+            grep_filters,
+            grep_target: args.grep_target,
+            grep_not: args.grep_not.as_deref().map(|pattern| content::grep_regex(pattern, case)).transpose()?,
+            sets: sets::resolve(&config.sets, &args.set, case)?,
+            excludes: exclude_globs(root, &args.exclude)?,
+            extensions: dotted_extensions(&args.ext),
+            excluded_extensions: dotted_extensions(&args.no_ext),
+            no_tests: args.no_tests,
+            tests_only: args.tests_only,
+            attributes: (!args.include_generated).then(|| Arc::new(attributes::Attributes::load(root))),
+            submodule_mode: args.submodules,
+            submodules: match args.submodules {
+                vcs::SubmoduleMode::Include => Vec::new(),
+                vcs::SubmoduleMode::Skip | vcs::SubmoduleMode::Shallow => vcs::submodules_in(root)?,
+            },
+            only_languages,
+            changes: args.annotate_changes.as_deref().map(|base| changes::Changes::load(root, base, args.diffstat)).transpose()?,
+            changed_since: match args.changed_since.as_deref() {
+                Some("") => Some(changes::Changes::load_from_default_base(root, false)?),
+                Some(base) => Some(changes::Changes::load(root, base, false)?),
+                None => None,
+            },
+            git_status: match args.status_filter() {
+                filter if filter.is_set() => Some(changes::StatusSelection::load(root, filter)?),
+                _ => None,
+            },
+            churn: match args.rank {
+                Some(rank::Rank::Churn) => Some(rank::Churn::load(root)?),
+                None => None,
+            },
+            mark_matches: args.mark_matches && matches!(args.format, render::Format::Text | render::Format::Markdown | render::Format::Xml),
+            only_matches: args.only_matches.then_some(args.context),
+            languages,
+            layout: layout::Layout::new(&config.layout, config.dir().unwrap_or(root))?,
+            project: project::Project::detect(root, &config.project)?,
+        })
+    }
+
+    // Path as shown in file headers. Reproducible output avoids anything specific to the machine:
+    // project files are shown relative to the scanned directory, and Cargo home (as resolved
+    // from --cargo-home or the environment) and home directory prefixes are replaced with
+    // `$CARGO_HOME` and `~`. Tar archives name their members the same way.
+    fn display_path(&self, path: &Path) -> String {
+        if let Some(virtual_path) = self.layout.virtual_path(path) {
+            return virtual_path.display().to_string();
+        }
+        if !self.reproducible && !self.archive {
+            return path.display().to_string();
+        }
+        if let Ok(relative) = path.strip_prefix(&self.root) {
+            return relative.display().to_string();
+        }
+        let home = env::var_os("HOME").map(PathBuf::from);
+        for (prefix, placeholder) in [(&self.cargo_home, "$CARGO_HOME"), (&home, "~")] {
+            if let Some(relative) = prefix.as_ref().and_then(|prefix| path.strip_prefix(prefix).ok()) {
+                return format!("{}/{}", placeholder, relative.display());
+            }
+        }
+        path.display().to_string()
+    }
+
+    // Apply the same prefix rewriting to free text (command output, reasons): directories of
+    // the [layout] always, the rest for reproducible output, so absolute paths in logs don't
+    // differ between machines
+    fn normalize_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = match self.layout.rewrite_text(text) {
+            Some(rewritten) => Cow::Owned(rewritten),
+            None => Cow::Borrowed(text),
+        };
+        if !self.reproducible {
+            return text;
+        }
+        let home = env::var_os("HOME").map(PathBuf::from);
+        for (prefix, placeholder) in [(Some(&self.root), ""), (self.cargo_home.as_ref(), "$CARGO_HOME/"), (home.as_ref(), "~/")] {
+            // A prefix of `/` would rewrite every path separator
+            let Some(prefix) = prefix.filter(|prefix| prefix.components().count() > 1) else {
+                continue;
+            };
+            let prefix = format!("{}/", prefix.display());
+            if text.contains(&prefix) {
+                text = Cow::Owned(text.replace(&prefix, placeholder));
+            }
+        }
+        text
+    }
+
+    // Language of a file for fence tags, syntax-aware truncation, and comment markers
+    fn language(&self, path: &Path) -> Option<String> {
+        self.languages.language(&self.matched_path(path), &self.root, path)
+    }
+
+    // Whether a `skip` entry in [languages] excludes the file
+    fn is_skipped(&self, path: &Path) -> bool {
+        self.languages.is_skipped(&self.matched_path(path), &self.root)
+    }
+
+    // The path patterns are matched against: a file in a [layout] directory sits at its
+    // virtual path under the scanned directory
+    fn matched_path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        match self.layout.virtual_path(path) {
+            Some(virtual_path) => Cow::Owned(self.root.join(virtual_path)),
+            None => Cow::Borrowed(path),
+        }
+    }
+
+    // Apply truncation and whitespace minification to a file's contents
+    fn transform<'a>(&self, path: &Path, contents: &'a str) -> Cow<'a, str> {
+        let mut contents = Cow::Borrowed(contents);
+        if let Some(max_lines) = self.max_file_lines {
+            let language = self.language(path);
+            if let Cow::Owned(truncated) = truncate::truncate_lines(language.as_deref(), &contents, max_lines) {
+                warnings::warn(
+                    warnings::Kind::Truncated,
+                    format!("Truncated {} to {} lines (--max-file-lines)", self.display_path(path), max_lines),
+                );
+                contents = Cow::Owned(truncated);
+            }
+        }
+        if self.minify_whitespace {
+            contents = Cow::Owned(minify::minify_whitespace(&contents, self.minify_indent));
+        }
+        contents
+    }
+}
+
+// `--exclude` globs as one matcher; like .gitignore lines, a pattern without `/` matches at any depth
+fn exclude_globs(root: &Path, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder.add_line(None, pattern).with_context(|| format!("Invalid --exclude pattern '{}'", pattern))?;
+    }
+    builder.build().context("Invalid --exclude patterns")
+}
+
+// `rs`, `.RS` -> `.rs`; multi-part extensions such as `d.ts` match as file name endings
+fn dotted_extensions(extensions: &[String]) -> Vec<String> {
+    extensions
+        .iter()
+        .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+        .filter(|extension| !extension.is_empty())
+        .map(|extension| format!(".{}", extension))
+        .collect()
+}
+
+// Whether a file passes `--ext` and `--no-ext`
+fn extension_selected(path: &Path, extensions: &[String], excluded: &[String]) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let has = |extension: &String| name.len() > extension.len() && name.ends_with(extension.as_str());
+    (extensions.is_empty() || extensions.iter().any(has)) && !excluded.iter().any(has)
+}
+
+// Directories that hold tests, at any depth
+const TEST_DIRS: &[&str] = &["tests", "test", "__tests__", "spec"];
+
+// Whether a path relative to the scanned directory is a test, for --no-tests and --tests-only:
+// anything under a test directory, or a file named the way its ecosystem names tests
+fn is_test_path(relative: &Path) -> bool {
+    if relative.components().any(|component| TEST_DIRS.iter().any(|dir| component.as_os_str() == *dir)) {
+        return true;
+    }
+    let Some(name) = relative.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    match extension {
+        "rs" => stem == "tests" || stem.ends_with("_test") || stem.ends_with("_tests"),
+        "go" => stem.ends_with("_test"),
+        "py" => stem.starts_with("test_") || stem.ends_with("_test") || stem == "conftest",
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" => stem.ends_with(".test") || stem.ends_with(".spec"),
+        "rb" => stem.ends_with("_spec") || stem.ends_with("_test"),
+        "java" | "kt" => stem.ends_with("Test") || stem.ends_with("Tests"),
+        _ => false,
+    }
 }
 
 fn parse_pathbuf(s: &str) -> Result<PathBuf, String> {
     Ok(PathBuf::from(s.trim()))
 }
 
+// Parse a byte size like `4096`, `200k`, `512MB`, or `1GiB` (units are powers of 1024)
+fn parse_size(s: &str) -> Result<usize, String> {
+    let lower = s.trim().to_lowercase();
+    let digits_end = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(digits_end);
+    let number: usize = number.parse().map_err(|_| format!("Invalid size: {}", s))?;
+    let multiplier = match unit.trim() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size unit in '{}' (expected k, m, or g)", s)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size too large: {}", s))
+}
+
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let started = Instant::now();
+    let argv = alias::expand(env::args_os().collect(), &env::current_dir()?)?;
+    let args = Args::parse_from(&argv);
+    // `[defaults]` and profiles come from the .contree.toml of the scanned directory, known once parsed
+    let dir = args.dir.clone().unwrap_or(env::current_dir()?);
+    let argv = defaults::apply(argv, &config::Config::load(&dir)?, args.profile.as_deref(), &Args::command())?;
+    let args = Args::parse_from(&argv);
+    if args.timings {
+        timings::enable();
+    }
 
     // Determine the directory to work in
-    let cwd = args.dir.unwrap_or_else(|| std::env::current_dir().unwrap());
+    let cwd = args.dir.clone().unwrap_or_else(|| std::env::current_dir().unwrap());
+
+    if args.capabilities {
+        writeln!(io::stdout(), "{}", serde_json::to_string_pretty(&capabilities::describe())?)?;
+        return Ok(());
+    }
+
+    match &args.command {
+        Some(Commands::Sessions { action }) => return run_sessions(action, &cwd),
+        Some(Commands::IndexDeps { background }) => {
+            return index_deps(&cwd, args.cargo_home.as_deref(), *background)
+        }
+        Some(Commands::CheckIgnore { paths, verbose, non_matching, exclude }) => {
+            let mut stdout = io::stdout();
+            let any_ignored = check_ignore::check_ignore(&cwd, paths, exclude, *verbose, *non_matching, &mut stdout)?;
+            stdout.flush()?;
+            // Like git: exit status 1 when none of the paths is ignored
+            if !any_ignored {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Commands::Merge { inputs, output }) => {
+            let mut writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(fs::File::create(path).context("Failed to create output file")?),
+                None => Box::new(io::stdout()),
+            };
+            merge::merge(inputs, &mut writer)?;
+            return Ok(writer.flush()?);
+        }
+        Some(Commands::LintContext { context }) => {
+            let linter = match context {
+                Some(path) => {
+                    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+                    let linter = lint::Linter::from_context(&text);
+                    if linter.is_empty() {
+                        anyhow::bail!("{} has no files or command output; lint-context reads contexts written with --format text", path.display());
+                    }
+                    linter
+                }
+                None => {
+                    // The selection as it would be written, without any command output
+                    let mut linter = lint::Linter::default();
+                    let run = Run { full_output: "", commands: Vec::new() };
+                    write_sections(&args, &cwd, &run, &mut Manifest::new(), &mut linter)?;
+                    linter
+                }
+            };
+            let findings = linter.lint(args.max_tokens);
+            let mut stdout = io::stdout();
+            lint::report(&findings, &mut stdout)?;
+            stdout.flush()?;
+            // Nonzero when there is anything to look at, so scripts can stop before the model call
+            if !findings.is_empty() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Commands::SelfUpdate { check }) => return self_update::self_update(*check),
+        Some(Commands::Run { .. }) => {}
+        Some(Commands::Schema { kind }) => {
+            writeln!(io::stdout(), "{}", schema::schema(*kind).trim_end())?;
+            return Ok(());
+        }
+        None => {}
+    }
+    let config = config::Config::load(&cwd)?;
+    pathspec::check_matches(&args.pathspecs(), &cwd, &layout::Layout::new(&config.layout, config.dir().unwrap_or(&cwd))?)?;
+    let project = project::Project::detect(&cwd, &config.project)?;
+
+    let budget = spill::MemoryBudget::new(args.memory_cap.unwrap_or(spill::DEFAULT_MEMORY_CAP));
+
+    // The commands to run, from --run or the run subcommand. Saved logs replace them, which is
+    // how session replays regenerate a run's context without running it again.
+    let mut run_argvs = match (&args.run[..], &args.command) {
+        ([_, ..], Some(Commands::Run { .. })) => anyhow::bail!("Use either --run or the run subcommand, not both"),
+        (_, Some(Commands::Run { command })) => vec![command.clone()],
+        (command_lines, _) => command_lines.iter().map(|line| capture::split_command_line(line)).collect::<Result<_>>()?,
+    };
+    if !run_argvs.is_empty() && !args.inputs.is_empty() {
+        eprintln!("Note: Reading the --input logs instead of running the command");
+        run_argvs.clear();
+    }
+
+    // Captured command output: a command run by contree or piped stdin, both passed through to
+    // the console, or saved logs. Only the text format can share stdout with the passthrough.
+    let shares_stdout = args.output.is_none() && args.upload.is_none();
+    let echo_to_stderr = shares_stdout && args.format != render::Format::Text;
+    let piped = run_argvs.is_empty() && args.inputs.is_empty() && !atty::is(Stream::Stdin);
+    // One output per command, in the order they ran; logs and piped input are a single output
+    let mut outputs = Vec::new();
+    let mut command_runs = Vec::new();
+    if !run_argvs.is_empty() {
+        // Every command runs, even after one fails, so the whole sequence is captured
+        for argv in &run_argvs {
+            let (output, run) = capture::run_command(argv, &budget, echo_to_stderr)?;
+            outputs.push(output);
+            command_runs.push(Some(run));
+        }
+    } else if !args.inputs.is_empty() {
+        outputs.push(capture::read_input_logs(&args.inputs, &budget)?);
+        command_runs.push(None);
+    } else if piped {
+        let output = capture::read_stdin_passthrough(&budget, echo_to_stderr)?;
+        // An empty pipe (e.g. stdin redirected from /dev/null) had no command behind it
+        command_runs.push((!output.trim().is_empty()).then(|| capture::CommandRun::piped(started.elapsed())));
+        outputs.push(output);
+    }
+    // Error references, test failures, and recorded sessions read all of it as one log
+    let joined_output = match &outputs[..] {
+        [_, _, ..] => Some(capture::join_outputs(&outputs, &budget)?),
+        _ => None,
+    };
+    let full_output: &str = joined_output.as_deref().or(outputs.first().map(|output| &**output)).unwrap_or_default();
+    if args.only_on_failure && !run_failed(&command_runs, full_output) {
+        eprintln!("Note: Nothing failed, so no context was generated (--only-on-failure)");
+        return Ok(());
+    }
+
+    // Record the output in the context unless the passthrough already put it in the same stream
+    let passthrough = piped || !run_argvs.is_empty();
+    let mut recorded_outputs = Vec::new();
+    for output in &outputs {
+        recorded_outputs.push(if passthrough && shares_stdout && !echo_to_stderr {
+            None
+        } else {
+            let capture_options = capture::CaptureOptions {
+                strip_cargo_noise: !args.keep_cargo_noise,
+                filter: args.capture_filter,
+                ecosystems: &project.ecosystems,
+                filter_patterns: &args.capture_filter_regex,
+                collapse_repeats: !args.keep_repeated_lines,
+            };
+            Some(capture::process_output(output, &capture_options, &budget)?)
+        });
+    }
+
+    let remote_output = match args.output.as_ref().and_then(|p| p.to_str()) {
+        Some(output) => store::parse_output(output)?,
+        None => None,
+    };
 
-    let mut full_output = String::new();
-    let mut line_buffer = String::new();
+    if args.sign.is_some() && args.output.is_none() {
+        anyhow::bail!("--sign requires --output so the signature can be written alongside it");
+    }
+    if args.compression().is_some() && (args.upload.is_some() || args.clipboard || args.split_limit().is_some()) {
+        anyhow::bail!("Compressed output can't be uploaded, copied, or split; use an --output name without .gz or .zst");
+    }
+    if args.format == render::Format::Tar {
+        if args.split_limit().is_some() || args.clipboard {
+            anyhow::bail!("--format tar writes one archive of files, which can't be split or copied to the clipboard");
+        }
+        if args.output.is_none() && args.upload.is_none() && args.encrypt_to.is_empty() && atty::is(Stream::Stdout) {
+            anyhow::bail!("Refusing to write a tar archive to the terminal; use --output or redirect stdout");
+        }
+    }
+    if args.split_limit().is_some() && remote_output.is_some() {
+        anyhow::bail!("--split-tokens and --split-output write their parts next to --output, which can't be a bucket URL");
+    }
+    let mut manifest = Manifest::new();
+    manifest.output = args.output.as_ref().map(|p| p.display().to_string());
+    match &command_runs[..] {
+        [run] => manifest.command = run.as_ref().map(Into::into),
+        runs => manifest.commands = runs.iter().flatten().map(Into::into).collect(),
+    }
+    let run = Run {
+        full_output,
+        commands: recorded_outputs
+            .iter()
+            .zip(&command_runs)
+            .map(|(output, run)| render::CapturedCommand { output: output.as_deref(), run: run.as_ref() })
+            .collect(),
+    };
 
-    // Open the output file early if specified, default to stdout
+    // For remote outputs, uploads, and the clipboard, render into memory so the same bytes can be
+    // published
+    if remote_output.is_some() || args.upload.is_some() || args.clipboard {
+        let mut buffer = spill::SpillBuffer::new(&budget);
+        if args.encrypt_to.is_empty() {
+            write_compressed(&args, &cwd, &run, &mut manifest, &budget, &mut buffer)?;
+        } else {
+            // Paste services and the clipboard need text, so they get armor; object stores get
+            // binary age files
+            let mut encrypting = encrypt::wrap(&args.encrypt_to, args.upload.is_some() || args.clipboard, buffer)?;
+            write_compressed(&args, &cwd, &run, &mut manifest, &budget, &mut encrypting)?;
+            buffer = encrypting.finish()?;
+        }
+        let buffer = buffer.finish()?;
+        manifest.output_sha256 = Some(digest::sha256_hex(&buffer));
+        match (&remote_output, &args.output) {
+            (Some(remote), Some(output_url)) => {
+                remote.store.put(&remote.key, &buffer)?;
+                if let Some(key) = &args.sign {
+                    // Sign a local copy, then publish the signature next to the output
+                    let dir = tempfile::tempdir()?;
+                    let local_copy = dir.path().join("context");
+                    fs::write(&local_copy, &*buffer)?;
+                    let mut signature = sign::sign_file(key, &local_copy)?;
+                    let extension = sign::signature_extension(&signature.format);
+                    remote
+                        .store
+                        .put(&format!("{}.{}", remote.key, extension), &fs::read(&signature.path)?)?;
+                    signature.path = format!("{}.{}", output_url.display(), extension);
+                    manifest.signature = Some(signature);
+                }
+            }
+            (None, Some(output_path)) => {
+                fs::write(output_path, &*buffer).context("Failed to write output file")?;
+                if let Some(key) = &args.sign {
+                    manifest.signature = Some(sign::sign_file(key, output_path)?);
+                }
+            }
+            _ => {}
+        }
+        manifest.warnings = warnings::collected();
+        if let Some(manifest_path) = &args.manifest {
+            manifest.write(manifest_path)?;
+        }
+        if let Some(target) = &args.upload {
+            let upload_options = upload::UploadOptions {
+                chunk_size: args.upload_chunk_size,
+                requests_per_minute: args.upload_rate_limit,
+                root: &cwd,
+            };
+            let url = upload::upload(target, &buffer, &budget, &upload_options)?;
+            println!("{}", url);
+        } else if args.output.is_none() && !args.clipboard {
+            io::stdout().write_all(&buffer)?;
+        }
+        if args.clipboard {
+            clipboard::copy(&buffer, args.clipboard_chunk_size)?;
+        }
+        return finish_run(&args, &argv, &cwd, &run, &manifest, started);
+    }
+
+    // Split contexts go to numbered parts next to the output, plus an index of their files
+    if let (Some(limit), Some(output_path)) = (args.split_limit(), &args.output) {
+        let mut out = split::SplitRenderer::new(args.format, &budget, output_path, limit);
+        write_sections(&args, &cwd, &run, &mut manifest, &mut out)?;
+        let index = out.close()?;
+        manifest.output = Some(index.display().to_string());
+        manifest.output_sha256 = Some(digest::sha256_file(&index)?);
+        manifest.warnings = warnings::collected();
+        if let Some(manifest_path) = &args.manifest {
+            manifest.write(manifest_path)?;
+        }
+        return finish_run(&args, &argv, &cwd, &run, &manifest, started);
+    }
+
+    // Open the output file if specified, default to stdout
     let mut output_writer: Box<dyn Write> = if let Some(output_path) = &args.output {
         Box::new(std::fs::File::create(output_path).context("Failed to create output file")?)
     } else {
         Box::new(std::io::stdout())
     };
 
-    if !atty::is(Stream::Stdin) {
-        // Input piped: read from stdin and passthrough to console
-        let mut stdin_reader = BufReader::new(io::stdin());
-        let mut stdout_handle = io::stdout();
-
-        loop {
-            line_buffer.clear();
-            match stdin_reader.read_line(&mut line_buffer) {
-                Ok(0) => break, // EOF
-                Ok(_) => {
-                    print!("{}", line_buffer);
-                    stdout_handle.flush()?;
-                    full_output.push_str(&line_buffer);
-                }
-                Err(e) => eprintln!("Error reading stdin: {}", e),
+    if !args.encrypt_to.is_empty() {
+        // Armor when writing to the terminal; files get binary age format
+        let mut encrypting = encrypt::wrap(&args.encrypt_to, args.output.is_none(), output_writer)?;
+        write_compressed(&args, &cwd, &run, &mut manifest, &budget, &mut encrypting)?;
+        output_writer = encrypting.finish()?;
+    } else {
+        write_compressed(&args, &cwd, &run, &mut manifest, &budget, &mut output_writer)?;
+    }
+    timings::measure(Phase::Writing, || output_writer.flush())?;
+    drop(output_writer);
+
+    if let Some(output_path) = &args.output {
+        manifest.output_sha256 = Some(digest::sha256_file(output_path)?);
+        if let Some(key) = &args.sign {
+            manifest.signature = Some(sign::sign_file(key, output_path)?);
+        }
+    }
+    manifest.warnings = warnings::collected();
+    if let Some(manifest_path) = &args.manifest {
+        manifest.write(manifest_path)?;
+    }
+    finish_run(&args, &argv, &cwd, &run, &manifest, started)
+}
+
+// The captured command output and how each command ended
+struct Run<'a> {
+    // Everything captured, as one log
+    full_output: &'a str,
+    // Each command's cleaned-up output for the context and how it ended
+    commands: Vec<render::CapturedCommand<'a>>,
+}
+
+// Shared tail of every context-generating run: record the session, report timings, and exit
+// with the status of a failed --run command so wrapping it doesn't hide the failure
+fn finish_run(args: &Args, argv: &[OsString], cwd: &Path, run: &Run, manifest: &Manifest, started: Instant) -> Result<()> {
+    if args.record && !args.encrypt_to.is_empty() {
+        // Sessions are plain files in the project; recording would leak what was encrypted
+        warnings::warn(warnings::Kind::Ignored, "Not recording the session: --encrypt-to is set and sessions are stored unencrypted");
+    } else if args.record {
+        let dir = session::record(cwd, &replay_argv(args, argv), &format!("{:#?}", args), run.full_output, manifest)?;
+        eprintln!("Recorded session {}", dir.display());
+    }
+    if args.timings {
+        timings::report(started.elapsed());
+    }
+    if let Some(command_line) = &args.on_exceed {
+        hook::on_exceed(command_line, manifest, args.manifest.as_deref())?;
+    }
+    // The first command that failed decides, as it would in a `set -e` script
+    if let Some(command) = run.commands.iter().filter_map(|captured| captured.run).find(|command| command.command.is_some() && command.failed()) {
+        // Like a shell, report death by signal N as 128 + N
+        std::process::exit(command.exit_code.unwrap_or(128 + command.signal.unwrap_or(0)));
+    }
+    Ok(())
+}
+
+// Whether the captured run failed: any command that exited with an error, or when the exit
+// status is unknown (piped input, logs), output reporting failed tests or compiler errors
+fn run_failed(runs: &[Option<capture::CommandRun>], output: &str) -> bool {
+    let mut finished = runs.iter().flatten().filter(|run| run.exit_code.is_some() || run.signal.is_some()).peekable();
+    if finished.peek().is_some() {
+        return finished.any(|run| run.failed());
+    }
+    !test_summary::failures(output).is_empty()
+        || !explain::error_codes(output).is_empty()
+        || output.lines().any(|line| line.starts_with("error: ") || line.starts_with("error["))
+}
+
+// The arguments a session replays: aliases expanded, and without the `run` subcommand, since
+// replays read the recorded output instead of running the command again
+fn replay_argv(args: &Args, argv: &[OsString]) -> Vec<String> {
+    let mut argv: Vec<String> = argv.iter().skip(1).map(|arg| arg.to_string_lossy().into_owned()).collect();
+    if let Some(Commands::Run { command }) = &args.command {
+        argv.truncate(argv.len().saturating_sub(command.len()));
+        if argv.last().is_some_and(|arg| arg == "--") {
+            argv.pop();
+        }
+        if argv.last().is_some_and(|arg| arg == "run") {
+            argv.pop();
+        }
+    }
+    argv
+}
+
+// Handle `contree sessions ...`; replays re-run this binary so they go through normal argument parsing
+fn run_sessions(action: &SessionsAction, cwd: &Path) -> Result<()> {
+    let mut stdout = io::stdout();
+    match action {
+        SessionsAction::List => session::list(cwd, &mut stdout),
+        SessionsAction::Show { id } => session::show(cwd, id, &mut stdout),
+        SessionsAction::Replay { id, extra } => {
+            let (argv, dir) = session::replay_command(cwd, id, extra)?;
+            let status = Command::new(env::current_exe()?)
+                .args(&argv)
+                .current_dir(dir)
+                .stdin(std::process::Stdio::null())
+                .status()
+                .context("Failed to re-run contree for replay")?;
+            if !status.success() {
+                std::process::exit(status.code().unwrap_or(1));
             }
+            Ok(())
         }
     }
+}
 
-    // Write project context to the output file (or stdout if no file specified)
-    writeln!(output_writer, "\n=== Project Context ===\n")?;
+// Write the context through the `--compress` compressor, if there is one. Encryption wraps the
+// compressed stream, since encrypted data doesn't compress.
+fn write_compressed(
+    args: &Args,
+    cwd: &PathBuf,
+    run: &Run,
+    manifest: &mut Manifest,
+    budget: &spill::MemoryBudget,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let Some(compression) = args.compression() else {
+        return write_context(args, cwd, run, manifest, budget, writer);
+    };
+    let mut compressing = compress::wrap(compression, writer)?;
+    write_context(args, cwd, run, manifest, budget, &mut compressing)?;
+    compressing.finish()?;
+    Ok(())
+}
 
-    // Print files from the scanned directory with grep filtering
-    print_project_files(&cwd, &args.grep, &args.include, &mut output_writer)?;
+// Write the context to the writer, followed by a digest of everything written when hashing
+fn write_context(
+    args: &Args,
+    cwd: &PathBuf,
+    run: &Run,
+    manifest: &mut Manifest,
+    budget: &spill::MemoryBudget,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    // Grep matches are colored by default only when the context goes straight to a terminal
+    let color = !args.grep.is_empty()
+        && match args.color {
+            render::ColorMode::Always => true,
+            render::ColorMode::Never => false,
+            render::ColorMode::Auto => {
+                args.output.is_none()
+                    && args.upload.is_none()
+                    && !args.clipboard
+                    && args.encrypt_to.is_empty()
+                    && args.compression().is_none()
+                    && atty::is(Stream::Stdout)
+                    && env::var_os("NO_COLOR").is_none()
+            }
+        };
+    if !args.hash {
+        let mut out = render::renderer(args.format, writer, color, budget);
+        write_sections(args, cwd, run, manifest, out.as_mut())?;
+        return Ok(out.finish()?);
+    }
+
+    let mut hashing_writer = digest::HashingWriter::new(writer);
+    let mut out = render::renderer(args.format, &mut hashing_writer, color, budget);
+    write_sections(args, cwd, run, manifest, out.as_mut())?;
+    out.finish()?;
+    drop(out);
+    let output_digest = hashing_writer.digest();
+    match render::digest_footer(args.format, &output_digest) {
+        Some(footer) => write!(writer, "{}", footer)?,
+        None if args.format == render::Format::Tar => {
+            warnings::warn(warnings::Kind::Ignored, "--format tar has no room for the output digest")
+        }
+        None => warnings::warn(warnings::Kind::Ignored, "--format json has no room for the output digest; each file's sha256 is still included"),
+    }
+    Ok(())
+}
+
+// Write the command output, project context, and dependency files (if requested)
+fn write_sections(
+    args: &Args,
+    cwd: &PathBuf,
+    run: &Run,
+    manifest: &mut Manifest,
+    out: &mut dyn render::Renderer,
+) -> Result<()> {
+    let options = OutputOptions::from_args(args, cwd)?;
+    let full_output = run.full_output;
+    let commands: Vec<_> = run
+        .commands
+        .iter()
+        .map(|captured| {
+            let output = captured.output.filter(|o| !o.trim().is_empty()).map(|o| options.normalize_text(o));
+            // Timings differ on every run, so reproducible output keeps only the exit status
+            let command = captured.run.map(|command| capture::CommandRun {
+                command: command.command.as_deref().map(|c| options.normalize_text(c).into_owned()),
+                duration: command.duration.filter(|_| !options.reproducible),
+                ..command.clone()
+            });
+            (output, command.filter(|command| !command.summary().is_empty()))
+        })
+        .filter(|(output, command)| output.is_some() || command.is_some())
+        .collect();
+    let captured: Vec<_> =
+        commands.iter().map(|(output, run)| render::CapturedCommand { output: output.as_deref(), run: run.as_ref() }).collect();
+    let problem = match &args.issue {
+        Some(reference) => Some(issue::problem_statement(&issue::parse_reference(reference, cwd)?)?),
+        None => None,
+    };
+    // The problem statement and command output always go in; files are admitted while the rest
+    // of the budget lasts
+    manifest.tokens = args.max_tokens.map(|max| ManifestTokens {
+        max,
+        estimated: problem.as_deref().map_or(0, tokens::estimate)
+            + captured.iter().map(|command| command.output.map_or(0, tokens::estimate)).sum::<usize>(),
+        skipped: Vec::new(),
+    });
+    if args.git_info {
+        out.repository(&vcs::detect(cwd)?.info()?)?;
+    }
+    if let Some(problem) = &problem {
+        out.problem_statement(problem)?;
+    }
+    // Failed tests go first, so they aren't buried in a long log
+    let failures = if args.no_test_summary { Vec::new() } else { test_summary::failures(full_output) };
+    if !failures.is_empty() {
+        let failures: Vec<_> = failures
+            .into_iter()
+            .map(|failure| test_summary::TestFailure {
+                location: failure.location.map(|location| options.normalize_text(&location).into_owned()),
+                message: failure.message.map(|message| options.normalize_text(&message).into_owned()),
+                ..failure
+            })
+            .collect();
+        out.test_failures(&failures)?;
+    }
+    if !captured.is_empty() {
+        out.command_output(&captured)?;
+    }
+
+    if args.explain_errors {
+        let explanations = explain::explanations(full_output);
+        if !explanations.is_empty() {
+            out.error_reference(&explanations)?;
+        }
+    }
+
+    if let Some(editor_state) = args.editor_state.as_ref().filter(|state| !state.buffers.is_empty()) {
+        out.editor_buffers(&editor_state.summary(&|path| options.display_path(path)))?;
+    }
+
+    // Print open editor buffers first, then files from the scanned directory with grep filtering
+    let priority_files = args
+        .editor_state
+        .as_ref()
+        .map(|editor_state| editor_state.paths(cwd))
+        .unwrap_or_default();
+    // Paths pulled from structured output and Miri/sanitizer stack frames: registry files
+    // feed dependency resolution, everything else is included like an explicit --include entry
+    let mut extracted = args
+        .extract_paths
+        .as_ref()
+        .map(|expression| expression.extract(full_output))
+        .unwrap_or_default();
+    let sources = DependencySources::resolve(args, cwd);
+    extracted.extend(sanitizer::frame_locations(full_output, cwd, &sources.roots));
+    let (dependency_locations, project_locations): (Vec<_>, Vec<_>) = extracted
+        .into_iter()
+        .partition(|location| sources.contains(&cwd.join(&location.path)));
+    let mut include_files = args.include.clone();
+    // URLs among them are fetched after the project files rather than read from disk
+    let mut urls = Vec::new();
+    if let Some(files) = include_files.as_mut() {
+        let (remote, local) = files.drain(..).partition(|path| remote::is_url(path));
+        urls = remote;
+        *files = local;
+    }
+    if !project_locations.is_empty() {
+        include_files.get_or_insert_with(Vec::new).extend(project_locations.iter().map(|location| {
+            let path = PathBuf::from(&location.path);
+            if path.is_relative() { cwd.join(path) } else { path }
+        }));
+    }
+    if args.include_manifests {
+        include_files.get_or_insert_with(Vec::new).extend(options.project.existing_manifests(cwd));
+    }
+
+    let project_files = select_project_files(&fileio::Disk, cwd, &args.pathspecs(), &priority_files, &include_files, &options)?;
+    if args.tree {
+        out.tree(&tree::ascii(&project_files.display_paths(&options)))?;
+    }
+    // Count tokens on every core ahead of writing, unless minification will change every text
+    if manifest.tokens.is_some() {
+        tokens::load_cache();
+        if !options.minify_whitespace {
+            let paths: Vec<PathBuf> = project_files.priority.iter().chain(&project_files.walked).chain(&project_files.included).cloned().collect();
+            timings::measure(Phase::Tokenizing, || tokens::prefetch(&paths));
+        }
+    }
+    if let Some(changes) = &options.changes {
+        out.note(&format!("Project files are marked with their changes since {}", changes.base()))?;
+    }
+    if let Some(changes) = &options.changed_since {
+        out.note(&format!("Only project files added or modified since {} are included", changes.base()))?;
+    }
+    if options.git_status.is_some() {
+        out.note(&format!("Only {} project files are included", args.status_filter().describe()))?;
+    }
+    if args.fingerprint {
+        let mut current = fingerprint::Fingerprint::new();
+        for path in &project_files.walked {
+            let bytes = fs::metadata(path).map_or(0, |metadata| metadata.len());
+            current.add(&options.layout.relative(path, cwd), bytes, options.language(path).as_deref());
+        }
+        out.note(&current.summary(fingerprint::Fingerprint::load(cwd).as_ref()))?;
+        current.save(cwd)?;
+    }
+    out.section("Project Context")?;
+    print_project_files(&project_files, &options, manifest, out)?;
+    for url in &urls {
+        print_remote_document(&url.to_string_lossy(), args, &options, manifest, out)?;
+    }
 
     // Include dependencies if requested
     if args.include_deps {
-        print_relevant_dependency_files(&full_output, &cwd, &mut output_writer)?;
+        print_relevant_dependency_files(
+            full_output,
+            &dependency_locations,
+            cwd,
+            &sources,
+            &options,
+            manifest,
+            out,
+        )?;
     }
 
-    output_writer.flush()?;
+    if manifest.tokens.is_some() {
+        tokens::save_cache();
+    }
+    if let Some(budget) = manifest.tokens.as_ref().filter(|budget| tokens::exhausted(budget)) {
+        warnings::warn(warnings::Kind::Limit, format!("--max-tokens {} was reached; {} files were left out", budget.max, budget.skipped.len()));
+        out.note(&tokens::skipped_note(budget))?;
+    }
+    if let Some(format) = args.answer_format {
+        let root = cwd.display().to_string();
+        out.answer_format(&format.instructions((!args.reproducible).then_some(root.as_str())))?;
+    }
+    out.warnings(&warnings::collected())?;
+
     Ok(())
 }
 
-// Check if the current directory or a parent contains a Cargo.toml
+// Where dependency sources live: the Cargo home's registry and git checkouts, plus the
+// directories cargo metadata reports for the packages (mirrors, vendored sources) when
+// dependency files are requested
+struct DependencySources {
+    packages: Vec<metadata::DependencyPackage>,
+    roots: Vec<PathBuf>,
+}
+
+impl DependencySources {
+    fn resolve(args: &Args, cwd: &PathBuf) -> Self {
+        let packages = if args.include_deps && is_rust_project(cwd) {
+            timings::measure(Phase::Dependencies, || dependency_packages(cwd, args.cargo_home.as_deref()))
+        } else {
+            Vec::new()
+        };
+        let mut roots = metadata::cargo_home(args.cargo_home.as_deref())
+            .map(|cargo_home| metadata::source_roots(&cargo_home))
+            .unwrap_or_default();
+        roots.extend(packages.iter().map(|package| package.dir.clone()));
+        DependencySources { packages, roots }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+}
+
+// Whether dependency resolution applies: a Cargo.toml in the directory or a parent, unless
+// [project] types say otherwise
 fn is_rust_project(cwd: &Path) -> bool {
-    let mut current = Some(cwd);
-    while let Some(dir) = current {
-        if dir.join("Cargo.toml").exists() {
-            return true;
+    config::Config::load(cwd)
+        .and_then(|config| project::Project::detect(cwd, &config.project))
+        .is_ok_and(|project| project.has(project::Ecosystem::Rust))
+}
+
+// Print a single file's contents
+fn print_file(
+    path: &Path,
+    options: &OutputOptions,
+    manifest: &mut Manifest,
+    out: &mut dyn render::Renderer,
+) -> Result<()> {
+    // Files over --max-file-size are judged by their metadata, before any of them is read
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let oversized = options.max_file_size.filter(|max| size > *max as u64);
+    if let Some(max) = oversized.filter(|_| options.oversized == content::Oversized::Skip) {
+        let reason = format!("{} is over --max-file-size {}", binary::format_size(size), binary::format_size(max as u64));
+        let display_path = options.display_path(path);
+        warnings::warn(warnings::Kind::Skipped, format!("Skipping {}: {}", display_path, reason));
+        out.note(&format!("Left out {}: {}", display_path, reason))?;
+        return Ok(());
+    }
+    let read = || match oversized {
+        Some(max) => content::read_text_prefix(path, max).map(|mut text| {
+            let omitted = format!("{} more omitted (--max-file-size)", binary::format_size(size - text.len() as u64));
+            truncate::push_marker(&mut text, options.language(path).as_deref(), &omitted);
+            content::FileContent::Heap(text)
+        }),
+        None => content::read_text(path),
+    };
+    let contents = match timings::measure(Phase::Reading, read) {
+        Err(e) if !fileio::is_not_text(&e) => {
+            return Err(anyhow::Error::from(e).context(format!("Failed to read file: {}", path.display())))
+        }
+        contents => contents,
+    };
+    let display_path = options.display_path(path);
+    if let (Some(max), Ok(_)) = (oversized, &contents) {
+        warnings::warn(
+            warnings::Kind::Truncated,
+            format!("Truncated {} to its first {} (--max-file-size)", display_path, binary::format_size(max as u64)),
+        );
+    }
+    if let Some(budget) = manifest.tokens.as_mut().filter(|budget| tokens::exhausted(budget)) {
+        budget.skipped.push(display_path);
+        return Ok(());
+    }
+    let file_digest = if options.hash {
+        // A truncated file is still identified by its whole contents
+        Some(match &contents {
+            Ok(contents) if oversized.is_none() => digest::sha256_hex(contents.as_bytes()),
+            _ => digest::sha256_file(path)
+                .with_context(|| format!("Failed to read file: {}", path.display()))?,
+        })
+    } else {
+        None
+    };
+    let mut matches = Vec::new();
+    // Whether the body is the file as read, so its prefetched token count applies
+    let mut unchanged = false;
+    let body = match &contents {
+        Ok(contents) => {
+            // Excerpts are numbered by the file's own lines, so they skip truncation and minifying
+            let mut text = match options.only_matches {
+                Some(_) => Cow::Borrowed(&**contents),
+                None => options.transform(path, contents),
+            };
+            if let Some(regex) = &options.grep {
+                matches = highlight::find_matches(regex, &text);
+            }
+            if options.mark_matches && !matches.is_empty() {
+                let language = options.language(path);
+                let comment = language.as_deref().and_then(language::comment_prefix).unwrap_or("//");
+                let (marked, shifted) = highlight::add_markers(&text, &matches, comment);
+                text = Cow::Owned(marked);
+                matches = shifted;
+            }
+            // Files without a match in their contents (editor buffers, --include, name matches)
+            // have nothing to cut down to, so they stay whole
+            if let Some(context) = options.only_matches.filter(|_| !matches.is_empty()) {
+                let (excerpt, shifted) = highlight::excerpt(&text, &matches, context);
+                text = Cow::Owned(excerpt);
+                matches = shifted;
+            }
+            unchanged = matches!(text, Cow::Borrowed(_));
+            render::FileBody::Text(text)
+        }
+        Err(_) => render::FileBody::Binary {
+            description: binary::describe(path)?,
+            preview: options
+                .binary_preview
+                .map(|mode| binary::preview(path, mode, options.binary_preview_bytes))
+                .transpose()?,
+            source: path.to_path_buf(),
+        },
+    };
+    if let Some(budget) = &mut manifest.tokens {
+        let estimated = timings::measure(Phase::Tokenizing, || {
+            tokens::estimate_file(&display_path, &body, unchanged.then_some(path))
+        });
+        if !tokens::admit(budget, &display_path, estimated) {
+            return Ok(());
         }
-        current = dir.parent();
     }
-    false
+    let match_count = (options.grep.is_some() && contents.is_ok()).then_some(matches.len());
+    let bytes = size;
+    manifest.files.push(ManifestFile {
+        path: display_path.clone(),
+        bytes,
+        binary: contents.is_err(),
+        sha256: file_digest.clone(),
+        hard_link_to: None,
+    });
+    let file = render::FileEntry {
+        path: display_path,
+        bytes,
+        language: contents.is_ok().then(|| options.language(path)).flatten(),
+        sha256: file_digest,
+        docs_url: None,
+        change: options.changes.as_ref().and_then(|changes| changes.get(path)),
+        reasons: Vec::new(),
+        body,
+        match_count,
+        matches,
+    };
+    timings::measure(Phase::Writing, || out.file(&file))?;
+    Ok(())
 }
 
-// Print a single file's contents to the writer
-fn print_file(path: &Path, writer: &mut Box<dyn Write>) -> Result<()> {
-    writeln!(writer, "File: {}", path.display())?;
-    writeln!(writer, "```")?;
-    match fs::read_to_string(path) {
-        Ok(contents) => writeln!(writer, "{}", contents)?,
-        Err(e) if e.to_string().contains("stream did not contain valid UTF-8") => {
-            writeln!(writer, "[binary file]")?;
+// Fetch an --include URL and print it as a document labeled with the URL. A failed fetch or a
+// binary response is skipped with a warning, like a missing --include file.
+fn print_remote_document(
+    url: &str,
+    args: &Args,
+    options: &OutputOptions,
+    manifest: &mut Manifest,
+    out: &mut dyn render::Renderer,
+) -> Result<()> {
+    let document = match timings::measure(Phase::Reading, || remote::fetch(url, args.max_url_size, args.html_to_text)) {
+        Ok(document) => document,
+        Err(e) => {
+            // Error pages can be long; their first line says enough
+            let error = format!("{:#}", e);
+            warnings::warn(warnings::Kind::Skipped, format!("Skipping included URL {}: {}", url, error.lines().next().unwrap_or_default()));
+            return Ok(());
         }
-        Err(e) => return Err(anyhow::Error::from(e).context(format!("Failed to read file: {}", path.display()))),
+    };
+    if let Some(budget) = manifest.tokens.as_mut().filter(|budget| tokens::exhausted(budget)) {
+        budget.skipped.push(url.to_string());
+        return Ok(());
+    }
+    let sha256 = options.hash.then(|| digest::sha256_hex(document.text.as_bytes()));
+    let mut text = document.text;
+    if document.truncated {
+        warnings::warn(
+            warnings::Kind::Truncated,
+            format!("Truncated {} to its first {} (--max-url-size)", url, binary::format_size(args.max_url_size as u64)),
+        );
+        truncate::push_marker(&mut text, document.language.as_deref(), "the rest omitted (--max-url-size)");
     }
-    writeln!(writer, "```")?;
-    writeln!(writer)?;
+    let matches = options.grep.as_ref().map(|regex| highlight::find_matches(regex, &text)).unwrap_or_default();
+    let match_count = options.grep.is_some().then_some(matches.len());
+    let body = render::FileBody::Text(Cow::Owned(text));
+    if let Some(budget) = &mut manifest.tokens {
+        if !tokens::admit(budget, url, tokens::estimate_file(url, &body, None)) {
+            return Ok(());
+        }
+    }
+    manifest.files.push(ManifestFile {
+        path: url.to_string(),
+        bytes: document.bytes,
+        binary: false,
+        sha256: sha256.clone(),
+        hard_link_to: None,
+    });
+    let file = render::FileEntry {
+        path: url.to_string(),
+        bytes: document.bytes,
+        language: document.language,
+        sha256,
+        docs_url: None,
+        change: None,
+        reasons: Vec::new(),
+        body,
+        matches,
+        match_count,
+    };
+    timings::measure(Phase::Writing, || out.file(&file))?;
     Ok(())
 }
 
-// Print all files in the project, respecting .gitignore, .contreeignore, grep filter, and include list
-fn print_project_files(
-    cwd: &PathBuf,
-    grep_pattern: &Option<String>,
-    include_files: &Option<Vec<PathBuf>>,
-    writer: &mut Box<dyn Write>,
+// Print a file unless it was already printed; another hard link to printed content gets a
+// header pointing at the first name instead of a second copy
+fn emit_file(
+    path: &Path,
+    emitted: &mut links::EmittedFiles,
+    options: &OutputOptions,
+    manifest: &mut Manifest,
+    out: &mut dyn render::Renderer,
 ) -> Result<()> {
-    // Compile the grep pattern into a regex if provided
-    let grep_regex = grep_pattern.as_ref().map(|pattern| {
-        let trimmed = pattern.trim();
-        if trimmed.starts_with('/') && trimmed.ends_with('/') {
-            Regex::new(&trimmed[1..trimmed.len() - 1])
-                .context("Invalid regex pattern")
-        } else {
-            Regex::new(&format!("(?i){}", regex::escape(trimmed)))
-                .context("Invalid grep pattern")
-        }
-    }).transpose()?;
-
-    // Build the walker for the directory
-    let mut builder = WalkBuilder::new(cwd);
-    builder.standard_filters(true); // Respect .gitignore, etc.
-    builder.hidden(false); // Skip hidden files/directories like .git by default
-    builder.git_ignore(true); // Respect .gitignore
-    builder.git_exclude(false);
-    builder.add_custom_ignore_filename(".contreeignore");
-    builder.add_ignore(".git"); // Explicitly ignore .git directories
-
-    // Add a custom filter to explicitly exclude .git directories at any depth
-    builder.filter_entry(|entry| {
-        !entry
-            .path()
+    match emitted.check(path)? {
+        links::Seen::New => print_file(path, options, manifest, out),
+        links::Seen::Duplicate => Ok(()),
+        links::Seen::HardLink(first) => {
+            let display_path = options.display_path(path);
+            let first = options.display_path(&first);
+            let body = render::FileBody::HardLink(first.clone());
+            if let Some(budget) = &mut manifest.tokens {
+                if !tokens::admit(budget, &display_path, tokens::estimate_file(&display_path, &body, None)) {
+                    return Ok(());
+                }
+            }
+            let bytes = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            manifest.files.push(ManifestFile {
+                path: display_path.clone(),
+                bytes,
+                binary: false,
+                sha256: None,
+                hard_link_to: Some(first.clone()),
+            });
+            out.file(&render::FileEntry {
+                path: display_path,
+                bytes,
+                language: None,
+                sha256: None,
+                docs_url: None,
+                change: options.changes.as_ref().and_then(|changes| changes.get(path)),
+                reasons: Vec::new(),
+                body,
+                matches: Vec::new(),
+                match_count: None,
+            })?;
+            Ok(())
+        }
+    }
+}
+
+// The files of the Project Context section, in output order
+struct ProjectFiles {
+    // Open editor buffers
+    priority: Vec<PathBuf>,
+    walked: Vec<PathBuf>,
+    // Why the walk ended early, if it did
+    walk_stopped: Option<String>,
+    // Submodules in the scanned directories, noted instead of walked with `--submodules shallow`
+    submodules: Vec<vcs::Submodule>,
+    included: Vec<PathBuf>,
+}
+
+impl ProjectFiles {
+    // Each file once, as shown in its header
+    fn display_paths(&self, options: &OutputOptions) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.priority
+            .iter()
+            .chain(&self.walked)
+            .chain(&self.included)
+            .map(|path| options.display_path(path))
+            .filter(|path| seen.insert(path.clone()))
+            .collect()
+    }
+}
+
+// Select priority files, then all files in the project (or the given paths), respecting .gitignore, .contreeignore, grep filter, and include list
+fn select_project_files(
+    fs: &dyn fileio::FileSystem,
+    cwd: &Path,
+    pathspecs: &[pathspec::Pathspec],
+    priority_files: &[PathBuf],
+    include_files: &Option<Vec<PathBuf>>,
+    options: &OutputOptions,
+) -> Result<ProjectFiles> {
+    // Walk the whole directory (or the directories of its [layout]), or only below the leading
+    // directories of the given pathspecs. Ignore files in the directory and its parents still
+    // apply to paths below them.
+    let mut roots = Vec::new();
+    let mut bases: Vec<&Path> = pathspecs.iter().filter(|p| !p.is_exclude()).map(|p| p.walk_base()).collect();
+    if bases.is_empty() && !options.layout.is_empty() {
+        bases.push(Path::new(""));
+    }
+    for base in bases {
+        for root in options.layout.walk_roots(cwd, base).into_iter().filter(|root| fs.kind(root).is_ok()) {
+            // Overlapping pathspecs (`'*.rs' '*.md'`, or `src` and `src/bin`) walk the outermost
+            // directory once
+            if roots.iter().any(|walked: &PathBuf| root.starts_with(walked)) {
+                continue;
+            }
+            roots.retain(|walked| !walked.starts_with(&root));
+            roots.push(root);
+        }
+    }
+    if roots.is_empty() {
+        roots.push(cwd.to_path_buf());
+    }
+
+    // Add a custom filter to explicitly exclude .git and .contree (session recordings) at any depth,
+    // directories removed by exclude pathspecs or --exclude globs, the build output and caches
+    // of the project's ecosystems, submodules unless --submodules includes them, files outside
+    // --ext or inside --no-ext, files .gitattributes marks generated or vendored, and tests or
+    // everything else for --no-tests and --tests-only
+    let walk_root = cwd.to_path_buf();
+    let walk_pathspecs = pathspecs.to_vec();
+    let walk_layout = options.layout.clone();
+    let walk_project = options.project.clone();
+    let walk_excludes = options.excludes.clone();
+    let walk_extensions = (options.extensions.clone(), options.excluded_extensions.clone());
+    let walk_tests = (options.no_tests, options.tests_only);
+    let walk_attributes = options.attributes.clone();
+    let walk_submodules: HashSet<PathBuf> = options.submodules.iter().map(|submodule| submodule.path.clone()).collect();
+    let filter: fileio::EntryFilter = Arc::new(move |path, is_dir| {
+        let relative = walk_layout.relative(path, &walk_root);
+        !path
             .components()
-            .any(|comp| comp.as_os_str() == ".git")
+            .any(|comp| comp.as_os_str() == ".contree" || vcs::METADATA_DIRS.iter().any(|dir| comp.as_os_str() == *dir))
+            && !pathspec::is_excluded(&walk_pathspecs, &relative)
+            && !walk_excludes.matched(&relative, is_dir).is_ignore()
+            && walk_project.excluded_by(&relative, is_dir).is_none()
+            && (!is_dir || !walk_submodules.contains(path))
+            && (is_dir || extension_selected(path, &walk_extensions.0, &walk_extensions.1))
+            && (is_dir || walk_attributes.as_ref().is_none_or(|attributes| attributes.linguist(path).is_none()))
+            // Test directories are pruned whole; --tests-only has to look inside every directory
+            && !(walk_tests.0 && is_test_path(&relative))
+            && (is_dir || !walk_tests.1 || is_test_path(&relative))
     });
 
-    let walker = builder.build();
-    for entry in walker {
+    // Priority files (open editor buffers) come first and bypass the grep filter
+    let mut files = ProjectFiles {
+        priority: Vec::new(),
+        walked: Vec::new(),
+        walk_stopped: None,
+        submodules: Vec::new(),
+        included: Vec::new(),
+    };
+    for path in priority_files {
+        if fs.kind(path).ok() != Some(fileio::EntryKind::File) {
+            warnings::warn(warnings::Kind::Skipped, format!("Skipping editor buffer {}: {}", path.display(), fileio::not_a_file_reason(fs, path)));
+            continue;
+        }
+        files.priority.push(path.clone());
+    }
+
+    // Guards against runs at `/` or on slow network mounts: stop the walk, keep what was
+    // collected, and say so in the output
+    let walk_started = Instant::now();
+    let mut walker = fs.walk(fileio::WalkOptions {
+        roots: roots.clone(),
+        max_depth: options.max_depth,
+        sorted: options.reproducible,
+        filter,
+    });
+    while let Some(entry) = timings::measure(Phase::Walking, || walker.next()) {
+        if let Some(max) = options.max_walk.filter(|max| walk_started.elapsed() >= *max) {
+            files.walk_stopped = Some(format!("it ran longer than --max-walk-seconds {}", max.as_secs()));
+            break;
+        }
         let entry = entry.context("Failed to read directory entry")?;
-        if entry.file_type().map_or(false, |ft| ft.is_file()) {
-            let path = entry.path();
-
-            // Apply grep filter if provided
-            if let Some(ref regex) = grep_regex {
-                let contents = match fs::read_to_string(path) {
-                    Ok(contents) => contents,
-                    Err(e) if e.to_string().contains("stream did not contain valid UTF-8") => continue, // Skip binary files
+        if let fileio::EntryKind::Special(kind) = entry.kind {
+            warnings::warn(warnings::Kind::Skipped, format!("Skipping {} {}", kind, entry.path.display()));
+            continue;
+        }
+        if entry.kind == fileio::EntryKind::File {
+            let path = entry.path.as_path();
+            if files.priority.iter().any(|priority| priority == path) {
+                continue; // Already listed as an editor buffer
+            }
+            if options.is_skipped(path) {
+                continue; // Excluded by a `skip` entry in [languages]
+            }
+            if !options.only_languages.is_empty()
+                && !options.language(path).is_some_and(|language| options.only_languages.contains(&language))
+            {
+                continue;
+            }
+            let relative = options.layout.relative(path, cwd);
+            if !pathspec::is_selected(pathspecs, &relative) {
+                continue;
+            }
+            if options.changed_since.as_ref().is_some_and(|changes| !changes.is_changed(path)) {
+                continue;
+            }
+            if options.git_status.as_ref().is_some_and(|selection| !selection.contains(path)) {
+                continue;
+            }
+
+            // Apply the grep filters, to the path relative to the scanned directory, the
+            // contents, or either (a matching path saves reading the file)
+            let mut grep_matched = true;
+            for regex in &options.grep_filters {
+                if options.grep_target != content::GrepTarget::Content && regex.is_match(&relative.to_string_lossy()) {
+                    continue;
+                }
+                grep_matched = options.grep_target != content::GrepTarget::Name
+                    && match timings::measure(Phase::Filtering, || fs.grep(path, regex)) {
+                        Ok(matched) => matched,
+                        Err(e) if fileio::is_not_text(&e) => false, // Skip binary files
+                        Err(e) => return Err(anyhow::Error::from(e).context(format!("Failed to read file: {}", path.display()))),
+                    };
+                if !grep_matched {
+                    break;
+                }
+            }
+            if !grep_matched {
+                continue; // Skip files that don't match the grep patterns
+            }
+
+            // Drop files whose contents match --grep-not; binary files can't, so they stay
+            if let Some(regex) = &options.grep_not {
+                let matched = match timings::measure(Phase::Filtering, || fs.grep(path, regex)) {
+                    Ok(matched) => matched,
+                    Err(e) if fileio::is_not_text(&e) => false,
                     Err(e) => return Err(anyhow::Error::from(e).context(format!("Failed to read file: {}", path.display()))),
                 };
-                if !regex.is_match(&contents) {
-                    continue; // Skip files that don't match the grep pattern
+                if matched {
+                    continue;
                 }
             }
 
-            print_file(path, writer)?;
+            // With `--set`, keep only files that belong to one of the sets
+            if !options.sets.is_empty() {
+                let contained = match timings::measure(Phase::Filtering, || sets::any_contains(&options.sets, fs, path, &relative)) {
+                    Ok(contained) => contained,
+                    Err(e) if fileio::is_not_text(&e) => continue, // Skip binary files
+                    Err(e) => return Err(anyhow::Error::from(e).context(format!("Failed to read file: {}", path.display()))),
+                };
+                if !contained {
+                    continue;
+                }
+            }
+
+            if let Some(max) = options.max_files.filter(|max| files.priority.len() + files.walked.len() >= *max) {
+                files.walk_stopped = Some(format!("--max-files {} was reached", max));
+                break;
+            }
+            files.walked.push(path.to_path_buf());
         }
     }
+    if let Some(churn) = &options.churn {
+        churn.rank(&mut files.walked);
+    }
+    if options.submodule_mode == vcs::SubmoduleMode::Shallow {
+        files.submodules = options
+            .submodules
+            .iter()
+            .filter(|submodule| roots.iter().any(|root| submodule.path.starts_with(root)))
+            .filter(|submodule| !pathspec::is_excluded(pathspecs, &options.layout.relative(&submodule.path, cwd)))
+            .cloned()
+            .collect();
+    }
 
     // Process explicitly included files
     if let Some(ref include_files) = include_files {
         for path in include_files {
-            // Skip if the file doesn't exist or isn't a file
-            if !path.exists() || !path.is_file() {
-                eprintln!("Warning: Included path {} does not exist or is not a file", path.display());
+            // Skip if the file doesn't exist or isn't a regular file
+            if fs.kind(path).ok() != Some(fileio::EntryKind::File) {
+                warnings::warn(warnings::Kind::Skipped, format!("Skipping included path {}: {}", path.display(), fileio::not_a_file_reason(fs, path)));
                 continue;
             }
 
-            // Print the file regardless of grep filter or directory
-            print_file(path, writer)?;
+            // Included regardless of grep filter or directory; printing skips it if the walk
+            // (or an earlier entry) already printed it
+            files.included.push(path.clone());
         }
     }
 
+    Ok(files)
+}
+
+// Print the selected project files, noting where the walk stopped early
+fn print_project_files(
+    files: &ProjectFiles,
+    options: &OutputOptions,
+    manifest: &mut Manifest,
+    out: &mut dyn render::Renderer,
+) -> Result<()> {
+    let mut emitted = links::EmittedFiles::default();
+    for path in files.priority.iter().chain(&files.walked) {
+        emit_file(path, &mut emitted, options, manifest, out)?;
+    }
+    for submodule in &files.submodules {
+        let url = submodule.url.as_ref().map(|url| format!(" ({})", url)).unwrap_or_default();
+        out.note(&format!(
+            "Submodule {}{} pinned at {}; its files are not included (--submodules shallow)",
+            options.display_path(&submodule.path),
+            url,
+            submodule.commit
+        ))?;
+    }
+
+    if let Some(reason) = &files.walk_stopped {
+        warnings::warn(warnings::Kind::Limit, format!("Stopped early because {}; the context is incomplete", reason));
+        out.note(&format!("Scan stopped early because {}; remaining files were not scanned", reason))?;
+        manifest.walk_stopped = Some(reason.clone());
+    }
+
+    for path in &files.included {
+        emit_file(path, &mut emitted, options, manifest, out)?;
+    }
+
     Ok(())
 }
 
 // Extract and print dependency files mentioned in errors (for Rust projects only)
 fn print_relevant_dependency_files(
     test_output: &str,
-    cwd: &PathBuf,
-    writer: &mut Box<dyn Write>,
+    extracted: &[extract::Location],
+    cwd: &Path,
+    sources: &DependencySources,
+    options: &OutputOptions,
+    manifest: &mut Manifest,
+    out: &mut dyn render::Renderer,
 ) -> Result<()> {
-    let mut relevant_files: HashMap<String, HashSet<String>> = HashMap::new();
+    let relevant_files = timings::measure(Phase::Dependencies, || {
+        find_relevant_dependency_files(test_output, extracted, cwd, sources)
+    })?;
+    let relevant_files: Vec<_> = relevant_files
+        .into_iter()
+        .filter(|(file_path, _)| !options.languages.is_skipped(Path::new(file_path), cwd))
+        .collect();
+
+    // Print the relevant files with their contents
+    if !relevant_files.is_empty() {
+        out.section("Relevant Dependency Files")?;
+        for (file_path, reasons) in relevant_files {
+            let path = Path::new(&file_path);
+            let display_path = options.display_path(path);
+            if let Some(budget) = manifest.tokens.as_mut().filter(|budget| tokens::exhausted(budget)) {
+                budget.skipped.push(display_path);
+                continue;
+            }
+            let docs_url = options.docs_links.then(|| docs_rs::module_url(path)).flatten();
+            let sha256 = options.hash.then(|| digest::sha256_file(path).ok()).flatten();
+            let contents = timings::measure(Phase::Reading, || content::read_text(path));
+            // Files syn can't parse are emitted in full
+            let public = match &contents {
+                Ok(contents) if options.deps_public_only => public_api::public_api(contents),
+                _ => None,
+            };
+            let body = match &contents {
+                Ok(contents) => render::FileBody::Text(options.transform(path, public.as_deref().unwrap_or(contents))),
+                Err(e) => render::FileBody::Unreadable(e.to_string()),
+            };
+            if let Some(budget) = &mut manifest.tokens {
+                if !tokens::admit(budget, &display_path, tokens::estimate_file(&display_path, &body, None)) {
+                    continue;
+                }
+            }
+            manifest.dependencies.push(ManifestDependency {
+                path: display_path.clone(),
+                reasons: reasons.iter().cloned().collect(),
+                docs_url: docs_url.clone(),
+            });
+            let file = render::FileEntry {
+                path: display_path,
+                bytes: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+                language: options.language(path),
+                sha256,
+                docs_url,
+                change: None,
+                reasons: reasons.iter().map(|reason| options.normalize_text(reason).into_owned()).collect(),
+                body,
+                matches: Vec::new(),
+                match_count: None,
+            };
+            timings::measure(Phase::Writing, || out.file(&file))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Find dependency files mentioned in errors, with the reasons each was selected
+fn find_relevant_dependency_files(
+    test_output: &str,
+    extracted: &[extract::Location],
+    cwd: &Path,
+    sources: &DependencySources,
+) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    let mut relevant_files: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
 
     // Handle direct file references in test output
     let re_direct = Regex::new(r"--> ([/\\].*?\.rs):(\d+):(\d+)")?;
     for cap in re_direct.captures_iter(test_output) {
         let file_path = cap.get(1).unwrap().as_str().to_string();
-        if file_path.contains(".cargo/registry") {
+        if sources.contains(Path::new(&file_path)) {
             relevant_files
                 .entry(file_path.clone())
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert("directly referenced".to_string());
         }
     }
+    for location in extracted {
+        let reason = match location.line {
+            Some(line) => format!("referenced in {} (line {})", location.origin, line),
+            None => format!("referenced in {}", location.origin),
+        };
+        relevant_files.entry(location.path.clone()).or_default().insert(reason);
+    }
 
     // Extract type and macro names from test output
     let re_type_e0599 = Regex::new(r"method not found in `([^`]+)`")?;
@@ -237,92 +2112,167 @@ fn print_relevant_dependency_files(
 
     // Only process Rust dependencies if in a Rust project
     if !is_rust_project(cwd) {
-        return Ok(()); // Skip dependency processing in non-Rust projects
+        return Ok(relevant_files); // Skip dependency processing in non-Rust projects
     }
 
-    // Get the used crate versions dynamically (only if in a Rust project)
-    let used_crate_versions = get_used_crate_versions(cwd)?;
-
-    // Determine the registry path dynamically
-    let cargo_home = env::var("CARGO_HOME").unwrap_or_else(|_| {
-        let home = env::var("HOME").expect("HOME environment variable not set");
-        format!("{}/.cargo", home)
-    });
-    let registry_path = PathBuf::from(cargo_home).join("registry").join("src");
+    // Match every type and macro name against each crate's cached index; only symbols the
+    // cache hasn't seen before cost a pass over the crate's files
+    let types: Vec<String> = types.into_iter().collect();
+    let macros: Vec<String> = macros.into_iter().collect();
+    let patterns: Vec<String> = types
+        .iter()
+        .map(|t| t.to_lowercase())
+        .chain(macros.iter().map(|m| format!("macro_rules! {}", m.to_lowercase())))
+        .collect();
 
     // Search only within directories matching used crate versions
-    for entry in WalkDir::new(registry_path)
-        .max_depth(2)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_dir() {
-            let dir_name = entry.file_name().to_str().unwrap();
-            if used_crate_versions.iter().any(|s| s == dir_name) {
-                for sub_entry in WalkDir::new(entry.path())
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                {
-                    let path = sub_entry.path();
-                    if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("rs") {
-                        let file_name = path.file_name().unwrap().to_str().unwrap().to_lowercase();
-                        let content = fs::read_to_string(path).unwrap_or_default().to_lowercase();
-
-                        // Check for types
-                        for type_name in &types {
-                            let type_name_lower = type_name.to_lowercase();
-                            if file_name.contains(&type_name_lower) || content.contains(&type_name_lower) {
-                                let path_str = path.to_str().unwrap().to_string();
-                                relevant_files
-                                    .entry(path_str.clone())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(format!("type {}", type_name));
-                            }
-                        }
-
-                        // Check for macros
-                        for macro_name in macros.iter() {
-                            if content.contains(&format!("macro_rules! {}", macro_name)) {
-                                let path_str = path.to_str().unwrap().to_string();
-                                relevant_files
-                                    .entry(path_str.clone())
-                                    .or_insert_with(HashSet::new)
-                                    .insert(format!("macro {}", macro_name));
-                            }
-                        }
-                    }
+    for package in &sources.packages {
+        let crate_dir = &package.dir;
+        let mut index = depcache::CrateIndex::load(crate_dir);
+        let hits = index.hits(crate_dir, &patterns)?;
+        if let Err(e) = index.save() {
+            warnings::warn(warnings::Kind::Dependencies, format!("Failed to cache dependency index: {:#}", e));
+        }
+
+        // Skip files gated off by features the build doesn't enable, when they are known
+        let mut feature_filter = package
+            .features
+            .as_deref()
+            .map(|features| cfg::FeatureFilter::new(crate_dir, features));
+
+        for (file_index, relative) in index.files.iter().enumerate() {
+            let path = crate_dir.join(relative);
+            let file_name = path.file_name().unwrap().to_str().unwrap().to_lowercase();
+            let mut reasons = BTreeSet::new();
+
+            // Check for types
+            for (i, type_name) in types.iter().enumerate() {
+                if hits[i].contains(&file_index) || file_name.contains(&type_name.to_lowercase()) {
+                    reasons.insert(format!("type {}", type_name));
+                }
+            }
+
+            // Check for macros
+            for (i, macro_name) in macros.iter().enumerate() {
+                if hits[types.len() + i].contains(&file_index) {
+                    reasons.insert(format!("macro {}", macro_name));
                 }
             }
+
+            if reasons.is_empty()
+                || feature_filter
+                    .as_mut()
+                    .is_some_and(|filter| !filter.is_enabled(Path::new(relative)))
+            {
+                continue;
+            }
+            relevant_files
+                .entry(path.to_str().unwrap().to_string())
+                .or_default()
+                .extend(reasons);
         }
     }
 
-    // Print the relevant files with their contents
-    if !relevant_files.is_empty() {
-        writeln!(writer, "\n=== Relevant Dependency Files ===\n")?;
-        for (file_path, reasons) in relevant_files {
-            writeln!(writer, "File: {}", file_path)?;
-            writeln!(writer, "  - {}", reasons.iter().map(|s| s.as_str()).collect::<Vec<_>>().join("\n  - "))?;
-            writeln!(writer, "```")?;
-            match fs::read_to_string(&file_path) {
-                Ok(contents) => writeln!(writer, "{}", contents)?,
-                Err(e) => writeln!(writer, "(Failed to read file: {})", e)?,
-            }
-            writeln!(writer, "```")?;
-            writeln!(writer)?;
+    Ok(relevant_files)
+}
+
+// Build the full symbol index for every dependency crate; a background run re-executes
+// this command detached so it keeps going after the shell prompt returns
+fn index_deps(cwd: &PathBuf, cargo_home: Option<&Path>, background: bool) -> Result<()> {
+    if !is_rust_project(cwd) {
+        anyhow::bail!("{} is not inside a Cargo project", cwd.display());
+    }
+    if background {
+        let mut command = Command::new(env::current_exe()?);
+        command.arg("--dir").arg(cwd);
+        if let Some(cargo_home) = cargo_home {
+            command.arg("--cargo-home").arg(cargo_home);
         }
+        let child = command
+            .arg("index-deps")
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .context("Failed to start background indexing")?;
+        eprintln!("Indexing dependencies in the background (pid {})", child.id());
+        return Ok(());
     }
 
+    let mut indexed = 0;
+    for package in dependency_packages(cwd, cargo_home) {
+        let mut index = depcache::CrateIndex::load(&package.dir);
+        // Vendored and git sources aren't cached, so there is nothing to build ahead for them
+        if index.is_cached() && !index.has_symbols() {
+            index.build_symbols(&package.dir);
+            index.save()?;
+            indexed += 1;
+        }
+    }
+    eprintln!("Indexed {} crates (others were already indexed, or aren't from a registry)", indexed);
     Ok(())
 }
 
+// Source directories (and resolved features) of the dependency crates the project uses. cargo metadata knows where
+// every registry, mirror, and git checkout put them; when it fails, fall back to matching
+// `cargo tree` versions against the default registry layout. Missing tools or registries
+// produce a warning and no dependency files rather than aborting the run.
+fn dependency_packages(cwd: &PathBuf, cargo_home: Option<&Path>) -> Vec<metadata::DependencyPackage> {
+    match metadata::dependency_packages(cwd, cargo_home) {
+        Ok(packages) => return packages,
+        Err(e) => warnings::warn(warnings::Kind::Dependencies, format!("{:#}; falling back to the default registry layout", e)),
+    }
+
+    // Get the used crate versions dynamically (only if in a Rust project)
+    let used_crate_versions = match get_used_crate_versions(cwd, cargo_home) {
+        Ok(versions) => versions,
+        Err(e) => {
+            warnings::warn(warnings::Kind::Dependencies, format!("{:#}; skipping dependency files", e));
+            return Vec::new();
+        }
+    };
+
+    // Determine the registry path: --cargo-home, then CARGO_HOME, then ~/.cargo
+    let Some(cargo_home) = metadata::cargo_home(cargo_home) else {
+        warnings::warn(warnings::Kind::Dependencies, "Cannot locate the Cargo registry (set CARGO_HOME or pass --cargo-home); skipping dependency files");
+        return Vec::new();
+    };
+    let registry_path = cargo_home.join("registry").join("src");
+    if !registry_path.is_dir() {
+        warnings::warn(
+            warnings::Kind::Dependencies,
+            format!("Cargo registry {} does not exist; skipping dependency files", registry_path.display()),
+        );
+        return Vec::new();
+    }
+
+    WalkDir::new(registry_path)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| {
+            entry.file_type().is_dir()
+                && entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|dir_name| used_crate_versions.iter().any(|s| s == dir_name))
+        })
+        .map(|entry| metadata::DependencyPackage {
+            dir: entry.into_path(),
+            features: None,
+        })
+        .collect()
+}
+
 // Function to get used crate versions dynamically (for Rust projects)
-fn get_used_crate_versions(cwd: &PathBuf) -> Result<Vec<String>> {
+fn get_used_crate_versions(cwd: &PathBuf, cargo_home: Option<&Path>) -> Result<Vec<String>> {
     // Run `cargo tree` in the project directory
-    let output = Command::new("cargo")
-        .arg("tree")
-        .current_dir(cwd)
-        .output()
-        .context("Failed to run cargo tree")?;
+    let mut command = Command::new("cargo");
+    command.arg("tree").current_dir(cwd);
+    if let Some(cargo_home) = cargo_home {
+        command.env("CARGO_HOME", cargo_home);
+    }
+    let output = command.output().context("Failed to run cargo tree")?;
 
     // Convert output to a UTF-8 string
     let output_str = String::from_utf8(output.stdout)
@@ -343,4 +2293,142 @@ fn get_used_crate_versions(cwd: &PathBuf) -> Result<Vec<String>> {
     }
 
     Ok(crate_versions.into_iter().collect())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decides_whether_the_run_failed() {
+        let exited = |code| capture::CommandRun { command: Some("cargo test".to_string()), exit_code: Some(code), signal: None, duration: None };
+        assert!(run_failed(&[Some(exited(0)), Some(exited(101))], ""));
+        // The exit status wins over what the output says
+        assert!(!run_failed(&[Some(exited(0))], "error: warning treated as an error\n"));
+        let piped = [Some(capture::CommandRun::piped(Duration::from_secs(1)))];
+        assert!(run_failed(&piped, "error[E0308]: mismatched types\n"));
+        assert!(run_failed(&piped, "test tests::adds ... FAILED\n"));
+        assert!(!run_failed(&piped, "test result: ok. 3 passed\n"));
+        assert!(!run_failed(&[], ""));
+    }
+
+    #[test]
+    fn recognizes_test_files() {
+        for test in ["tests/cli.rs", "src/parser/tests.rs", "pkg/api_test.go", "tools/test_cli.py", "web/src/App.test.tsx", "web/__tests__/a.js", "spec/models/user_spec.rb", "src/FooTest.java"] {
+            assert!(is_test_path(Path::new(test)), "{}", test);
+        }
+        for source in ["src/main.rs", "src/testing.rs", "latest.py", "web/src/spec.ts", "contest/main.go"] {
+            assert!(!is_test_path(Path::new(source)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4096));
+        assert_eq!(parse_size("2 MiB"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("12x").is_err());
+        assert!(parse_size("k").is_err());
+        assert!(parse_size("99999999999999999999g").is_err());
+    }
+
+    #[test]
+    fn replays_drop_the_run_subcommand() {
+        let argv: Vec<OsString> = ["contree", "-g", "run", "run", "--", "cargo", "test", "--", "--nocapture"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        let args = Args::parse_from(&argv);
+        assert!(matches!(&args.command, Some(Commands::Run { command }) if command[0] == "cargo"));
+        assert_eq!(replay_argv(&args, &argv), ["-g", "run"]);
+    }
+
+    // Select files from an in-memory project at /mem with the given command line
+    fn select(fs: &fileio::MemoryFs, argv: &[&str], priority: &[&str]) -> ProjectFiles {
+        let args = Args::parse_from(std::iter::once("contree").chain(argv.iter().copied()));
+        let root = Path::new("/mem");
+        let options = OutputOptions::from_args(&args, root).unwrap();
+        let priority: Vec<PathBuf> = priority.iter().map(PathBuf::from).collect();
+        select_project_files(fs, root, &args.pathspecs(), &priority, &args.include, &options).unwrap()
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths.iter().map(|path| path.display().to_string()).collect()
+    }
+
+    #[test]
+    fn selection_applies_pathspecs_grep_and_priority() {
+        let fs = fileio::MemoryFs::default()
+            .with("/mem/src/main.rs", "fn main() { retry(); }")
+            .with("/mem/src/lib.rs", "pub fn lib() {}")
+            .with("/mem/src/logo.png", [0x89, 0x50, 0xff, 0x00])
+            .with("/mem/vendor/dep.rs", "fn retry() {}")
+            .with("/mem/.git/config", "[core]")
+            .with("/mem/notes.md", "retry later");
+
+        let files = select(&fs, &["-g", "retry", ":!vendor", "-i", "/mem/missing.rs"], &["/mem/src/lib.rs", "/mem/gone.rs"]);
+        assert_eq!(names(&files.priority), ["/mem/src/lib.rs"]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md", "/mem/src/main.rs"]);
+        assert!(files.included.is_empty());
+        assert!(files.walk_stopped.is_none());
+
+        let files = select(&fs, &["src", "--max-files", "2"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/src/lib.rs", "/mem/src/logo.png"]);
+        assert_eq!(files.walk_stopped.as_deref(), Some("--max-files 2 was reached"));
+
+        let files = select(&fs, &["--max-depth", "1", "-i", "/mem/vendor/dep.rs"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md"]);
+        assert_eq!(names(&files.included), ["/mem/vendor/dep.rs"]);
+
+        let files = select(&fs, &["-e", "*.png", "--exclude", "vendor/**", "-e", "notes.md"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/src/lib.rs", "/mem/src/main.rs"]);
+
+        let files = select(&fs, &["-g", "/^src/", "--grep-target", "name"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/src/lib.rs", "/mem/src/logo.png", "/mem/src/main.rs"]);
+        let files = select(&fs, &["-g", "/lib|retry/", "--grep-target", "both", ":!vendor"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md", "/mem/src/lib.rs", "/mem/src/main.rs"]);
+
+        let files = select(&fs, &["-g", "retry", "-g", "lib", ":!vendor"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md", "/mem/src/lib.rs", "/mem/src/main.rs"]);
+        let files = select(&fs, &["-g", "retry", "-g", "/^fn/", "--grep-mode", "all"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/src/main.rs", "/mem/vendor/dep.rs"]);
+        let files = select(&fs, &["-g", "/^src/", "-g", "main", "--grep-mode", "all", "--grep-target", "both"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/src/main.rs"]);
+
+        let files = select(&fs, &["-g", "/Retry|LIB/", ":!vendor"], &[]);
+        assert!(files.walked.is_empty());
+        let files = select(&fs, &["-g", "/retry|lib/", "--smart-case", ":!vendor"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md", "/mem/src/lib.rs", "/mem/src/main.rs"]);
+        let files = select(&fs, &["-g", "Retry", "--case-sensitive"], &[]);
+        assert!(files.walked.is_empty());
+
+        let files = select(&fs, &["--ext", "RS,.md", "--no-ext", "md", ":!vendor"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/src/lib.rs", "/mem/src/main.rs"]);
+        let files = select(&fs, &["--no-ext", "rs,png"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md"]);
+        let files = select(&fs, &["--lang", "Markdown", "--lang", "rust", ":!vendor"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md", "/mem/src/lib.rs", "/mem/src/main.rs"]);
+
+        let files = select(&fs, &["-g", "retry", "--grep-not", "/fn retry/"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md", "/mem/src/main.rs"]);
+        let files = select(&fs, &["--grep-not", "fn", "src"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/src/logo.png"]);
+
+        let files = select(&fs, &["--include-glob", "**/*.rs", "--include-glob", "*.md", "-e", "vendor/"], &[]);
+        assert_eq!(names(&files.walked), ["/mem/notes.md", "/mem/src/lib.rs", "/mem/src/main.rs"]);
+    }
+
+    #[test]
+    fn command_line_overrides_config_defaults() {
+        let config: config::Config =
+            toml::from_str("[defaults]\ngrep = \"retry\"\nformat = \"markdown\"\ninclude = [\"a.rs\"]\ninclude_deps = true").unwrap();
+        let argv = ["contree", "-g", "fail", "-i", "b.rs"].iter().map(OsString::from).collect();
+        let args = Args::parse_from(defaults::apply(argv, &config, None, &Args::command()).unwrap());
+        assert_eq!(args.grep, ["fail"]);
+        assert_eq!(args.format, render::Format::Markdown);
+        assert_eq!(args.include, Some(vec![PathBuf::from("a.rs"), PathBuf::from("b.rs")]));
+        assert!(args.include_deps);
+    }
+}
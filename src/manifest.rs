@@ -0,0 +1,132 @@
+use crate::capture::CommandRun;
+use crate::warnings::Warning;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+// Bumped whenever a field is removed or changes meaning
+pub const MANIFEST_VERSION: u32 = 1;
+
+// Machine-readable sidecar describing what went into a generated context
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub version: u32,
+    pub generator: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_sha256: Option<String>,
+    pub files: Vec<ManifestFile>,
+    pub dependencies: Vec<ManifestDependency>,
+    // How the captured command ended (exit status only when contree ran it with --run)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<ManifestCommand>,
+    // Each command in the order it ran, instead of `command`, when --run was given several times
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<ManifestCommand>,
+    // Why the project walk ended before covering the whole tree (--max-walk-seconds, --max-files)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub walk_stopped: Option<String>,
+    // With --max-tokens: the estimated size of the context and the files left out to stay under it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<ManifestTokens>,
+    // Everything printed as a warning while generating the context
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ManifestSignature>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestFile {
+    pub path: String,
+    pub bytes: u64,
+    pub binary: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    // Set when this path is another hard link to a file listed earlier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hard_link_to: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestDependency {
+    pub path: String,
+    pub reasons: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub docs_url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestCommand {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    // Unix signal that killed the command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl From<&CommandRun> for ManifestCommand {
+    fn from(run: &CommandRun) -> Self {
+        ManifestCommand {
+            command: run.command.clone(),
+            exit_code: run.exit_code,
+            signal: run.signal,
+            duration_ms: run.duration.map(|duration| duration.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestTokens {
+    pub max: usize,
+    pub estimated: usize,
+    pub skipped: Vec<String>,
+}
+
+// Who signed the output, and where the detached signature was written
+#[derive(Serialize, Debug)]
+pub struct ManifestSignature {
+    pub format: String,
+    pub signer: String,
+    pub path: String,
+}
+
+impl Manifest {
+    // Why the context is incomplete: each budget or size guard that was tripped
+    pub fn exceeded(&self) -> Vec<String> {
+        let mut reasons: Vec<String> = self.walk_stopped.iter().cloned().collect();
+        if let Some(tokens) = self.tokens.as_ref().filter(|tokens| !tokens.skipped.is_empty()) {
+            reasons.push(format!("--max-tokens {} was reached", tokens.max));
+        }
+        reasons
+    }
+
+    pub fn new() -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            generator: concat!("contree ", env!("CARGO_PKG_VERSION")).to_string(),
+            output: None,
+            output_sha256: None,
+            files: Vec::new(),
+            dependencies: Vec::new(),
+            command: None,
+            commands: Vec::new(),
+            walk_stopped: None,
+            tokens: None,
+            warnings: Vec::new(),
+            signature: None,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write manifest: {}", path.display()))
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{Context as _, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const COMMAND_OUTPUT: &str = "=== Command Output ===";
+const PROJECT_CONTEXT: &str = "=== Project Context ===";
+const DEPENDENCY_FILES: &str = "=== Relevant Dependency Files ===";
+
+// One `File: ...` entry of a generated context: the header lines after the path (hash, docs,
+// reasons, hard link notes) and the fenced contents, if any
+#[derive(Debug, Clone)]
+pub struct FileBlock {
+    pub path: String,
+    pub header: Vec<String>,
+    // The info string after the opening "```", and the contents
+    pub fence: Option<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedContext {
+    pub command_output: Option<String>,
+    pub files: Vec<FileBlock>,
+    pub dependencies: Vec<FileBlock>,
+}
+
+// Split a context back into its sections. Fences aren't escaped in the text format, so a
+// fence only closes at a "```" line followed by a blank line and the next entry or section.
+pub fn parse(text: &str) -> ParsedContext {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut parsed = ParsedContext::default();
+    let mut section = "";
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.starts_with("=== ") && line.ends_with(" ===") {
+            section = line;
+            i += 1;
+            continue;
+        }
+        if section.starts_with(COMMAND_OUTPUT.trim_end_matches(" ===")) && line.starts_with("```") {
+            let end = closing_fence(&lines, i + 1);
+            parsed.command_output = Some(lines[i + 1..end].join("\n"));
+            i = end + 1;
+            continue;
+        }
+        let Some(path) = line.strip_prefix("File: ").filter(|_| section == PROJECT_CONTEXT || section == DEPENDENCY_FILES) else {
+            i += 1;
+            continue;
+        };
+        let mut block = FileBlock {
+            path: path.to_string(),
+            header: Vec::new(),
+            fence: None,
+        };
+        i += 1;
+        while i < lines.len() && !lines[i].is_empty() && !lines[i].starts_with("```") {
+            block.header.push(lines[i].to_string());
+            i += 1;
+        }
+        if let Some(tag) = lines.get(i).and_then(|line| line.strip_prefix("```")) {
+            let end = closing_fence(&lines, i + 1);
+            block.fence = Some((tag.to_string(), lines[i + 1..end].join("\n")));
+            i = end + 1;
+        }
+        if section == PROJECT_CONTEXT {
+            parsed.files.push(block);
+        } else {
+            parsed.dependencies.push(block);
+        }
+    }
+    parsed
+}
+
+// Index of the "```" line closing a fence opened just before `start`
+fn closing_fence(lines: &[&str], start: usize) -> usize {
+    (start..lines.len())
+        .find(|&i| {
+            lines[i] == "```" && {
+                let next = lines[i + 1..].iter().find(|line| !line.is_empty());
+                next.is_none_or(|next| next.starts_with("File: ") || next.starts_with("=== ") || next.starts_with('['))
+            }
+        })
+        .unwrap_or(lines.len())
+}
+
+// Entries keyed by path; a later entry for the same path replaces the earlier one in place.
+// Only differing contents are reported: headers vary with options such as --hash.
+#[derive(Default)]
+struct Entries {
+    blocks: Vec<FileBlock>,
+    positions: HashMap<String, usize>,
+}
+
+impl Entries {
+    fn insert(&mut self, block: FileBlock, source: &Path) {
+        match self.positions.get(&block.path) {
+            Some(&position) => {
+                if self.blocks[position].fence != block.fence {
+                    eprintln!("Note: {} differs between inputs; keeping the version from {}", block.path, source.display());
+                }
+                self.blocks[position] = block;
+            }
+            None => {
+                self.positions.insert(block.path.clone(), self.blocks.len());
+                self.blocks.push(block);
+            }
+        }
+    }
+}
+
+// Merge previously generated contexts into one: files are deduplicated by path, and where
+// contexts disagree the one modified most recently wins. Each input's command output is kept.
+pub fn merge(inputs: &[PathBuf], writer: &mut dyn Write) -> Result<()> {
+    let mut contexts = Vec::new();
+    for input in inputs {
+        let text = fs::read_to_string(input).with_context(|| format!("Failed to read {}", input.display()))?;
+        let modified = fs::metadata(input).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+        contexts.push((modified, input, parse(&text)));
+    }
+    // Stable, so equally old inputs keep command line order and the later one wins
+    contexts.sort_by_key(|(modified, _, _)| *modified);
+
+    let mut files = Entries::default();
+    let mut dependencies = Entries::default();
+    let mut outputs = Vec::new();
+    for (_, input, context) in contexts {
+        if let Some(output) = context.command_output {
+            outputs.push((input, output));
+        }
+        for block in context.files {
+            files.insert(block, input);
+        }
+        for block in context.dependencies {
+            dependencies.insert(block, input);
+        }
+    }
+
+    for (input, output) in outputs {
+        writeln!(writer, "\n=== Command Output ({}) ===\n", input.display())?;
+        writeln!(writer, "```\n{}\n```", output)?;
+    }
+    writeln!(writer, "\n{}\n", PROJECT_CONTEXT)?;
+    write_blocks(&files.blocks, writer)?;
+    if !dependencies.blocks.is_empty() {
+        writeln!(writer, "\n{}\n", DEPENDENCY_FILES)?;
+        write_blocks(&dependencies.blocks, writer)?;
+    }
+    Ok(())
+}
+
+fn write_blocks(blocks: &[FileBlock], writer: &mut dyn Write) -> Result<()> {
+    for block in blocks {
+        writeln!(writer, "File: {}", block.path)?;
+        for line in &block.header {
+            writeln!(writer, "{}", line)?;
+        }
+        if let Some((tag, contents)) = &block.fence {
+            writeln!(writer, "```{}\n{}\n```", tag, contents)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTEXT: &str = "\n=== Command Output ===\n\n```\nerror: boom\n```\n\n=== Project Context ===\n\n\
+        File: a.md\nSHA-256: 00\n```markdown\n# Title\n```\nstill a.md\n\n```\n\n\
+        File: b.txt\n(hard link to a.md; content shown there)\n\n\
+        \n=== Relevant Dependency Files ===\n\n\
+        File: /registry/c.rs\n  - type C\n```rust\npub struct C;\n\n```\n\n";
+
+    #[test]
+    fn parses_sections_and_fences_containing_fences() {
+        let parsed = parse(CONTEXT);
+        assert_eq!(parsed.command_output.as_deref(), Some("error: boom"));
+        assert_eq!(parsed.files.len(), 2);
+        assert_eq!(parsed.files[0].header, ["SHA-256: 00"]);
+        let (tag, contents) = parsed.files[0].fence.as_ref().unwrap();
+        assert_eq!(tag, "markdown");
+        assert_eq!(contents, "# Title\n```\nstill a.md\n");
+        assert_eq!(parsed.files[1].header, ["(hard link to a.md; content shown there)"]);
+        assert!(parsed.files[1].fence.is_none());
+        assert_eq!(parsed.dependencies[0].path, "/registry/c.rs");
+        assert_eq!(parsed.dependencies[0].header, ["  - type C"]);
+    }
+
+    #[test]
+    fn merged_output_parses_back_to_the_same_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.md");
+        let second = dir.path().join("second.md");
+        fs::write(&first, CONTEXT).unwrap();
+        fs::write(&second, CONTEXT.replace("pub struct C;", "pub struct C(u8);")).unwrap();
+
+        let mut merged = Vec::new();
+        merge(&[first, second], &mut merged).unwrap();
+        let reparsed = parse(&String::from_utf8(merged).unwrap());
+        assert_eq!(reparsed.files.len(), 2);
+        assert_eq!(reparsed.files[0].fence, parse(CONTEXT).files[0].fence);
+        assert_eq!(reparsed.dependencies.len(), 1);
+        assert!(reparsed.dependencies[0].fence.as_ref().unwrap().1.contains("C(u8)"));
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// A non-local package the project depends on directly
+#[derive(Debug, Clone)]
+pub struct DependencyPackage {
+    // Directory holding the package sources, wherever its registry, mirror, or git checkout put it
+    pub dir: PathBuf,
+    // Features enabled for this package in the resolved build, when known
+    pub features: Option<Vec<String>>,
+}
+
+// Resolve dependency packages with `cargo metadata`, which reports each package's actual
+// manifest path. That covers sparse and alternative registries, mirrors, vendored sources,
+// and git dependencies alike. Path dependencies (no source) are part of the project and skipped.
+pub fn dependency_packages(cwd: &Path, cargo_home: Option<&Path>) -> Result<Vec<DependencyPackage>> {
+    let mut command = Command::new("cargo");
+    command.args(["metadata", "--format-version", "1"]).current_dir(cwd);
+    if let Some(cargo_home) = cargo_home {
+        command.env("CARGO_HOME", cargo_home);
+    }
+    // Match `cargo tree`, which only shows dependencies for the host platform
+    if let Some(host) = host_triple() {
+        command.args(["--filter-platform", &host]);
+    }
+    let output = command.output().context("Failed to run cargo metadata")?;
+    if !output.status.success() {
+        // The final line carries cargo's error; earlier ones are progress and retries
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("cargo metadata failed: {}", stderr.trim().lines().last().unwrap_or_default());
+    }
+    let metadata: Value =
+        serde_json::from_slice(&output.stdout).context("cargo metadata returned invalid JSON")?;
+
+    // Direct dependencies of workspace members, matching the crates `cargo tree` lists first;
+    // packages absent from the resolve graph (other platforms) aren't built at all
+    let members: HashSet<&str> = metadata["workspace_members"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let features: HashMap<&str, Vec<String>> = metadata["resolve"]["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|node| {
+            let features = node["features"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|f| f.as_str().map(str::to_string))
+                .collect();
+            Some((node["id"].as_str()?, features))
+        })
+        .collect();
+    let direct: HashSet<&str> = metadata["resolve"]["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|node| node["id"].as_str().is_some_and(|id| members.contains(id)))
+        .flat_map(|node| node["deps"].as_array().into_iter().flatten())
+        .filter_map(|dep| dep["pkg"].as_str())
+        .collect();
+
+    let mut packages = Vec::new();
+    for package in metadata["packages"].as_array().into_iter().flatten() {
+        let (Some(id), Some(_source), Some(manifest_path)) = (
+            package["id"].as_str(),
+            package["source"].as_str(),
+            package["manifest_path"].as_str(),
+        ) else {
+            continue;
+        };
+        if !direct.contains(id) {
+            continue;
+        }
+        let Some(dir) = Path::new(manifest_path).parent() else {
+            continue;
+        };
+        packages.push(DependencyPackage {
+            dir: dir.to_path_buf(),
+            features: features.get(id).cloned(),
+        });
+    }
+    Ok(packages)
+}
+
+// Cargo home: --cargo-home, then $CARGO_HOME, then ~/.cargo
+pub fn cargo_home(explicit: Option<&Path>) -> Option<PathBuf> {
+    explicit
+        .map(Path::to_path_buf)
+        .or_else(|| env::var_os("CARGO_HOME").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")))
+}
+
+// Directories under a Cargo home that hold downloaded dependency sources
+pub fn source_roots(cargo_home: &Path) -> Vec<PathBuf> {
+    vec![cargo_home.join("registry").join("src"), cargo_home.join("git").join("checkouts")]
+}
+
+fn host_triple() -> Option<String> {
+    let output = Command::new("rustc").arg("-vV").output().ok()?;
+    String::from_utf8(output.stdout)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("host: ").map(str::to_string))
+}
@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+
+// Collapse runs of blank lines to one and strip trailing whitespace.
+// With `reduce_indent`, each indentation level becomes a single space; the unit is inferred
+// from the smallest indent in the file so relative nesting (and Python blocks) is preserved.
+pub fn minify_whitespace(contents: &str, reduce_indent: bool) -> String {
+    let unit = if reduce_indent { indent_unit(contents) } else { None };
+
+    let mut minified = String::with_capacity(contents.len());
+    let mut previous_blank = false;
+    for line in contents.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if !previous_blank {
+                minified.push('\n');
+            }
+            previous_blank = true;
+            continue;
+        }
+        previous_blank = false;
+
+        let line: Cow<str> = match unit {
+            Some(unit) => {
+                let (width, rest) = leading_width(line);
+                Cow::Owned(format!("{}{}", " ".repeat(width.div_ceil(unit)), rest))
+            }
+            None => Cow::Borrowed(line),
+        };
+        minified.push_str(&line);
+        minified.push('\n');
+    }
+    minified
+}
+
+// Width of a line's indentation (tabs count as four columns) and the text after it
+fn leading_width(line: &str) -> (usize, &str) {
+    let rest = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - rest.len()];
+    let width = indent.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
+    (width, rest)
+}
+
+fn indent_unit(contents: &str) -> Option<usize> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| leading_width(line).0)
+        .filter(|width| *width > 0)
+        .min()
+}
@@ -0,0 +1,264 @@
+use anyhow::Result;
+use globset::{GlobBuilder, GlobMatcher};
+use crate::layout::Layout;
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+
+// A positional path argument with git pathspec semantics. Magic comes in long form
+// (`:(exclude,icase)vendor`) or short form (`:!vendor`, `:^vendor`, `:/src`):
+// - no magic: `*` and `?` match across `/`, as in git's default fnmatch matching
+// - `glob`: `*` stops at `/` and `**` matches any number of directories
+// - `literal`: no wildcards at all
+// - `icase`: case-insensitive
+// - `exclude` (`!`, `^`): drop matching files; with only exclusions, everything else is kept
+// - `top` (`/`): accepted for compatibility; pathspecs are always relative to the scanned directory
+// A pathspec matches a file if it matches the file's path or any directory containing it.
+#[derive(Debug, Clone)]
+pub struct Pathspec {
+    spec: String,
+    pattern: String,
+    matcher: GlobMatcher,
+    exclude: bool,
+    walk_base: Option<PathBuf>,
+}
+
+impl Pathspec {
+    // Parse a pathspec; used as a clap value parser so unknown magic fails up front
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (magic, pattern) = split_magic(spec)?;
+        let mut exclude = false;
+        let mut glob = false;
+        let mut literal = false;
+        let mut icase = false;
+        for word in magic {
+            match word.as_str() {
+                "exclude" => exclude = true,
+                "glob" => glob = true,
+                "literal" => literal = true,
+                "icase" => icase = true,
+                "top" => {}
+                other => return Err(format!("Unsupported pathspec magic '{}' in '{}'", other, spec)),
+            }
+        }
+        if glob && literal {
+            return Err(format!("'glob' and 'literal' pathspec magic are incompatible in '{}'", spec));
+        }
+
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+        let pattern = if pattern == "." { "" } else { pattern };
+        let glob_pattern = match (pattern.is_empty(), literal) {
+            (true, _) => "**".to_string(),
+            (false, true) => globset::escape(pattern),
+            (false, false) => pattern.to_string(),
+        };
+        let matcher = GlobBuilder::new(&glob_pattern)
+            .literal_separator(glob)
+            .case_insensitive(icase)
+            .backslash_escape(true)
+            .build()
+            .map_err(|e| format!("Invalid pathspec '{}': {}", spec, e))?
+            .compile_matcher();
+
+        // The walk can start below the scanned directory at the pattern's leading literal
+        // directories; case-insensitive patterns can't name a directory on disk, so they walk everything
+        let walk_base = (!icase).then(|| {
+            Path::new(pattern)
+                .components()
+                .take_while(|c| literal || !c.as_os_str().to_string_lossy().contains(['*', '?', '[', '\\']))
+                .collect()
+        });
+        Ok(Pathspec {
+            spec: spec.to_string(),
+            pattern: pattern.to_string(),
+            matcher,
+            exclude,
+            walk_base,
+        })
+    }
+
+    // Value parser for options that only take exclusions, like `check-ignore --exclude`
+    pub fn parse_exclude(spec: &str) -> Result<Self, String> {
+        let pathspec = Pathspec::parse(spec)?;
+        if !pathspec.exclude {
+            return Err(format!("'{}' is not an exclude pathspec (use ':!{}')", spec, spec));
+        }
+        Ok(pathspec)
+    }
+
+    // Value parser for options that take globs, like `--include-glob`: the pattern as a
+    // `:(glob)` pathspec, so `*` stops at `/` and `**` spans directories
+    pub fn parse_glob(pattern: &str) -> Result<Self, String> {
+        if pattern.starts_with(':') {
+            return Err(format!("'{}' is a pathspec; pass it as a path instead", pattern));
+        }
+        Pathspec::parse(&format!(":(glob){}", pattern))
+    }
+
+    pub fn is_exclude(&self) -> bool {
+        self.exclude
+    }
+
+    // The pathspec as given on the command line
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    // Directory (relative to the scanned directory) that every match lies under
+    pub fn walk_base(&self) -> &Path {
+        self.walk_base.as_deref().unwrap_or(Path::new(""))
+    }
+
+    // Whether the pattern names an exact path rather than a wildcard or case-insensitive match
+    pub fn is_plain_path(&self) -> bool {
+        self.walk_base() == Path::new(&self.pattern)
+    }
+
+    fn matches(&self, relative: &Path) -> bool {
+        relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| self.matcher.is_match(ancestor))
+    }
+}
+
+// Split `:(magic,words)pattern` or `:<short magic>[:]pattern` into magic words and the pattern
+fn split_magic(spec: &str) -> Result<(Vec<String>, &str), String> {
+    let Some(rest) = spec.strip_prefix(':') else {
+        return Ok((Vec::new(), spec));
+    };
+    if let Some(long) = rest.strip_prefix('(') {
+        let (words, pattern) = long
+            .split_once(')')
+            .ok_or_else(|| format!("Missing ')' in pathspec magic '{}'", spec))?;
+        let words = words
+            .split(',')
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        return Ok((words, pattern));
+    }
+
+    let mut words = Vec::new();
+    let mut pattern = rest;
+    while let Some(c) = pattern.chars().next() {
+        let word = match c {
+            '!' | '^' => "exclude",
+            '/' => "top",
+            _ => break,
+        };
+        words.push(word.to_string());
+        pattern = &pattern[1..];
+    }
+    Ok((words, pattern.strip_prefix(':').unwrap_or(pattern)))
+}
+
+// Git semantics: a file is selected if it matches some non-exclude pathspec (or there are none)
+// and no exclude pathspec
+pub fn is_selected(pathspecs: &[Pathspec], relative: &Path) -> bool {
+    let (excludes, includes): (Vec<&Pathspec>, Vec<&Pathspec>) = pathspecs.iter().partition(|p| p.is_exclude());
+    (includes.is_empty() || includes.iter().any(|p| p.matches(relative)))
+        && !excludes.iter().any(|p| p.matches(relative))
+}
+
+// Whether a directory is excluded as a whole, so the walk can skip it
+pub fn is_excluded(pathspecs: &[Pathspec], relative: &Path) -> bool {
+    excluded_by(pathspecs, relative).is_some()
+}
+
+// The exclude pathspec that drops a path, if any
+pub fn excluded_by<'a>(pathspecs: &'a [Pathspec], relative: &Path) -> Option<&'a Pathspec> {
+    pathspecs.iter().find(|p| p.is_exclude() && p.matches(relative))
+}
+
+// Like git, fail when a pathspec matches no files. Checked before any output is written, so a
+// typo never leaves a partial context behind.
+// With a [layout], pathspecs name paths in the virtual tree.
+pub fn check_matches(pathspecs: &[Pathspec], root: &Path, layout: &Layout) -> Result<()> {
+    for pathspec in pathspecs.iter().filter(|p| !p.is_exclude()) {
+        let matched = layout.walk_roots(root, pathspec.walk_base()).iter().any(|base| {
+            base.exists()
+                && (pathspec.is_plain_path()
+                    || WalkBuilder::new(base)
+                        .hidden(false)
+                        .add_custom_ignore_filename(".contreeignore")
+                        .build()
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+                        .any(|entry| pathspec.matches(&layout.relative(entry.path(), root))))
+        });
+        if !matched {
+            anyhow::bail!("Pathspec '{}' did not match any files in {}", pathspec.spec, root.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(specs: &[&str]) -> Vec<Pathspec> {
+        specs.iter().map(|spec| Pathspec::parse(spec).unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_long_and_short_magic() {
+        assert!(Pathspec::parse(":(exclude)vendor").unwrap().is_exclude());
+        assert!(Pathspec::parse(":!vendor").unwrap().is_exclude());
+        assert!(Pathspec::parse(":^vendor").unwrap().is_exclude());
+        assert!(!Pathspec::parse(":/src").unwrap().is_exclude());
+        assert!(Pathspec::parse(":(bogus)x").is_err());
+        assert!(Pathspec::parse(":(glob,literal)x").is_err());
+        assert!(Pathspec::parse(":(glob").is_err());
+    }
+
+    #[test]
+    fn walk_base_stops_at_first_wildcard() {
+        assert_eq!(Pathspec::parse("src/api/*.rs").unwrap().walk_base(), Path::new("src/api"));
+        assert_eq!(Pathspec::parse(":(literal)src/*").unwrap().walk_base(), Path::new("src/*"));
+        assert_eq!(Pathspec::parse(":(icase)SRC").unwrap().walk_base(), Path::new(""));
+        assert!(Pathspec::parse("./src/").unwrap().is_plain_path());
+        assert!(!Pathspec::parse("src/*.rs").unwrap().is_plain_path());
+    }
+
+    #[test]
+    fn default_wildcards_cross_directories_but_glob_magic_does_not() {
+        let default = specs(&["src/*.rs"]);
+        assert!(is_selected(&default, Path::new("src/main.rs")));
+        assert!(is_selected(&default, Path::new("src/sub/deep.rs")));
+
+        let glob = specs(&[":(glob)src/*.rs"]);
+        assert!(is_selected(&glob, Path::new("src/main.rs")));
+        assert!(!is_selected(&glob, Path::new("src/sub/deep.rs")));
+        assert!(is_selected(&specs(&[":(glob)src/**/*.rs"]), Path::new("src/sub/deep.rs")));
+    }
+
+    #[test]
+    fn directories_match_their_contents() {
+        let dir = specs(&["src"]);
+        assert!(is_selected(&dir, Path::new("src/a/b.rs")));
+        assert!(!is_selected(&dir, Path::new("srcs/b.rs")));
+    }
+
+    #[test]
+    fn icase_and_literal() {
+        assert!(is_selected(&specs(&[":(icase)readme*"]), Path::new("README.md")));
+        assert!(!is_selected(&specs(&["readme*"]), Path::new("README.md")));
+        assert!(is_selected(&specs(&[":(literal)a*b"]), Path::new("a*b")));
+        assert!(!is_selected(&specs(&[":(literal)a*b"]), Path::new("axb")));
+    }
+
+    #[test]
+    fn exclusions_apply_after_inclusions() {
+        let only_excludes = specs(&[":!vendor"]);
+        assert!(is_selected(&only_excludes, Path::new("src/main.rs")));
+        assert!(!is_selected(&only_excludes, Path::new("vendor/lib.rs")));
+        assert!(is_excluded(&only_excludes, Path::new("vendor")));
+
+        let mixed = specs(&["src", ":(exclude)src/generated"]);
+        assert!(is_selected(&mixed, Path::new("src/main.rs")));
+        assert!(!is_selected(&mixed, Path::new("src/generated/out.rs")));
+        assert!(!is_selected(&mixed, Path::new("docs/a.md")));
+        assert!(!is_excluded(&mixed, Path::new("src")));
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+// A build ecosystem contree tunes its defaults for
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Ecosystem {
+    /// Cargo.toml
+    Rust,
+    /// package.json
+    Node,
+    /// pyproject.toml, setup.py, or requirements.txt
+    Python,
+    /// go.mod
+    Go,
+    /// build.gradle or build.gradle.kts
+    Gradle,
+}
+
+impl Ecosystem {
+    // Manifest file names that mark a project of this ecosystem
+    pub fn markers(self) -> &'static [&'static str] {
+        match self {
+            Ecosystem::Rust => &["Cargo.toml"],
+            Ecosystem::Node => &["package.json"],
+            Ecosystem::Python => &["pyproject.toml", "setup.py", "requirements.txt"],
+            Ecosystem::Go => &["go.mod"],
+            Ecosystem::Gradle => &["build.gradle", "build.gradle.kts"],
+        }
+    }
+
+    // Build and dependency configuration a model needs to make sense of the sources, for
+    // `--include-manifests`
+    fn manifests(self) -> &'static [&'static str] {
+        match self {
+            Ecosystem::Rust => &["Cargo.toml"],
+            Ecosystem::Node => &["package.json", "tsconfig.json"],
+            Ecosystem::Python => &["pyproject.toml", "setup.py", "setup.cfg", "requirements.txt"],
+            Ecosystem::Go => &["go.mod"],
+            Ecosystem::Gradle => &["build.gradle", "build.gradle.kts", "settings.gradle", "settings.gradle.kts"],
+        }
+    }
+
+    // Build output, installed dependencies, and caches, skipped even without a .gitignore
+    fn default_excludes(self) -> &'static [&'static str] {
+        match self {
+            Ecosystem::Rust => &["target/"],
+            Ecosystem::Node => &["node_modules/", "dist/", "coverage/", ".next/", ".turbo/"],
+            Ecosystem::Python => &["__pycache__/", ".venv/", "venv/", ".tox/", ".mypy_cache/", ".pytest_cache/", "*.egg-info/"],
+            Ecosystem::Go => &["vendor/"],
+            Ecosystem::Gradle => &["build/", ".gradle/"],
+        }
+    }
+
+    // Diagnostic lines `--capture-filter errors` keeps for this toolchain
+    pub fn error_patterns(self) -> &'static [&'static str] {
+        match self {
+            Ecosystem::Rust => &[
+                r"^\s*(error|warning)(\[[A-Z]\d+\])?:",
+                r"^\s*-->\s",
+                r"panicked at",
+                r"^test .* \.\.\. FAILED$",
+            ],
+            Ecosystem::Python => &[
+                r"^Traceback \(most recent call last\)",
+                r#"^\s*File ".*", line \d+"#,
+                r"^\w*(Error|Exception)\b.*:",
+            ],
+            Ecosystem::Node => &[r"^\s*at .*\(?.*:\d+:\d+\)?$", r"\bTS\d{4}:"],
+            Ecosystem::Go => &[r"^panic:", r"^--- FAIL:", r"^\S+\.go:\d+(:\d+)?:"],
+            Ecosystem::Gradle => &[
+                r"^\s*at [\w.$]+\(\w+\.(java|kt):\d+\)",
+                r"^e: ",
+                r"^\S+\.(java|kt):\d+: (error|warning):",
+                r"^FAILURE: Build failed",
+            ],
+        }
+    }
+}
+
+// `[project]` in .contree.toml: overrides for what detection would pick
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    // The ecosystems to assume instead of detecting them; empty for none
+    pub types: Option<Vec<Ecosystem>>,
+    // Paths to skip instead of the ecosystems' defaults, in .gitignore syntax
+    pub exclude: Option<Vec<String>>,
+    // Files `--include-manifests` adds instead of the ecosystems' manifests, relative to the
+    // scanned directory
+    pub always_include: Option<Vec<String>>,
+}
+
+// The ecosystems of the scanned directory and the defaults they bring
+#[derive(Clone)]
+pub struct Project {
+    pub ecosystems: Vec<Ecosystem>,
+    excludes: Gitignore,
+    // Relative to the scanned directory
+    manifests: Vec<String>,
+}
+
+impl Project {
+    // Each ecosystem is found by its marker file in the directory or a parent, so running
+    // in a subdirectory of a workspace still counts
+    pub fn detect(root: &Path, config: &ProjectConfig) -> Result<Self> {
+        let ecosystems = match &config.types {
+            Some(types) => types.clone(),
+            None => Ecosystem::value_variants()
+                .iter()
+                .copied()
+                .filter(|ecosystem| {
+                    root.ancestors().any(|dir| ecosystem.markers().iter().any(|marker| dir.join(marker).is_file()))
+                })
+                .collect(),
+        };
+        let default_excludes: Vec<String> = ecosystems
+            .iter()
+            .flat_map(|ecosystem| ecosystem.default_excludes())
+            .map(|pattern| pattern.to_string())
+            .collect();
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in config.exclude.as_ref().unwrap_or(&default_excludes) {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid [project] exclude pattern '{}'", pattern))?;
+        }
+        let manifests = match &config.always_include {
+            Some(paths) => paths.clone(),
+            None => ecosystems.iter().flat_map(|ecosystem| ecosystem.manifests()).map(|name| name.to_string()).collect(),
+        };
+        Ok(Project {
+            ecosystems,
+            excludes: builder.build().context("Invalid [project] exclude patterns")?,
+            manifests,
+        })
+    }
+
+    // The manifests `--include-manifests` adds that exist in the scanned directory
+    pub fn existing_manifests(&self, root: &Path) -> Vec<PathBuf> {
+        self.manifests.iter().map(|name| root.join(name)).filter(|path| path.is_file()).collect()
+    }
+
+    pub fn has(&self, ecosystem: Ecosystem) -> bool {
+        self.ecosystems.contains(&ecosystem)
+    }
+
+    // The default (or configured) exclude pattern that skips a path relative to the root
+    pub fn excluded_by(&self, relative: &Path, is_dir: bool) -> Option<&str> {
+        match self.excludes.matched(relative, is_dir) {
+            ignore::Match::Ignore(glob) => Some(glob.original()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn detects_ecosystems_from_markers_in_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        fs::create_dir_all(dir.path().join("web")).unwrap();
+        fs::write(dir.path().join("web/package.json"), "{}").unwrap();
+
+        let project = Project::detect(&dir.path().join("web"), &ProjectConfig::default()).unwrap();
+        assert_eq!(project.ecosystems, [Ecosystem::Rust, Ecosystem::Node]);
+        assert_eq!(project.excluded_by(Path::new("node_modules"), true), Some("node_modules/"));
+        assert_eq!(project.excluded_by(Path::new("src/target"), true), Some("target/"));
+        assert_eq!(project.excluded_by(Path::new("target"), false), None);
+        assert_eq!(project.excluded_by(Path::new("src"), true), None);
+        // Only the scanned directory's own manifests
+        fs::write(dir.path().join("web/tsconfig.json"), "{}").unwrap();
+        let manifests = project.existing_manifests(&dir.path().join("web"));
+        assert_eq!(manifests, [dir.path().join("web/package.json"), dir.path().join("web/tsconfig.json")]);
+    }
+
+    #[test]
+    fn config_overrides_detection_and_excludes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        fs::write(dir.path().join("Makefile"), "").unwrap();
+        let config: ProjectConfig =
+            toml::from_str("types = [\"python\"]\nexclude = [\"fixtures/\"]\nalways_include = [\"Makefile\", \"Cargo.toml\"]").unwrap();
+        let project = Project::detect(dir.path(), &config).unwrap();
+        assert_eq!(project.ecosystems, [Ecosystem::Python]);
+        assert_eq!(project.excluded_by(Path::new("fixtures"), true), Some("fixtures/"));
+        assert_eq!(project.excluded_by(Path::new(".venv"), true), None);
+        assert_eq!(project.existing_manifests(dir.path()), [dir.path().join("Makefile"), dir.path().join("Cargo.toml")]);
+    }
+}
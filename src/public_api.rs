@@ -0,0 +1,145 @@
+use crate::minify;
+use proc_macro2::LineColumn;
+use syn::spanned::Spanned;
+use syn::{ImplItem, Item, TraitItem, Visibility};
+
+// Reduce a Rust source file to its public API: private items are dropped and function
+// bodies become `{ ... }`, while docs, signatures, and type definitions are kept verbatim.
+// Returns None when the file doesn't parse, so callers can fall back to the full text.
+pub fn public_api(source: &str) -> Option<String> {
+    let file = syn::parse_file(source).ok()?;
+    let lines = LineIndex::new(source);
+    let mut edits = Vec::new();
+    collect_items(&file.items, &lines, &mut edits);
+
+    edits.sort_by_key(|edit| edit.start);
+    let mut stripped = String::with_capacity(source.len());
+    let mut position = 0;
+    for edit in edits {
+        if edit.start < position {
+            continue; // Nested inside an edit already applied
+        }
+        stripped.push_str(&source[position..edit.start]);
+        stripped.push_str(edit.replacement);
+        position = edit.end;
+    }
+    stripped.push_str(&source[position..]);
+
+    // Removed items leave runs of blank lines behind
+    let minified = minify::minify_whitespace(&stripped, false);
+    Some(minified.trim_start_matches('\n').to_string())
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    replacement: &'static str,
+}
+
+// Only plain `pub` is public API; `pub(crate)` and friends are internal
+fn is_public(vis: &Visibility) -> bool {
+    matches!(vis, Visibility::Public(_))
+}
+
+fn collect_items(items: &[Item], lines: &LineIndex, edits: &mut Vec<Edit>) {
+    for item in items {
+        match item {
+            Item::Fn(f) if is_public(&f.vis) => edits.push(lines.body(f.block.span())),
+            Item::Impl(imp) => {
+                // Trait impls are public whenever the type is; inherent impls expose only pub items
+                let is_trait_impl = imp.trait_.is_some();
+                for impl_item in &imp.items {
+                    match impl_item {
+                        ImplItem::Fn(f) if is_trait_impl || is_public(&f.vis) => {
+                            edits.push(lines.body(f.block.span()))
+                        }
+                        ImplItem::Fn(f) => edits.push(lines.remove(f.span())),
+                        ImplItem::Const(c) if !is_trait_impl && !is_public(&c.vis) => {
+                            edits.push(lines.remove(c.span()))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Item::Trait(t) if is_public(&t.vis) => {
+                for trait_item in &t.items {
+                    if let TraitItem::Fn(f) = trait_item {
+                        if let Some(block) = &f.default {
+                            edits.push(lines.body(block.span()));
+                        }
+                    }
+                }
+            }
+            Item::Mod(m) if is_public(&m.vis) => {
+                if let Some((_, items)) = &m.content {
+                    collect_items(items, lines, edits);
+                }
+            }
+            // Invocations such as `cfg_if! { ... }` may define public items; `macro_rules!`
+            // definitions are only public when exported
+            Item::Macro(m)
+                if m.ident.is_none() || m.attrs.iter().any(|attr| attr.path().is_ident("macro_export")) => {}
+            Item::ForeignMod(_) | Item::Verbatim(_) => {}
+            Item::Const(c) if is_public(&c.vis) => {}
+            Item::Static(s) if is_public(&s.vis) => {}
+            Item::Struct(s) if is_public(&s.vis) => {}
+            Item::Enum(e) if is_public(&e.vis) => {}
+            Item::Union(u) if is_public(&u.vis) => {}
+            Item::Type(t) if is_public(&t.vis) => {}
+            Item::TraitAlias(t) if is_public(&t.vis) => {}
+            Item::Use(u) if is_public(&u.vis) => {}
+            Item::ExternCrate(e) if is_public(&e.vis) => {}
+            other => edits.push(lines.remove(other.span())),
+        }
+    }
+}
+
+// Converts span positions (1-based lines, 0-based char columns) into byte offsets
+struct LineIndex<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { source, starts }
+    }
+
+    fn offset(&self, position: LineColumn) -> usize {
+        let start = self.starts.get(position.line.saturating_sub(1)).copied().unwrap_or(self.source.len());
+        self.source[start..]
+            .char_indices()
+            .nth(position.column)
+            .map_or(self.source.len(), |(i, _)| start + i)
+    }
+
+    fn body(&self, span: proc_macro2::Span) -> Edit {
+        Edit {
+            start: self.offset(span.start()),
+            end: self.offset(span.end()),
+            replacement: "{ ... }",
+        }
+    }
+
+    // Remove whole lines when the item has them to itself
+    fn remove(&self, span: proc_macro2::Span) -> Edit {
+        let mut start = self.offset(span.start());
+        let mut end = self.offset(span.end());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        if self.source[line_start..start].trim().is_empty() {
+            start = line_start;
+        }
+        let rest = &self.source[end..];
+        let line_end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        if rest[..line_end].trim().is_empty() {
+            end += line_end;
+        }
+        Edit {
+            start,
+            end,
+            replacement: "",
+        }
+    }
+}
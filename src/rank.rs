@@ -0,0 +1,81 @@
+use crate::vcs;
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Commits of history `--rank churn` reads
+const CHURN_COMMITS: usize = 500;
+
+// How `--rank` orders the scanned files
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rank {
+    /// Files changed in the most recent commits first, by the repository's history
+    Churn,
+}
+
+// How often each file changed in the latest commits, and how recently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Score {
+    commits: usize,
+    // Position of the newest commit that changed it, 0 for HEAD
+    newest: usize,
+}
+
+// Recent change frequency of the repository's files; paths are canonicalized
+pub struct Churn {
+    scores: HashMap<PathBuf, Score>,
+}
+
+impl Churn {
+    pub fn load(cwd: &Path) -> Result<Churn> {
+        let history = vcs::detect(cwd)?.history(CHURN_COMMITS)?;
+        Ok(Churn { scores: scores(&history) })
+    }
+
+    // Hottest files first: changed in the most commits, then most recently. Files that didn't
+    // change keep their order after them.
+    pub fn rank(&self, files: &mut [PathBuf]) {
+        files.sort_by_cached_key(|path| match fs::canonicalize(path).ok().and_then(|path| self.scores.get(&path)) {
+            Some(score) => (false, Reverse(score.commits), score.newest),
+            None => (true, Reverse(0), 0),
+        });
+    }
+}
+
+// Scores from the files of each commit, newest first
+fn scores(history: &[Vec<PathBuf>]) -> HashMap<PathBuf, Score> {
+    let mut scores: HashMap<PathBuf, Score> = HashMap::new();
+    for (position, files) in history.iter().enumerate() {
+        for path in files {
+            scores.entry(path.clone()).or_insert(Score { commits: 0, newest: position }).commits += 1;
+        }
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_the_most_changed_files_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        for name in ["quiet.rs", "old.rs", "hot.rs", "recent.rs", "untouched.rs"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let history = [
+            vec![root.join("hot.rs"), root.join("recent.rs")],
+            vec![root.join("hot.rs"), root.join("gone.rs")],
+            vec![root.join("old.rs"), root.join("quiet.rs")],
+            vec![root.join("old.rs")],
+        ];
+        let churn = Churn { scores: scores(&history) };
+        let mut files: Vec<PathBuf> = ["untouched.rs", "quiet.rs", "old.rs", "recent.rs", "hot.rs"].iter().map(|name| root.join(name)).collect();
+        churn.rank(&mut files);
+        let names: Vec<_> = files.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, ["hot.rs", "old.rs", "recent.rs", "quiet.rs", "untouched.rs"]);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::http;
+use crate::language;
+use crate::truncate;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+// Give up on a server that stops answering rather than hang the whole run
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// Media types read as text besides `text/*`
+const TEXT_TYPES: &[&str] = &[
+    "application/json",
+    "application/xml",
+    "application/javascript",
+    "application/x-yaml",
+    "application/yaml",
+    "application/toml",
+    "application/x-sh",
+];
+
+// A document fetched for an `--include` URL
+pub struct RemoteDocument {
+    // Bytes received, at most the size cap
+    pub bytes: u64,
+    pub language: Option<String>,
+    pub text: String,
+    // Whether the body went past the size cap and was cut
+    pub truncated: bool,
+}
+
+// `--include` entries starting with `http://` or `https://` are fetched instead of read
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+// Fetch a text document, reading at most `max_bytes` of it. HTML pages become plain text
+// when `html_to_text` asks for it; anything that isn't text is an error. Errors leave out the
+// URL, which the caller reports.
+pub fn fetch(url: &str, max_bytes: usize, html_to_text: bool) -> Result<RemoteDocument> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let response = agent
+        .get(url)
+        .set("User-Agent", http::USER_AGENT)
+        .call()
+        .map_err(http::describe_error)?;
+    let content_type = response.content_type().to_lowercase();
+    let is_text = content_type.starts_with("text/")
+        || TEXT_TYPES.contains(&content_type.as_str())
+        || content_type.ends_with("+json")
+        || content_type.ends_with("+xml");
+    if !is_text {
+        bail!("the server sent {}, not text", content_type);
+    }
+
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(max_bytes as u64 + 1)
+        .read_to_end(&mut body)
+        .context("Failed to read the response")?;
+    let truncated = body.len() > max_bytes;
+    body.truncate(truncate::cut_point(&body, max_bytes));
+    let bytes = body.len() as u64;
+    let mut text = String::from_utf8_lossy(&body).into_owned();
+
+    let is_html = content_type == "text/html" || content_type == "application/xhtml+xml";
+    let language = if is_html && html_to_text {
+        text = html_text(&text);
+        None
+    } else {
+        media_language(&content_type).or_else(|| url_language(url)).map(str::to_string)
+    };
+    Ok(RemoteDocument { bytes, language, text, truncated })
+}
+
+// The fence language of a media type
+fn media_language(content_type: &str) -> Option<&'static str> {
+    let language = match content_type {
+        "text/markdown" | "text/x-markdown" => "markdown",
+        "text/html" | "application/xhtml+xml" => "html",
+        "text/css" => "css",
+        "text/javascript" | "application/javascript" => "javascript",
+        "application/json" => "json",
+        "text/xml" | "application/xml" => "xml",
+        "text/yaml" | "application/yaml" | "application/x-yaml" => "yaml",
+        "application/toml" => "toml",
+        "application/x-sh" => "bash",
+        other if other.ends_with("+json") => "json",
+        other if other.ends_with("+xml") => "xml",
+        _ => return None,
+    };
+    Some(language)
+}
+
+// Plain text is often served for any file, so its name decides: `.../design-doc.md` is markdown
+fn url_language(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    language::default_language(Path::new(path.rsplit('/').next().unwrap_or(path)))
+}
+
+// The readable text of an HTML page: scripts, styles, and tags dropped, block elements on lines
+// of their own, list items as `- ` lines, and common entities decoded. Preformatted text loses
+// its line breaks too; this is for reading a page, not for copying code out of it.
+fn html_text(html: &str) -> String {
+    let item = Regex::new(r"(?i)<li\b[^>]*>").expect("valid regex");
+    let block = Regex::new(r"(?i)<(br|/?(p|div|section|article|h[1-6]|ul|ol|tr|table|pre|blockquote|header|footer|nav|main))\b[^>]*>")
+        .expect("valid regex");
+    let tag = Regex::new(r"<[^>]*>").expect("valid regex");
+    let mut text = Regex::new(r"(?s)<!--.*?-->").expect("valid regex").replace_all(html, "").into_owned();
+    for hidden in ["script", "style", "noscript", "template", "head"] {
+        let element = Regex::new(&format!(r"(?is)<{}\b.*?</{}\s*>", hidden, hidden)).expect("valid regex");
+        text = element.replace_all(&text, "").into_owned();
+    }
+    // Line breaks in the source are spaces; only the elements decide where lines end
+    let text = Regex::new(r"\s+").expect("valid regex").replace_all(&text, " ");
+    let text = item.replace_all(&text, "\n- ");
+    let text = block.replace_all(&text, "\n");
+    let text = tag.replace_all(&text, "");
+
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = decode_entities(line.trim());
+        // One blank line between paragraphs, none at the start
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n") + "\n"
+}
+
+fn decode_entities(text: &str) -> String {
+    let entity = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").expect("valid regex");
+    entity
+        .replace_all(text, |caps: &regex::Captures| {
+            let name = &caps[1];
+            let decoded = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => name.strip_prefix('#').and_then(|decimal| decimal.parse().ok()).and_then(char::from_u32),
+                },
+            };
+            decoded.map_or_else(|| caps[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_html_into_readable_text() {
+        let html = "<html><head><title>Spec</title><style>p { color: red }</style></head>\
+                    <body><h1>Design &amp; scope</h1><p>Retries use <b>exponential</b>\n   backoff.</p>\
+                    <script>track()</script><ul><li>one</li><li>two &#8212; &lt;b&gt;</li></ul><!-- hidden --></body></html>";
+        assert_eq!(html_text(html), "Design & scope\n\nRetries use exponential backoff.\n\n- one\n- two \u{2014} <b>\n");
+    }
+
+    #[test]
+    fn picks_languages_from_the_media_type_or_the_url() {
+        assert_eq!(media_language("application/vnd.api+json"), Some("json"));
+        assert_eq!(media_language("text/plain"), None);
+        assert_eq!(url_language("https://example.com/docs/design-doc.md?raw=1"), Some("markdown"));
+        assert_eq!(url_language("https://example.com/issues/42"), None);
+        assert!(is_url(Path::new("https://example.com")) && !is_url(Path::new("src/http.rs")));
+    }
+}
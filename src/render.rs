@@ -0,0 +1,1464 @@
+use crate::capture::CommandRun;
+use crate::changes::FileChange;
+use crate::line_stats::{self, LineCounts};
+use crate::manifest::ManifestCommand;
+use crate::spill::{MemoryBudget, SpillBuffer};
+use crate::test_summary::TestFailure;
+use crate::tree::TreeNode;
+use crate::vcs::RepositoryInfo;
+use crate::warnings::{self, Warning};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+// How the context is laid out
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Plain text sections with `File:` headers and fenced contents
+    Text,
+    /// A single self-contained HTML page with a file tree, highlighting, and search
+    Html,
+    /// One JSON document (see `contree schema context`)
+    Json,
+    /// Markdown with a linked table of contents and a heading per file
+    Markdown,
+    /// `<documents>` of `<document path="...">` elements, for XML-delimited prompts
+    Xml,
+    /// A tar archive of the files themselves at their relative paths, for tools that want files back
+    Tar,
+}
+
+// One captured command: its recorded output (absent when it was already passed through to the
+// same stream) and how it ended
+pub struct CapturedCommand<'a> {
+    pub output: Option<&'a str>,
+    pub run: Option<&'a CommandRun>,
+}
+
+impl CapturedCommand<'_> {
+    // "Command Output", or "Command Output (cargo test)" when it is one of several commands
+    fn title(&self, several: bool) -> String {
+        match self.run.and_then(|run| run.command.as_deref()).filter(|_| several) {
+            Some(command) => format!("Command Output ({})", command),
+            None => "Command Output".to_string(),
+        }
+    }
+}
+
+// One file as it appears in the context
+pub struct FileEntry<'a> {
+    pub path: String,
+    // Size on disk
+    pub bytes: u64,
+    pub language: Option<String>,
+    pub sha256: Option<String>,
+    pub docs_url: Option<String>,
+    // How the file differs from the `--annotate-changes` base
+    pub change: Option<FileChange>,
+    // Why a dependency file was included
+    pub reasons: Vec<String>,
+    pub body: FileBody<'a>,
+    // Byte ranges of grep matches in a text body
+    pub matches: Vec<Range<usize>>,
+    // How many times the --grep patterns match, when there are any
+    pub match_count: Option<usize>,
+}
+
+pub enum FileBody<'a> {
+    Text(Cow<'a, str>),
+    // `source` is the file on disk, for formats that carry its bytes
+    Binary { description: String, preview: Option<String>, source: PathBuf },
+    // Another hard link to the file at this path, whose content was already written
+    HardLink(String),
+    Unreadable(String),
+}
+
+// Writes each part of the context in one output format. Sections arrive in output order:
+// repository, problem statement, test failures, command output, error reference, editor
+// buffers, then project and dependency files.
+pub trait Renderer {
+    // The `--git-info` header: the remote, branch, and commit the context was taken at
+    fn repository(&mut self, info: &RepositoryInfo) -> io::Result<()>;
+    // The `--issue` being worked on: its title, description, and latest comments
+    fn problem_statement(&mut self, text: &str) -> io::Result<()>;
+    // The failed tests found in the command output, ahead of the output itself
+    fn test_failures(&mut self, failures: &[TestFailure]) -> io::Result<()>;
+    // Each `--run` command in the order they ran, or the single piped or logged output
+    fn command_output(&mut self, commands: &[CapturedCommand]) -> io::Result<()>;
+    // `rustc --explain` text per error code
+    fn error_reference(&mut self, explanations: &[(String, String)]) -> io::Result<()>;
+    fn editor_buffers(&mut self, buffers: &[String]) -> io::Result<()>;
+    // The `--tree` listing of the project files that follow, as drawn by `tree::ascii`
+    fn tree(&mut self, tree: &str) -> io::Result<()>;
+    // Start a group of files ("Project Context", "Relevant Dependency Files")
+    fn section(&mut self, title: &str) -> io::Result<()>;
+    fn file(&mut self, file: &FileEntry) -> io::Result<()>;
+    // A remark about the run, such as a scan that stopped early
+    fn note(&mut self, text: &str) -> io::Result<()>;
+    // The `--answer-format` instructions, one per line, after everything else
+    fn answer_format(&mut self, instructions: &str) -> io::Result<()>;
+    // Warnings printed so far; only JSON keeps them, since other formats are read by people
+    // who saw them on stderr
+    fn warnings(&mut self, _warnings: &[Warning]) -> io::Result<()> {
+        Ok(())
+    }
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+// When to highlight grep matches with ANSI colors in text output
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// When writing straight to a terminal, unless NO_COLOR is set
+    Auto,
+    /// Always, even into files and pipes
+    Always,
+    /// Never
+    Never,
+}
+
+// `color` highlights grep matches with ANSI escapes in text output meant for a terminal;
+// HTML always marks them. Markdown holds its body under `budget` until the table of contents
+// is known.
+pub fn renderer<'a>(format: Format, writer: impl Write + 'a, color: bool, budget: &MemoryBudget) -> Box<dyn Renderer + 'a> {
+    let writer: Box<dyn Write + 'a> = Box::new(writer);
+    match format {
+        Format::Text => Box::new(TextRenderer { writer, color }),
+        Format::Html => Box::new(HtmlRenderer::new(writer)),
+        Format::Json => Box::new(JsonRenderer::new(writer)),
+        Format::Markdown => Box::new(MarkdownRenderer::new(writer, budget)),
+        Format::Xml => Box::new(XmlRenderer { writer, in_documents: false, index: 0 }),
+        Format::Tar => Box::new(TarRenderer { writer }),
+    }
+}
+
+// Footer carrying the SHA-256 of everything rendered before it, in a form the format tolerates.
+// Nothing can follow a JSON document or a tar archive, so they have none.
+pub fn digest_footer(format: Format, digest: &str) -> Option<String> {
+    match format {
+        Format::Text => Some(format!("\n=== Output Digest ===\n\nSHA-256: {}\n", digest)),
+        Format::Html | Format::Markdown | Format::Xml => Some(format!("<!-- Output SHA-256: {} -->\n", digest)),
+        Format::Json | Format::Tar => None,
+    }
+}
+
+// Splits text into (is_match, piece) runs around the match ranges
+fn pieces<'t>(text: &'t str, matches: &[Range<usize>]) -> Vec<(bool, &'t str)> {
+    let mut pieces = Vec::new();
+    let mut last = 0;
+    for range in matches {
+        if range.start < last || range.end > text.len() {
+            continue;
+        }
+        pieces.push((false, &text[last..range.start]));
+        pieces.push((true, &text[range.clone()]));
+        last = range.end;
+    }
+    pieces.push((false, &text[last..]));
+    pieces
+}
+
+struct TextRenderer<'a> {
+    writer: Box<dyn Write + 'a>,
+    color: bool,
+}
+
+impl Renderer for TextRenderer<'_> {
+    fn repository(&mut self, info: &RepositoryInfo) -> io::Result<()> {
+        writeln!(self.writer, "\n=== Repository ===\n\n{}", info.summary().join("\n"))
+    }
+
+    fn problem_statement(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.writer, "\n=== Problem Statement ===\n\n{}", text)
+    }
+
+    fn test_failures(&mut self, failures: &[TestFailure]) -> io::Result<()> {
+        writeln!(self.writer, "\n=== Test Failures ===\n")?;
+        for failure in failures {
+            match &failure.location {
+                Some(location) => writeln!(self.writer, "- {} at {}", failure.name, location)?,
+                None => writeln!(self.writer, "- {}", failure.name)?,
+            }
+            for line in failure.message.iter().flat_map(|message| message.lines()) {
+                writeln!(self.writer, "    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn command_output(&mut self, commands: &[CapturedCommand]) -> io::Result<()> {
+        for command in commands {
+            writeln!(self.writer, "\n=== {} ===\n", command.title(commands.len() > 1))?;
+            if let Some(run) = command.run {
+                writeln!(self.writer, "{}", run.summary().join("\n"))?;
+            }
+            let Some(output) = command.output else {
+                continue;
+            };
+            writeln!(self.writer, "```")?;
+            write!(self.writer, "{}", output)?;
+            if !output.ends_with('\n') {
+                writeln!(self.writer)?;
+            }
+            writeln!(self.writer, "```")?;
+        }
+        Ok(())
+    }
+
+    fn error_reference(&mut self, explanations: &[(String, String)]) -> io::Result<()> {
+        writeln!(self.writer, "\n=== Error Code Reference ===\n")?;
+        for (code, text) in explanations {
+            // Explanations contain their own ``` examples, so the fence is one backtick longer
+            writeln!(self.writer, "{} (rustc --explain {}):", code, code)?;
+            writeln!(self.writer, "````markdown")?;
+            writeln!(self.writer, "{}", text)?;
+            writeln!(self.writer, "````")?;
+            writeln!(self.writer)?;
+        }
+        Ok(())
+    }
+
+    fn editor_buffers(&mut self, buffers: &[String]) -> io::Result<()> {
+        writeln!(self.writer, "\n=== Open Editor Buffers ===\n")?;
+        for buffer in buffers {
+            writeln!(self.writer, "- {}", buffer)?;
+        }
+        Ok(())
+    }
+
+    fn tree(&mut self, tree: &str) -> io::Result<()> {
+        writeln!(self.writer, "\n=== Directory Tree ===\n")?;
+        write!(self.writer, "```\n{}```\n", tree)
+    }
+
+    fn section(&mut self, title: &str) -> io::Result<()> {
+        writeln!(self.writer, "\n=== {} ===\n", title)
+    }
+
+    fn file(&mut self, file: &FileEntry) -> io::Result<()> {
+        writeln!(self.writer, "File: {}", file.path)?;
+        if let Some(sha256) = &file.sha256 {
+            writeln!(self.writer, "SHA-256: {}", sha256)?;
+        }
+        if let Some(docs_url) = &file.docs_url {
+            writeln!(self.writer, "Docs: {}", docs_url)?;
+        }
+        if let Some(change) = &file.change {
+            writeln!(self.writer, "Change: {}", change.summary())?;
+        }
+        if let Some(count) = file.match_count {
+            writeln!(self.writer, "Matches: {}", count)?;
+        }
+        if !file.reasons.is_empty() {
+            writeln!(self.writer, "  - {}", file.reasons.join("\n  - "))?;
+        }
+        let language = file.language.as_deref().unwrap_or_default();
+        match &file.body {
+            FileBody::Text(contents) if self.color && !file.matches.is_empty() => {
+                writeln!(self.writer, "```{}", language)?;
+                for (is_match, piece) in pieces(contents, &file.matches) {
+                    if is_match {
+                        write!(self.writer, "\x1b[1;31m{}\x1b[0m", piece)?;
+                    } else {
+                        write!(self.writer, "{}", piece)?;
+                    }
+                }
+                writeln!(self.writer, "\n```")?
+            }
+            FileBody::Text(contents) => writeln!(self.writer, "```{}\n{}\n```", language, contents)?,
+            FileBody::Binary { description, preview, .. } => {
+                writeln!(self.writer, "```\n[binary file: {}]", description)?;
+                write!(self.writer, "{}", preview.as_deref().unwrap_or_default())?;
+                writeln!(self.writer, "```")?;
+            }
+            FileBody::HardLink(first) => writeln!(self.writer, "(hard link to {}; content shown there)", first)?,
+            FileBody::Unreadable(error) => {
+                writeln!(self.writer, "```{}\n(Failed to read file: {})\n```", language, error)?
+            }
+        }
+        writeln!(self.writer)
+    }
+
+    fn note(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.writer, "[{}]\n", text)
+    }
+
+    fn answer_format(&mut self, instructions: &str) -> io::Result<()> {
+        writeln!(self.writer, "\n=== Answer Format ===\n\n{}", instructions)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const HTML_STYLE: &str = include_str!("render/report.css");
+const HTML_SCRIPT: &str = include_str!("render/report.js");
+
+// Streams the page body as files arrive; the sidebar tree is written at the end (and placed
+// on the left by CSS), since it needs every path
+struct HtmlRenderer<'a> {
+    writer: Box<dyn Write + 'a>,
+    started: bool,
+    section: String,
+    // Section title -> (path, anchor) of its files
+    tree: Vec<(String, Vec<(String, String)>)>,
+    files: usize,
+}
+
+impl<'a> HtmlRenderer<'a> {
+    fn new(writer: Box<dyn Write + 'a>) -> Self {
+        HtmlRenderer {
+            writer,
+            started: false,
+            section: String::new(),
+            tree: Vec::new(),
+            files: 0,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            writeln!(self.writer, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+            writeln!(self.writer, "<title>contree context</title>\n<style>\n{}</style>\n</head>\n<body>\n<main>", HTML_STYLE)?;
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for HtmlRenderer<'_> {
+    fn repository(&mut self, info: &RepositoryInfo) -> io::Result<()> {
+        self.start()?;
+        let summary: Vec<_> = info.summary().iter().map(|line| escape(line).into_owned()).collect();
+        writeln!(self.writer, "<h2>Repository</h2>\n<div class=\"meta\">{}</div>", summary.join("<br>"))
+    }
+
+    fn problem_statement(&mut self, text: &str) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<h2>Problem Statement</h2>\n<pre>{}</pre>", escape(text))
+    }
+
+    fn test_failures(&mut self, failures: &[TestFailure]) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<h2>Test Failures</h2>\n<ul>")?;
+        for failure in failures {
+            write!(self.writer, "<li><code>{}</code>", escape(&failure.name))?;
+            if let Some(location) = &failure.location {
+                write!(self.writer, " at <code>{}</code>", escape(location))?;
+            }
+            if let Some(message) = &failure.message {
+                write!(self.writer, "<pre>{}</pre>", escape(message))?;
+            }
+            writeln!(self.writer, "</li>")?;
+        }
+        writeln!(self.writer, "</ul>")
+    }
+
+    fn command_output(&mut self, commands: &[CapturedCommand]) -> io::Result<()> {
+        self.start()?;
+        for command in commands {
+            writeln!(self.writer, "<h2>{}</h2>", escape(&command.title(commands.len() > 1)))?;
+            if let Some(run) = command.run {
+                let summary: Vec<_> = run.summary().iter().map(|line| escape(line).into_owned()).collect();
+                writeln!(self.writer, "<div class=\"meta\">{}</div>", summary.join("<br>"))?;
+            }
+            if let Some(output) = command.output {
+                writeln!(self.writer, "<pre class=\"output\">{}</pre>", escape(output))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn error_reference(&mut self, explanations: &[(String, String)]) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<h2>Error Code Reference</h2>")?;
+        for (code, text) in explanations {
+            writeln!(self.writer, "<details><summary>{} (rustc --explain {})</summary>", code, code)?;
+            writeln!(self.writer, "<pre>{}</pre>\n</details>", escape(text))?;
+        }
+        Ok(())
+    }
+
+    fn editor_buffers(&mut self, buffers: &[String]) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<h2>Open Editor Buffers</h2>\n<ul>")?;
+        for buffer in buffers {
+            writeln!(self.writer, "<li>{}</li>", escape(buffer))?;
+        }
+        writeln!(self.writer, "</ul>")
+    }
+
+    fn tree(&mut self, tree: &str) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<h2>Directory Tree</h2>\n<pre class=\"tree\">{}</pre>", escape(tree))
+    }
+
+    fn section(&mut self, title: &str) -> io::Result<()> {
+        self.start()?;
+        self.section = title.to_string();
+        self.tree.push((title.to_string(), Vec::new()));
+        writeln!(self.writer, "<h2>{}</h2>", escape(title))
+    }
+
+    fn file(&mut self, file: &FileEntry) -> io::Result<()> {
+        self.start()?;
+        self.files += 1;
+        let anchor = format!("file-{}", self.files);
+        if self.tree.is_empty() {
+            self.tree.push((self.section.clone(), Vec::new()));
+        }
+        if let Some((_, files)) = self.tree.last_mut() {
+            files.push((file.path.clone(), anchor.clone()));
+        }
+
+        let path = escape(&file.path);
+        writeln!(self.writer, "<details class=\"file\" id=\"{}\" data-path=\"{}\" open>", anchor, path)?;
+        writeln!(self.writer, "<summary>{}</summary>", path)?;
+        let mut meta = Vec::new();
+        if let Some(sha256) = &file.sha256 {
+            meta.push(format!("SHA-256: {}", escape(sha256)));
+        }
+        if let Some(docs_url) = &file.docs_url {
+            let docs_url = escape(docs_url);
+            meta.push(format!("Docs: <a href=\"{}\">{}</a>", docs_url, docs_url));
+        }
+        if let Some(change) = &file.change {
+            meta.push(format!("Change: {}", change.summary()));
+        }
+        if let Some(count) = file.match_count {
+            meta.push(format!("Matches: {}", count));
+        }
+        meta.extend(file.reasons.iter().map(|reason| escape(reason).into_owned()));
+        if !meta.is_empty() {
+            writeln!(self.writer, "<div class=\"meta\">{}</div>", meta.join("<br>"))?;
+        }
+        match &file.body {
+            FileBody::Text(contents) => {
+                let language = file.language.as_deref().unwrap_or_default();
+                write!(self.writer, "<pre><code data-lang=\"{}\">", escape(language))?;
+                for (is_match, piece) in pieces(contents, &file.matches) {
+                    if is_match {
+                        write!(self.writer, "<mark>{}</mark>", escape(piece))?;
+                    } else {
+                        write!(self.writer, "{}", escape(piece))?;
+                    }
+                }
+                writeln!(self.writer, "</code></pre>")?
+            }
+            FileBody::Binary { description, preview, .. } => {
+                writeln!(self.writer, "<p class=\"note\">Binary file: {}</p>", escape(description))?;
+                if let Some(preview) = preview {
+                    writeln!(self.writer, "<pre>{}</pre>", escape(preview))?;
+                }
+            }
+            FileBody::HardLink(first) => {
+                writeln!(self.writer, "<p class=\"note\">Hard link to {}; content shown there</p>", escape(first))?
+            }
+            FileBody::Unreadable(error) => {
+                writeln!(self.writer, "<p class=\"note\">Failed to read file: {}</p>", escape(error))?
+            }
+        }
+        writeln!(self.writer, "</details>")
+    }
+
+    fn note(&mut self, text: &str) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<p class=\"note\">{}</p>", escape(text))
+    }
+
+    fn answer_format(&mut self, instructions: &str) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<h2>Answer Format</h2>")?;
+        for line in instructions.lines() {
+            writeln!(self.writer, "<p>{}</p>", escape(line))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "</main>\n<nav>\n<input id=\"search\" type=\"search\" placeholder=\"Search paths and contents\">")?;
+        for (title, files) in &self.tree {
+            writeln!(self.writer, "<h3>{}</h3>", escape(title))?;
+            write_tree(&mut *self.writer, &TreeNode::build(files))?;
+        }
+        writeln!(self.writer, "</nav>\n<script>\n{}</script>\n</body>\n</html>", HTML_SCRIPT)
+    }
+}
+
+// Bumped whenever a field of the JSON context is removed or changes meaning
+pub const CONTEXT_VERSION: u32 = 1;
+
+// A file in the JSON context; exactly one of content, binary, hard_link_to, and error is set
+#[derive(Serialize)]
+struct JsonFile<'e> {
+    path: &'e str,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'e str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<&'e str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    docs_url: Option<&'e str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<&'e FileChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matches: Option<usize>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    reasons: &'e [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'e str>,
+    // Code, comment, and blank lines of the content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lines: Option<LineCounts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binary: Option<JsonBinary<'e>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hard_link_to: Option<&'e str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'e str>,
+}
+
+#[derive(Serialize)]
+struct JsonBinary<'e> {
+    description: &'e str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preview: Option<&'e str>,
+}
+
+// Streams one JSON object: top-level fields as they arrive, and each section as an array of
+// files ("files" for the project, "dependencies" for dependency files). Notes can arrive in
+// the middle of a section, so they are collected and written last.
+struct JsonRenderer<'a> {
+    writer: Box<dyn Write + 'a>,
+    started: bool,
+    // Whether a section's array is open, and whether it has an element yet
+    in_section: bool,
+    section_empty: bool,
+    notes: Vec<String>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> JsonRenderer<'a> {
+    fn new(writer: Box<dyn Write + 'a>) -> Self {
+        JsonRenderer {
+            writer,
+            started: false,
+            in_section: false,
+            section_empty: true,
+            notes: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            write!(self.writer, "{{\"version\":{}", CONTEXT_VERSION)?;
+        }
+        Ok(())
+    }
+
+    fn close_section(&mut self) -> io::Result<()> {
+        if self.in_section {
+            self.in_section = false;
+            write!(self.writer, "]")?;
+        }
+        Ok(())
+    }
+
+    fn field(&mut self, key: &str, value: &impl Serialize) -> io::Result<()> {
+        self.start()?;
+        self.close_section()?;
+        write!(self.writer, ",\"{}\":", key)?;
+        serde_json::to_writer(&mut *self.writer, value)?;
+        Ok(())
+    }
+}
+
+// "Relevant Dependency Files" -> "dependencies", and other titles as snake case
+fn section_key(title: &str) -> String {
+    match title {
+        "Project Context" => "files".to_string(),
+        "Relevant Dependency Files" => "dependencies".to_string(),
+        other => other.to_lowercase().replace(' ', "_"),
+    }
+}
+
+impl Renderer for JsonRenderer<'_> {
+    fn repository(&mut self, info: &RepositoryInfo) -> io::Result<()> {
+        self.field("repository", info)
+    }
+
+    fn problem_statement(&mut self, text: &str) -> io::Result<()> {
+        self.field("problem_statement", &text)
+    }
+
+    fn test_failures(&mut self, failures: &[TestFailure]) -> io::Result<()> {
+        self.field("test_failures", &failures)
+    }
+
+    fn command_output(&mut self, commands: &[CapturedCommand]) -> io::Result<()> {
+        // A single command keeps the flat `command` and `command_output` fields
+        if let [command] = commands {
+            if let Some(run) = command.run {
+                self.field("command", &ManifestCommand::from(run))?;
+            }
+            return match command.output {
+                Some(output) => self.field("command_output", &output),
+                None => Ok(()),
+            };
+        }
+        #[derive(Serialize)]
+        struct JsonCommand<'a> {
+            #[serde(flatten)]
+            run: Option<ManifestCommand>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            output: Option<&'a str>,
+        }
+        let commands: Vec<JsonCommand> =
+            commands.iter().map(|command| JsonCommand { run: command.run.map(ManifestCommand::from), output: command.output }).collect();
+        self.field("commands", &commands)
+    }
+
+    fn error_reference(&mut self, explanations: &[(String, String)]) -> io::Result<()> {
+        let explanations: BTreeMap<&str, &str> =
+            explanations.iter().map(|(code, text)| (code.as_str(), text.as_str())).collect();
+        self.field("error_reference", &explanations)
+    }
+
+    fn editor_buffers(&mut self, buffers: &[String]) -> io::Result<()> {
+        self.field("editor_buffers", &buffers)
+    }
+
+    fn tree(&mut self, tree: &str) -> io::Result<()> {
+        self.field("tree", &tree)
+    }
+
+    fn section(&mut self, title: &str) -> io::Result<()> {
+        self.start()?;
+        self.close_section()?;
+        write!(self.writer, ",\"{}\":[", section_key(title))?;
+        self.in_section = true;
+        self.section_empty = true;
+        Ok(())
+    }
+
+    fn file(&mut self, file: &FileEntry) -> io::Result<()> {
+        if !self.in_section {
+            self.section("Project Context")?;
+        }
+        if !self.section_empty {
+            write!(self.writer, ",")?;
+        }
+        self.section_empty = false;
+        let mut json = JsonFile {
+            path: &file.path,
+            size: file.bytes,
+            language: file.language.as_deref(),
+            sha256: file.sha256.as_deref(),
+            docs_url: file.docs_url.as_deref(),
+            change: file.change.as_ref(),
+            matches: file.match_count,
+            reasons: &file.reasons,
+            content: None,
+            lines: None,
+            binary: None,
+            hard_link_to: None,
+            error: None,
+        };
+        match &file.body {
+            FileBody::Text(contents) => {
+                json.content = Some(contents);
+                json.lines = Some(line_stats::count(file.language.as_deref(), contents));
+            }
+            FileBody::Binary { description, preview, .. } => {
+                json.binary = Some(JsonBinary {
+                    description,
+                    preview: preview.as_deref(),
+                })
+            }
+            FileBody::HardLink(first) => json.hard_link_to = Some(first),
+            FileBody::Unreadable(error) => json.error = Some(error),
+        }
+        serde_json::to_writer(&mut *self.writer, &json)?;
+        Ok(())
+    }
+
+    fn note(&mut self, text: &str) -> io::Result<()> {
+        self.notes.push(text.to_string());
+        Ok(())
+    }
+
+    fn answer_format(&mut self, instructions: &str) -> io::Result<()> {
+        self.field("answer_format", &instructions)
+    }
+
+    fn warnings(&mut self, warnings: &[Warning]) -> io::Result<()> {
+        self.warnings = warnings.to_vec();
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.start()?;
+        self.close_section()?;
+        if !self.notes.is_empty() {
+            let notes = std::mem::take(&mut self.notes);
+            self.field("notes", &notes)?;
+        }
+        if !self.warnings.is_empty() {
+            let warnings = std::mem::take(&mut self.warnings);
+            self.field("warnings", &warnings)?;
+        }
+        writeln!(self.writer, "}}")
+    }
+}
+
+// Writes the body as files arrive, then the table of contents followed by the body, since
+// the contents list needs every heading. Anchors follow GitHub's heading slugs.
+struct MarkdownRenderer<'a> {
+    writer: Box<dyn Write + 'a>,
+    budget: MemoryBudget,
+    body: SpillBuffer,
+    // (depth, title, anchor) of each section and file heading
+    toc: Vec<(usize, String, String)>,
+    // Times each anchor was used, for GitHub's `-1`, `-2` suffixes
+    slugs: HashMap<String, usize>,
+    // Path -> anchor, so hard links can point at the copy that was written
+    anchors: HashMap<String, String>,
+}
+
+impl<'a> MarkdownRenderer<'a> {
+    fn new(writer: Box<dyn Write + 'a>, budget: &MemoryBudget) -> Self {
+        MarkdownRenderer {
+            writer,
+            budget: budget.clone(),
+            body: SpillBuffer::new(budget),
+            toc: Vec::new(),
+            slugs: HashMap::new(),
+            anchors: HashMap::new(),
+        }
+    }
+
+    // Write a heading to the body and list it in the table of contents
+    fn heading(&mut self, depth: usize, title: &str, text: &str) -> io::Result<String> {
+        let mut anchor = slug(text);
+        let used = self.slugs.entry(anchor.clone()).or_insert(0);
+        if *used > 0 {
+            anchor = format!("{}-{}", anchor, used);
+        }
+        *used += 1;
+        self.toc.push((depth, title.to_string(), anchor.clone()));
+        writeln!(self.body, "{} {}\n", "#".repeat(depth + 1), title)?;
+        Ok(anchor)
+    }
+
+    // Every block ends with a blank line
+    fn fenced(&mut self, language: &str, text: &str) -> io::Result<()> {
+        let fence = fence_for(text);
+        writeln!(self.body, "{}{}", fence, language)?;
+        write!(self.body, "{}", text)?;
+        if !text.ends_with('\n') {
+            writeln!(self.body)?;
+        }
+        writeln!(self.body, "{}\n", fence)
+    }
+}
+
+impl Renderer for MarkdownRenderer<'_> {
+    fn repository(&mut self, info: &RepositoryInfo) -> io::Result<()> {
+        self.heading(1, "Repository", "Repository")?;
+        for line in info.summary() {
+            writeln!(self.body, "- {}", line)?;
+        }
+        writeln!(self.body)
+    }
+
+    fn problem_statement(&mut self, text: &str) -> io::Result<()> {
+        self.heading(1, "Problem Statement", "Problem Statement")?;
+        self.fenced("markdown", text)
+    }
+
+    fn test_failures(&mut self, failures: &[TestFailure]) -> io::Result<()> {
+        self.heading(1, "Test Failures", "Test Failures")?;
+        for failure in failures {
+            match &failure.location {
+                Some(location) => writeln!(self.body, "**`{}`** at `{}`\n", failure.name, location)?,
+                None => writeln!(self.body, "**`{}`**\n", failure.name)?,
+            }
+            if let Some(message) = &failure.message {
+                self.fenced("", message)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn command_output(&mut self, commands: &[CapturedCommand]) -> io::Result<()> {
+        for command in commands {
+            let title = command.title(commands.len() > 1);
+            self.heading(1, &title, &title)?;
+            if let Some(run) = command.run {
+                for line in run.summary() {
+                    writeln!(self.body, "- {}", line)?;
+                }
+                writeln!(self.body)?;
+            }
+            if let Some(output) = command.output {
+                self.fenced("", output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn error_reference(&mut self, explanations: &[(String, String)]) -> io::Result<()> {
+        self.heading(1, "Error Code Reference", "Error Code Reference")?;
+        for (code, text) in explanations {
+            writeln!(self.body, "**{}** (`rustc --explain {}`):\n", code, code)?;
+            self.fenced("markdown", text)?;
+        }
+        Ok(())
+    }
+
+    fn editor_buffers(&mut self, buffers: &[String]) -> io::Result<()> {
+        self.heading(1, "Open Editor Buffers", "Open Editor Buffers")?;
+        for buffer in buffers {
+            writeln!(self.body, "- {}", buffer)?;
+        }
+        writeln!(self.body)
+    }
+
+    fn tree(&mut self, tree: &str) -> io::Result<()> {
+        self.heading(1, "Directory Tree", "Directory Tree")?;
+        self.fenced("text", tree)
+    }
+
+    fn section(&mut self, title: &str) -> io::Result<()> {
+        self.heading(1, title, title)?;
+        Ok(())
+    }
+
+    fn file(&mut self, file: &FileEntry) -> io::Result<()> {
+        let anchor = self.heading(2, &format!("`{}`", file.path), &file.path)?;
+        self.anchors.entry(file.path.clone()).or_insert(anchor);
+        let mut meta = Vec::new();
+        if let Some(sha256) = &file.sha256 {
+            meta.push(format!("SHA-256: `{}`", sha256));
+        }
+        if let Some(docs_url) = &file.docs_url {
+            meta.push(format!("Docs: <{}>", docs_url));
+        }
+        if let Some(change) = &file.change {
+            meta.push(format!("Change: {}", change.summary()));
+        }
+        if let Some(count) = file.match_count {
+            meta.push(format!("Matches: {}", count));
+        }
+        meta.extend(file.reasons.iter().cloned());
+        if !meta.is_empty() {
+            writeln!(self.body, "- {}\n", meta.join("\n- "))?;
+        }
+        match &file.body {
+            FileBody::Text(contents) => self.fenced(file.language.as_deref().unwrap_or_default(), contents)?,
+            FileBody::Binary { description, preview, .. } => {
+                writeln!(self.body, "*Binary file: {}*\n", description)?;
+                if let Some(preview) = preview {
+                    self.fenced("", preview)?;
+                }
+            }
+            FileBody::HardLink(first) => match self.anchors.get(first) {
+                Some(anchor) => writeln!(self.body, "*Hard link to [`{}`](#{}); content shown there*\n", first, anchor)?,
+                None => writeln!(self.body, "*Hard link to `{}`; content shown there*\n", first)?,
+            },
+            FileBody::Unreadable(error) => writeln!(self.body, "*Failed to read file: {}*\n", error)?,
+        }
+        Ok(())
+    }
+
+    fn note(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.body, "> {}\n", text)
+    }
+
+    fn answer_format(&mut self, instructions: &str) -> io::Result<()> {
+        self.heading(1, "Answer Format", "Answer Format")?;
+        for line in instructions.lines() {
+            writeln!(self.body, "{}\n", line)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        writeln!(self.writer, "## Contents\n")?;
+        for (depth, title, anchor) in &self.toc {
+            writeln!(self.writer, "{}- [{}](#{})", "  ".repeat(depth - 1), title, anchor)?;
+        }
+        writeln!(self.writer)?;
+        let body = std::mem::replace(&mut self.body, SpillBuffer::new(&self.budget)).finish()?;
+        self.writer.write_all(&body)
+    }
+}
+
+// GitHub's heading anchor: lowercase, spaces to hyphens, and punctuation other than `-` and
+// `_` dropped
+fn slug(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+// A backtick fence longer than any run of backticks in the text, so contents that contain
+// their own fences (Markdown files, rustc explanations) can't close it early
+fn fence_for(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+// Writes each part as a top-level element, and each section's files as a `<documents>` list,
+// the layout Anthropic's prompting guides use for long documents. Text keeps its `>`s (only
+// `&`, `<`, and `]]>` are escaped) so code stays readable while the result is well-formed.
+struct XmlRenderer<'a> {
+    writer: Box<dyn Write + 'a>,
+    in_documents: bool,
+    // Files so far, numbered across sections
+    index: usize,
+}
+
+impl XmlRenderer<'_> {
+    fn close_documents(&mut self) -> io::Result<()> {
+        if self.in_documents {
+            self.in_documents = false;
+            writeln!(self.writer, "</documents>")?;
+        }
+        Ok(())
+    }
+
+    fn element(&mut self, name: &str, text: &str) -> io::Result<()> {
+        self.close_documents()?;
+        writeln!(self.writer, "<{}>\n{}\n</{}>", name, escape_text(text.trim_end_matches('\n')), name)
+    }
+}
+
+impl Renderer for XmlRenderer<'_> {
+    fn repository(&mut self, info: &RepositoryInfo) -> io::Result<()> {
+        self.element("repository", &info.summary().join("\n"))
+    }
+
+    fn problem_statement(&mut self, text: &str) -> io::Result<()> {
+        self.close_documents()?;
+        self.element("problem_statement", text)
+    }
+
+    fn test_failures(&mut self, failures: &[TestFailure]) -> io::Result<()> {
+        self.close_documents()?;
+        writeln!(self.writer, "<test_failures>")?;
+        for failure in failures {
+            write!(self.writer, "<failure name=\"{}\"", escape(&failure.name))?;
+            if let Some(location) = &failure.location {
+                write!(self.writer, " location=\"{}\"", escape(location))?;
+            }
+            writeln!(self.writer, ">\n{}\n</failure>", escape_text(failure.message.as_deref().unwrap_or_default()))?;
+        }
+        writeln!(self.writer, "</test_failures>")
+    }
+
+    fn command_output(&mut self, commands: &[CapturedCommand]) -> io::Result<()> {
+        self.close_documents()?;
+        // Each command's summary comes right before its output
+        for command in commands {
+            if let Some(run) = command.run {
+                self.element("command", &run.summary().join("\n"))?;
+            }
+            if let Some(output) = command.output {
+                self.element("command_output", output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn error_reference(&mut self, explanations: &[(String, String)]) -> io::Result<()> {
+        self.close_documents()?;
+        writeln!(self.writer, "<error_reference>")?;
+        for (code, text) in explanations {
+            writeln!(self.writer, "<explanation code=\"{}\">\n{}\n</explanation>", escape(code), escape_text(text))?;
+        }
+        writeln!(self.writer, "</error_reference>")
+    }
+
+    fn editor_buffers(&mut self, buffers: &[String]) -> io::Result<()> {
+        self.element("editor_buffers", &buffers.join("\n"))
+    }
+
+    fn tree(&mut self, tree: &str) -> io::Result<()> {
+        self.element("tree", tree)
+    }
+
+    fn section(&mut self, title: &str) -> io::Result<()> {
+        self.close_documents()?;
+        self.in_documents = true;
+        writeln!(self.writer, "<documents section=\"{}\">", escape(title))
+    }
+
+    fn file(&mut self, file: &FileEntry) -> io::Result<()> {
+        if !self.in_documents {
+            self.section("Project Context")?;
+        }
+        self.index += 1;
+        write!(self.writer, "<document index=\"{}\" path=\"{}\"", self.index, escape(&file.path))?;
+        if let Some(language) = &file.language {
+            write!(self.writer, " language=\"{}\"", escape(language))?;
+        }
+        if let Some(sha256) = &file.sha256 {
+            write!(self.writer, " sha256=\"{}\"", escape(sha256))?;
+        }
+        if let Some(docs_url) = &file.docs_url {
+            write!(self.writer, " docs_url=\"{}\"", escape(docs_url))?;
+        }
+        if let Some(change) = &file.change {
+            write!(self.writer, " change=\"{}\"", escape(&change.summary()))?;
+        }
+        if let Some(count) = file.match_count {
+            write!(self.writer, " matches=\"{}\"", count)?;
+        }
+        match &file.body {
+            FileBody::HardLink(first) => return writeln!(self.writer, " hard_link_to=\"{}\"/>", escape(first)),
+            FileBody::Binary { description, .. } => write!(self.writer, " binary=\"{}\">", escape(description))?,
+            FileBody::Unreadable(error) => write!(self.writer, " error=\"{}\">", escape(error))?,
+            FileBody::Text(_) => write!(self.writer, ">")?,
+        }
+        writeln!(self.writer)?;
+        for reason in &file.reasons {
+            writeln!(self.writer, "<reason>{}</reason>", escape_text(reason))?;
+        }
+        match &file.body {
+            FileBody::Text(contents) => writeln!(self.writer, "{}", escape_text(contents.trim_end_matches('\n')))?,
+            FileBody::Binary { preview: Some(preview), .. } => {
+                writeln!(self.writer, "{}", escape_text(preview.trim_end_matches('\n')))?
+            }
+            _ => {}
+        }
+        writeln!(self.writer, "</document>")
+    }
+
+    fn note(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.writer, "<note>{}</note>", escape_text(text))
+    }
+
+    fn answer_format(&mut self, instructions: &str) -> io::Result<()> {
+        self.element("answer_format", instructions)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close_documents()
+    }
+}
+
+// The files alone, as members of a ustar archive named by their display paths; the other
+// sections have no file to go in. Entries are owned by root and dated 1970, so the same files
+// always make the same archive.
+struct TarRenderer<'a> {
+    writer: Box<dyn Write + 'a>,
+}
+
+impl TarRenderer<'_> {
+    // A member's header; names too long for it go first in a PAX extended header
+    fn header(&mut self, path: &str, kind: u8, size: u64, link: &str) -> io::Result<()> {
+        let mut records = Vec::new();
+        if path.len() > 100 {
+            records.extend(pax_record("path", path));
+        }
+        if link.len() > 100 {
+            records.extend(pax_record("linkpath", link));
+        }
+        if !records.is_empty() {
+            self.writer.write_all(&tar_header("././@PaxHeader", b'x', records.len() as u64, ""))?;
+            self.writer.write_all(&records)?;
+            self.pad(records.len() as u64)?;
+        }
+        self.writer.write_all(&tar_header(path, kind, size, link))
+    }
+
+    // Contents fill whole 512-byte blocks
+    fn pad(&mut self, size: u64) -> io::Result<()> {
+        let used = (size % 512) as usize;
+        if used > 0 {
+            self.writer.write_all(&[0; 512][used..])?;
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for TarRenderer<'_> {
+    fn repository(&mut self, _info: &RepositoryInfo) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn problem_statement(&mut self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn test_failures(&mut self, _failures: &[TestFailure]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn command_output(&mut self, _commands: &[CapturedCommand]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn error_reference(&mut self, _explanations: &[(String, String)]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn editor_buffers(&mut self, _buffers: &[String]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn tree(&mut self, _tree: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn section(&mut self, _title: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn file(&mut self, file: &FileEntry) -> io::Result<()> {
+        let Some(path) = archive_path(&file.path) else {
+            warnings::warn(warnings::Kind::Skipped, format!("Leaving {} out of the tar archive: it has no relative path", file.path));
+            return Ok(());
+        };
+        match &file.body {
+            FileBody::Text(contents) => {
+                self.header(&path, b'0', contents.len() as u64, "")?;
+                self.writer.write_all(contents.as_bytes())?;
+                self.pad(contents.len() as u64)
+            }
+            FileBody::Binary { source, .. } => {
+                let reader = File::open(source)?;
+                let size = reader.metadata()?.len();
+                self.header(&path, b'0', size, "")?;
+                if io::copy(&mut reader.take(size), &mut self.writer)? < size {
+                    let error = format!("{} shrank while it was archived", source.display());
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+                }
+                self.pad(size)
+            }
+            // A link to a member that was left out would extract as nothing, so it is left out too
+            FileBody::HardLink(first) => match archive_path(first) {
+                Some(first) => self.header(&path, b'1', 0, &first),
+                None => Ok(()),
+            },
+            FileBody::Unreadable(error) => {
+                warnings::warn(warnings::Kind::Skipped, format!("Leaving {} out of the tar archive: {}", file.path, error));
+                Ok(())
+            }
+        }
+    }
+
+    fn note(&mut self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn answer_format(&mut self, _instructions: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Two zero blocks end the archive
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.write_all(&[0; 1024])
+    }
+}
+
+// A display path as a member name: relative, without `.` components. URLs and paths climbing
+// out with `..` have none.
+fn archive_path(path: &str) -> Option<String> {
+    if path.contains("://") {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::ParentDir => return None,
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+// A ustar header block; `path` and `link` are cut to their 100-byte fields
+fn tar_header(path: &str, kind: u8, size: u64, link: &str) -> [u8; 512] {
+    let mut header = [0; 512];
+    let mut field = |offset: usize, length: usize, value: &[u8]| {
+        let length = value.len().min(length);
+        header[offset..offset + length].copy_from_slice(&value[..length]);
+    };
+    field(0, 100, path.as_bytes());
+    field(100, 8, b"0000644\0");
+    field(108, 8, b"0000000\0");
+    field(116, 8, b"0000000\0");
+    field(124, 12, format!("{:011o}\0", size).as_bytes());
+    field(136, 12, b"00000000000\0");
+    // The checksum is taken with its own field as spaces
+    field(148, 8, b"        ");
+    field(156, 1, &[kind]);
+    field(157, 100, link.as_bytes());
+    // Magic and version
+    field(257, 6, b"ustar\0");
+    field(263, 2, b"00");
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+// A `length key=value` line of a PAX extended header, where the length counts its own digits
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + 1;
+    while length != rest + length.to_string().len() {
+        length = rest + length.to_string().len();
+    }
+    format!("{} {}={}\n", length, key, value).into_bytes()
+}
+
+// XML character data: `&` and `<` always need escaping, `>` only to break up `]]>`
+fn escape_text(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<']) && !text.contains("]]>") {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.replace('&', "&amp;").replace('<', "&lt;").replace("]]>", "]]&gt;"))
+}
+
+fn write_tree(writer: &mut dyn Write, node: &TreeNode<String>) -> io::Result<()> {
+    writeln!(writer, "<ul>")?;
+    for (name, child) in &node.dirs {
+        writeln!(writer, "<li><details open><summary>{}/</summary>", escape(name))?;
+        write_tree(writer, child)?;
+        writeln!(writer, "</details></li>")?;
+    }
+    for (name, anchor) in &node.files {
+        writeln!(writer, "<li><a href=\"#{}\" data-target=\"{}\">{}</a></li>", anchor, anchor, escape(name))?;
+    }
+    writeln!(writer, "</ul>")
+}
+
+fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(format: Format, file: &FileEntry) -> String {
+        let mut out = Vec::new();
+        {
+            let mut renderer = renderer(format, &mut out, true, &MemoryBudget::new(1 << 20));
+            renderer.section("Project Context").unwrap();
+            renderer.file(file).unwrap();
+            renderer.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn entry(path: &str, contents: &'static str) -> FileEntry<'static> {
+        FileEntry {
+            path: path.to_string(),
+            bytes: contents.len() as u64,
+            language: Some("rust".to_string()),
+            sha256: None,
+            docs_url: None,
+            change: None,
+            reasons: Vec::new(),
+            body: FileBody::Text(Cow::Borrowed(contents)),
+            matches: Vec::new(),
+            match_count: None,
+        }
+    }
+
+    #[test]
+    fn text_keeps_the_plain_layout() {
+        let out = render(Format::Text, &entry("src/main.rs", "fn main() {}"));
+        assert_eq!(out, "\n=== Project Context ===\n\nFile: src/main.rs\n```rust\nfn main() {}\n```\n\n");
+    }
+
+    #[test]
+    fn html_escapes_contents_and_links_the_tree() {
+        let out = render(Format::Html, &entry("src/<a>.rs", "if a < b && c > \"d\" {}"));
+        assert!(out.starts_with("<!DOCTYPE html>"));
+        assert!(out.contains("if a &lt; b &amp;&amp; c &gt; &quot;d&quot; {}"));
+        assert!(out.contains("<summary>src/</summary>"));
+        assert!(out.contains("<a href=\"#file-1\" data-target=\"file-1\">&lt;a&gt;.rs</a>"));
+        assert!(out.trim_end().ends_with("</html>"));
+    }
+
+    #[test]
+    fn marks_grep_matches() {
+        let mut file = entry("a.rs", "retry(); x < retry");
+        file.matches = vec![0..5, 13..18];
+        file.match_count = Some(2);
+        let text = render(Format::Text, &file);
+        assert!(text.contains("File: a.rs\nMatches: 2\n"), "{}", text);
+        assert!(text.contains("```rust\n\x1b[1;31mretry\x1b[0m(); x < \x1b[1;31mretry\x1b[0m\n```"));
+        let html = render(Format::Html, &file);
+        assert!(html.contains("<mark>retry</mark>(); x &lt; <mark>retry</mark></code>"));
+    }
+
+    #[test]
+    fn json_is_one_document_with_sections() {
+        let mut out = Vec::new();
+        {
+            let mut renderer = renderer(Format::Json, &mut out, false, &MemoryBudget::new(1 << 20));
+            renderer.command_output(&[CapturedCommand { output: Some("error: boom\n"), run: None }]).unwrap();
+            renderer.section("Project Context").unwrap();
+            renderer.file(&entry("a.rs", "fn a() {}")).unwrap();
+            renderer.note("Scan stopped early").unwrap();
+            renderer.file(&entry("b.rs", "fn b() {}")).unwrap();
+            renderer.section("Relevant Dependency Files").unwrap();
+            renderer.finish().unwrap();
+        }
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["version"], CONTEXT_VERSION);
+        assert_eq!(json["command_output"], "error: boom\n");
+        assert_eq!(json["files"][1]["content"], "fn b() {}");
+        assert_eq!(json["files"][0]["size"], 9);
+        assert_eq!(json["files"][0]["language"], "rust");
+        assert_eq!(json["files"][0]["lines"], serde_json::json!({"code": 1, "comment": 0, "blank": 0}));
+        assert_eq!(json["dependencies"], serde_json::json!([]));
+        assert_eq!(json["notes"][0], "Scan stopped early");
+    }
+
+    #[test]
+    fn labels_each_of_several_commands() {
+        let run = |command: &str, exit_code| CommandRun {
+            command: Some(command.to_string()),
+            exit_code: Some(exit_code),
+            signal: None,
+            duration: None,
+        };
+        let (build, test) = (run("cargo build", 0), run("cargo test", 101));
+        let commands = [
+            CapturedCommand { output: Some("Finished\n"), run: Some(&build) },
+            CapturedCommand { output: Some("test a ... FAILED\n"), run: Some(&test) },
+        ];
+        let render = |format| {
+            let mut out = Vec::new();
+            {
+                let mut renderer = renderer(format, &mut out, false, &MemoryBudget::new(1 << 20));
+                renderer.command_output(&commands).unwrap();
+                renderer.finish().unwrap();
+            }
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            render(Format::Text),
+            "\n=== Command Output (cargo build) ===\n\nCommand: cargo build\nExit status: 0\n```\nFinished\n```\n\
+             \n=== Command Output (cargo test) ===\n\nCommand: cargo test\nExit status: 101\n```\ntest a ... FAILED\n```\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&render(Format::Json)).unwrap();
+        assert_eq!(
+            json["commands"],
+            serde_json::json!([
+                { "command": "cargo build", "exit_code": 0, "output": "Finished\n" },
+                { "command": "cargo test", "exit_code": 101, "output": "test a ... FAILED\n" },
+            ])
+        );
+        assert!(json.get("command_output").is_none());
+    }
+
+    #[test]
+    fn markdown_lists_headings_with_github_anchors() {
+        let mut out = Vec::new();
+        {
+            let mut renderer = renderer(Format::Markdown, &mut out, false, &MemoryBudget::new(1 << 20));
+            renderer.section("Project Context").unwrap();
+            renderer.file(&entry("src/main.rs", "fn main() {}")).unwrap();
+            renderer.file(&entry("README.md", "```sh\nmake\n```")).unwrap();
+            renderer.file(&entry("src/main.rs", "")).unwrap();
+            renderer.finish().unwrap();
+        }
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("## Contents\n\n- [Project Context](#project-context)\n  - [`src/main.rs`](#srcmainrs)\n"));
+        assert!(out.contains("  - [`src/main.rs`](#srcmainrs-1)\n"));
+        assert!(out.contains("\n\n## Project Context\n\n### `src/main.rs`\n\n```rust\nfn main() {}\n```\n\n###"));
+        assert!(out.contains("````rust\n```sh\nmake\n```\n````\n"));
+    }
+
+    #[test]
+    fn xml_wraps_each_section_in_documents() {
+        let mut out = Vec::new();
+        {
+            let mut renderer = renderer(Format::Xml, &mut out, false, &MemoryBudget::new(1 << 20));
+            renderer.section("Project Context").unwrap();
+            renderer.file(&entry("src/a.rs", "if a < b && c -> d {}\n")).unwrap();
+            renderer.section("Relevant Dependency Files").unwrap();
+            let mut dependency = entry("$CARGO_HOME/x/lib.rs", "pub fn x() {}");
+            dependency.reasons = vec!["x::y at src/a.rs:1".to_string()];
+            renderer.file(&dependency).unwrap();
+            renderer.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<documents section=\"Project Context\">\n\
+             <document index=\"1\" path=\"src/a.rs\" language=\"rust\">\nif a &lt; b &amp;&amp; c -> d {}\n</document>\n\
+             </documents>\n\
+             <documents section=\"Relevant Dependency Files\">\n\
+             <document index=\"2\" path=\"$CARGO_HOME/x/lib.rs\" language=\"rust\">\n\
+             <reason>x::y at src/a.rs:1</reason>\npub fn x() {}\n</document>\n\
+             </documents>\n"
+        );
+    }
+
+    #[test]
+    fn tar_archives_the_files_alone() {
+        let long = format!("{}/main.rs", "src".repeat(40));
+        let mut out = Vec::new();
+        {
+            let mut renderer = renderer(Format::Tar, &mut out, false, &MemoryBudget::new(1 << 20));
+            renderer.tree(".\n└── src\n").unwrap();
+            renderer.section("Project Context").unwrap();
+            renderer.file(&entry("./src/a.rs", "fn a() {}\n")).unwrap();
+            renderer.file(&entry(&long, "fn main() {}\n")).unwrap();
+            renderer.file(&entry("../outside.rs", "")).unwrap();
+            renderer.note("stopped").unwrap();
+            renderer.finish().unwrap();
+        }
+        let field = |block: usize, range: Range<usize>| {
+            let bytes = &out[block * 512..][range];
+            String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string()
+        };
+        // Header, one block of contents; PAX header, its record, header, contents; end of archive
+        assert_eq!(out.len(), 512 * 8);
+        assert_eq!((field(0, 0..100), field(0, 124..136), field(1, 0..10)), ("src/a.rs".into(), "00000000012".into(), "fn a() {}\n".into()));
+        assert_eq!((field(0, 257..263), field(0, 263..265)), ("ustar".into(), "00".into()));
+        let checksum: u32 = out[..512].iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { 32 } else { u32::from(b) }).sum();
+        assert_eq!(field(0, 148..154), format!("{:06o}", checksum));
+        assert_eq!(field(2, 156..157), "x");
+        assert_eq!(field(3, 0..140), format!("138 path={}\n", long));
+        assert_eq!(field(4, 0..100), long[..100]);
+        assert!(out[6 * 512..].iter().all(|&b| b == 0));
+        assert_eq!(archive_path("/usr/include/a.h").as_deref(), Some("usr/include/a.h"));
+        assert_eq!(archive_path("https://example.com/a.md"), None);
+    }
+}
@@ -0,0 +1,67 @@
+// Keyword highlighting for common languages, applied to each file as it scrolls into view
+const KEYWORDS = {
+  rust: "as async await break const continue crate dyn else enum extern false fn for if impl in let loop match mod move mut pub ref return self Self static struct super trait true type unsafe use where while",
+  python: "and as assert async await break class continue def del elif else except False finally for from global if import in is lambda None nonlocal not or pass raise return True try while with yield",
+  javascript: "async await break case catch class const continue default delete do else export extends false finally for function if import in instanceof let new null return super switch this throw true try typeof undefined var void while yield",
+  go: "break case chan const continue default defer else fallthrough for func go goto if import interface map nil package range return select struct switch type var",
+  c: "auto break case char const continue default do double else enum extern float for goto if int long register return short signed sizeof static struct switch typedef union unsigned void volatile while",
+  java: "abstract boolean break byte case catch char class continue default do double else enum extends final finally float for if implements import instanceof int interface long new null package private protected public return short static super switch this throw throws true false try void while",
+  shell: "case do done elif else esac export fi for function if in local return then while",
+};
+KEYWORDS.typescript = KEYWORDS.javascript + " interface type enum implements private public readonly";
+KEYWORDS.tsx = KEYWORDS.typescript;
+KEYWORDS.cpp = KEYWORDS.c + " class namespace template typename public private protected virtual new delete true false nullptr";
+KEYWORDS.bash = KEYWORDS.shell;
+const HASH_COMMENTS = ["python", "shell", "bash", "toml", "yaml", "ruby", "make"];
+
+function escapeHtml(text) {
+  return text.replace(/[&<>]/g, (c) => ({ "&": "&amp;", "<": "&lt;", ">": "&gt;" })[c]);
+}
+
+function highlight(code) {
+  const lang = code.dataset.lang;
+  const hash = HASH_COMMENTS.includes(lang);
+  const words = KEYWORDS[lang];
+  if (!words && !hash) return;
+  const comment = hash ? "#.*" : "\\/\\/.*|\\/\\*[\\s\\S]*?\\*\\/";
+  // Rust lifetimes and chars share the quote, so only double-quoted strings there
+  const string = lang === "rust" ? '"(?:\\\\.|[^"\\\\])*"' : "\"(?:\\\\.|[^\"\\\\])*\"|'(?:\\\\.|[^'\\\\])*'";
+  const keyword = words ? "|\\b(" + words.split(" ").join("|") + ")\\b" : "";
+  const re = new RegExp("(" + comment + ")|(" + string + ")|\\b(\\d[\\w.]*)" + keyword, "g");
+  // Grep matches arrive as <mark> elements; only the text around them is highlighted
+  for (const node of [...code.childNodes]) {
+    if (node.nodeType !== Node.TEXT_NODE) continue;
+    const text = node.textContent;
+    let html = "";
+    let last = 0;
+    for (const m of text.matchAll(re)) {
+      const cls = m[1] ? "c" : m[2] ? "s" : m[3] ? "n" : "k";
+      html += escapeHtml(text.slice(last, m.index)) + '<span class="' + cls + '">' + escapeHtml(m[0]) + "</span>";
+      last = m.index + m[0].length;
+    }
+    const span = document.createElement("span");
+    span.innerHTML = html + escapeHtml(text.slice(last));
+    node.replaceWith(...span.childNodes);
+  }
+}
+
+const observer = new IntersectionObserver((entries) => {
+  for (const entry of entries) {
+    if (entry.isIntersecting) {
+      observer.unobserve(entry.target);
+      highlight(entry.target);
+    }
+  }
+});
+document.querySelectorAll("code[data-lang]").forEach((code) => observer.observe(code));
+
+// Filter files (and their tree entries) by path or content
+document.getElementById("search").addEventListener("input", (event) => {
+  const query = event.target.value.toLowerCase();
+  document.querySelectorAll("details.file").forEach((file) => {
+    const match = !query || file.dataset.path.toLowerCase().includes(query) || file.textContent.toLowerCase().includes(query);
+    file.hidden = !match;
+    const link = document.querySelector('nav a[data-target="' + file.id + '"]');
+    if (link) link.parentElement.hidden = !match;
+  });
+});
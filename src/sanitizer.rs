@@ -0,0 +1,63 @@
+use crate::extract::Location;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Source files worth resolving from a stack frame; C and C++ cover `-sys` crates
+const SOURCE_EXTENSIONS: &[&str] = &["rs", "c", "cc", "cpp", "h", "hpp"];
+
+// Toolchain and system locations, dropped even when they happen to sit below the project
+const TOOLCHAIN_MARKERS: &[&str] = &["/rustc/", "/rustlib/src/", "/.rustup/", "/usr/include/", "/usr/lib/"];
+
+// File locations from Miri undefined-behavior reports and AddressSanitizer/ThreadSanitizer
+// stack traces (frames and allocation sites). Only frames in the project or in a dependency
+// source directory are kept; standard library, toolchain, and system header frames are dropped.
+pub fn frame_locations(output: &str, root: &Path, dependency_roots: &[PathBuf]) -> Vec<Location> {
+    // ASan: `#3 0x55d1 in crate::f /src/lib.rs:10:5`; TSan: `#0 crate::f /src/lib.rs:10:5 (bin+0x1)`
+    let sanitizer_frame =
+        Regex::new(r"^\s*#\d+\s+(?:0x[0-9a-fA-F]+\s+in\s+)?\S.*?\s+\(?([^\s()]+?):(\d+)(?::\d+)?\)?").unwrap();
+    // Miri: "inside `f` at src/main.rs:5:14"
+    let miri_frame = Regex::new(r"\binside `.*` at ([^\s]+):(\d+):\d+").unwrap();
+    // Miri points at the offending code and allocation sites with compiler-style arrows
+    let miri_arrow = Regex::new(r"^\s*--> ([^\s]+):(\d+):\d+").unwrap();
+    let is_miri = output.contains("Undefined Behavior") || miri_frame.is_match(output);
+
+    let mut locations: Vec<Location> = Vec::new();
+    for line in output.lines() {
+        let (cap, origin) = if let Some(cap) = sanitizer_frame.captures(line) {
+            (cap, "sanitizer report")
+        } else if let Some(cap) = miri_frame.captures(line) {
+            (cap, "Miri report")
+        } else if let Some(cap) = miri_arrow.captures(line).filter(|_| is_miri) {
+            (cap, "Miri report")
+        } else {
+            continue;
+        };
+
+        let path = &cap[1];
+        let is_source = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| SOURCE_EXTENSIONS.contains(&e));
+        if !is_source || TOOLCHAIN_MARKERS.iter().any(|marker| path.contains(marker)) {
+            continue;
+        }
+        // Joining leaves absolute paths unchanged and resolves relative ones against the project
+        let Ok(resolved) = fs::canonicalize(root.join(path)) else {
+            continue; // Not on this machine
+        };
+        let within = |dir: &Path| fs::canonicalize(dir).is_ok_and(|dir| resolved.starts_with(dir));
+        if !within(root) && !dependency_roots.iter().any(|dir| within(dir)) {
+            continue;
+        }
+        if locations.iter().any(|location| location.path == path) {
+            continue;
+        }
+        locations.push(Location {
+            path: path.to_string(),
+            line: cap[2].parse().ok(),
+            origin,
+        });
+    }
+    locations
+}
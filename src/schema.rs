@@ -0,0 +1,172 @@
+// JSON Schemas for contree's machine-readable outputs, printed by `contree schema`.
+// Each schema's `$id` carries the format version; they change together with it.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SchemaKind {
+    /// The --manifest sidecar
+    Manifest,
+    /// The context written by --format json
+    Context,
+}
+
+pub fn schema(kind: SchemaKind) -> &'static str {
+    match kind {
+        SchemaKind::Manifest => include_str!("../schema/manifest.schema.json"),
+        SchemaKind::Context => include_str!("../schema/context.schema.json"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Manifest, ManifestCommand, ManifestDependency, ManifestFile, ManifestSignature, ManifestTokens};
+    use crate::warnings::{Kind, Warning};
+    use serde_json::Value;
+
+    fn load(kind: SchemaKind) -> Value {
+        serde_json::from_str(schema(kind)).unwrap()
+    }
+
+    // Every key of `value` is declared by `schema`, and every required key is present.
+    // `root` is the whole schema, for resolving `$ref`s.
+    fn assert_conforms(value: &Value, schema: &Value, root: &Value, at: &str) {
+        let schema = match schema["$ref"].as_str().and_then(|reference| reference.strip_prefix("#/$defs/")) {
+            Some(name) => &root["$defs"][name],
+            None => schema,
+        };
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map {
+                    let child_schema = schema["properties"]
+                        .get(key)
+                        .or_else(|| schema.get("additionalProperties").filter(|extra| extra.is_object()))
+                        .unwrap_or_else(|| panic!("{}.{} is not in the schema", at, key));
+                    assert_conforms(child, child_schema, root, &format!("{}.{}", at, key));
+                }
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    assert!(map.contains_key(required.as_str().unwrap()), "{} lacks {}", at, required);
+                }
+            }
+            Value::Array(items) if schema["items"].is_object() => {
+                for item in items {
+                    assert_conforms(item, &schema["items"], root, &format!("{}[]", at));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn manifest_matches_its_schema() {
+        let schema = load(SchemaKind::Manifest);
+        assert_eq!(schema["properties"]["version"]["const"], crate::manifest::MANIFEST_VERSION);
+
+        let mut manifest = Manifest::new();
+        manifest.output = Some("out.md".to_string());
+        manifest.output_sha256 = Some("0".repeat(64));
+        manifest.command = Some(ManifestCommand {
+            command: Some("cargo test".to_string()),
+            exit_code: Some(101),
+            signal: None,
+            duration_ms: Some(840_000),
+        });
+        manifest.commands.push(ManifestCommand { command: Some("cargo build".to_string()), exit_code: Some(0), signal: None, duration_ms: None });
+        manifest.walk_stopped = Some("--max-files 1 was reached".to_string());
+        manifest.tokens = Some(ManifestTokens { max: 100, estimated: 90, skipped: vec!["d".to_string()] });
+        manifest.warnings.push(Warning { kind: Kind::Limit, message: "--max-tokens 100 was reached".to_string() });
+        manifest.files.push(ManifestFile {
+            path: "a".to_string(),
+            bytes: 1,
+            binary: false,
+            sha256: Some("0".repeat(64)),
+            hard_link_to: Some("b".to_string()),
+        });
+        manifest.dependencies.push(ManifestDependency {
+            path: "c".to_string(),
+            reasons: vec!["type C".to_string()],
+            docs_url: Some("https://docs.rs/c".to_string()),
+        });
+        manifest.signature = Some(ManifestSignature {
+            format: "minisign".to_string(),
+            signer: "key".to_string(),
+            path: "out.md.minisig".to_string(),
+        });
+        assert_conforms(&serde_json::to_value(&manifest).unwrap(), &schema, &schema, "manifest");
+    }
+
+    #[test]
+    fn json_context_matches_its_schema() {
+        use crate::capture::CommandRun;
+        use crate::render::{self, FileBody, FileEntry, Format};
+        use std::borrow::Cow;
+
+        let schema = load(SchemaKind::Context);
+        assert_eq!(schema["properties"]["version"]["const"], render::CONTEXT_VERSION);
+
+        let file = |body| FileEntry {
+            path: "a".to_string(),
+            bytes: 1,
+            language: Some("rust".to_string()),
+            sha256: Some("0".repeat(64)),
+            docs_url: Some("https://docs.rs/a".to_string()),
+            change: Some(crate::changes::FileChange { status: crate::changes::Status::Modified, added: Some(2), removed: Some(1) }),
+            reasons: vec!["type A".to_string()],
+            body,
+            matches: Vec::new(),
+            match_count: Some(3),
+        };
+        let mut out = Vec::new();
+        {
+            let mut renderer = render::renderer(Format::Json, &mut out, false, &crate::spill::MemoryBudget::new(1 << 20));
+            let run = CommandRun::piped(std::time::Duration::from_secs(1));
+            let failure = crate::test_summary::TestFailure {
+                name: "tests::adds".to_string(),
+                location: Some("src/lib.rs:10:9".to_string()),
+                message: Some("assertion failed".to_string()),
+            };
+            renderer
+                .repository(&crate::vcs::RepositoryInfo {
+                    remote: Some("https://github.com/o/r.git".to_string()),
+                    branch: Some("main".to_string()),
+                    commit: Some("0".repeat(40)),
+                    uncommitted_files: 1,
+                })
+                .unwrap();
+            renderer.problem_statement("Issue o/r#1: Retries never stop").unwrap();
+            renderer.test_failures(&[failure]).unwrap();
+            renderer.command_output(&[render::CapturedCommand { output: Some("error"), run: Some(&run) }]).unwrap();
+            renderer.error_reference(&[("E0308".to_string(), "mismatched types".to_string())]).unwrap();
+            renderer.editor_buffers(&["a".to_string()]).unwrap();
+            renderer.tree(".\n└── a\n").unwrap();
+            renderer.section("Project Context").unwrap();
+            renderer.file(&file(FileBody::Text(Cow::Borrowed("fn a() {}")))).unwrap();
+            renderer.file(&file(FileBody::Binary {
+                description: "PNG".to_string(),
+                preview: Some("00".to_string()),
+                source: "a.png".into(),
+            })).unwrap();
+            renderer.file(&file(FileBody::HardLink("b".to_string()))).unwrap();
+            renderer.note("stopped").unwrap();
+            renderer.answer_format(&crate::answer::AnswerFormat::Diff.instructions(None)).unwrap();
+            renderer.warnings(&[Warning { kind: Kind::Skipped, message: "Skipping socket s".to_string() }]).unwrap();
+            renderer.section("Relevant Dependency Files").unwrap();
+            renderer.file(&file(FileBody::Unreadable("denied".to_string()))).unwrap();
+            renderer.finish().unwrap();
+        }
+        let context: Value = serde_json::from_slice(&out).unwrap();
+        assert_conforms(&context, &schema, &schema, "context");
+
+        // Several --run commands are listed under `commands` instead
+        let mut out = Vec::new();
+        {
+            let mut renderer = render::renderer(Format::Json, &mut out, false, &crate::spill::MemoryBudget::new(1 << 20));
+            let run = CommandRun::piped(std::time::Duration::from_secs(1));
+            let command = render::CapturedCommand { output: Some("error"), run: Some(&run) };
+            renderer.command_output(&[command, render::CapturedCommand { output: None, run: Some(&run) }]).unwrap();
+            renderer.section("Project Context").unwrap();
+            renderer.finish().unwrap();
+        }
+        let context: Value = serde_json::from_slice(&out).unwrap();
+        assert!(context["commands"].is_array());
+        assert_conforms(&context, &schema, &schema, "context");
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{digest, http};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/harmony-labs/contree-cli/releases/latest";
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Clone)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+// Replace the running executable with the latest GitHub release for this platform, after
+// checking the download against the SHA-256 published with the release. With `check_only`,
+// just report whether a newer release exists.
+pub fn self_update(check_only: bool) -> Result<()> {
+    let release: Release = serde_json::from_reader(get(LATEST_RELEASE_URL)?.into_reader())
+        .context("GitHub returned an unexpected release response")?;
+    let current = env!("CARGO_PKG_VERSION");
+    if !is_newer(&release.tag_name, current) {
+        eprintln!("contree {} is up to date (latest release: {})", current, release.tag_name);
+        return Ok(());
+    }
+    if check_only {
+        eprintln!("contree {} is available (installed: {}); run `contree self-update` to install it", release.tag_name, current);
+        return Ok(());
+    }
+
+    let asset = platform_asset(&release.assets, env::consts::OS, env::consts::ARCH).ok_or_else(|| {
+        anyhow!("Release {} has no binary for {}-{}", release.tag_name, env::consts::OS, env::consts::ARCH)
+    })?;
+    let expected = expected_sha256(&release.assets, &asset)?;
+
+    let dir = tempfile::tempdir()?;
+    let download = dir.path().join(&asset.name);
+    let mut bytes = Vec::new();
+    get(&asset.browser_download_url)?
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("Failed to download {}", asset.name))?;
+    let actual = digest::sha256_hex(&bytes);
+    if actual != expected {
+        bail!("Checksum mismatch for {}: expected {}, got {}; not installing", asset.name, expected, actual);
+    }
+    fs::write(&download, &bytes)?;
+
+    let binary = unpack(&download, dir.path())?;
+    let current_exe = env::current_exe()?;
+    replace_executable(&binary, &current_exe)?;
+    eprintln!("Updated {} from {} to {}", current_exe.display(), current, release.tag_name);
+    Ok(())
+}
+
+fn get(url: &str) -> Result<ureq::Response> {
+    let mut request = ureq::get(url).set("User-Agent", http::USER_AGENT);
+    if let Ok(token) = env::var("GITHUB_TOKEN").or_else(|_| env::var("GH_TOKEN")) {
+        // Only to raise the API rate limit
+        request = request.set("Authorization", &format!("Bearer {}", token));
+    }
+    request
+        .call()
+        .map_err(http::describe_error)
+        .with_context(|| format!("Failed to fetch {}", url))
+}
+
+// `v1.2.3` > `1.2.2`, comparing numeric components
+fn is_newer(tag: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    parse(tag) > parse(current)
+}
+
+// Pick the release asset for an OS and architecture the way `ubi` does: by name tokens,
+// skipping checksum and signature files
+fn platform_asset(assets: &[Asset], os: &str, arch: &str) -> Option<Asset> {
+    let os_names = match os {
+        "macos" => vec!["darwin", "macos", "apple"],
+        "windows" => vec!["windows", "win64"],
+        other => vec![other],
+    };
+    let arch_names = match arch {
+        "x86_64" => vec!["x86_64", "amd64", "x64"],
+        "aarch64" => vec!["aarch64", "arm64"],
+        other => vec![other],
+    };
+    assets
+        .iter()
+        .filter(|asset| !is_checksum_or_signature(&asset.name))
+        .find(|asset| {
+            let name = asset.name.to_lowercase();
+            os_names.iter().any(|os| name.contains(os)) && arch_names.iter().any(|arch| name.contains(arch))
+        })
+        .cloned()
+}
+
+fn is_checksum_or_signature(name: &str) -> bool {
+    let name = name.to_lowercase();
+    [".sha256", ".sha256sum", ".minisig", ".sig", ".asc"].iter().any(|ext| name.ends_with(ext))
+        || name.contains("checksums")
+        || name.contains("sha256sums")
+}
+
+// The SHA-256 published for an asset, from `<asset>.sha256` or a combined checksums file
+fn expected_sha256(assets: &[Asset], asset: &Asset) -> Result<String> {
+    let lower = |name: &str| name.to_lowercase();
+    let checksum_asset = assets
+        .iter()
+        .find(|a| lower(&a.name) == format!("{}.sha256", lower(&asset.name)))
+        .or_else(|| assets.iter().find(|a| lower(&a.name).contains("checksums") || lower(&a.name).contains("sha256sums")))
+        .ok_or_else(|| anyhow!("Release publishes no checksum for {}; refusing to install an unverified binary", asset.name))?;
+    let text = get(&checksum_asset.browser_download_url)?.into_string()?;
+    find_checksum(&text, &asset.name)
+        .ok_or_else(|| anyhow!("{} does not list a SHA-256 for {}", checksum_asset.name, asset.name))
+}
+
+// `sha256sum` format (`<hex>  <name>`), or a bare hash in a single-asset checksum file
+fn find_checksum(text: &str, name: &str) -> Option<String> {
+    let is_hash = |word: &str| word.len() == 64 && word.chars().all(|c| c.is_ascii_hexdigit());
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let listed = lines.iter().find_map(|line| {
+        let (hash, file) = line.split_once(char::is_whitespace)?;
+        (is_hash(hash) && file.trim().trim_start_matches('*') == name).then(|| hash.to_lowercase())
+    });
+    match lines.as_slice() {
+        [only] if listed.is_none() => only.split_whitespace().next().filter(|word| is_hash(word)).map(str::to_lowercase),
+        _ => listed,
+    }
+}
+
+// Extract an archive with the system `tar` (which also reads zip files on Windows and macOS)
+// and return the contree binary inside; a bare binary is returned as is
+fn unpack(download: &Path, dir: &Path) -> Result<PathBuf> {
+    let name = download.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_lowercase();
+    let is_archive = [".tar.gz", ".tgz", ".tar.xz", ".tar", ".zip"].iter().any(|ext| name.ends_with(ext));
+    if !is_archive {
+        return Ok(download.to_path_buf());
+    }
+    let extracted = dir.join("extracted");
+    fs::create_dir(&extracted)?;
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(download)
+        .arg("-C")
+        .arg(&extracted)
+        .status()
+        .context("Failed to run tar to unpack the release")?;
+    if !status.success() {
+        bail!("tar failed to unpack {}", download.display());
+    }
+    let binary_name = format!("contree{}", env::consts::EXE_SUFFIX);
+    walkdir::WalkDir::new(&extracted)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_type().is_file() && e.file_name() == binary_name.as_str())
+        .map(|e| e.into_path())
+        .ok_or_else(|| anyhow!("{} does not contain {}", download.display(), binary_name))
+}
+
+// Move the new binary into place next to the old one, then swap them. A running executable
+// can be renamed but not overwritten on Windows, so the old one is moved aside first.
+fn replace_executable(new: &Path, current: &Path) -> Result<()> {
+    let dir = current.parent().ok_or_else(|| anyhow!("Cannot locate the directory of {}", current.display()))?;
+    let staged = dir.join(format!(".contree-update{}", env::consts::EXE_SUFFIX));
+    fs::copy(new, &staged).with_context(|| format!("Failed to write to {} (is it writable?)", dir.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(windows)]
+    {
+        let old = current.with_extension("old.exe");
+        let _ = fs::remove_file(&old);
+        fs::rename(current, &old)?;
+    }
+    fs::rename(&staged, current).with_context(|| format!("Failed to replace {}", current.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            name: name.to_string(),
+            browser_download_url: format!("https://example.com/{}", name),
+        }
+    }
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(is_newer("v0.10.0", "0.9.3"));
+        assert!(is_newer("v1.0.0", "0.99.0"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("v0.1.0", "0.2.0"));
+    }
+
+    #[test]
+    fn picks_platform_asset_and_skips_checksums() {
+        let assets = [
+            asset("contree-x86_64-unknown-linux-gnu.tar.gz.sha256"),
+            asset("contree-x86_64-unknown-linux-gnu.tar.gz"),
+            asset("contree-aarch64-apple-darwin.tar.gz"),
+            asset("contree-x86_64-pc-windows-msvc.zip"),
+        ];
+        let pick = |os, arch| platform_asset(&assets, os, arch).map(|a| a.name);
+        assert_eq!(pick("linux", "x86_64").as_deref(), Some("contree-x86_64-unknown-linux-gnu.tar.gz"));
+        assert_eq!(pick("macos", "aarch64").as_deref(), Some("contree-aarch64-apple-darwin.tar.gz"));
+        assert_eq!(pick("windows", "x86_64").as_deref(), Some("contree-x86_64-pc-windows-msvc.zip"));
+        assert_eq!(pick("linux", "aarch64"), None);
+    }
+
+    #[test]
+    fn reads_checksum_files() {
+        let hash = "a".repeat(64);
+        let sums = format!("{}  other.tar.gz\n{} *contree.tar.gz\n", "b".repeat(64), hash);
+        assert_eq!(find_checksum(&sums, "contree.tar.gz"), Some(hash.clone()));
+        assert_eq!(find_checksum(&format!("{}\n", hash.to_uppercase()), "contree.tar.gz"), Some(hash));
+        assert_eq!(find_checksum(&sums, "missing.tar.gz"), None);
+    }
+}
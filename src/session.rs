@@ -0,0 +1,228 @@
+use crate::clock;
+use crate::manifest::Manifest;
+use crate::warnings;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Sessions live inside the scanned project, which is why the walker skips `.contree`
+pub const CONTREE_DIR: &str = ".contree";
+pub const SESSIONS_DIR: &str = ".contree/sessions";
+
+// Keeps recorded output and arguments out of `git add .`
+const GITIGNORE: &str = ".contree/.gitignore";
+
+const ARGS_FILE: &str = "args.json";
+const CONFIG_FILE: &str = "config.txt";
+const OUTPUT_FILE: &str = "output.log";
+const MANIFEST_FILE: &str = "manifest.json";
+
+// Save one run into a new timestamped session directory and return its path. `argv` holds the
+// arguments a replay passes back to contree.
+pub fn record(root: &Path, argv: &[String], config: &str, output: &str, manifest: &Manifest) -> Result<PathBuf> {
+    contree_dir(root)?;
+    let sessions = root.join(SESSIONS_DIR);
+    fs::create_dir_all(&sessions)
+        .with_context(|| format!("Failed to create {}", sessions.display()))?;
+
+    // Runs within the same second get a numeric suffix rather than overwriting each other
+    let (timestamp, _) = clock::compact_utc(SystemTime::now());
+    let mut dir = sessions.join(&timestamp);
+    let mut attempt = 1;
+    while dir.exists() {
+        attempt += 1;
+        dir = sessions.join(format!("{}-{}", timestamp, attempt));
+    }
+    fs::create_dir(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let cwd = std::env::current_dir()?;
+    let args = json!({ "argv": argv, "cwd": cwd.display().to_string() });
+    fs::write(dir.join(ARGS_FILE), serde_json::to_string_pretty(&args)? + "\n")?;
+    fs::write(dir.join(CONFIG_FILE), config)?;
+    fs::write(dir.join(OUTPUT_FILE), output)?;
+    manifest.write(&dir.join(MANIFEST_FILE))?;
+    Ok(dir)
+}
+
+// The project's `.contree` directory for state kept between runs, created with a .gitignore
+pub fn contree_dir(root: &Path) -> Result<PathBuf> {
+    let dir = root.join(CONTREE_DIR);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let gitignore = root.join(GITIGNORE);
+    if !gitignore.exists() {
+        fs::write(&gitignore, "*\n").with_context(|| format!("Failed to write {}", gitignore.display()))?;
+    }
+    Ok(dir)
+}
+
+// Print one line per recorded session, oldest first
+pub fn list(root: &Path, writer: &mut dyn Write) -> Result<()> {
+    let ids = session_ids(root)?;
+    if ids.is_empty() {
+        writeln!(writer, "No sessions recorded under {}", root.join(SESSIONS_DIR).display())?;
+        return Ok(());
+    }
+    for id in ids {
+        let dir = root.join(SESSIONS_DIR).join(&id);
+        let argv = read_argv(&dir).unwrap_or_default();
+        let files = read_manifest(&dir)
+            .ok()
+            .and_then(|manifest| manifest["files"].as_array().map(Vec::len))
+            .unwrap_or(0);
+        writeln!(writer, "{}  {:>4} files  contree {}", id, files, argv.join(" "))?;
+    }
+    Ok(())
+}
+
+// Print the arguments, resolved options, included files, and captured output of a session
+pub fn show(root: &Path, id: &str, writer: &mut dyn Write) -> Result<()> {
+    let dir = resolve(root, id)?;
+    let argv = read_argv(&dir)?;
+    writeln!(writer, "Session: {}", dir.display())?;
+    writeln!(writer, "Command: contree {}", argv.join(" "))?;
+    let manifest = read_manifest(&dir)?;
+    if let Some(command) = manifest["command"]["command"].as_str() {
+        writeln!(writer, "Output of: {}", command)?;
+    }
+
+    writeln!(writer, "\n=== Resolved Options ===\n")?;
+    writeln!(writer, "{}", fs::read_to_string(dir.join(CONFIG_FILE))?.trim_end())?;
+
+    writeln!(writer, "\n=== Included Files ===\n")?;
+    for file in manifest["files"].as_array().into_iter().flatten() {
+        writeln!(writer, "{}", file["path"].as_str().unwrap_or_default())?;
+    }
+    for dependency in manifest["dependencies"].as_array().into_iter().flatten() {
+        writeln!(writer, "{} (dependency)", dependency["path"].as_str().unwrap_or_default())?;
+    }
+
+    writeln!(writer, "\n=== Captured Output ===\n")?;
+    writer.write_all(&fs::read(dir.join(OUTPUT_FILE))?)?;
+    Ok(())
+}
+
+// Build the argument list and working directory that regenerate a session's context.
+// The recorded log replaces the original input (or `--run` command), and `extra` arguments are appended so a
+// replay can tweak options; `--record` is dropped so replays don't pile up new sessions.
+// Replays never publish: `--upload`, object storage `--output` targets, and the `--sign`
+// that goes with them are dropped (with a note), and can be passed again as `extra`.
+pub fn replay_command(root: &Path, id: &str, extra: &[String]) -> Result<(Vec<OsString>, PathBuf)> {
+    let dir = resolve(root, id)?;
+    let args: Value = serde_json::from_str(&fs::read_to_string(dir.join(ARGS_FILE))?)
+        .with_context(|| format!("Failed to parse {}", dir.join(ARGS_FILE).display()))?;
+    let cwd = PathBuf::from(args["cwd"].as_str().unwrap_or("."));
+
+    // Group the recorded arguments into flags with their values
+    let mut recorded: Vec<(String, Vec<String>, Option<String>)> = Vec::new();
+    let mut args = read_argv(&dir)?.into_iter();
+    while let Some(arg) = args.next() {
+        let flag = VALUE_FLAGS.iter().find_map(|(long, short)| flag_value(&arg, long, *short).map(|v| (*long, v)));
+        match flag {
+            Some((long, Some(value))) => recorded.push((long.to_string(), vec![arg], Some(value))),
+            Some((long, None)) => {
+                let value = args.next();
+                let tokens = std::iter::once(arg).chain(value.clone()).collect();
+                recorded.push((long.to_string(), tokens, value));
+            }
+            None => recorded.push((arg.clone(), vec![arg], None)),
+        }
+    }
+    let remote_output = recorded.iter().any(|(flag, _, value)| {
+        flag == "--output" && value.as_deref().is_some_and(|v| v.starts_with("s3://") || v.starts_with("gs://"))
+    });
+
+    let mut argv = Vec::new();
+    for (flag, tokens, _) in recorded {
+        let publishes = flag == "--upload" || (remote_output && (flag == "--output" || flag == "--sign"));
+        if publishes {
+            warnings::warn(warnings::Kind::Ignored, format!("Replay does not publish; dropped `{}` (pass it after `--` to publish again)", tokens.join(" ")));
+        } else if flag != "--input" && flag != "--run" && flag != "--record" {
+            argv.extend(tokens.into_iter().map(OsString::from));
+        }
+    }
+    argv.push("--input".into());
+    argv.push(dir.join(OUTPUT_FILE).into_os_string());
+    argv.extend(extra.iter().map(OsString::from));
+    Ok((argv, cwd))
+}
+
+// Recorded flags that take a value and may be dropped on replay, with their short forms
+const VALUE_FLAGS: &[(&str, Option<char>)] = &[
+    ("--input", None),
+    ("--run", None),
+    ("--upload", None),
+    ("--output", Some('o')),
+    ("--sign", None),
+];
+
+// Match `--flag`, `--flag=value`, `-f`, or `-fvalue`: Some(None) when the value is the next
+// argument, Some(Some(value)) when it is attached, None when `arg` is another flag
+fn flag_value(arg: &str, long: &str, short: Option<char>) -> Option<Option<String>> {
+    if arg == long {
+        return Some(None);
+    }
+    if let Some(value) = arg.strip_prefix(long).and_then(|rest| rest.strip_prefix('=')) {
+        return Some(Some(value.to_string()));
+    }
+    let short = short?;
+    let rest = arg.strip_prefix('-')?.strip_prefix(short)?;
+    if arg.starts_with("--") {
+        return None;
+    }
+    Some((!rest.is_empty()).then(|| rest.trim_start_matches('=').to_string()))
+}
+
+// `latest` names the most recent session; any other id must match a directory exactly
+fn resolve(root: &Path, id: &str) -> Result<PathBuf> {
+    let ids = session_ids(root)?;
+    let found = if id == "latest" {
+        ids.last()
+    } else {
+        ids.iter().find(|candidate| *candidate == id)
+    };
+    match found {
+        Some(id) => Ok(root.join(SESSIONS_DIR).join(id)),
+        None => bail!(
+            "No session '{}' under {} (see `contree sessions list`)",
+            id,
+            root.join(SESSIONS_DIR).display()
+        ),
+    }
+}
+
+// Timestamped ids sort chronologically as plain strings
+fn session_ids(root: &Path) -> Result<Vec<String>> {
+    let sessions = root.join(SESSIONS_DIR);
+    if !sessions.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(&sessions)? {
+        let entry = entry?;
+        if entry.path().join(ARGS_FILE).is_file() {
+            ids.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+fn read_argv(dir: &Path) -> Result<Vec<String>> {
+    let args: Value = serde_json::from_str(&fs::read_to_string(dir.join(ARGS_FILE))?)
+        .with_context(|| format!("Failed to parse {}", dir.join(ARGS_FILE).display()))?;
+    Ok(args["argv"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|arg| arg.as_str().map(str::to_string))
+        .collect())
+}
+
+fn read_manifest(dir: &Path) -> Result<Value> {
+    serde_json::from_str(&fs::read_to_string(dir.join(MANIFEST_FILE))?)
+        .with_context(|| format!("Failed to parse {}", dir.join(MANIFEST_FILE).display()))
+}
@@ -0,0 +1,110 @@
+use crate::content::{self, GrepCase};
+use crate::fileio::FileSystem;
+use crate::pathspec::{self, Pathspec};
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+// One entry of `[sets]` in .contree.toml: a saved selection, e.g.
+// `auth = { paths = ["src/auth"], grep = "/token|session/" }`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SetConfig {
+    // Pathspecs, as on the command line; none selects every file
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // A pattern as for --grep
+    pub grep: Option<String>,
+}
+
+// A set named with `--set`, ready to match walked files
+#[derive(Debug)]
+pub struct FileSet {
+    pathspecs: Vec<Pathspec>,
+    grep: Option<Regex>,
+}
+
+impl FileSet {
+    // Whether a file (at `relative` in the scanned tree) belongs to the set. Fails with
+    // `InvalidData` if the set greps and the file isn't text.
+    fn contains(&self, fs: &dyn FileSystem, path: &Path, relative: &Path) -> io::Result<bool> {
+        if !pathspec::is_selected(&self.pathspecs, relative) {
+            return Ok(false);
+        }
+        match &self.grep {
+            Some(regex) => fs.grep(path, regex),
+            None => Ok(true),
+        }
+    }
+}
+
+// The sets named with `--set`, in the order given, their patterns following the command line's
+// case flags
+pub fn resolve(config: &BTreeMap<String, SetConfig>, names: &[String], case: GrepCase) -> Result<Vec<FileSet>> {
+    let mut sets = Vec::new();
+    for name in names {
+        let Some(set) = config.get(name) else {
+            if config.is_empty() {
+                bail!("No set '{}': define it in [sets] in .contree.toml", name);
+            }
+            bail!("No set '{}'; available: {}", name, config.keys().cloned().collect::<Vec<_>>().join(", "));
+        };
+        let pathspecs = set
+            .paths
+            .iter()
+            .map(|spec| Pathspec::parse(spec).map_err(|e| anyhow!("[sets] {} has an invalid path '{}': {}", name, spec, e)))
+            .collect::<Result<_>>()?;
+        let grep = set
+            .grep
+            .as_deref()
+            .map(|pattern| content::grep_regex(pattern, case))
+            .transpose()
+            .with_context(|| format!("[sets] {} has an invalid grep pattern", name))?;
+        sets.push(FileSet { pathspecs, grep });
+    }
+    Ok(sets)
+}
+
+// Sets combine as a union: a file is kept if any of them contains it
+pub fn any_contains(sets: &[FileSet], fs: &dyn FileSystem, path: &Path, relative: &Path) -> io::Result<bool> {
+    for set in sets {
+        if set.contains(fs, path, relative)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileio::MemoryFs;
+
+    #[test]
+    fn sets_match_paths_and_grep_as_a_union() {
+        let config: BTreeMap<String, SetConfig> = toml::from_str(
+            "auth = { paths = [\"src/auth\"], grep = \"/token|session/\" }\nbuild = { paths = [\"Cargo.toml\", \"build.rs\"] }",
+        )
+        .unwrap();
+        let fs = MemoryFs::default()
+            .with("/p/src/auth/login.rs", "let token = 1;")
+            .with("/p/src/auth/hash.rs", "fn hash() {}")
+            .with("/p/Cargo.toml", "[package]");
+        let kept = |sets: &[FileSet], relative: &str| any_contains(sets, &fs, &Path::new("/p").join(relative), Path::new(relative)).unwrap();
+
+        let auth = resolve(&config, &["auth".to_string()], GrepCase::Default).unwrap();
+        assert!(kept(&auth, "src/auth/login.rs"));
+        assert!(!kept(&auth, "src/auth/hash.rs"));
+        assert!(!kept(&auth, "Cargo.toml"));
+
+        let both = resolve(&config, &["auth".to_string(), "build".to_string()], GrepCase::Default).unwrap();
+        assert!(kept(&both, "src/auth/login.rs"));
+        assert!(kept(&both, "Cargo.toml"));
+
+        let unknown = resolve(&config, &["docs".to_string()], GrepCase::Default).unwrap_err().to_string();
+        assert_eq!(unknown, "No set 'docs'; available: auth, build");
+    }
+}
@@ -0,0 +1,107 @@
+use crate::manifest::ManifestSignature;
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Namespace for SSH signatures, so they can't be replayed as signatures for other purposes
+const SSH_NAMESPACE: &str = "contree";
+
+// Produce a detached signature for `file` with a minisign secret key or an SSH private key.
+// Signing is delegated to the `minisign` and `ssh-keygen` tools so passphrase prompts,
+// agents, and hardware keys work as they do elsewhere.
+pub fn sign_file(key: &Path, file: &Path) -> Result<ManifestSignature> {
+    let key_text = fs::read_to_string(key)
+        .with_context(|| format!("Failed to read signing key: {}", key.display()))?;
+    if key_text.starts_with("untrusted comment:") {
+        sign_minisign(key, file)
+    } else {
+        sign_ssh(key, file)
+    }
+}
+
+// File extension used for the detached signature of each format
+pub fn signature_extension(format: &str) -> &'static str {
+    if format == "minisign" {
+        "minisig"
+    } else {
+        "sig"
+    }
+}
+
+fn signature_path(file: &Path, format: &str) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".");
+    path.push(signature_extension(format));
+    PathBuf::from(path)
+}
+
+fn sign_minisign(key: &Path, file: &Path) -> Result<ManifestSignature> {
+    let signature = signature_path(file, "minisign");
+    let status = Command::new("minisign")
+        .arg("-S")
+        .arg("-s")
+        .arg(key)
+        .arg("-m")
+        .arg(file)
+        .arg("-x")
+        .arg(&signature)
+        .arg("-t")
+        .arg(concat!("signed by contree ", env!("CARGO_PKG_VERSION")))
+        .status()
+        .context("Failed to run minisign (is it installed?)")?;
+    if !status.success() {
+        bail!("minisign failed to sign {}", file.display());
+    }
+
+    // The signature line holds the algorithm (2 bytes) and the little-endian key id (8 bytes)
+    let sig_text = fs::read_to_string(&signature)?;
+    let sig_line = sig_text.lines().nth(1).unwrap_or_default();
+    let sig_bytes = base64::engine::general_purpose::STANDARD
+        .decode(sig_line.trim())
+        .context("minisign produced an unreadable signature")?;
+    if sig_bytes.len() < 10 {
+        bail!("minisign produced a truncated signature");
+    }
+    let mut key_id = [0u8; 8];
+    key_id.copy_from_slice(&sig_bytes[2..10]);
+
+    Ok(ManifestSignature {
+        format: "minisign".to_string(),
+        signer: format!("minisign:{:016X}", u64::from_le_bytes(key_id)),
+        path: signature.display().to_string(),
+    })
+}
+
+fn sign_ssh(key: &Path, file: &Path) -> Result<ManifestSignature> {
+    let status = Command::new("ssh-keygen")
+        .args(["-Y", "sign", "-n", SSH_NAMESPACE, "-f"])
+        .arg(key)
+        .arg(file)
+        .status()
+        .context("Failed to run ssh-keygen")?;
+    if !status.success() {
+        bail!("ssh-keygen failed to sign {}", file.display());
+    }
+
+    // `ssh-keygen -l` prints "<bits> <fingerprint> <comment> (<type>)"
+    let fingerprint = Command::new("ssh-keygen")
+        .arg("-l")
+        .arg("-f")
+        .arg(key)
+        .output()
+        .context("Failed to run ssh-keygen")?;
+    let fingerprint = String::from_utf8_lossy(&fingerprint.stdout);
+    let signer = fingerprint
+        .split_whitespace()
+        .nth(1)
+        .map(|fp| format!("ssh:{}", fp))
+        .unwrap_or_else(|| format!("ssh:{}", key.display()));
+
+    Ok(ManifestSignature {
+        format: "ssh".to_string(),
+        signer,
+        path: signature_path(file, "ssh").display().to_string(),
+    })
+}
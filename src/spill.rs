@@ -0,0 +1,129 @@
+use crate::content::FileContent;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Default cap on in-memory buffers before they spill to a temporary file
+pub const DEFAULT_MEMORY_CAP: usize = 256 * 1024 * 1024;
+
+// One memory allowance shared by every SpillBuffer in a run (captured input, the recorded
+// output, the rendered context), so together they stay under the cap. Bytes are returned to
+// the budget only when a buffer spills; finished in-memory buffers keep their share.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    remaining: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    pub fn new(cap: usize) -> Self {
+        MemoryBudget {
+            remaining: Arc::new(AtomicUsize::new(cap)),
+        }
+    }
+
+    fn reserve(&self, bytes: usize) -> bool {
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| remaining.checked_sub(bytes))
+            .is_ok()
+    }
+
+    fn release(&self, bytes: usize) {
+        self.remaining.fetch_add(bytes, Ordering::SeqCst);
+    }
+}
+
+// A write buffer that stays in memory while the shared budget allows, then moves its contents
+// to an anonymous temporary file so large captures don't grow the heap without bound
+pub struct SpillBuffer {
+    budget: MemoryBudget,
+    memory: Vec<u8>,
+    file: Option<File>,
+}
+
+impl SpillBuffer {
+    pub fn new(budget: &MemoryBudget) -> Self {
+        SpillBuffer {
+            budget: budget.clone(),
+            memory: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) -> io::Result<()> {
+        self.write_all(s.as_bytes())
+    }
+
+    // Stop writing and expose the buffered bytes, mapping the spill file if one was used
+    pub fn finish(self) -> io::Result<Spilled> {
+        match self.file {
+            None => Ok(Spilled::Memory(self.memory)),
+            Some(mut file) => {
+                file.flush()?;
+                // SAFETY: the spill file is private to this process and no longer written to
+                let map = unsafe { Mmap::map(&file)? };
+                Ok(Spilled::Mapped(map))
+            }
+        }
+    }
+
+    // Finish a buffer that was only ever written UTF-8 text
+    pub fn into_text(self) -> io::Result<FileContent> {
+        match self.finish()? {
+            Spilled::Memory(bytes) => String::from_utf8(bytes)
+                .map(FileContent::Heap)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Spilled::Mapped(map) => {
+                if std::str::from_utf8(&map).is_err() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stream did not contain valid UTF-8",
+                    ));
+                }
+                Ok(FileContent::Mapped(map))
+            }
+        }
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && !self.budget.reserve(buf.len()) {
+            let mut file = tempfile::tempfile()?;
+            file.write_all(&self.memory)?;
+            self.budget.release(self.memory.len());
+            self.memory = Vec::new();
+            self.file = Some(file);
+        }
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => self.memory.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// The finished contents of a SpillBuffer
+pub enum Spilled {
+    Memory(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Spilled {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Spilled::Memory(bytes) => bytes,
+            Spilled::Mapped(map) => map,
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use crate::render::{self, CapturedCommand, FileBody, FileEntry, Format, Renderer};
+use crate::spill::MemoryBudget;
+use crate::test_summary::TestFailure;
+use crate::tokens;
+use crate::vcs::RepositoryInfo;
+use crate::warnings::Warning;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Version of the index layout, bumped on incompatible changes
+const INDEX_VERSION: u32 = 1;
+
+// Bytes a file's header and fences add to its path and contents, for `--split-output`
+const FILE_BYTES_OVERHEAD: usize = 64;
+
+// Room each part keeps for its header besides the list of its files: its own line and the
+// other parts, as many of them as fit (the index lists them all)
+const PART_HEADER_BYTES: usize = 256;
+const PART_HEADER_TOKENS: usize = 64;
+
+// How big a part may get: about this many tokens (`--split-tokens`) or bytes (`--split-output`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Tokens(usize),
+    Bytes(usize),
+}
+
+// `context.md` -> `context.part2.md`, next to it
+fn part_path(output: &Path, number: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(extension) => format!("{}.part{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}.part{}", stem, number),
+    };
+    output.with_file_name(name)
+}
+
+// `context.md` -> `context.index.json`
+pub fn index_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{}.index.json", stem))
+}
+
+#[derive(Serialize)]
+struct IndexFile {
+    path: String,
+    section: String,
+    tokens: usize,
+}
+
+#[derive(Serialize)]
+struct IndexPart {
+    // File name, next to the index
+    path: String,
+    tokens: usize,
+    // Estimated like the limit, with --split-output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<usize>,
+    files: Vec<IndexFile>,
+    // Kept for the header and the document around the contents, which the limit covers too
+    #[serde(skip)]
+    reserved_tokens: usize,
+    #[serde(skip)]
+    reserved_bytes: usize,
+}
+
+#[derive(Serialize)]
+struct Index<'a> {
+    version: u32,
+    format: &'a str,
+    tokenizer: &'a str,
+    parts: &'a [IndexPart],
+}
+
+// A renderer that writes the context as numbered parts within a limit, in the chosen format,
+// so each part can be fed to a model on its own. A part ends before the file that would take it
+// over the limit (a file larger than the limit gets a part to itself); the section a part
+// starts in is repeated at its top. `close` heads each part with a list of its files and the
+// other parts, and writes the index of which files live in which part.
+pub struct SplitRenderer {
+    format: Format,
+    budget: MemoryBudget,
+    output: PathBuf,
+    limit: Limit,
+    current: Option<Box<dyn Renderer>>,
+    section: Option<String>,
+    parts: Vec<IndexPart>,
+    // What an empty part's document and header take, reserved by every part
+    empty_tokens: usize,
+    empty_bytes: usize,
+}
+
+impl SplitRenderer {
+    pub fn new(format: Format, budget: &MemoryBudget, output: &Path, limit: Limit) -> Self {
+        // Rendered once to learn its size, like the stylesheet and script of an HTML page
+        let mut empty = Vec::new();
+        let _ = render::renderer(format, &mut empty, false, budget).finish();
+        let empty = String::from_utf8_lossy(&empty);
+        let (empty_tokens, empty_bytes) = match part_header(format, &[]) {
+            Some(_) => (tokens::estimate(&empty) + PART_HEADER_TOKENS, empty.len() + PART_HEADER_BYTES),
+            None => (tokens::estimate(&empty), empty.len()),
+        };
+        SplitRenderer {
+            format,
+            budget: budget.clone(),
+            output: output.to_path_buf(),
+            limit,
+            current: None,
+            section: None,
+            parts: Vec::new(),
+            empty_tokens,
+            empty_bytes,
+        }
+    }
+
+    // The part being written, starting the first one if needed
+    fn part(&mut self) -> io::Result<&mut Box<dyn Renderer>> {
+        if self.current.is_none() {
+            self.start_part()?;
+        }
+        Ok(self.current.as_mut().expect("part started"))
+    }
+
+    fn start_part(&mut self) -> io::Result<()> {
+        if let Some(mut finished) = self.current.take() {
+            finished.finish()?;
+        }
+        let path = part_path(&self.output, self.parts.len() + 1);
+        let writer = BufWriter::new(File::create(&path)?);
+        let mut renderer = render::renderer(self.format, writer, false, &self.budget);
+        if let Some(section) = &self.section {
+            renderer.section(section)?;
+        }
+        self.current = Some(renderer);
+        self.parts.push(IndexPart {
+            path: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            tokens: 0,
+            bytes: matches!(self.limit, Limit::Bytes(_)).then_some(0),
+            files: Vec::new(),
+            reserved_tokens: self.empty_tokens,
+            reserved_bytes: self.empty_bytes,
+        });
+        if let Some(section) = self.section.clone() {
+            self.count_heading(&section);
+        }
+        Ok(())
+    }
+
+    // Count text written outside of files against the part
+    fn count<'a>(&mut self, texts: impl IntoIterator<Item = &'a str>) {
+        for text in texts {
+            self.add(tokens::estimate(text), text.len());
+        }
+    }
+
+    // A section heading, which adds less than a file's header and fences to its title
+    fn count_heading(&mut self, title: &str) {
+        self.add(tokens::estimate(title), title.len() + FILE_BYTES_OVERHEAD);
+    }
+
+    fn add(&mut self, tokens: usize, bytes: usize) {
+        if let Some(part) = self.parts.last_mut() {
+            part.tokens += tokens;
+            part.bytes = part.bytes.map(|total| total + bytes);
+        }
+    }
+
+    // Whether a file of this size would take the current part over the limit
+    fn overflows(&self, tokens: usize, bytes: usize) -> bool {
+        self.parts.last().is_some_and(|part| {
+            !part.files.is_empty()
+                && match self.limit {
+                    Limit::Tokens(max) => part.tokens + part.reserved_tokens + tokens > max,
+                    Limit::Bytes(max) => part.bytes.unwrap_or_default() + part.reserved_bytes + bytes > max,
+                }
+        })
+    }
+
+    // Put each part's header above its contents, now that every part's files are known
+    fn write_headers(&self) -> Result<()> {
+        let dir = self.output.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let index = index_path(&self.output);
+        let index = index.file_name().unwrap_or_default().to_string_lossy();
+        for (i, part) in self.parts.iter().enumerate() {
+            let mut lines = vec![format!("Part {} of {}; {} lists the files of every part", i + 1, self.parts.len(), index)];
+            lines.push(match part.files.len() {
+                0 => "Files in this part: none".to_string(),
+                _ => format!("Files in this part: {}", part.files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>().join(", ")),
+            });
+            let mut others: Vec<String> = self
+                .parts
+                .iter()
+                .filter(|other| other.path != part.path)
+                .map(|other| match other.files.len() {
+                    1 => format!("{} (1 file)", other.path),
+                    count => format!("{} ({} files)", other.path, count),
+                })
+                .collect();
+            // Other parts that don't fit in the room the part kept are left to the index
+            let mut left_out = 0;
+            let header = loop {
+                let mut lines = lines.clone();
+                match (others.len(), left_out) {
+                    (0, 0) => {}
+                    (0, _) => lines.push(format!("Other parts: {}, listed in {}", left_out, index)),
+                    (_, 0) => lines.push(format!("Other parts: {}", others.join(", "))),
+                    _ => lines.push(format!("Other parts: {}, and {} more in {}", others.join(", "), left_out, index)),
+                }
+                let Some(header) = part_header(self.format, &lines) else {
+                    return Ok(());
+                };
+                let fits = match self.limit {
+                    Limit::Tokens(_) => tokens::estimate(&header) <= part.reserved_tokens,
+                    Limit::Bytes(_) => header.len() <= part.reserved_bytes,
+                };
+                if fits || others.pop().is_none() {
+                    break header;
+                }
+                left_out += 1;
+            };
+            let path = dir.join(&part.path);
+            let mut rewritten = tempfile::NamedTempFile::new_in(dir)?;
+            rewritten.write_all(header.as_bytes())?;
+            io::copy(&mut File::open(&path)?, &mut rewritten)?;
+            rewritten.persist(&path).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    // Write the index of parts, returning its path
+    fn write_index(&self) -> Result<PathBuf> {
+        let path = index_path(&self.output);
+        let format = self.format.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        let index = Index { version: INDEX_VERSION, format: &format, tokenizer: tokens::TOKENIZER, parts: &self.parts };
+        let mut json = serde_json::to_vec_pretty(&index)?;
+        json.push(b'\n');
+        fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    // End the last part, head the parts, and write the index
+    pub fn close(mut self) -> Result<PathBuf> {
+        self.part()?.finish()?;
+        // Dropped before the headers go in, which flushes it
+        self.current = None;
+        self.write_headers()?;
+        self.write_index()
+    }
+}
+
+// A part's header in its format: a section in text, a quote in Markdown, and a comment in
+// HTML and XML. JSON has nowhere to put one ahead of the document; the index lists its parts.
+fn part_header(format: Format, lines: &[String]) -> Option<String> {
+    match format {
+        Format::Text => Some(format!("=== Parts ===\n\n{}\n", lines.join("\n"))),
+        Format::Markdown => Some(format!("> {}\n\n", lines.join("  \n> "))),
+        Format::Html | Format::Xml => Some(format!("<!--\n{}\n-->\n", lines.join("\n").replace("--", "- -"))),
+        Format::Json | Format::Tar => None,
+    }
+}
+
+impl Renderer for SplitRenderer {
+    fn repository(&mut self, info: &RepositoryInfo) -> io::Result<()> {
+        self.part()?.repository(info)
+    }
+
+    fn problem_statement(&mut self, text: &str) -> io::Result<()> {
+        self.part()?.problem_statement(text)?;
+        self.count([text]);
+        Ok(())
+    }
+
+    fn test_failures(&mut self, failures: &[TestFailure]) -> io::Result<()> {
+        self.part()?.test_failures(failures)?;
+        self.count(failures.iter().flat_map(|failure| [Some(failure.name.as_str()), failure.message.as_deref()]).flatten());
+        Ok(())
+    }
+
+    fn command_output(&mut self, commands: &[CapturedCommand]) -> io::Result<()> {
+        self.part()?.command_output(commands)?;
+        self.count(commands.iter().filter_map(|command| command.output));
+        Ok(())
+    }
+
+    fn error_reference(&mut self, explanations: &[(String, String)]) -> io::Result<()> {
+        self.part()?.error_reference(explanations)?;
+        self.count(explanations.iter().map(|(_, text)| text.as_str()));
+        Ok(())
+    }
+
+    fn editor_buffers(&mut self, buffers: &[String]) -> io::Result<()> {
+        self.part()?.editor_buffers(buffers)
+    }
+
+    fn tree(&mut self, tree: &str) -> io::Result<()> {
+        self.part()?.tree(tree)?;
+        self.count([tree]);
+        Ok(())
+    }
+
+    fn section(&mut self, title: &str) -> io::Result<()> {
+        self.section = Some(title.to_string());
+        // A new part starts with the section already
+        match &mut self.current {
+            Some(part) => {
+                part.section(title)?;
+                self.count_heading(title);
+                Ok(())
+            }
+            None => self.start_part(),
+        }
+    }
+
+    fn file(&mut self, file: &FileEntry) -> io::Result<()> {
+        let estimated = tokens::estimate_file(&file.path, &file.body, None);
+        let body_bytes = match &file.body {
+            FileBody::Text(text) => text.len(),
+            FileBody::Binary { description, preview, .. } => description.len() + preview.as_ref().map_or(0, String::len),
+            FileBody::HardLink(first) => first.len(),
+            FileBody::Unreadable(reason) => reason.len(),
+        };
+        // Markdown names it twice more in the table of contents (the link and its anchor), and
+        // HTML in its file tree and the element's data
+        let mentions = if matches!(self.format, Format::Markdown | Format::Html) { 3 } else { 1 };
+        let bytes = file.path.len() * mentions + body_bytes + FILE_BYTES_OVERHEAD;
+        // The part's header lists it too, after a comma
+        let (listed_tokens, listed_bytes) = (tokens::estimate(&file.path) + 1, file.path.len() + 2);
+        if self.overflows(estimated + listed_tokens, bytes + listed_bytes) {
+            self.start_part()?;
+        }
+        self.part()?.file(file)?;
+        self.add(estimated, bytes);
+        let section = self.section.clone().unwrap_or_default();
+        if let Some(part) = self.parts.last_mut() {
+            part.files.push(IndexFile { path: file.path.clone(), section, tokens: estimated });
+            part.reserved_tokens += listed_tokens;
+            part.reserved_bytes += listed_bytes;
+        }
+        Ok(())
+    }
+
+    fn note(&mut self, text: &str) -> io::Result<()> {
+        self.part()?.note(text)
+    }
+
+    fn answer_format(&mut self, instructions: &str) -> io::Result<()> {
+        self.part()?.answer_format(instructions)
+    }
+
+    fn warnings(&mut self, warnings: &[Warning]) -> io::Result<()> {
+        self.part()?.warnings(warnings)
+    }
+
+    // Parts are finished by `close`, which also reports where the index went
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn file(path: &str, text: &str) -> FileEntry<'static> {
+        FileEntry {
+            path: path.to_string(),
+            bytes: text.len() as u64,
+            language: None,
+            sha256: None,
+            docs_url: None,
+            change: None,
+            reasons: Vec::new(),
+            body: FileBody::Text(Cow::Owned(text.to_string())),
+            matches: Vec::new(),
+            match_count: None,
+        }
+    }
+
+    #[test]
+    fn starts_a_part_before_the_file_that_does_not_fit() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("context.md");
+        let mut out = SplitRenderer::new(Format::Markdown, &MemoryBudget::new(1 << 20), &output, Limit::Tokens(150));
+        out.section("Project Context").unwrap();
+        out.file(&file("a.rs", &"word ".repeat(30))).unwrap();
+        out.file(&file("b.rs", "fn b() {}")).unwrap();
+        out.file(&file("c.rs", &"word ".repeat(30))).unwrap();
+        let index_path = out.close().unwrap();
+
+        assert_eq!(index_path, dir.path().join("context.index.json"));
+        let index: serde_json::Value = serde_json::from_slice(&fs::read(&index_path).unwrap()).unwrap();
+        let parts = index["parts"].as_array().unwrap();
+        let files: Vec<Vec<&str>> = parts
+            .iter()
+            .map(|part| part["files"].as_array().unwrap().iter().map(|file| file["path"].as_str().unwrap()).collect())
+            .collect();
+        assert_eq!(files, [vec!["a.rs", "b.rs"], vec!["c.rs"]]);
+        assert_eq!(parts[1]["path"], "context.part2.md");
+        // Each part is a document of its own, repeating the section it starts in
+        let second = fs::read_to_string(dir.path().join("context.part2.md")).unwrap();
+        assert!(second.contains("# Project Context"), "{}", second);
+        assert!(second.contains("c.rs"));
+        // Headed with what is where
+        assert!(
+            second.starts_with(
+                "> Part 2 of 2; context.index.json lists the files of every part  \n\
+                 > Files in this part: c.rs  \n\
+                 > Other parts: context.part1.md (2 files)\n\n"
+            ),
+            "{}",
+            second
+        );
+    }
+
+    #[test]
+    fn limits_parts_by_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("context.txt");
+        let mut out = SplitRenderer::new(Format::Text, &MemoryBudget::new(1 << 20), &output, Limit::Bytes(800));
+        out.section("Project Context").unwrap();
+        for name in ["a.rs", "b.rs", "c.rs"] {
+            out.file(&file(name, &"x".repeat(100))).unwrap();
+        }
+        out.close().unwrap();
+
+        let first = fs::read_to_string(dir.path().join("context.part1.txt")).unwrap();
+        assert!(first.starts_with("=== Parts ===\n\nPart 1 of 2;"), "{}", first);
+        assert!(first.contains("Files in this part: a.rs, b.rs\n") && first.contains("File: b.rs"), "{}", first);
+        let second = fs::read_to_string(dir.path().join("context.part2.txt")).unwrap();
+        assert!(second.contains("File: c.rs") && !second.contains("File: a.rs"), "{}", second);
+    }
+
+    #[test]
+    fn keeps_every_part_under_the_byte_limit() {
+        for (format, limit) in [(Format::Text, 1500), (Format::Markdown, 1500), (Format::Xml, 1500)] {
+            let dir = tempfile::tempdir().unwrap();
+            let output = dir.path().join("context.out");
+            let mut out = SplitRenderer::new(format, &MemoryBudget::new(1 << 20), &output, Limit::Bytes(limit));
+            out.section("Project Context").unwrap();
+            for i in 0..60 {
+                let path = format!("src/{}/module_{}.rs", "nested/".repeat(i % 5), i);
+                out.file(&file(&path, &"let x = 1;\n".repeat(i % 12))).unwrap();
+            }
+            out.section("Dependencies").unwrap();
+            out.file(&file("$CARGO_HOME/registry/src/serde-1.0.0/src/lib.rs", "pub mod de;\n")).unwrap();
+            out.close().unwrap();
+
+            let index: serde_json::Value = serde_json::from_slice(&fs::read(dir.path().join("context.index.json")).unwrap()).unwrap();
+            let parts = index["parts"].as_array().unwrap();
+            assert!(parts.len() > 3, "{:?}", format);
+            for part in parts {
+                let path = dir.path().join(part["path"].as_str().unwrap());
+                let size = fs::metadata(&path).unwrap().len() as usize;
+                assert!(size <= limit, "{:?}: {} is {} bytes", format, path.display(), size);
+            }
+        }
+    }
+}
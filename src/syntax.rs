@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use tree_sitter::{Language, Node, Parser};
+
+// Languages with a compiled-in grammar
+pub const GRAMMARS: &[&str] = &["rust", "python", "javascript", "typescript", "tsx", "go"];
+
+// Tree-sitter grammar and line-comment prefix for a language (as named by `language::LanguageMap`)
+fn grammar_for(language: &str) -> Option<(Language, &'static str)> {
+    let grammar = match language {
+        "rust" => (tree_sitter_rust::LANGUAGE.into(), "//"),
+        "python" => (tree_sitter_python::LANGUAGE.into(), "#"),
+        "javascript" => (tree_sitter_javascript::LANGUAGE.into(), "//"),
+        "typescript" => (tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(), "//"),
+        "tsx" => (tree_sitter_typescript::LANGUAGE_TSX.into(), "//"),
+        "go" => (tree_sitter_go::LANGUAGE.into(), "//"),
+        _ => return None,
+    };
+    Some(grammar)
+}
+
+// Human label for an item node, or None for nodes that shouldn't be counted as omitted
+fn item_label(kind: &str) -> Option<&'static str> {
+    let label = match kind {
+        "function_item" | "function_definition" | "function_declaration" | "method_declaration"
+        | "method_definition" | "function_signature_item" | "generator_function_declaration" => {
+            "function"
+        }
+        "struct_item" => "struct",
+        "enum_item" => "enum",
+        "impl_item" => "impl",
+        "trait_item" => "trait",
+        "mod_item" => "module",
+        "class_definition" | "class_declaration" => "class",
+        "use_declaration" | "import_statement" | "import_from_statement" | "import_declaration" => {
+            "import"
+        }
+        "type_item" | "type_declaration" | "type_alias_declaration" | "interface_declaration" => "type",
+        "comment" | "line_comment" | "block_comment" | "attribute_item" | "inner_attribute_item" => {
+            return None
+        }
+        _ => "item",
+    };
+    Some(label)
+}
+
+fn plural(label: &str, count: usize) -> String {
+    match (label, count) {
+        (_, 1) => format!("1 {}", label),
+        ("class", n) => format!("{} classes", n),
+        (label, n) => format!("{} {}s", n, label),
+    }
+}
+
+// Describe omitted items, e.g. "... 3 functions, 1 struct omitted ..."
+fn omission_marker(prefix: &str, nodes: &[Node]) -> Option<String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for node in nodes {
+        if let Some(label) = item_label(node.kind()) {
+            *counts.entry(label).or_default() += 1;
+        }
+    }
+    if counts.is_empty() {
+        return None;
+    }
+    let summary: Vec<String> = counts.iter().map(|(label, n)| plural(label, *n)).collect();
+    Some(format!("{} ... {} omitted ...", prefix, summary.join(", ")))
+}
+
+// Attributes and doc comments belong to the item after them; rustc rejects them dangling
+fn is_attached(node: &Node, source: &str) -> bool {
+    let text = &source[node.byte_range()];
+    match node.kind() {
+        "attribute_item" => true,
+        "line_comment" => text.starts_with("///") && !text.starts_with("////"),
+        "block_comment" => text.starts_with("/**") && !text.starts_with("/***") && text != "/**/",
+        _ => false,
+    }
+}
+
+// Index of the last node ending before `max_lines`, stepping back over attributes and doc
+// comments whose item doesn't fit
+fn last_fitting(nodes: &[Node], source: &str, max_lines: usize) -> Option<usize> {
+    let mut last = nodes.iter().rposition(|n| n.end_position().row < max_lines)?;
+    while is_attached(&nodes[last], source) {
+        last = last.checked_sub(1)?;
+    }
+    Some(last)
+}
+
+// Truncate source to at most `max_lines` lines (plus markers) at item boundaries.
+// If the first item that doesn't fit is a container (impl, class, module), its leading
+// members are kept and the container is closed. Returns None when the file has no grammar,
+// fails to parse cleanly, or no item fits; callers then fall back to a plain line cut.
+pub fn truncate_at_items(language: &str, source: &str, max_lines: usize) -> Option<String> {
+    let (language, prefix) = grammar_for(language)?;
+    let mut parser = Parser::new();
+    parser.set_language(&language).ok()?;
+    let tree = parser.parse(source, None)?;
+    let root = tree.root_node();
+    if root.has_error() {
+        return None;
+    }
+
+    let mut cursor = root.walk();
+    let items: Vec<Node> = root.named_children(&mut cursor).collect();
+    let fitting = last_fitting(&items, source, max_lines);
+    // The first omitted item, past any attributes and doc comments attached to it
+    let next = fitting.map_or(0, |i| i + 1);
+    let next = items[next..]
+        .iter()
+        .position(|n| !is_attached(n, source))
+        .map_or(items.len(), |k| next + k);
+
+    // Try to keep the leading members of a container that straddles the limit
+    if let Some(container) = items.get(next).filter(|n| n.start_position().row + 1 < max_lines) {
+        if let Some(body) = container.child_by_field_name("body") {
+            let mut body_cursor = body.walk();
+            let members: Vec<Node> = body.named_children(&mut body_cursor).collect();
+            // Leave a line for the closing brace
+            if let Some(m) = last_fitting(&members, source, max_lines.saturating_sub(1)) {
+                let line_start = source[..container.start_byte()].rfind('\n').map_or(0, |i| i + 1);
+                let indent: String = source[line_start..]
+                    .chars()
+                    .take_while(|c| *c == ' ' || *c == '\t')
+                    .collect();
+                let member_indent = members[m].start_position().column;
+
+                let mut truncated = source[..members[m].end_byte()].to_string();
+                if let Some(marker) = omission_marker(prefix, &members[m + 1..]) {
+                    truncated.push_str(&format!("\n\n{}{}", " ".repeat(member_indent), marker));
+                }
+                if prefix == "//" {
+                    truncated.push_str(&format!("\n{}}}", indent));
+                }
+                truncated.push('\n');
+                if let Some(marker) = omission_marker(prefix, &items[next + 1..]) {
+                    truncated.push_str(&format!("\n{}\n", marker));
+                }
+                return Some(truncated);
+            }
+        }
+    }
+
+    let fitting = fitting?;
+    let mut truncated = source[..items[fitting].end_byte()].to_string();
+    truncated.push('\n');
+    if let Some(marker) = omission_marker(prefix, &items[fitting + 1..]) {
+        truncated.push_str(&format!("\n{}\n", marker));
+    }
+    Some(truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cut_moves_before_attributes_of_omitted_item() {
+        let source = "fn a() {}\n\nfn b() {}\n\n/// Docs\n#[derive(Debug)]\nstruct C {\n    x: u8,\n}\n";
+        let truncated = truncate_at_items("rust", source, 6).unwrap();
+        assert_eq!(truncated, "fn a() {}\n\nfn b() {}\n\n// ... 1 struct omitted ...\n");
+    }
+
+    #[test]
+    fn container_keeps_leading_members_and_closes() {
+        let source = "impl S {\n    fn a() {}\n    fn b() {}\n    #[inline]\n    fn c() {}\n    fn d() {}\n}\n";
+        let truncated = truncate_at_items("rust", source, 5).unwrap();
+        assert_eq!(
+            truncated,
+            "impl S {\n    fn a() {}\n    fn b() {}\n\n    // ... 2 functions omitted ...\n}\n"
+        );
+    }
+
+    #[test]
+    fn plain_comments_may_stay_before_the_cut() {
+        let source = "fn a() {}\n// note\nfn b() {\n}\n";
+        let truncated = truncate_at_items("rust", source, 2).unwrap();
+        assert_eq!(truncated, "fn a() {}\n// note\n\n// ... 1 function omitted ...\n");
+    }
+
+    #[test]
+    fn every_listed_grammar_loads() {
+        for language in GRAMMARS {
+            assert!(grammar_for(language).is_some(), "{}", language);
+        }
+    }
+
+    #[test]
+    fn unknown_language_and_parse_errors_fall_back() {
+        assert!(truncate_at_items("cobol", "x", 1).is_none());
+        assert!(truncate_at_items("rust", "fn (", 1).is_none());
+    }
+}
@@ -0,0 +1,278 @@
+use regex::Regex;
+use serde::Serialize;
+
+// Longer assertion messages are cut, since the full text is still in the command output
+const MAX_MESSAGE_LINES: usize = 12;
+
+// A failed test as the runner reported it: its name, where it failed, and the assertion message
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct TestFailure {
+    pub name: String,
+    // `file:line[:column]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// The failed tests in captured output from cargo test, cargo nextest, pytest, or jest, in the
+// order they were reported. Output from anything else has none.
+pub fn failures(output: &str) -> Vec<TestFailure> {
+    let lines: Vec<&str> = output.lines().map(|line| line.trim_end_matches('\r')).collect();
+    let mut failures = Vec::new();
+    for failure in rust_failures(&lines).into_iter().chain(pytest_failures(&lines)).chain(jest_failures(&lines)) {
+        if !failures.iter().any(|seen: &TestFailure| seen.name == failure.name) {
+            failures.push(failure);
+        }
+    }
+    failures
+}
+
+// libtest's `test name ... FAILED` and nextest's `FAIL [ 0.004s] crate name` name the failed
+// tests; the panic in the thread named after each test has its location and message
+fn rust_failures(lines: &[&str]) -> Vec<TestFailure> {
+    let failed = Regex::new(r"^test (\S+) \.\.\. FAILED$|^\s*FAIL \[\s*[\d.]+s\] (?:\(\d+/\d+\) )?\S+ (\S+)$").expect("valid regex");
+    // `panicked at src/lib.rs:10:9:` with the message on the next lines (Rust 1.73 and later),
+    // or `panicked at 'message', src/lib.rs:10:9` before that
+    let panicked = Regex::new(r"^thread '([^']+)'(?: \(\d+\))? panicked at (?:'(.*)', )?(\S+?):?$").expect("valid regex");
+    // What can follow a message without a blank line: nextest's status lines and output headers
+    let status = Regex::new(r"^\s*\w+ \[\s*[\d.]+s\]|^-{3,} ").expect("valid regex");
+    let mut failures: Vec<TestFailure> = Vec::new();
+    for line in lines {
+        if let Some(caps) = failed.captures(line) {
+            let name = caps.get(1).or(caps.get(2)).map_or("", |name| name.as_str());
+            if !failures.iter().any(|failure| failure.name == name) {
+                failures.push(TestFailure { name: name.to_string(), location: None, message: None });
+            }
+        }
+    }
+    for (i, line) in lines.iter().enumerate() {
+        let Some(caps) = panicked.captures(line) else {
+            continue;
+        };
+        let Some(failure) = failures.iter_mut().find(|failure| failure.name == caps[1] && failure.location.is_none()) else {
+            continue;
+        };
+        failure.location = Some(caps[3].to_string());
+        failure.message = match caps.get(2) {
+            Some(message) => Some(message.as_str().to_string()),
+            None => message(lines[i + 1..].iter().copied().take_while(|line| {
+                !line.trim().is_empty()
+                    && !line.starts_with("note: ")
+                    && !line.starts_with("stack backtrace:")
+                    && !line.starts_with("thread '")
+                    && !status.is_match(line)
+            })),
+        };
+    }
+    failures
+}
+
+// The `FAILED path::name - message` lines of the short summary, with the location and `E`
+// lines from the name's `____ name ____` traceback block when it was printed
+fn pytest_failures(lines: &[&str]) -> Vec<TestFailure> {
+    let summary = Regex::new(r"^(?:FAILED|ERROR) (\S+?)(?: - (.*))?$").expect("valid regex");
+    let block = Regex::new(r"^_{3,} (.+?) _{3,}$").expect("valid regex");
+    let location = Regex::new(r"^(\S+\.py:\d+): ").expect("valid regex");
+    let mut failures = Vec::new();
+    for line in lines {
+        let Some(caps) = summary.captures(line) else {
+            continue;
+        };
+        let id = &caps[1];
+        // `tests/test_a.py::TestA::test_b[1]` has the block `TestA.test_b[1]`
+        let block_name = id.split_once("::").map_or(id, |(_, name)| name).replace("::", ".");
+        let mut failure = TestFailure {
+            name: id.to_string(),
+            location: None,
+            message: caps.get(2).map(|message| message.as_str().to_string()),
+        };
+        if let Some(start) = lines.iter().position(|line| block.captures(line).is_some_and(|caps| caps[1] == block_name)) {
+            let body: Vec<&str> = lines[start + 1..]
+                .iter()
+                .take_while(|line| !block.is_match(line) && !line.starts_with("====="))
+                .copied()
+                .collect();
+            // The last location is where the exception was raised
+            failure.location = body.iter().rev().find_map(|line| location.captures(line)).map(|caps| caps[1].to_string());
+            let explanation = message(body.iter().filter_map(|line| line.strip_prefix("E ")));
+            failure.message = explanation.or(failure.message);
+        }
+        failures.push(failure);
+    }
+    failures
+}
+
+// `● Suite › test` headers, each followed by the assertion message, a code frame, and the
+// stack, whose first frame outside node_modules is the location
+fn jest_failures(lines: &[&str]) -> Vec<TestFailure> {
+    let header = Regex::new(r"^\s*● (.+)$").expect("valid regex");
+    let code_frame = Regex::new(r"^\s*>?\s*\d+ \|").expect("valid regex");
+    let frame = Regex::new(r"^\s*at .*?\(?([^\s()]+:\d+:\d+)\)?$").expect("valid regex");
+    let mut failures = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let Some(caps) = header.captures(line) else {
+            continue;
+        };
+        // Logged output gets a `● Console` block of its own
+        if &caps[1] == "Console" {
+            continue;
+        }
+        let body: Vec<&str> = lines[i + 1..].iter().take_while(|line| !header.is_match(line)).copied().collect();
+        let text = body.iter().take_while(|line| !code_frame.is_match(line) && !frame.is_match(line));
+        failures.push(TestFailure {
+            name: caps[1].to_string(),
+            location: body
+                .iter()
+                .filter_map(|line| frame.captures(line))
+                .map(|caps| caps[1].to_string())
+                .find(|location| !location.contains("node_modules")),
+            message: message(text.copied()),
+        });
+    }
+    failures
+}
+
+// Message lines without their common indentation, blank runs squeezed, and cut after
+// MAX_MESSAGE_LINES; None when there is no text
+fn message<'a>(lines: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut kept: Vec<&str> = Vec::new();
+    for line in lines {
+        if line.trim().is_empty() && kept.last().is_none_or(|last| last.trim().is_empty()) {
+            continue;
+        }
+        kept.push(line.trim_end());
+    }
+    while kept.last().is_some_and(|line| line.trim().is_empty()) {
+        kept.pop();
+    }
+    let indent = kept
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()?;
+    let cut = kept.len() > MAX_MESSAGE_LINES;
+    let mut text: Vec<&str> = kept.iter().take(MAX_MESSAGE_LINES).map(|line| line.get(indent..).unwrap_or_default()).collect();
+    if cut {
+        text.push("...");
+    }
+    Some(text.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(name: &str, location: Option<&str>, message: Option<&str>) -> TestFailure {
+        TestFailure { name: name.to_string(), location: location.map(str::to_string), message: message.map(str::to_string) }
+    }
+
+    #[test]
+    fn reads_cargo_test_and_nextest_failures() {
+        let cargo = "\
+running 3 tests
+test tests::passes ... ok
+test tests::adds ... FAILED
+test tests::old ... FAILED
+
+failures:
+
+---- tests::adds stdout ----
+
+thread 'tests::adds' panicked at src/lib.rs:10:9:
+assertion `left == right` failed
+  left: 3
+ right: 4
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+---- tests::old stdout ----
+thread 'tests::old' panicked at 'boom', src/old.rs:2:5
+
+failures:
+    tests::adds
+    tests::old
+";
+        assert_eq!(
+            failures(cargo),
+            [
+                failure("tests::adds", Some("src/lib.rs:10:9"), Some("assertion `left == right` failed\n  left: 3\n right: 4")),
+                failure("tests::old", Some("src/old.rs:2:5"), Some("boom")),
+            ]
+        );
+
+        let nextest = "\
+        FAIL [   0.004s] app tests::adds
+--- STDERR:              app tests::adds ---
+thread 'tests::adds' panicked at src/lib.rs:10:9:
+it broke
+     Summary [   0.010s] 2 tests run: 1 passed, 1 failed, 0 skipped
+        FAIL [   0.004s] app tests::adds
+";
+        assert_eq!(failures(nextest), [failure("tests::adds", Some("src/lib.rs:10:9"), Some("it broke"))]);
+    }
+
+    #[test]
+    fn reads_pytest_failures() {
+        let output = "\
+=================================== FAILURES ===================================
+________________________________ TestMath.test_add ________________________________
+
+    def test_add(self):
+>       assert add(1, 2) == 4
+E       assert 3 == 4
+E        +  where 3 = add(1, 2)
+
+tests/test_math.py:5: AssertionError
+=========================== short test summary info ============================
+FAILED tests/test_math.py::TestMath::test_add - assert 3 == 4
+ERROR tests/test_db.py - ModuleNotFoundError: No module named 'db'
+========================= 1 failed, 1 error in 0.02s ==========================
+";
+        assert_eq!(
+            failures(output),
+            [
+                failure("tests/test_math.py::TestMath::test_add", Some("tests/test_math.py:5"), Some("assert 3 == 4\n +  where 3 = add(1, 2)")),
+                failure("tests/test_db.py", None, Some("ModuleNotFoundError: No module named 'db'")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_jest_failures() {
+        let output = "\
+ FAIL  src/sum.test.js
+  ● math › adds numbers
+
+    expect(received).toBe(expected) // Object.is equality
+
+    Expected: 4
+    Received: 5
+
+      10 | test('adds numbers', () => {
+    > 12 |   expect(sum(2, 2)).toBe(4);
+         |                     ^
+
+      at Object.toBe (node_modules/expect/build/index.js:1:1)
+      at Object.<anonymous> (src/sum.test.js:12:21)
+
+  ● Console
+
+    console.log
+      hello
+
+Summary of all failing tests
+  ● math › adds numbers
+
+    expect(received).toBe(expected) // Object.is equality
+";
+        assert_eq!(
+            failures(output),
+            [failure(
+                "math › adds numbers",
+                Some("src/sum.test.js:12:21"),
+                Some("expect(received).toBe(expected) // Object.is equality\n\nExpected: 4\nReceived: 5")
+            )]
+        );
+        assert!(failures("error: could not compile `app`\ntest result: ok. 3 passed").is_empty());
+    }
+}
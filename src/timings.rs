@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Stages of a run whose wall time is reported by --timings
+#[derive(Clone, Copy)]
+pub enum Phase {
+    Walking,
+    Filtering,
+    Reading,
+    Tokenizing,
+    Dependencies,
+    Writing,
+}
+
+const PHASES: [(Phase, &str); 6] = [
+    (Phase::Walking, "walking"),
+    (Phase::Filtering, "filtering"),
+    (Phase::Reading, "reading"),
+    (Phase::Tokenizing, "token counting"),
+    (Phase::Dependencies, "dependency resolution"),
+    (Phase::Writing, "writing"),
+];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ELAPSED_NANOS: [AtomicU64; 6] = [const { AtomicU64::new(0) }; 6];
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Run `f`, adding its wall time to `phase` when timings are enabled
+pub fn measure<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    ELAPSED_NANOS[phase as usize].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+// Print the per-phase breakdown to stderr so it never mixes with the generated context
+pub fn report(total: Duration) {
+    eprintln!("\n=== Timings ===");
+    for (phase, name) in PHASES {
+        let elapsed = Duration::from_nanos(ELAPSED_NANOS[phase as usize].load(Ordering::Relaxed));
+        eprintln!("{:>24}: {:>10.3} ms", name, elapsed.as_secs_f64() * 1000.0);
+    }
+    eprintln!("{:>24}: {:>10.3} ms", "total", total.as_secs_f64() * 1000.0);
+}
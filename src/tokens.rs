@@ -0,0 +1,326 @@
+use crate::manifest::ManifestTokens;
+use crate::render::FileBody;
+use crate::{content, depcache, digest};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Name of the built-in tokenizer, as listed by --capabilities
+pub const TOKENIZER: &str = "cl100k-estimate";
+
+// Tokens added by a file's header and fences around its contents
+const FILE_OVERHEAD: usize = 8;
+
+// cl100k's pre-tokenizer: contractions, letter runs with one leading non-letter, numbers of up
+// to three digits, punctuation runs with one leading space, and whitespace (without its
+// lookahead, which the regex crate doesn't support)
+fn pieces() -> &'static Regex {
+    static PIECES: OnceLock<Regex> = OnceLock::new();
+    PIECES.get_or_init(|| {
+        Regex::new(r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+")
+            .expect("valid regex")
+    })
+}
+
+// Bumped whenever `estimate` changes, so counts cached by older versions aren't reused
+const ESTIMATE_VERSION: u32 = 2;
+
+// Estimated token count of text, without the BPE vocabulary: each pre-tokenized piece is one
+// token, except that words longer than the vocabulary's common ones, non-ASCII letters (which
+// cl100k mostly spends a token or more on), and punctuation runs are split. Short reference
+// strings come within a token of cl100k (see the tests); longer texts drift either way.
+pub fn estimate(text: &str) -> usize {
+    pieces()
+        .find_iter(text)
+        .map(|piece| {
+            let piece = piece.as_str();
+            match piece.chars().last() {
+                Some(c) if c.is_alphabetic() => {
+                    let ascii = piece.chars().filter(char::is_ascii_alphabetic).count();
+                    let other = piece.chars().filter(|c| c.is_alphabetic() && !c.is_ascii()).count();
+                    let ascii = match ascii {
+                        0 => 0,
+                        1..=7 => 1,
+                        letters => letters.div_ceil(5),
+                    };
+                    ascii + other
+                }
+                Some(c) if !c.is_whitespace() && !c.is_numeric() => piece.chars().count().div_ceil(3),
+                _ => 1,
+            }
+        })
+        .sum()
+}
+
+// Texts shorter than this are estimated again rather than looked up: hashing them costs about
+// as much, and they would only bloat the cache
+const CACHED_BYTES: usize = 4096;
+
+// The cache file is shared by every project, so entries unused this long are dropped rather than
+// all but this run's, and past this many the least recently used go first
+const CACHE_MAX_AGE_DAYS: u64 = 30;
+const CACHE_MAX_ENTRIES: usize = 100_000;
+
+// A cached count and the day (since the epoch) a run last used it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+struct CachedCount {
+    tokens: usize,
+    day: u64,
+}
+
+// Counts of longer texts by SHA-256, so unchanged files aren't tokenized again on the next run.
+// `loaded` comes from the cache file; `used` is what this run counted or looked up, and is
+// merged back into it. `prefetched` holds this run's counts by path, so files written as read
+// aren't hashed a second time.
+#[derive(Default)]
+struct Cache {
+    loaded: HashMap<String, CachedCount>,
+    used: HashMap<String, usize>,
+    prefetched: HashMap<PathBuf, usize>,
+}
+
+fn cache() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+fn cache_location() -> Option<PathBuf> {
+    depcache::cache_root().map(|dir| dir.join("tokens").join(format!("{}-v{}.json", TOKENIZER, ESTIMATE_VERSION)))
+}
+
+// A missing or unreadable file is an empty cache
+fn read_cache(path: &Path) -> HashMap<String, CachedCount> {
+    fs::read(path).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok()).unwrap_or_default()
+}
+
+// Read the counts saved by earlier runs
+pub fn load_cache() {
+    let loaded = cache_location().map(|path| read_cache(&path)).unwrap_or_default();
+    cache().lock().unwrap().loaded = loaded;
+}
+
+// Merge the counts this run used into the cache file. It is read again first, so entries other
+// runs wrote meanwhile are kept, and replaced whole, so no run reads a partial file. Failing to
+// write is harmless, so errors are ignored.
+pub fn save_cache() {
+    let Some(path) = cache_location() else {
+        return;
+    };
+    let today = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() / 86_400);
+    let cache = cache().lock().unwrap();
+    let fresh = |key: &String| cache.loaded.get(key).is_some_and(|cached| cached.day == today);
+    if cache.used.keys().all(fresh) {
+        return;
+    }
+    let merged = merge(read_cache(&path), &cache.used, today, CACHE_MAX_ENTRIES);
+    if let Ok(json) = serde_json::to_vec(&merged) {
+        let _ = depcache::write_atomically(&path, &json);
+    }
+}
+
+// `stored` with `used` marked as used today, less entries too old or too many to keep
+fn merge(
+    mut stored: HashMap<String, CachedCount>,
+    used: &HashMap<String, usize>,
+    today: u64,
+    max_entries: usize,
+) -> HashMap<String, CachedCount> {
+    for (key, &tokens) in used {
+        stored.insert(key.clone(), CachedCount { tokens, day: today });
+    }
+    stored.retain(|_, cached| cached.day + CACHE_MAX_AGE_DAYS >= today);
+    if stored.len() > max_entries {
+        let mut entries: Vec<_> = stored.into_iter().collect();
+        entries.sort_by_key(|(_, cached)| Reverse(cached.day));
+        entries.truncate(max_entries);
+        stored = entries.into_iter().collect();
+    }
+    stored
+}
+
+// `estimate`, through the cache for longer texts
+pub fn count(text: &str) -> usize {
+    if text.len() < CACHED_BYTES {
+        return estimate(text);
+    }
+    let key = digest::sha256_hex(text.as_bytes());
+    let known = {
+        let mut cache = cache().lock().unwrap();
+        let known = cache.used.get(&key).copied().or_else(|| cache.loaded.get(&key).map(|cached| cached.tokens));
+        if let Some(tokens) = known {
+            cache.used.insert(key.clone(), tokens);
+        }
+        known
+    };
+    known.unwrap_or_else(|| {
+        let tokens = estimate(text);
+        cache().lock().unwrap().used.insert(key, tokens);
+        tokens
+    })
+}
+
+// Count the files about to be emitted on every core, so the counts `estimate_file` needs are
+// already cached when each file is written. Files that can't be read as text are left to the
+// writer, which reports them.
+pub fn prefetch(paths: &[PathBuf]) {
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    prefetch_file(path);
+                }
+            });
+        }
+    });
+}
+
+fn prefetch_file(path: &Path) {
+    if let Ok(text) = content::read_text(path) {
+        let tokens = count(&text);
+        cache().lock().unwrap().prefetched.insert(path.to_path_buf(), tokens);
+    }
+}
+
+// Estimated tokens of a file as it appears in the context: its header, fences, and body.
+// `source` is the file a text body was read from unchanged, whose prefetched count is used.
+pub fn estimate_file(path: &str, body: &FileBody, source: Option<&Path>) -> usize {
+    let body = match body {
+        FileBody::Text(text) => source
+            .and_then(|source| cache().lock().unwrap().prefetched.get(source).copied())
+            .unwrap_or_else(|| count(text)),
+        FileBody::Binary { description, preview, .. } => estimate(description) + preview.as_deref().map_or(0, estimate),
+        FileBody::HardLink(first) => estimate(first),
+        FileBody::Unreadable(reason) => estimate(reason),
+    };
+    estimate(path) + body + FILE_OVERHEAD
+}
+
+// Whether an earlier file went over --max-tokens, so later ones needn't be read
+pub fn exhausted(budget: &ManifestTokens) -> bool {
+    !budget.skipped.is_empty()
+}
+
+// Count a file against --max-tokens. Once one doesn't fit, it and every later file are left
+// out and listed, so the context keeps the files that come first.
+pub fn admit(budget: &mut ManifestTokens, path: &str, tokens: usize) -> bool {
+    if exhausted(budget) || budget.estimated + tokens > budget.max {
+        budget.skipped.push(path.to_string());
+        return false;
+    }
+    budget.estimated += tokens;
+    true
+}
+
+// The note listing what --max-tokens left out, naming the first few files
+pub fn skipped_note(budget: &ManifestTokens) -> String {
+    const LISTED: usize = 10;
+    let mut names = budget.skipped.iter().take(LISTED).cloned().collect::<Vec<_>>().join(", ");
+    if budget.skipped.len() > LISTED {
+        names.push_str(&format!(", and {} more", budget.skipped.len() - LISTED));
+    }
+    format!(
+        "Token budget of {} reached at about {} tokens; {} files left out: {}",
+        budget.max,
+        budget.estimated,
+        budget.skipped.len(),
+        names
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_roughly_one_token_per_piece() {
+        assert_eq!(estimate("fn main() {}"), 4);
+        assert_eq!(estimate("let x = 12345;"), 7);
+        assert_eq!(estimate(""), 0);
+        // Identifiers split at underscores
+        assert_eq!(estimate("process_output_with_options"), 4);
+    }
+
+    #[test]
+    fn estimates_come_within_a_token_of_cl100k() {
+        // Counts from tiktoken's cl100k_base, as published in its README and the OpenAI
+        // cookbook's token counting guide
+        let reference = [
+            ("hello world", 2),
+            ("Hello, world!", 4),
+            ("tiktoken is great!", 6),
+            ("antidisestablishmentarianism", 6),
+            ("2 + 2 = 4", 7),
+            ("お誕生日おめでとう", 9),
+        ];
+        for (text, tokens) in reference {
+            assert!(estimate(text).abs_diff(tokens) <= 1, "{:?}: estimated {}, cl100k has {}", text, estimate(text), tokens);
+        }
+    }
+
+    #[test]
+    fn prefetched_counts_match_estimates() {
+        let dir = tempfile::tempdir().unwrap();
+        let texts = ["fn main() {}\n".repeat(500), "let x = 12345;\n".repeat(400), "short".to_string()];
+        let paths: Vec<PathBuf> = texts
+            .iter()
+            .enumerate()
+            .map(|(index, text)| {
+                let path = dir.path().join(format!("{}.rs", index));
+                fs::write(&path, text).unwrap();
+                path
+            })
+            .collect();
+        prefetch(&paths);
+        for (path, text) in paths.iter().zip(&texts) {
+            assert_eq!(count(text), estimate(text));
+            let body = FileBody::Text(text.as_str().into());
+            assert_eq!(estimate_file("a.rs", &body, Some(path)), estimate_file("a.rs", &body, None));
+        }
+        let key = digest::sha256_hex(texts[0].as_bytes());
+        assert_eq!(cache().lock().unwrap().used.get(&key), Some(&estimate(&texts[0])));
+    }
+
+    #[test]
+    fn merges_counts_into_the_shared_cache() {
+        let cached = |tokens, day| CachedCount { tokens, day };
+        let stored = HashMap::from([
+            ("other-project".to_string(), cached(10, 100)),
+            ("changed".to_string(), cached(20, 100)),
+            ("stale".to_string(), cached(30, 60)),
+        ]);
+        let used = HashMap::from([("changed".to_string(), 25), ("new".to_string(), 40)]);
+        let merged = merge(stored.clone(), &used, 101, 10);
+        assert_eq!(
+            merged,
+            HashMap::from([
+                ("other-project".to_string(), cached(10, 100)),
+                ("changed".to_string(), cached(25, 101)),
+                ("new".to_string(), cached(40, 101)),
+            ])
+        );
+        // Past the limit, the least recently used entries go
+        let mut kept: Vec<_> = merge(stored, &used, 101, 2).into_keys().collect();
+        kept.sort();
+        assert_eq!(kept, ["changed", "new"]);
+    }
+
+    #[test]
+    fn budget_stops_at_the_first_file_that_does_not_fit() {
+        let mut budget = ManifestTokens { max: 100, estimated: 30, skipped: Vec::new() };
+        assert!(admit(&mut budget, "a.rs", 50));
+        assert!(!admit(&mut budget, "big.rs", 40));
+        assert!(!admit(&mut budget, "small.rs", 1));
+        assert_eq!(budget.estimated, 80);
+        assert_eq!(budget.skipped, ["big.rs", "small.rs"]);
+        assert_eq!(skipped_note(&budget), "Token budget of 100 reached at about 80 tokens; 2 files left out: big.rs, small.rs");
+    }
+}
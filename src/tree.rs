@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+// Directory -> children, with files as leaves carrying a value (their anchor in the HTML sidebar)
+pub struct TreeNode<T> {
+    pub dirs: BTreeMap<String, TreeNode<T>>,
+    pub files: Vec<(String, T)>,
+}
+
+impl<T> Default for TreeNode<T> {
+    fn default() -> Self {
+        TreeNode {
+            dirs: BTreeMap::new(),
+            files: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> TreeNode<T> {
+    // Absolute paths keep their leading `/` as a directory of its own, so they can't merge
+    // with relative ones (e.g. dependency sources next to [layout] paths)
+    pub fn build(files: &[(String, T)]) -> Self {
+        let mut root = TreeNode::default();
+        for (path, value) in files {
+            let mut parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+            if path.starts_with('/') {
+                parts.insert(0, "/");
+            }
+            let name = parts.pop().unwrap_or(path).to_string();
+            let mut node = &mut root;
+            for part in parts {
+                node = node.dirs.entry(part.to_string()).or_default();
+            }
+            node.files.push((name, value.clone()));
+        }
+        root
+    }
+}
+
+// The paths as a `tree`-style listing: directories first, then files, each level sorted. Leading
+// directories shared by every path are folded into the first line, so absolute paths don't
+// start from `/`.
+pub fn ascii(paths: &[String]) -> String {
+    let files: Vec<(String, ())> = paths.iter().map(|path| (path.clone(), ())).collect();
+    let mut node = &TreeNode::build(&files);
+    let mut label = String::new();
+    while node.files.is_empty() && node.dirs.len() == 1 {
+        let (name, child) = node.dirs.iter().next().expect("one directory");
+        if !label.is_empty() && !label.ends_with('/') {
+            label.push('/');
+        }
+        label.push_str(name);
+        node = child;
+    }
+    if label.is_empty() {
+        label.push('.');
+    }
+    let mut out = format!("{}\n", label);
+    write_level(&mut out, node, "");
+    out
+}
+
+fn write_level<T>(out: &mut String, node: &TreeNode<T>, indent: &str) {
+    let mut files: Vec<&str> = node.files.iter().map(|(name, _)| name.as_str()).collect();
+    files.sort_unstable();
+    files.dedup();
+    let count = node.dirs.len() + files.len();
+    let mut entries = 0;
+    for (name, child) in &node.dirs {
+        entries += 1;
+        let (branch, next) = if entries == count { ("└── ", "    ") } else { ("├── ", "│   ") };
+        out.push_str(&format!("{}{}{}/\n", indent, branch, name));
+        write_level(out, child, &format!("{}{}", indent, next));
+    }
+    for name in files {
+        entries += 1;
+        let branch = if entries == count { "└── " } else { "├── " };
+        out.push_str(&format!("{}{}{}\n", indent, branch, name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_nested_tree_from_paths() {
+        let files = [
+            ("src/a.rs".to_string(), "file-1".to_string()),
+            ("src/bin/b.rs".to_string(), "file-2".to_string()),
+            ("README.md".to_string(), "file-3".to_string()),
+        ];
+        let tree = TreeNode::build(&files);
+        assert_eq!(tree.files, [("README.md".to_string(), "file-3".to_string())]);
+        let src = &tree.dirs["src"];
+        assert_eq!(src.files, [("a.rs".to_string(), "file-1".to_string())]);
+        assert_eq!(src.dirs["bin"].files, [("b.rs".to_string(), "file-2".to_string())]);
+    }
+
+    #[test]
+    fn draws_ascii_tree_under_the_common_directory() {
+        let paths = ["/home/me/app/src/main.rs", "/home/me/app/src/cli/args.rs", "/home/me/app/Cargo.toml"];
+        let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+        assert_eq!(
+            ascii(&paths),
+            "/home/me/app\n├── src/\n│   ├── cli/\n│   │   └── args.rs\n│   └── main.rs\n└── Cargo.toml\n"
+        );
+        assert_eq!(ascii(&["a.rs".to_string(), "b/c.rs".to_string()]), ".\n├── b/\n│   └── c.rs\n└── a.rs\n");
+    }
+}
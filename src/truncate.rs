@@ -0,0 +1,121 @@
+use crate::language;
+use std::borrow::Cow;
+use std::ops::Range;
+
+// Cap a file's contents at `max_lines` lines. Source files with a tree-sitter grammar are cut
+// at item boundaries so they stay parseable; anything else is cut at the line limit.
+pub fn truncate_lines<'a>(language: Option<&str>, contents: &'a str, max_lines: usize) -> Cow<'a, str> {
+    let total_lines = contents.lines().count();
+    if total_lines <= max_lines {
+        return Cow::Borrowed(contents);
+    }
+
+    if let Some(truncated) = language.and_then(|language| truncate_at_items(language, contents, max_lines)) {
+        return Cow::Owned(truncated);
+    }
+
+    let mut truncated: String = contents
+        .lines()
+        .take(max_lines)
+        .flat_map(|line| [line, "\n"])
+        .collect();
+    push_marker(&mut truncated, language, &format!("{} more lines omitted", total_lines - max_lines));
+    Cow::Owned(truncated)
+}
+
+// Where to cut `bytes` to keep at most `max` of them: after the last line that fits, or when
+// even the first line is longer, before the character the limit falls in. Every cap on text
+// (file sizes, fetched documents, upload chunks) cuts here, so a multi-byte character is never
+// split into an invalid sequence.
+pub fn cut_point(bytes: &[u8], max: usize) -> usize {
+    if bytes.len() <= max {
+        return bytes.len();
+    }
+    if let Some(newline) = bytes[..max].iter().rposition(|byte| *byte == b'\n') {
+        return newline + 1;
+    }
+    // UTF-8 continuation bytes look like 0b10xxxxxx
+    let mut end = max;
+    while end > 0 && bytes[end] & 0xC0 == 0x80 {
+        end -= 1;
+    }
+    end
+}
+
+// End cut text with a line saying what was left out, e.g. `// ... 40 more lines omitted ...`;
+// a comment in languages that have line comments, so the cut file still parses. A cut inside
+// a line gets the marker on a line of its own.
+pub fn push_marker(text: &mut String, language: Option<&str>, omitted: &str) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    match language.and_then(language::comment_prefix) {
+        Some(prefix) => text.push_str(&format!("{} ... {} ...\n", prefix, omitted)),
+        None => text.push_str(&format!("... {} ...\n", omitted)),
+    }
+}
+
+// Byte ranges of consecutive chunks of at most `chunk_size` bytes, each ending on a line (or
+// at least a character) boundary so every chunk is readable on its own
+pub fn chunk_ranges(content: &[u8], chunk_size: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < content.len() {
+        let rest = &content[start..];
+        // A chunk smaller than one character still has to move forward
+        let mut len = cut_point(rest, chunk_size.max(1));
+        if len == 0 {
+            len = rest.iter().skip(1).position(|byte| byte & 0xC0 != 0x80).map_or(rest.len(), |i| i + 1);
+        }
+        ranges.push(start..start + len);
+        start += len;
+    }
+    ranges
+}
+
+#[cfg(feature = "syntax")]
+fn truncate_at_items(language: &str, contents: &str, max_lines: usize) -> Option<String> {
+    crate::syntax::truncate_at_items(language, contents, max_lines)
+}
+
+#[cfg(not(feature = "syntax"))]
+fn truncate_at_items(_language: &str, _contents: &str, _max_lines: usize) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(text: &str, max: usize) -> &str {
+        &text[..cut_point(text.as_bytes(), max)]
+    }
+
+    #[test]
+    fn cuts_on_line_and_character_boundaries() {
+        let text = "fn a() {}\nlet s = \"héllo\";\n";
+        assert_eq!(prefix(text, text.len()), text);
+        assert_eq!(prefix(text, 20), "fn a() {}\n");
+        // Inside the first line, the cut backs up to the start of the two-byte `é`
+        let line = "héllo wörld";
+        assert_eq!(prefix(line, 2), "h");
+        assert_eq!(prefix(line, 3), "hé");
+        assert_eq!(prefix("日本", 5), "日");
+        assert_eq!(prefix("日本", 2), "");
+        let mut cut = prefix(line, 3).to_string();
+        push_marker(&mut cut, Some("rust"), "9 bytes more omitted");
+        assert_eq!(cut, "hé\n// ... 9 bytes more omitted ...\n");
+        let mut cut = String::from("a\n");
+        push_marker(&mut cut, None, "the rest omitted");
+        assert_eq!(cut, "a\n... the rest omitted ...\n");
+    }
+
+    #[test]
+    fn splits_content_into_chunks() {
+        assert_eq!(chunk_ranges(b"abcdefghij", 4), [0..4, 4..8, 8..10]);
+        assert_eq!(chunk_ranges(b"abcdefgh", 4), [0..4, 4..8]);
+        assert!(chunk_ranges(b"", 4).is_empty());
+        assert_eq!(chunk_ranges(b"ab\ncdefg\n", 4), [0..3, 3..7, 7..9]);
+        assert_eq!(chunk_ranges("aé€".as_bytes(), 2), [0..1, 1..3, 3..6]);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::capture::format_duration;
+use crate::digest;
+use crate::http::{self, describe_error};
+use crate::session;
+use crate::spill::{MemoryBudget, SpillBuffer};
+use crate::truncate;
+use crate::warnings;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const GIST_API_URL: &str = "https://api.github.com/gists";
+
+// Progress of chunked uploads, kept until the last chunk is accepted
+const UPLOADS_DIR: &str = "uploads";
+
+// Requests are tried this many times on rate limiting, server errors, and dropped connections
+const MAX_ATTEMPTS: u32 = 5;
+
+// Longest wait between attempts, including one asked for by `Retry-After`
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+// How uploads are delivered to services that limit request size and rate
+pub struct UploadOptions<'a> {
+    // Split paste uploads into POSTs of at most this many bytes
+    pub chunk_size: Option<usize>,
+    // Most requests sent per minute
+    pub requests_per_minute: Option<u32>,
+    // Project directory whose `.contree` keeps the progress of chunked uploads
+    pub root: &'a Path,
+}
+
+// Publish the generated context to the given target and return the URL it can be viewed at.
+// The target is either `gist` or the URL of a paste endpoint that accepts a raw POST body.
+// The content (possibly a mapped spill file) is sent as is rather than copied; the gist's
+// JSON envelope is built in a buffer under the run's memory budget.
+pub fn upload(target: &str, content: &[u8], budget: &MemoryBudget, options: &UploadOptions) -> Result<String> {
+    let mut limiter = RateLimiter::new(options.requests_per_minute);
+    match target.trim() {
+        "gist" => upload_gist(&String::from_utf8_lossy(content), budget, &mut limiter),
+        url if url.starts_with("http://") || url.starts_with("https://") => match options.chunk_size {
+            Some(chunk_size) if content.len() > chunk_size => {
+                upload_chunked(url, content, chunk_size, options.root, &mut limiter)
+            }
+            _ => upload_paste(url, content, &mut limiter),
+        },
+        other => bail!(
+            "Unknown upload target '{}' (expected 'gist' or an http(s) paste endpoint URL)",
+            other
+        ),
+    }
+}
+
+#[derive(Serialize)]
+struct GistRequest<'a> {
+    description: &'a str,
+    public: bool,
+    files: BTreeMap<&'a str, GistFile<'a>>,
+}
+
+#[derive(Serialize)]
+struct GistFile<'a> {
+    content: &'a str,
+}
+
+// Create a secret gist holding the context, authenticated with GITHUB_TOKEN or GH_TOKEN
+fn upload_gist(content: &str, budget: &MemoryBudget, limiter: &mut RateLimiter) -> Result<String> {
+    let token = env::var("GITHUB_TOKEN")
+        .or_else(|_| env::var("GH_TOKEN"))
+        .context("Uploading a gist requires GITHUB_TOKEN or GH_TOKEN to be set")?;
+
+    // Serialized from borrowed fields so the content is escaped straight into the buffer
+    let request = GistRequest {
+        description: "contree context",
+        public: false,
+        files: BTreeMap::from([("context.md", GistFile { content })]),
+    };
+    let mut body = SpillBuffer::new(budget);
+    serde_json::to_writer(&mut body, &request)?;
+    let body = body.finish()?;
+
+    let request = ureq::post(GIST_API_URL)
+        .set("Authorization", &format!("Bearer {}", token))
+        .set("Accept", "application/vnd.github+json")
+        .set("User-Agent", http::USER_AGENT)
+        .set("Content-Type", "application/json");
+    let response = send_with_retry("GitHub", limiter, &request, &body).context("Failed to create gist")?;
+
+    let gist: Value = serde_json::from_reader(response.into_reader())
+        .context("GitHub returned an unexpected gist response")?;
+    gist["html_url"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("GitHub gist response did not contain a URL"))
+}
+
+// POST the raw context to a paste service and work out the resulting URL from the response.
+// Services differ: some redirect via Location, some return the URL as the body, some return JSON.
+fn upload_paste(endpoint: &str, content: &[u8], limiter: &mut RateLimiter) -> Result<String> {
+    let agent = ureq::AgentBuilder::new().redirects(0).build();
+    let request = agent
+        .post(endpoint)
+        .set("Content-Type", "text/plain; charset=utf-8")
+        .set("User-Agent", http::USER_AGENT);
+    let response = send_with_retry(endpoint, limiter, &request, content)
+        .with_context(|| format!("Failed to upload to {}", endpoint))?;
+    paste_url(endpoint, response)
+}
+
+// Where a paste service says the upload can be viewed
+fn paste_url(endpoint: &str, response: ureq::Response) -> Result<String> {
+    if let Some(location) = response.header("Location") {
+        return Ok(location.to_string());
+    }
+
+    let body = response
+        .into_string()
+        .context("Failed to read paste service response")?;
+    let body = body.trim();
+    if let Ok(value) = serde_json::from_str::<Value>(body) {
+        for key in ["url", "link", "html_url"] {
+            if let Some(url) = value[key].as_str() {
+                return Ok(url.to_string());
+            }
+        }
+        // Hastebin-style services return only the paste's key, served from the site root
+        if let Some(key) = value["key"].as_str() {
+            return Ok(format!("{}/{}", origin(endpoint), key));
+        }
+    }
+    if body.starts_with("http://") || body.starts_with("https://") {
+        return Ok(body.lines().next().unwrap_or(body).to_string());
+    }
+
+    bail!("Could not find a URL in the response from {}", endpoint)
+}
+
+// Progress of a chunked upload, saved after every accepted chunk so a failed upload of the
+// same context resumes where it stopped
+#[derive(Serialize, Deserialize)]
+struct ChunkedUpload {
+    endpoint: String,
+    total: usize,
+    chunk_size: usize,
+    // Chunks the endpoint has accepted
+    sent: usize,
+}
+
+// POST the context in chunks, each with `Content-Range: bytes <first>-<last>/<total>` and an
+// `X-Upload-Id` naming the whole upload (the context's SHA-256), for endpoints that take
+// requests of limited size and assemble them. The last chunk's response carries the URL.
+fn upload_chunked(endpoint: &str, content: &[u8], chunk_size: usize, root: &Path, limiter: &mut RateLimiter) -> Result<String> {
+    let upload_id = digest::sha256_hex(content);
+    let state_path = session::contree_dir(root)?.join(UPLOADS_DIR).join(format!("{}.json", upload_id));
+    let mut state = ChunkedUpload {
+        endpoint: endpoint.to_string(),
+        total: content.len(),
+        chunk_size,
+        sent: 0,
+    };
+    // Only the same context, sent to the same endpoint in the same chunks, can pick up
+    if let Some(saved) = load_state(&state_path) {
+        if saved.endpoint == state.endpoint && saved.total == state.total && saved.chunk_size == chunk_size {
+            state.sent = saved.sent;
+        }
+    }
+    let chunks = truncate::chunk_ranges(content, chunk_size);
+    if state.sent > 0 {
+        eprintln!("Resuming upload to {} at chunk {}/{}", endpoint, state.sent + 1, chunks.len());
+    }
+
+    let agent = ureq::AgentBuilder::new().redirects(0).build();
+    for (i, range) in chunks.iter().enumerate().skip(state.sent) {
+        let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, content.len());
+        let request = agent
+            .post(endpoint)
+            .set("Content-Type", "text/plain; charset=utf-8")
+            .set("User-Agent", http::USER_AGENT)
+            .set("Content-Range", &content_range)
+            .set("X-Upload-Id", &upload_id);
+        let response = send_with_retry(endpoint, limiter, &request, &content[range.clone()]).with_context(|| {
+            format!("Failed to upload chunk {}/{} to {}; run again to resume", i + 1, chunks.len(), endpoint)
+        })?;
+        eprintln!("Uploaded chunk {}/{} ({} of {} bytes)", i + 1, chunks.len(), range.end, content.len());
+        if i + 1 == chunks.len() {
+            let _ = fs::remove_file(&state_path);
+            return paste_url(endpoint, response);
+        }
+        state.sent = i + 1;
+        save_state(&state_path, &state)?;
+    }
+    bail!("Nothing left to upload to {}", endpoint)
+}
+
+fn load_state(path: &Path) -> Option<ChunkedUpload> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn save_state(path: &Path, state: &ChunkedUpload) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, serde_json::to_string_pretty(state)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// Spaces requests evenly to stay under a requests-per-minute limit
+struct RateLimiter {
+    interval: Option<Duration>,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_minute: Option<u32>) -> Self {
+        RateLimiter {
+            interval: requests_per_minute.filter(|n| *n > 0).map(|n| Duration::from_secs(60) / n),
+            last: None,
+        }
+    }
+
+    fn wait(&mut self) {
+        if let (Some(interval), Some(last)) = (self.interval, self.last) {
+            if let Some(remaining) = interval.checked_sub(last.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+// Send a request, retrying with exponential backoff on rate limiting (429), server errors, and
+// dropped connections. A `Retry-After` in seconds from the server takes the place of the backoff.
+fn send_with_retry(service: &str, limiter: &mut RateLimiter, request: &ureq::Request, body: &[u8]) -> Result<ureq::Response> {
+    let mut attempt = 1;
+    loop {
+        limiter.wait();
+        let (reason, retry_after) = match request.clone().send_bytes(body) {
+            Ok(response) => return Ok(response),
+            Err(ureq::Error::Status(code, response)) if is_retryable(code) && attempt < MAX_ATTEMPTS => {
+                (format!("HTTP {}", code), response.header("Retry-After").and_then(|s| s.trim().parse().ok()))
+            }
+            Err(ureq::Error::Transport(transport)) if attempt < MAX_ATTEMPTS => (transport.to_string(), None),
+            Err(e) => return Err(describe_error(e)),
+        };
+        let delay = backoff(attempt, retry_after);
+        warnings::warn(warnings::Kind::Retry, format!("{} failed ({}); retrying in {} (attempt {}/{})", service, reason, format_duration(delay), attempt + 1, MAX_ATTEMPTS));
+        thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+fn is_retryable(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+// 1s, 2s, 4s, ... or the server's `Retry-After`, capped at MAX_BACKOFF
+fn backoff(attempt: u32, retry_after_secs: Option<u64>) -> Duration {
+    let delay = match retry_after_secs {
+        Some(secs) => Duration::from_secs(secs),
+        None => Duration::from_secs(1 << (attempt - 1).min(8)),
+    };
+    delay.min(MAX_BACKOFF)
+}
+
+// `https://host:port` of a URL, without its path
+fn origin(url: &str) -> &str {
+    let after_scheme = url.find("://").map_or(0, |i| i + 3);
+    match url[after_scheme..].find('/') {
+        Some(i) => &url[..after_scheme + i],
+        None => url,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially_unless_told_when() {
+        assert_eq!(backoff(1, None), Duration::from_secs(1));
+        assert_eq!(backoff(4, None), Duration::from_secs(8));
+        assert_eq!(backoff(2, Some(30)), Duration::from_secs(30));
+        assert_eq!(backoff(1, Some(86_400)), MAX_BACKOFF);
+        assert!(is_retryable(429) && is_retryable(503) && !is_retryable(404));
+    }
+
+    #[test]
+    fn finds_paste_origin() {
+        assert_eq!(origin("https://paste.example.com:8080/documents"), "https://paste.example.com:8080");
+        assert_eq!(origin("https://paste.example.com"), "https://paste.example.com");
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Serialize;
+use std::sync::Mutex;
+
+// What a warning is about, so automation can react to some and ignore others
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // A file or path that was asked for (or walked) but left out
+    Skipped,
+    // A --max-* guard stopped the scan or left files out
+    Limit,
+    // --max-file-lines shortened a file
+    Truncated,
+    // Dependency files couldn't be resolved fully
+    Dependencies,
+    // An option that had no effect in this run
+    Ignored,
+    // A failed request that will be retried
+    Retry,
+}
+
+// One warning as recorded in the manifest and JSON output
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: Kind,
+    pub message: String,
+}
+
+static WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+// Print `Warning: <message>` to stderr and keep it for the manifest and JSON output
+pub fn warn(kind: Kind, message: impl Into<String>) {
+    let message = message.into();
+    eprintln!("Warning: {}", message);
+    WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).push(Warning { kind, message });
+}
+
+// Every warning so far, in the order they were printed
+pub fn collected() -> Vec<Warning> {
+    WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_warnings_in_order() {
+        warn(Kind::Skipped, "Skipping socket /tmp/a.sock");
+        warn(Kind::Truncated, "Truncated src/lib.rs to 200 lines");
+        let collected = collected();
+        let position = |message: &str| collected.iter().position(|warning| warning.message == message).unwrap();
+        assert!(position("Skipping socket /tmp/a.sock") < position("Truncated src/lib.rs to 200 lines"));
+        assert_eq!(serde_json::to_value(&collected[position("Skipping socket /tmp/a.sock")]).unwrap()["kind"], "skipped");
+    }
+}